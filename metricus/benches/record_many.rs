@@ -0,0 +1,81 @@
+//! Cost of recording a batch of values into a histogram one `record` call at a time compared to a
+//! single `record_many` call. The backend used here takes a lock for every call into it, as backends
+//! that buffer values typically synchronize, so that the difference is the dispatch and synchronization
+//! amortized over the batch.
+//!
+//! ```text
+//! cargo bench -p metricus --bench record_many
+//! ```
+
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use metricus::{Histogram, HistogramOps, Id, Metrics, Tags, set_metrics};
+use std::sync::Mutex;
+
+const BATCH_SIZE: usize = 10_000;
+
+#[derive(Default)]
+struct CustomBackend {
+    sum: Mutex<u64>,
+}
+
+impl Metrics for CustomBackend {
+    fn name(&self) -> &'static str {
+        "custom"
+    }
+
+    fn new_counter(&mut self, _name: &str, _tags: Tags) -> Id {
+        0
+    }
+
+    fn delete_counter(&mut self, _id: Id) {
+        // no-op
+    }
+
+    fn increment_counter_by(&mut self, _id: Id, _delta: u64) {
+        // no-op
+    }
+
+    fn new_histogram(&mut self, _name: &str, _tags: Tags) -> Id {
+        0
+    }
+
+    fn delete_histogram(&mut self, _id: Id) {
+        // no-op
+    }
+
+    fn record(&mut self, _id: Id, value: u64) {
+        let mut sum = self.sum.lock().unwrap();
+        *sum = sum.wrapping_add(black_box(value));
+    }
+
+    fn record_many(&mut self, _id: Id, values: &[u64]) {
+        let mut sum = self.sum.lock().unwrap();
+        for value in values {
+            *sum = sum.wrapping_add(black_box(*value));
+        }
+    }
+}
+
+fn benchmark_record(c: &mut Criterion) {
+    set_metrics(CustomBackend::default());
+    let histogram = Histogram::new("record_many", &[]);
+    let values: Vec<u64> = (0..BATCH_SIZE as u64).collect();
+
+    let mut group = c.benchmark_group("record_many");
+    group.bench_function("record", |b| {
+        b.iter(|| {
+            for value in &values {
+                histogram.record(*value);
+            }
+        });
+    });
+    group.bench_function("record_many", |b| {
+        b.iter(|| {
+            histogram.record_many(black_box(&values));
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_record);
+criterion_main!(benches);
@@ -0,0 +1,92 @@
+//! Cost of timing a region with a span, i.e. `Histogram::span()` followed by the drop of the span,
+//! which takes two clock reads and records the elapsed time with the installed backend. The backend
+//! used here does nothing with the recorded values, so the results are the overhead of the clock and
+//! of the dispatch alone.
+//!
+//! Run it once per clock to compare them on the target hardware:
+//!
+//! ```text
+//! cargo bench -p metricus --bench span                     # Instant
+//! cargo bench -p metricus --bench span --features rdtsc    # TSC converted to nanoseconds
+//! cargo bench -p metricus --bench span --features cycles   # raw TSC cycles
+//! ```
+//!
+//! Criterion reports the time per iteration, the median is included in the report written to
+//! `target/criterion/span/report/index.html` and is the figure to compare. Multiply it by the
+//! TSC frequency (e.g. the nominal frequency in `/proc/cpuinfo`) to get cycles. The `clock_read`
+//! benchmark is the cost of a single read of the same clock, so that `span - 2 * clock_read` is the
+//! overhead added on top of reading the clock. The TSC is usually an order of magnitude cheaper to
+//! read than `Instant`, unless it is not invariant or the platform virtualizes it, in which case
+//! `rdtsc` may be no faster or drift across cores and should not be used.
+
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use metricus::{Histogram, HistogramOps, Id, Metrics, Tags, set_metrics};
+
+struct CustomBackend;
+
+impl Metrics for CustomBackend {
+    fn name(&self) -> &'static str {
+        "custom"
+    }
+
+    fn new_counter(&mut self, _name: &str, _tags: Tags) -> Id {
+        0
+    }
+
+    fn delete_counter(&mut self, _id: Id) {
+        // no-op
+    }
+
+    fn increment_counter_by(&mut self, _id: Id, _delta: u64) {
+        // no-op
+    }
+
+    fn new_histogram(&mut self, _name: &str, _tags: Tags) -> Id {
+        0
+    }
+
+    fn delete_histogram(&mut self, _id: Id) {
+        // no-op
+    }
+
+    fn record(&mut self, _id: Id, value: u64) {
+        black_box(value);
+    }
+}
+
+const CLOCK: &str = if cfg!(feature = "cycles") {
+    "cycles"
+} else if cfg!(feature = "rdtsc") {
+    "rdtsc"
+} else {
+    "instant"
+};
+
+fn benchmark_clock_read(c: &mut Criterion) {
+    #[cfg(feature = "rdtsc")]
+    let clock = quanta::Clock::new();
+
+    c.benchmark_group("span")
+        .bench_function(format!("clock_read/{CLOCK}"), |b| {
+            b.iter(|| {
+                #[cfg(feature = "rdtsc")]
+                black_box(clock.raw());
+                #[cfg(not(feature = "rdtsc"))]
+                black_box(std::time::Instant::now());
+            });
+        });
+}
+
+fn benchmark_span(c: &mut Criterion) {
+    set_metrics(CustomBackend);
+    let histogram = Histogram::new("latencies", &[]);
+
+    c.benchmark_group("span").bench_function(format!("span/{CLOCK}"), |b| {
+        b.iter(|| {
+            let _span = histogram.span();
+        });
+    });
+}
+
+criterion_group!(benches, benchmark_clock_read, benchmark_span);
+criterion_main!(benches);
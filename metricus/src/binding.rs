@@ -0,0 +1,222 @@
+//! Binding of metric objects to the active backend, which is renewed when the backend is replaced.
+
+use crate::access::{bind_metrics, generation};
+use crate::{Id, MetricsHandle, Tags, ValueTransform};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering, fence};
+
+/// Backend and id a metric object records with, along with the backend generation (see
+/// [crate::set_metrics]) they belong to. Once the generation is stale the metric is registered again
+/// with the active backend on its next use, so that objects created before the backend was set, such
+/// as the statics generated by the macros, do not keep recording into the no-op backend.
+///
+/// The backend, id and generation are published together behind a single pointer, so that a reader
+/// never pairs the id of one backend with another backend. Updates racing with the re-registration
+/// are still recorded into the previous backend. The bindings replaced by a re-registration are leaked
+/// as other threads may still be reading them, which is bounded by the number of backend replacements.
+pub(crate) struct Binding {
+    /// Binding to the backend active at creation.
+    initial: Bound,
+    /// Binding to the backend registered with most recently, null until the backend is replaced.
+    rebound: AtomicPtr<Bound>,
+    /// Generation of the latest registration, claimed by the thread performing it.
+    claimed: AtomicU64,
+    registration: Registration,
+}
+
+/// Backend and id of a metric object for one backend generation, immutable once published.
+struct Bound {
+    handle: &'static MetricsHandle,
+    id: Id,
+    generation: u64,
+}
+
+/// How to register the metric again with a new backend.
+pub(crate) enum Registration {
+    /// Registered by the backend beforehand, only the handle is renewed.
+    Id,
+    Counter(Key),
+    /// Counter with a name and tags that live for the rest of the program, which are not copied.
+    CounterStatic(&'static str, Tags<'static>),
+    Gauge(Key),
+    Histogram(Key),
+    HistogramWithTransform(Key, SharedTransform),
+    HistogramWithResolution(Key, u64),
+}
+
+/// Name and tags of a metric in the order they were given, copied into a single string along with the
+/// end offset of each tag key and value, which is not allocated for metrics without tags.
+pub(crate) struct Key {
+    text: Box<str>,
+    name_len: usize,
+    ends: Box<[usize]>,
+}
+
+impl Key {
+    pub fn new(name: &str, tags: Tags) -> Self {
+        let len = name.len() + tags.iter().map(|(k, v)| k.len() + v.len()).sum::<usize>();
+        let mut text = String::with_capacity(len);
+        text.push_str(name);
+        let ends = tags
+            .iter()
+            .flat_map(|(k, v)| [k, v])
+            .map(|part| {
+                text.push_str(part);
+                text.len()
+            })
+            .collect();
+        Self {
+            text: text.into_boxed_str(),
+            name_len: name.len(),
+            ends,
+        }
+    }
+
+    fn register(&self, register: impl FnOnce(&str, Tags) -> Id) -> Id {
+        let mut start = self.name_len;
+        let parts: Vec<_> = self
+            .ends
+            .iter()
+            .map(|&end| {
+                let part = &self.text[start..end];
+                start = end;
+                part
+            })
+            .collect();
+        let tags: Vec<_> = parts.chunks_exact(2).map(|tag| (tag[0], tag[1])).collect();
+        register(&self.text[..self.name_len], &tags)
+    }
+}
+
+/// Transform handed to every backend the histogram is registered with.
+pub(crate) type SharedTransform = Arc<dyn Fn(u64) -> u64 + Send + Sync>;
+
+/// Boxes a shared transform for a backend.
+pub(crate) fn share_transform(transform: &SharedTransform) -> ValueTransform {
+    let transform = transform.clone();
+    Box::new(move |value| transform(value))
+}
+
+impl Binding {
+    /// Registers the metric with the active backend.
+    pub fn new(registration: Registration, register: impl FnOnce(&'static MetricsHandle) -> Id) -> Self {
+        match Self::try_new(registration, |metrics| Ok::<_, Infallible>(register(metrics))) {
+            Ok(binding) => binding,
+            Err(err) => match err {},
+        }
+    }
+
+    /// Registers the metric with the active backend, unless `register` fails.
+    pub fn try_new<E>(
+        registration: Registration,
+        register: impl FnOnce(&'static MetricsHandle) -> Result<Id, E>,
+    ) -> Result<Self, E> {
+        let generation = generation();
+        // pairs with the generation bump after the backend was set
+        fence(Ordering::Acquire);
+        let metrics = bind_metrics();
+        Ok(Self {
+            initial: Bound {
+                handle: metrics,
+                id: register(metrics)?,
+                generation,
+            },
+            rebound: AtomicPtr::new(std::ptr::null_mut()),
+            claimed: AtomicU64::new(generation),
+            registration,
+        })
+    }
+
+    /// Creates a binding for a metric that the backend has registered beforehand.
+    pub fn with_id(id: Id) -> Self {
+        Self::new(Registration::Id, |_| id)
+    }
+
+    /// Backend and id to record with, registering the metric again first if the backend was replaced.
+    #[inline]
+    pub fn get(&self) -> (&MetricsHandle, Id) {
+        let mut bound = self.bound();
+        let generation = generation();
+        if bound.generation != generation {
+            bound = self.rebind(generation);
+        }
+        (bound.handle, bound.id)
+    }
+
+    /// Id the metric is registered with in the backend it is bound to.
+    pub fn id(&self) -> Id {
+        self.bound().id
+    }
+
+    /// Backend and id to delete the metric from, `None` if the metric was never registered with the
+    /// active backend, in which case the backend it was registered with may no longer exist.
+    pub fn current(&self) -> Option<(&MetricsHandle, Id)> {
+        let bound = self.bound();
+        (bound.generation == generation()).then_some((bound.handle, bound.id))
+    }
+
+    /// Latest binding, loaded with a single acquire load that pairs with its publication in [Self::rebind].
+    #[inline(always)]
+    fn bound(&self) -> &Bound {
+        let rebound = self.rebound.load(Ordering::Acquire);
+        if rebound.is_null() {
+            &self.initial
+        } else {
+            // published bindings are never freed while the binding is alive
+            unsafe { &*rebound }
+        }
+    }
+
+    /// Registers the metric with the backend of `generation` and publishes the new binding, unless
+    /// another thread is already doing so, in which case the current binding is returned.
+    #[cold]
+    #[inline(never)]
+    fn rebind(&self, generation: u64) -> &Bound {
+        let claimed = self.claimed.load(Ordering::Relaxed);
+        // only one thread registers the metric again, and claiming the generation up front stops the
+        // registration from recursing when it allocates under an instrumented allocator
+        if claimed == generation
+            || self
+                .claimed
+                .compare_exchange(claimed, generation, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            return self.bound();
+        }
+        fence(Ordering::Acquire);
+        let metrics = bind_metrics();
+        let id = match &self.registration {
+            Registration::Id => self.initial.id,
+            Registration::Counter(key) => key.register(|name, tags| metrics.new_counter(name, tags)),
+            Registration::CounterStatic(name, tags) => metrics.new_counter_static(name, tags),
+            Registration::Gauge(key) => key.register(|name, tags| metrics.new_gauge(name, tags)),
+            Registration::Histogram(key) => key.register(|name, tags| metrics.new_histogram(name, tags)),
+            Registration::HistogramWithTransform(key, transform) => {
+                key.register(|name, tags| metrics.new_histogram_with_transform(name, tags, share_transform(transform)))
+            }
+            Registration::HistogramWithResolution(key, resolution_ns) => {
+                key.register(|name, tags| metrics.new_histogram_with_resolution(name, tags, *resolution_ns))
+            }
+        };
+        let bound = Box::leak(Box::new(Bound {
+            handle: metrics,
+            id,
+            generation,
+        }));
+        // the handle and id are published along with the generation, the replaced binding is leaked as
+        // other threads may still be reading it
+        self.rebound.store(bound, Ordering::Release);
+        bound
+    }
+}
+
+impl Drop for Binding {
+    fn drop(&mut self) {
+        let rebound = *self.rebound.get_mut();
+        if !rebound.is_null() {
+            // no other thread can be reading the binding while it is dropped
+            drop(unsafe { Box::from_raw(rebound) });
+        }
+    }
+}
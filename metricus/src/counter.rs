@@ -1,7 +1,7 @@
 //! A `Counter` proxy struct for managing a metrics counter.
 
-use crate::access::get_metrics;
-use crate::{Id, MetricsHandle, Tags};
+use crate::binding::{Binding, Key, Registration};
+use crate::{Id, RegisterError, Tag, Tags};
 use std::ops::Deref;
 
 /// Provides methods to create a new counter, increment it, and
@@ -36,13 +36,12 @@ use std::ops::Deref;
 /// my_function_with_tags();
 /// ````
 pub struct Counter {
-    id: Id,
-    handle: &'static MetricsHandle,
+    binding: Binding,
 }
 
 impl std::fmt::Debug for Counter {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Counter").field("id", &self.id).finish()
+        f.debug_struct("Counter").field("id", &self.binding.id()).finish()
     }
 }
 
@@ -66,11 +65,52 @@ impl Counter {
     /// let counter = Counter::new("user_count", empty_tags());
     /// ```
     pub fn new(name: &str, tags: Tags) -> Self {
-        let metrics = get_metrics();
-        let counter_id = metrics.new_counter(name, tags);
         Self {
-            id: counter_id,
-            handle: metrics,
+            binding: Binding::new(Registration::Counter(Key::new(name, tags)), |metrics| {
+                metrics.new_counter(name, tags)
+            }),
+        }
+    }
+
+    /// Creates a new counter like [Counter::new], but returns an error if the backend could not
+    /// register it instead of handing out a counter that records nowhere. Which backends can fail
+    /// is backend specific, e.g. the metrics agent fails when its registration queue is full.
+    ///
+    /// ## Examples
+    ///
+    /// ```no_run
+    /// use metricus::{Counter, CounterOps};
+    ///
+    /// match Counter::try_new("user_count", &[]) {
+    ///     Ok(counter) => counter.increment(),
+    ///     Err(err) => eprintln!("unable to register counter: {err}"),
+    /// }
+    /// ```
+    pub fn try_new(name: &str, tags: Tags) -> Result<Self, RegisterError> {
+        Ok(Self {
+            binding: Binding::try_new(Registration::Counter(Key::new(name, tags)), |metrics| {
+                metrics.try_new_counter(name, tags)
+            })?,
+        })
+    }
+
+    /// Creates a new counter like [Counter::new] from a name and tags that live for the rest of the
+    /// program, e.g. string literals. Neither the counter nor backends that specialize
+    /// [crate::Metrics::new_counter_static] copy them, so registering does not allocate.
+    ///
+    /// ## Examples
+    ///
+    /// ```no_run
+    /// use metricus::{Counter, CounterOps};
+    ///
+    /// let counter = Counter::new_static("requests", &[("service", "api")]);
+    /// counter.increment();
+    /// ```
+    pub fn new_static(name: &'static str, tags: Tags<'static>) -> Self {
+        Self {
+            binding: Binding::new(Registration::CounterStatic(name, tags), |metrics| {
+                metrics.new_counter_static(name, tags)
+            }),
         }
     }
 
@@ -87,14 +127,86 @@ impl Counter {
     /// let counter = Counter::new_with_id(1);
     /// ```
     pub fn new_with_id(id: Id) -> Self {
-        let metrics = get_metrics();
-        Self { id, handle: metrics }
+        Self {
+            binding: Binding::with_id(id),
+        }
+    }
+
+    /// Starts building a counter with owned tags, see [CounterBuilder].
+    pub fn builder(name: impl Into<String>) -> CounterBuilder {
+        CounterBuilder::new(name)
+    }
+}
+
+/// Builds a [Counter] from an owned name and tags, so that tags computed at runtime (e.g. from request
+/// context) do not have to be kept alive as string slices by the caller. The tags are only borrowed as
+/// [Tags] when the counter is registered on [CounterBuilder::build], which can be called repeatedly to create
+/// more counters with the same name and tags.
+///
+/// ## Examples
+///
+/// ```no_run
+/// use metricus::{Counter, CounterOps};
+///
+/// let route = String::from("/v1/orders");
+/// let mut builder = Counter::builder("requests").with_tag("service", "api");
+/// builder.push_tag("route", route);
+/// let counter = builder.build();
+/// counter.increment();
+/// ```
+#[derive(Debug, Clone)]
+pub struct CounterBuilder {
+    name: String,
+    tags: Vec<(String, String)>,
+}
+
+impl CounterBuilder {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            tags: Vec::new(),
+        }
+    }
+
+    /// Adds a tag.
+    pub fn with_tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.push_tag(key, value);
+        self
+    }
+
+    /// Adds all the tags.
+    pub fn with_tags<K: Into<String>, V: Into<String>>(mut self, tags: impl IntoIterator<Item = (K, V)>) -> Self {
+        self.tags
+            .extend(tags.into_iter().map(|(key, value)| (key.into(), value.into())));
+        self
+    }
+
+    /// Adds a tag to the builder in place, e.g. when tags are added conditionally.
+    pub fn push_tag(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+
+    /// Creates the counter with [Counter::new].
+    pub fn build(&self) -> Counter {
+        Counter::new(&self.name, &self.tags())
+    }
+
+    /// Creates the counter with [Counter::try_new].
+    pub fn try_build(&self) -> Result<Counter, RegisterError> {
+        Counter::try_new(&self.name, &self.tags())
+    }
+
+    fn tags(&self) -> Vec<Tag<'_>> {
+        self.tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect()
     }
 }
 
 impl Drop for Counter {
     fn drop(&mut self) {
-        self.handle.delete_counter(self.id);
+        if let Some((metrics, id)) = self.binding.current() {
+            metrics.delete_counter(id);
+        }
     }
 }
 
@@ -123,17 +235,97 @@ pub trait CounterOps {
     /// counter.increment_by(5);
     /// ```
     fn increment_by(&self, delta: u64);
+
+    /// Decrements the counter by 1, e.g. for counters of active sessions. The counter saturates at zero
+    /// by default, although backends may wrap around instead, see [crate::Metrics::decrement_counter_by].
+    ///
+    /// ## Examples
+    ///
+    /// ```no_run
+    /// use metricus::{Counter, CounterOps};
+    ///
+    /// let sessions = Counter::new("active_sessions", &[]);
+    /// sessions.increment();
+    /// sessions.decrement();
+    /// ```
+    fn decrement(&self) {
+        self.decrement_by(1)
+    }
+
+    /// Decrements the counter by a specified amount, see [CounterOps::decrement].
+    ///
+    /// ## Examples
+    ///
+    /// ```no_run
+    /// use metricus::{Counter, CounterOps};
+    ///
+    /// let sessions = Counter::new("active_sessions", &[]);
+    /// sessions.increment_by(5);
+    /// sessions.decrement_by(3);
+    /// ```
+    fn decrement_by(&self, delta: u64);
+
+    /// Current value of the counter as held by the backend, or `None` if the backend does not keep
+    /// counter values (see [crate::Metrics::read_counter]). Depending on the backend this can be an
+    /// expensive call, e.g. the metrics agent waits for its aggregator to process all pending updates,
+    /// so it is meant for tests and health checks rather than the hot path.
+    ///
+    /// ## Examples
+    ///
+    /// ```no_run
+    /// use metricus::{Counter, CounterOps};
+    ///
+    /// let counter = Counter::new("example_counter", &[]);
+    /// counter.increment_by(5);
+    /// assert_eq!(Some(5), counter.value());
+    /// ```
+    fn value(&self) -> Option<u64>;
+
+    /// Sets the counter back to zero, e.g. after its value has been collected by a pull-based system.
+    /// Backends that do not keep counter values ignore it. Resetting races with increments made
+    /// concurrently on other threads, which may or may not be included in the value before the reset,
+    /// so it is best done by the single thread that owns the counter.
+    ///
+    /// ## Examples
+    ///
+    /// ```no_run
+    /// use metricus::{Counter, CounterOps};
+    ///
+    /// let counter = Counter::new("example_counter", &[]);
+    /// counter.increment_by(5);
+    /// counter.reset();
+    /// ```
+    fn reset(&self);
 }
 
 impl CounterOps for Counter {
     #[inline]
     fn increment(&self) {
-        self.handle.increment_counter(self.id);
+        let (metrics, id) = self.binding.get();
+        metrics.increment_counter(id);
     }
 
     #[inline]
     fn increment_by(&self, delta: u64) {
-        self.handle.increment_counter_by(self.id, delta);
+        let (metrics, id) = self.binding.get();
+        metrics.increment_counter_by(id, delta);
+    }
+
+    #[inline]
+    fn decrement_by(&self, delta: u64) {
+        let (metrics, id) = self.binding.get();
+        metrics.decrement_counter_by(id, delta);
+    }
+
+    fn value(&self) -> Option<u64> {
+        let (metrics, id) = self.binding.get();
+        metrics.read_counter(id)
+    }
+
+    #[inline]
+    fn reset(&self) {
+        let (metrics, id) = self.binding.get();
+        metrics.reset_counter(id);
     }
 }
 
@@ -150,4 +342,69 @@ where
     fn increment_by(&self, delta: u64) {
         self.deref().increment_by(delta)
     }
+
+    #[inline]
+    fn decrement_by(&self, delta: u64) {
+        self.deref().decrement_by(delta)
+    }
+
+    fn value(&self) -> Option<u64> {
+        self.deref().value()
+    }
+
+    #[inline]
+    fn reset(&self) {
+        self.deref().reset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Metrics;
+
+    #[test]
+    fn decrement_saturates_at_zero_by_default() {
+        /// Keeps counter values, with decrements made through the default implementation.
+        struct Totals(u64);
+
+        impl Metrics for Totals {
+            fn name(&self) -> &'static str {
+                "totals"
+            }
+
+            fn new_counter(&mut self, _name: &str, _tags: Tags) -> Id {
+                0
+            }
+
+            fn delete_counter(&mut self, _id: Id) {}
+
+            fn increment_counter_by(&mut self, _id: Id, delta: u64) {
+                self.0 += delta;
+            }
+
+            fn reset_counter(&mut self, _id: Id) {
+                self.0 = 0;
+            }
+
+            fn read_counter(&mut self, _id: Id) -> Option<u64> {
+                Some(self.0)
+            }
+
+            fn new_histogram(&mut self, _name: &str, _tags: Tags) -> Id {
+                0
+            }
+
+            fn delete_histogram(&mut self, _id: Id) {}
+
+            fn record(&mut self, _id: Id, _value: u64) {}
+        }
+
+        let mut totals = Totals(0);
+        totals.increment_counter_by(0, 10);
+        totals.decrement_counter_by(0, 3);
+        assert_eq!(Some(7), totals.read_counter(0));
+        totals.decrement_counter_by(0, 8);
+        assert_eq!(Some(0), totals.read_counter(0));
+    }
 }
@@ -0,0 +1,301 @@
+//! A `Gauge` proxy struct for managing a metrics gauge.
+
+use crate::binding::{Binding, Key, Registration};
+use crate::{Id, Tags};
+use std::ops::Deref;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Sample interval of the callback gauges without one of their own, see
+/// [CallbackGauge::set_default_sample_interval].
+static DEFAULT_SAMPLE_INTERVAL_NS: AtomicU64 = AtomicU64::new(0);
+
+/// Provides methods to create a new gauge and set it to the current value of e.g. a queue length or
+/// the number of open connections. Unlike a counter, the exported value is the last value set rather
+/// than an accumulation. It automatically deletes the gauge when it is dropped.
+///
+/// Gauges are optional for backends (see [crate::Metrics::new_gauge]). The `metricus_agent` aggregator
+/// does not support them, so gauges set while it is the active backend are dropped and never exported.
+///
+/// ## Examples
+///
+/// ```no_run
+/// use metricus::{Gauge, GaugeOps};
+///
+/// let gauge = Gauge::new("queue_length", &[("queue", "orders")]);
+/// gauge.set(42);
+/// ```
+pub struct Gauge {
+    binding: Binding,
+}
+
+impl std::fmt::Debug for Gauge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Gauge").field("id", &self.binding.id()).finish()
+    }
+}
+
+impl Gauge {
+    /// Creates a new gauge with the specified `name` and `tags`.
+    ///
+    /// ## Examples
+    ///
+    /// ```no_run
+    /// use metricus::Gauge;
+    ///
+    /// let gauge = Gauge::new("open_connections", &[("service", "gateway")]);
+    /// ```
+    pub fn new(name: &str, tags: Tags) -> Self {
+        Self {
+            binding: Binding::new(Registration::Gauge(Key::new(name, tags)), |metrics| metrics.new_gauge(name, tags)),
+        }
+    }
+
+    /// Create a gauge object without registering it.
+    /// This creates a new gauge proxy that assumes the metrics backend has already created the gauge.
+    ///
+    /// ## Examples
+    ///
+    /// ```no_run
+    /// use metricus::Gauge;
+    ///
+    /// let gauge = Gauge::new_with_id(1);
+    /// ```
+    pub fn new_with_id(id: Id) -> Self {
+        Self {
+            binding: Binding::with_id(id),
+        }
+    }
+}
+
+impl Drop for Gauge {
+    fn drop(&mut self) {
+        if let Some((metrics, id)) = self.binding.current() {
+            metrics.delete_gauge(id);
+        }
+    }
+}
+
+/// Defines a series of operations that can be performed on a `Gauge`.
+pub trait GaugeOps {
+    /// Sets the gauge to `value`, replacing the previous value.
+    ///
+    /// ## Examples
+    ///
+    /// ```no_run
+    /// use metricus::{Gauge, GaugeOps};
+    ///
+    /// let gauge = Gauge::new("temperature", &[]);
+    /// gauge.set(-5);
+    /// ```
+    fn set(&self, value: i64);
+}
+
+impl GaugeOps for Gauge {
+    #[inline]
+    fn set(&self, value: i64) {
+        let (metrics, id) = self.binding.get();
+        metrics.set_gauge(id, value);
+    }
+}
+
+impl<T> GaugeOps for T
+where
+    T: Deref<Target = Gauge>,
+{
+    #[inline]
+    fn set(&self, value: i64) {
+        self.deref().set(value)
+    }
+}
+
+/// A gauge whose value is read from a callback, e.g. the size of a cache or the memory used by the
+/// process, rather than set where it changes. The metrics backend does not pull the value, the
+/// application samples it periodically with [CallbackGauge::sample], typically from the loop that also
+/// publishes the metrics, and each sample sets the underlying [Gauge]. Backends that do not export
+/// gauges, such as the `metricus_agent` aggregator, produce no output for callback gauges either.
+///
+/// Reading the value can be expensive, so a sample interval can be set per gauge with
+/// [CallbackGauge::with_sample_interval], or for all gauges without one of their own with
+/// [CallbackGauge::set_default_sample_interval], after which the callback runs at most once per interval
+/// however often the gauge is sampled. In between, the gauge keeps the last sampled value, so exports
+/// may report a value that is up to one sample interval old. The interval is a tradeoff between the
+/// cost of the callback and the staleness of the exported value. Without an interval, the callback runs
+/// on every sample.
+///
+/// ## Examples
+///
+/// ```no_run
+/// use metricus::CallbackGauge;
+/// use std::time::Duration;
+///
+/// let gauge = CallbackGauge::new("open_files", &[], || 42).with_sample_interval(Duration::from_secs(10));
+/// // e.g. before every publish, only the first call within 10 seconds runs the callback
+/// gauge.sample();
+/// ```
+pub struct CallbackGauge {
+    gauge: Gauge,
+    callback: Box<dyn Fn() -> i64 + Send + Sync>,
+    /// Interval of the gauge, the default sample interval applies if not set.
+    sample_interval: Option<Duration>,
+    /// Time and value of the last sample, if sampled yet.
+    last_sample: Mutex<Option<(Instant, i64)>>,
+}
+
+impl std::fmt::Debug for CallbackGauge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallbackGauge")
+            .field("gauge", &self.gauge)
+            .field("sample_interval", &self.sample_interval())
+            .finish()
+    }
+}
+
+impl CallbackGauge {
+    /// Creates a new gauge with the specified `name` and `tags`, set to the value returned by
+    /// `callback` whenever it is sampled.
+    pub fn new(name: &str, tags: Tags, callback: impl Fn() -> i64 + Send + Sync + 'static) -> Self {
+        Self {
+            gauge: Gauge::new(name, tags),
+            callback: Box::new(callback),
+            sample_interval: None,
+            last_sample: Mutex::new(None),
+        }
+    }
+
+    /// Runs the callback at most once per `sample_interval`, see [CallbackGauge]. This takes precedence
+    /// over the default sample interval.
+    pub fn with_sample_interval(self, sample_interval: Duration) -> Self {
+        Self {
+            sample_interval: Some(sample_interval),
+            ..self
+        }
+    }
+
+    /// Sets the sample interval of all callback gauges that have not been given one with
+    /// [CallbackGauge::with_sample_interval], including gauges that have already been created. The
+    /// default is zero, i.e. the callback runs on every sample.
+    ///
+    /// ## Examples
+    ///
+    /// ```no_run
+    /// use metricus::CallbackGauge;
+    /// use std::time::Duration;
+    ///
+    /// CallbackGauge::set_default_sample_interval(Duration::from_secs(30));
+    /// let gauge = CallbackGauge::new("cache_size", &[], || 42);
+    /// gauge.sample();
+    /// ```
+    pub fn set_default_sample_interval(sample_interval: Duration) {
+        DEFAULT_SAMPLE_INTERVAL_NS.store(sample_interval.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Sample interval of the gauge, or the default sample interval if it does not have its own.
+    pub fn sample_interval(&self) -> Duration {
+        self.sample_interval
+            .unwrap_or_else(|| Duration::from_nanos(DEFAULT_SAMPLE_INTERVAL_NS.load(Ordering::Relaxed)))
+    }
+
+    /// Runs the callback and sets the gauge to its value, unless the sample interval has not elapsed
+    /// since the last sample. Returns the current value of the gauge, i.e. the last sampled value.
+    pub fn sample(&self) -> i64 {
+        self.sample_at(Instant::now())
+    }
+
+    /// Like [CallbackGauge::sample], with the current time supplied by the caller, e.g. to share a
+    /// single clock read between several gauges.
+    pub fn sample_at(&self, now: Instant) -> i64 {
+        // held while running the callback, so that concurrent samples do not run it more than once
+        let mut last_sample = self.last_sample.lock().unwrap_or_else(|err| err.into_inner());
+        match *last_sample {
+            Some((sampled_at, value)) if now.saturating_duration_since(sampled_at) < self.sample_interval() => value,
+            _ => {
+                let value = (self.callback)();
+                self.gauge.set(value);
+                *last_sample = Some((now, value));
+                value
+            }
+        }
+    }
+
+    /// Value of the last sample, if sampled yet.
+    pub fn last_value(&self) -> Option<i64> {
+        self.last_sample
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .map(|(_, value)| value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestMetrics;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicI64;
+
+    #[test]
+    fn gauge_keeps_the_last_value_set() {
+        let metrics = TestMetrics::install();
+        let gauge = Gauge::new("gauge_queue_length", &[("queue", "orders")]);
+        let value = || metrics.gauge_value("gauge_queue_length", &[("queue", "orders")]);
+        assert_eq!(Some(0), value());
+        gauge.set(42);
+        gauge.set(-7);
+        assert_eq!(Some(-7), value());
+
+        // a proxy for the same id updates the same gauge, which outlives the proxies
+        let id = gauge.binding.id();
+        Gauge::new_with_id(id).set(3);
+        drop(gauge);
+        assert_eq!(Some(3), value());
+        assert_eq!(None, metrics.gauge_value("gauge_queue_length", &[]));
+    }
+
+    #[test]
+    fn callback_gauge_is_sampled_once_per_interval() {
+        let metrics = TestMetrics::install();
+        let reading = Arc::new(AtomicI64::new(1));
+        let samples = Arc::new(AtomicI64::new(0));
+        let gauge = CallbackGauge::new("callback_gauge_open_files", &[], {
+            let (reading, samples) = (reading.clone(), samples.clone());
+            move || {
+                samples.fetch_add(1, Ordering::Relaxed);
+                reading.load(Ordering::Relaxed)
+            }
+        })
+        .with_sample_interval(Duration::from_secs(10));
+        let value = || metrics.gauge_value("callback_gauge_open_files", &[]);
+
+        let start = Instant::now();
+        assert_eq!(None, gauge.last_value());
+        assert_eq!(1, gauge.sample_at(start));
+        assert_eq!(Some(1), value());
+
+        // within the interval the last sampled value is kept, however much the reading changed
+        reading.store(2, Ordering::Relaxed);
+        assert_eq!(1, gauge.sample_at(start + Duration::from_secs(5)));
+        assert_eq!(Some(1), value());
+        assert_eq!(1, samples.load(Ordering::Relaxed));
+
+        assert_eq!(2, gauge.sample_at(start + Duration::from_secs(10)));
+        assert_eq!(Some(2), value());
+        assert_eq!(Some(2), gauge.last_value());
+        assert_eq!(2, samples.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn callback_gauge_without_interval_is_sampled_every_time() {
+        let metrics = TestMetrics::install();
+        let reading = Arc::new(AtomicI64::new(0));
+        let gauge = CallbackGauge::new("callback_gauge_queue_length", &[("queue", "orders")], {
+            let reading = reading.clone();
+            move || reading.fetch_add(1, Ordering::Relaxed) + 1
+        });
+        let now = Instant::now();
+        assert_eq!(1, gauge.sample_at(now));
+        assert_eq!(2, gauge.sample_at(now));
+        assert_eq!(Some(2), metrics.gauge_value("callback_gauge_queue_length", &[("queue", "orders")]));
+    }
+}
@@ -1,10 +1,15 @@
 //! A `Histogram` proxy struct for managing a metrics histogram.
 
-use crate::access::get_metrics;
-use crate::{Id, MetricsHandle, Tags};
+use crate::binding::{Binding, Key, Registration, SharedTransform, share_transform};
+use crate::{Counter, CounterOps, Id, RegisterError, Tag, Tags};
 #[cfg(all(feature = "span", feature = "rdtsc"))]
 use quanta::Clock;
+use std::future::Future;
 use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
 #[cfg(all(feature = "span", not(feature = "rdtsc")))]
 use std::time::Instant;
 
@@ -12,6 +17,7 @@ use std::time::Instant;
 /// generation of spans for timing operations.
 /// The `Histogram` does not have an inherent notion of measurement units (e.g., milliseconds, bytes)
 /// and some convention should be in place.
+/// Use [TypedHistogram](crate::TypedHistogram) to have the compiler enforce the unit instead.
 ///
 /// ## Examples
 ///
@@ -41,8 +47,7 @@ use std::time::Instant;
 /// my_function_with_tags();
 /// ````
 pub struct Histogram {
-    id: Id,
-    handle: &'static MetricsHandle,
+    binding: Binding,
     #[cfg(all(feature = "span", feature = "rdtsc"))]
     clock: Clock,
 }
@@ -50,7 +55,7 @@ pub struct Histogram {
 impl std::fmt::Debug for Histogram {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut debug = f.debug_struct("Histogram");
-        debug.field("id", &self.id);
+        debug.field("id", &self.binding.id());
         #[cfg(all(feature = "span", feature = "rdtsc"))]
         {
             debug.field("clock", &self.clock);
@@ -81,15 +86,320 @@ impl Histogram {
     /// let histogram = Histogram::new("login_duration", empty_tags());
     /// ```
     pub fn new(name: &str, tags: Tags) -> Self {
-        let metrics = get_metrics();
-        let histogram_id = metrics.new_histogram(name, tags);
         Self {
-            id: histogram_id,
-            handle: metrics,
+            binding: Binding::new(Registration::Histogram(Key::new(name, tags)), |metrics| {
+                metrics.new_histogram(name, tags)
+            }),
             #[cfg(all(feature = "span", feature = "rdtsc"))]
             clock: Clock::new(),
         }
     }
+
+    /// Creates a new histogram like [Histogram::new], but returns an error if the backend could not
+    /// register it instead of handing out a histogram that records nowhere. Which backends can fail
+    /// is backend specific, e.g. the metrics agent fails when its registration queue is full.
+    ///
+    /// ## Examples
+    ///
+    /// ```no_run
+    /// use metricus::{Histogram, HistogramOps};
+    ///
+    /// match Histogram::try_new("login_duration", &[]) {
+    ///     Ok(histogram) => histogram.record(1500),
+    ///     Err(err) => eprintln!("unable to register histogram: {err}"),
+    /// }
+    /// ```
+    pub fn try_new(name: &str, tags: Tags) -> Result<Self, RegisterError> {
+        Ok(Self {
+            binding: Binding::try_new(Registration::Histogram(Key::new(name, tags)), |metrics| {
+                metrics.try_new_histogram(name, tags)
+            })?,
+            #[cfg(all(feature = "span", feature = "rdtsc"))]
+            clock: Clock::new(),
+        })
+    }
+
+    /// Creates a new histogram whose recorded values are passed through `transform` by the backend
+    /// before being summarized and exported. This allows e.g. unit conversion or clamping of outliers
+    /// in one place without changing the call sites that record values. The transform is not applied
+    /// on the recording path, so it adds no cost there. Backends that do not support transforms
+    /// ignore it. The transform is shared, without locking, with every backend the histogram is
+    /// registered with (see [crate::set_metrics]), hence it must be `Sync`.
+    ///
+    /// ## Examples
+    ///
+    /// Record nanoseconds but export microseconds.
+    /// ```no_run
+    /// use metricus::{Histogram, HistogramOps};
+    ///
+    /// let histogram = Histogram::new_with_transform("request_latency_us", &[], |nanos| nanos / 1000);
+    /// histogram.record(1_500_000);
+    /// ```
+    pub fn new_with_transform(name: &str, tags: Tags, transform: impl Fn(u64) -> u64 + Send + Sync + 'static) -> Self {
+        // shared with the backends the histogram is registered with again, see `set_metrics`
+        let transform: SharedTransform = Arc::new(transform);
+        Self {
+            binding: Binding::new(
+                Registration::HistogramWithTransform(Key::new(name, tags), transform.clone()),
+                |metrics| metrics.new_histogram_with_transform(name, tags, share_transform(&transform)),
+            ),
+            #[cfg(all(feature = "span", feature = "rdtsc"))]
+            clock: Clock::new(),
+        }
+    }
+
+    /// Creates a new histogram whose recorded values are rounded by the backend to the nearest multiple
+    /// of `resolution_ns` before being summarized, e.g. the resolution of a coarse clock, so that the
+    /// distribution does not suggest more precision than the clock can measure. Values half way between
+    /// two multiples are rounded up. Like a transform, the rounding is not applied on the recording path.
+    /// A resolution of 0 or 1 leaves values unchanged, and backends that do not support a resolution
+    /// ignore it.
+    ///
+    /// The rounding is applied after any transform. Values below half the resolution are rounded to
+    /// zero and still recorded, they are counted as samples of zero rather than dropped.
+    ///
+    /// ## Examples
+    ///
+    /// ```no_run
+    /// use metricus::{Histogram, HistogramOps};
+    ///
+    /// let histogram = Histogram::new_with_resolution("request_latency", &[], 100);
+    /// // recorded as 1300
+    /// histogram.record(1_260);
+    /// ```
+    pub fn new_with_resolution(name: &str, tags: Tags, resolution_ns: u64) -> Self {
+        Self {
+            binding: Binding::new(
+                Registration::HistogramWithResolution(Key::new(name, tags), resolution_ns),
+                |metrics| metrics.new_histogram_with_resolution(name, tags, resolution_ns),
+            ),
+            #[cfg(all(feature = "span", feature = "rdtsc"))]
+            clock: Clock::new(),
+        }
+    }
+
+    /// Create a histogram object without registering it.
+    /// This creates a new histogram proxy that assumes the metrics backend has already created the histogram.
+    ///
+    /// ## Examples
+    ///
+    /// Create a histogram with specific id.
+    ///
+    /// ```no_run
+    /// use metricus::Histogram;
+    ///
+    /// let histogram = Histogram::new_with_id(1);
+    /// ```
+    pub fn new_with_id(id: Id) -> Self {
+        Self {
+            binding: Binding::with_id(id),
+            #[cfg(all(feature = "span", feature = "rdtsc"))]
+            clock: Clock::new(),
+        }
+    }
+
+    /// Starts building a histogram with owned tags, see [HistogramBuilder].
+    pub fn builder(name: impl Into<String>) -> HistogramBuilder {
+        HistogramBuilder::new(name)
+    }
+}
+
+/// Builds a [Histogram] from an owned name and tags, so that tags computed at runtime (e.g. from request
+/// context) do not have to be kept alive as string slices by the caller. The tags are only borrowed as
+/// [Tags] when the histogram is registered on [HistogramBuilder::build], which can be called repeatedly to create
+/// more histograms with the same name and tags.
+///
+/// ## Examples
+///
+/// ```no_run
+/// use metricus::{Histogram, HistogramOps};
+///
+/// let route = String::from("/v1/orders");
+/// let mut builder = Histogram::builder("request_latency").with_tag("service", "api");
+/// builder.push_tag("route", route);
+/// let histogram = builder.build();
+/// histogram.record(1_250);
+/// ```
+#[derive(Debug, Clone)]
+pub struct HistogramBuilder {
+    name: String,
+    tags: Vec<(String, String)>,
+}
+
+impl HistogramBuilder {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            tags: Vec::new(),
+        }
+    }
+
+    /// Adds a tag.
+    pub fn with_tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.push_tag(key, value);
+        self
+    }
+
+    /// Adds all the tags.
+    pub fn with_tags<K: Into<String>, V: Into<String>>(mut self, tags: impl IntoIterator<Item = (K, V)>) -> Self {
+        self.tags
+            .extend(tags.into_iter().map(|(key, value)| (key.into(), value.into())));
+        self
+    }
+
+    /// Adds a tag to the builder in place, e.g. when tags are added conditionally.
+    pub fn push_tag(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+
+    /// Creates the histogram with [Histogram::new].
+    pub fn build(&self) -> Histogram {
+        Histogram::new(&self.name, &self.tags())
+    }
+
+    /// Creates the histogram with [Histogram::try_new].
+    pub fn try_build(&self) -> Result<Histogram, RegisterError> {
+        Histogram::try_new(&self.name, &self.tags())
+    }
+
+    fn tags(&self) -> Vec<Tag<'_>> {
+        self.tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect()
+    }
+}
+
+#[cfg(all(feature = "span", feature = "rdtsc"))]
+type SpanStart = u64;
+#[cfg(all(feature = "span", not(feature = "rdtsc")))]
+type SpanStart = Instant;
+
+#[cfg(feature = "span")]
+impl Histogram {
+    /// Captures the start of a timed region using the configured clock.
+    #[inline]
+    fn start_time(&self) -> SpanStart {
+        #[cfg(feature = "rdtsc")]
+        {
+            self.clock.raw()
+        }
+        #[cfg(not(feature = "rdtsc"))]
+        {
+            Instant::now()
+        }
+    }
+
+    /// Time elapsed since `start` in nanoseconds (or in TSC cycles with the `cycles` feature).
+    #[inline]
+    fn elapsed_since(&self, start: SpanStart) -> u64 {
+        #[cfg(feature = "cycles")]
+        {
+            self.clock.raw().saturating_sub(start)
+        }
+        #[cfg(all(feature = "rdtsc", not(feature = "cycles")))]
+        {
+            self.clock.delta_as_nanos(start, self.clock.raw())
+        }
+        #[cfg(not(feature = "rdtsc"))]
+        {
+            duration_as_nanos(start.elapsed())
+        }
+    }
+}
+
+/// Nanoseconds in `duration`, wrapping around for durations of more than about 584 years.
+#[inline]
+fn duration_as_nanos(duration: Duration) -> u64 {
+    duration
+        .as_secs()
+        .wrapping_mul(1_000_000_000)
+        .wrapping_add(u64::from(duration.subsec_nanos()))
+}
+
+impl Histogram {
+    /// Starts a span timed by `clock` rather than by the built-in clock, e.g. a shared clock or a clock
+    /// controlled by a test, so that durations are deterministic. The duration between the readings of
+    /// `clock` when the span starts and when it is dropped is recorded, in the unit of the clock. Nothing
+    /// is recorded if the clock went backwards, as with [HistogramOps::record_span]. This does not depend
+    /// on the `span` feature.
+    ///
+    /// ```no_run
+    /// use metricus::Histogram;
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    ///
+    /// let now = AtomicU64::new(1_000);
+    /// let clock = || now.load(Ordering::Relaxed);
+    /// let histogram = Histogram::new("task_duration", &[]);
+    /// {
+    ///     let _span = histogram.span_with_clock(&clock);
+    ///     now.fetch_add(250, Ordering::Relaxed);
+    /// } // records 250
+    /// ```
+    #[inline]
+    pub fn span_with_clock<'a, C: ClockSource>(&'a self, clock: &'a C) -> ClockSpan<'a, C> {
+        ClockSpan {
+            histogram: self,
+            start: clock.now_nanos(),
+            clock,
+        }
+    }
+
+    /// Starts a span that records the elapsed time into each of the `histograms` once dropped, so that
+    /// the same duration can be recorded e.g. per operation and across all operations while reading the
+    /// clock only twice. Every additional histogram costs one more record when the span is dropped.
+    ///
+    /// ```no_run
+    /// use metricus::Histogram;
+    ///
+    /// let operation = Histogram::new("op_latency", &[("op", "insert")]);
+    /// let all = Histogram::new("all_latency", &[]);
+    /// {
+    ///     let _span = Histogram::span_all([&operation, &all]);
+    ///     // Execute operation...
+    /// }
+    /// ```
+    #[inline]
+    #[cfg(feature = "span")]
+    pub fn span_all<const N: usize>(histograms: [&Histogram; N]) -> MultiSpan<'_, N> {
+        MultiSpan {
+            start: histograms.first().map(|histogram| histogram.start_time()),
+            histograms,
+        }
+    }
+
+    #[inline]
+    #[cfg(not(feature = "span"))]
+    pub fn span_all<const N: usize>(_histograms: [&Histogram; N]) -> MultiSpan<'_, N> {
+        MultiSpan {
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Starts a span that is not tied to a scope and records nothing on its own, the duration is
+    /// recorded by [RawSpan::stop]. This allows timing a region that starts and ends in different
+    /// places, e.g. in separate callbacks or across `.await` points of a hand-written state machine.
+    /// It uses the same clock as [HistogramOps::span].
+    ///
+    /// ```no_run
+    /// use metricus::Histogram;
+    ///
+    /// let histogram = Histogram::new("request_duration", &[]);
+    /// let span = histogram.start_span();
+    /// // Execute operation, possibly elsewhere...
+    /// span.stop(&histogram);
+    /// ```
+    #[inline]
+    #[cfg(feature = "span")]
+    pub fn start_span(&self) -> RawSpan {
+        RawSpan {
+            start: self.start_time(),
+        }
+    }
+
+    #[inline]
+    #[cfg(not(feature = "span"))]
+    pub fn start_span(&self) -> RawSpan {
+        RawSpan {}
+    }
 }
 
 /// Defines a series of operations that can be performed on a `Histogram`.
@@ -106,6 +416,41 @@ pub trait HistogramOps {
     /// ```
     fn record(&self, value: u64);
 
+    /// Records a fractional value, e.g. a ratio or a latency in fractional milliseconds, for backends
+    /// that summarize floating point values. Other backends round the value to the nearest integer, see
+    /// [crate::Metrics::record_f64].
+    ///
+    /// ```no_run
+    /// use metricus::{Histogram, HistogramOps};
+    ///
+    /// let histogram = Histogram::new("fill_ratio", &[]);
+    /// histogram.record_f64(0.25);
+    /// ```
+    fn record_f64(&self, value: f64);
+
+    /// Records several values with a single call into the backend, which is cheaper than recording
+    /// them one by one for backends that take the whole slice at once, see [crate::Metrics::record_many].
+    ///
+    /// ```no_run
+    /// use metricus::{Histogram, HistogramOps};
+    ///
+    /// let histogram = Histogram::new("batch_latency", &[]);
+    /// histogram.record_many(&[120, 95, 143]);
+    /// ```
+    fn record_many(&self, values: &[u64]);
+
+    /// Records a duration measured elsewhere in nanoseconds, the unit recorded by spans (unless the
+    /// `cycles` feature is enabled, in which case spans record cycles instead).
+    ///
+    /// ```no_run
+    /// use metricus::{Histogram, HistogramOps};
+    /// use std::time::Duration;
+    ///
+    /// let histogram = Histogram::new("task_duration", &[]);
+    /// histogram.record_duration(Duration::from_millis(1500));
+    /// ```
+    fn record_duration(&self, duration: Duration);
+
     /// Starts a span for timing an operation, automatically recording the duration upon completion.
     /// The duration recorded is in nanoseconds.
     ///
@@ -134,12 +479,98 @@ pub trait HistogramOps {
     /// });
     /// ```
     fn with_span<F: FnOnce() -> R, R>(&self, f: F) -> R;
+
+    /// Wraps a future so that the total time spent inside its `poll` calls is recorded once it
+    /// completes. Unlike a [Span] held across `.await` points this excludes the time the future
+    /// spends suspended, so comparing both shows the suspension overhead. Nothing is recorded if the
+    /// future is dropped before completion. Each poll costs two additional clock reads.
+    ///
+    /// ```no_run
+    /// use metricus::{Histogram, HistogramOps};
+    ///
+    /// async fn task() {
+    ///     let histogram = Histogram::new("task_active_duration", &[]);
+    ///     histogram.poll_timed(async {
+    ///         // Execute operation...
+    ///     }).await;
+    /// }
+    /// ```
+    fn poll_timed<F: Future>(&self, future: F) -> PollTimed<'_, F>;
+
+    /// Records the number of TSC cycles elapsed between two raw counter readings (as returned by
+    /// `quanta::Clock::raw`), without converting them to nanoseconds. The recorded unit is then cycles
+    /// and the TSC frequency must be known downstream to turn it back into time.
+    ///
+    /// ```no_run
+    /// use metricus::{Histogram, HistogramOps};
+    ///
+    /// let clock = quanta::Clock::new();
+    /// let histogram = Histogram::new("task_cycles", &[]);
+    /// let start = clock.raw();
+    /// // Execute operation...
+    /// histogram.record_cycles(start, clock.raw());
+    /// ```
+    #[cfg(feature = "rdtsc")]
+    fn record_cycles(&self, start_raw: u64, end_raw: u64);
+
+    /// Records the duration between two timestamps taken by an external clock (e.g. kernel or NIC
+    /// timestamps), so that externally timed operations end up in the same histograms as spans.
+    /// Both timestamps must be in nanoseconds and taken from the same clock, the recorded value is
+    /// `end_ns - start_ns` nanoseconds. Nothing is recorded if `end_ns` is before `start_ns`, which
+    /// indicates a clock anomaly such as a non-monotonic source, rather than skewing the histogram
+    /// with a zero duration. Such spans are counted by the `metricus` counter tagged with
+    /// `stat=clock_anomalies` instead, see [CLOCK_ANOMALIES_MEASUREMENT].
+    ///
+    /// With the `cycles` feature spans record TSC cycles rather than nanoseconds, so externally
+    /// timed durations should not share a histogram with spans in that case. Raw TSC readings can
+    /// be recorded as cycles with `record_cycles`, or converted with `quanta::Clock::delta_as_nanos`.
+    ///
+    /// ```no_run
+    /// use metricus::{Histogram, HistogramOps};
+    ///
+    /// let histogram = Histogram::new("packet_latency", &[]);
+    /// let (rx_timestamp_ns, processed_timestamp_ns) = (1_000_000, 1_002_500);
+    /// histogram.record_span(rx_timestamp_ns, processed_timestamp_ns);
+    /// ```
+    fn record_span(&self, start_ns: u64, end_ns: u64);
+
+    /// Discards all samples recorded so far by the histogram. This is useful in tests or when
+    /// the roll-up interval is controlled manually. Note that clearing races with any records
+    /// happening concurrently on other threads, so samples recorded around the same time may
+    /// or may not survive the clear.
+    ///
+    /// ```no_run
+    /// use metricus::{Histogram, HistogramOps};
+    ///
+    /// let histogram = Histogram::new("task_duration", &[]);
+    /// histogram.record(200);
+    /// histogram.clear();
+    /// ```
+    fn clear(&self);
 }
 
 impl HistogramOps for Histogram {
     #[inline]
     fn record(&self, value: u64) {
-        self.handle.record(self.id, value);
+        let (metrics, id) = self.binding.get();
+        metrics.record(id, value);
+    }
+
+    #[inline]
+    fn record_f64(&self, value: f64) {
+        let (metrics, id) = self.binding.get();
+        metrics.record_f64(id, value);
+    }
+
+    #[inline]
+    fn record_many(&self, values: &[u64]) {
+        let (metrics, id) = self.binding.get();
+        metrics.record_many(id, values);
+    }
+
+    #[inline]
+    fn record_duration(&self, duration: Duration) {
+        self.record(duration_as_nanos(duration));
     }
 
     #[inline]
@@ -147,10 +578,7 @@ impl HistogramOps for Histogram {
     fn span(&self) -> Span<'_> {
         Span {
             histogram: self,
-            #[cfg(feature = "rdtsc")]
-            start_raw: self.clock.raw(),
-            #[cfg(not(feature = "rdtsc"))]
-            start_instant: Instant::now(),
+            start: self.start_time(),
         }
     }
 
@@ -167,6 +595,36 @@ impl HistogramOps for Histogram {
         let _span = self.span();
         f()
     }
+
+    #[inline]
+    fn poll_timed<F: Future>(&self, future: F) -> PollTimed<'_, F> {
+        PollTimed {
+            future,
+            histogram: self,
+            #[cfg(feature = "span")]
+            active: 0,
+        }
+    }
+
+    #[inline]
+    #[cfg(feature = "rdtsc")]
+    fn record_cycles(&self, start_raw: u64, end_raw: u64) {
+        self.record(end_raw.saturating_sub(start_raw));
+    }
+
+    #[inline]
+    fn record_span(&self, start_ns: u64, end_ns: u64) {
+        match end_ns.checked_sub(start_ns) {
+            Some(duration) => self.record(duration),
+            None => CLOCK_ANOMALIES.increment(),
+        }
+    }
+
+    #[inline]
+    fn clear(&self) {
+        let (metrics, id) = self.binding.get();
+        metrics.clear_histogram(id);
+    }
 }
 
 impl<T> HistogramOps for T
@@ -178,6 +636,21 @@ where
         self.deref().record(value);
     }
 
+    #[inline]
+    fn record_f64(&self, value: f64) {
+        self.deref().record_f64(value);
+    }
+
+    #[inline]
+    fn record_many(&self, values: &[u64]) {
+        self.deref().record_many(values);
+    }
+
+    #[inline]
+    fn record_duration(&self, duration: Duration) {
+        self.deref().record_duration(duration);
+    }
+
     #[inline]
     fn span(&self) -> Span<'_> {
         self.deref().span()
@@ -187,22 +660,52 @@ where
     fn with_span<F: FnOnce() -> R, R>(&self, f: F) -> R {
         self.deref().with_span(f)
     }
+
+    #[inline]
+    fn poll_timed<F: Future>(&self, future: F) -> PollTimed<'_, F> {
+        self.deref().poll_timed(future)
+    }
+
+    #[inline]
+    #[cfg(feature = "rdtsc")]
+    fn record_cycles(&self, start_raw: u64, end_raw: u64) {
+        self.deref().record_cycles(start_raw, end_raw)
+    }
+
+    #[inline]
+    fn record_span(&self, start_ns: u64, end_ns: u64) {
+        self.deref().record_span(start_ns, end_ns)
+    }
+
+    #[inline]
+    fn clear(&self) {
+        self.deref().clear()
+    }
 }
 
+/// Measurement of the counter of spans recorded with [HistogramOps::record_span] that end before they
+/// start, tagged with `stat=clock_anomalies`. It is registered with the active backend on the first
+/// anomaly, so it only shows up once there has been one.
+pub const CLOCK_ANOMALIES_MEASUREMENT: &str = "metricus";
+
+static CLOCK_ANOMALIES: LazyLock<Counter> =
+    LazyLock::new(|| Counter::new_static(CLOCK_ANOMALIES_MEASUREMENT, &[("stat", "clock_anomalies")]));
+
 impl Drop for Histogram {
     fn drop(&mut self) {
-        self.handle.delete_histogram(self.id);
+        if let Some((metrics, id)) = self.binding.current() {
+            metrics.delete_histogram(id);
+        }
     }
 }
 
-/// Used for measuring how long given operation takes. The duration is recorded in nanoseconds.
+/// Used for measuring how long given operation takes. The duration is recorded in nanoseconds,
+/// unless the `cycles` feature is enabled in which case the raw number of elapsed TSC cycles is
+/// recorded instead (the TSC frequency must then be known downstream to convert it into time).
 #[cfg(feature = "span")]
 pub struct Span<'a> {
     histogram: &'a Histogram,
-    #[cfg(feature = "rdtsc")]
-    start_raw: u64,
-    #[cfg(not(feature = "rdtsc"))]
-    start_instant: Instant,
+    start: SpanStart,
 }
 
 /// No-op span used when the `span` feature is disabled.
@@ -211,23 +714,241 @@ pub struct Span<'a> {
     _marker: std::marker::PhantomData<&'a ()>,
 }
 
+impl<'a> Span<'a> {
+    /// Records the duration only if the span is dropped while the thread is unwinding from a panic,
+    /// e.g. to time the calls of a function that panic.
+    ///
+    /// ```no_run
+    /// use metricus::{Histogram, HistogramOps};
+    ///
+    /// let histogram = Histogram::new("panicked_duration", &[]);
+    /// let _span = histogram.span().on_panic();
+    /// ```
+    #[inline]
+    pub fn on_panic(self) -> PanicSpan<'a> {
+        PanicSpan { span: Some(self) }
+    }
+
+    /// Ends the span without recording anything, e.g. when the operation turned out to be of no interest.
+    #[inline]
+    // the no-op span without the `span` feature has nothing to forget
+    #[cfg_attr(not(feature = "span"), allow(clippy::forget_non_drop))]
+    pub fn cancel(self) {
+        std::mem::forget(self);
+    }
+}
+
 #[cfg(feature = "span")]
 impl Drop for Span<'_> {
     fn drop(&mut self) {
-        #[cfg(feature = "rdtsc")]
+        self.histogram.record(self.histogram.elapsed_since(self.start));
+    }
+}
+
+/// Start of a timed region that is recorded explicitly rather than on drop, see [Histogram::start_span].
+#[cfg(feature = "span")]
+#[derive(Debug, Clone, Copy)]
+#[must_use = "nothing is recorded unless the span is stopped"]
+pub struct RawSpan {
+    start: SpanStart,
+}
+
+/// No-op raw span used when the `span` feature is disabled.
+#[cfg(not(feature = "span"))]
+#[derive(Debug, Clone, Copy)]
+#[must_use = "nothing is recorded unless the span is stopped"]
+pub struct RawSpan {}
+
+impl RawSpan {
+    /// Records the time elapsed since the span was started into `histogram`, in the same unit as
+    /// [Span]. The histogram does not have to be the one that started the span.
+    #[inline]
+    #[cfg(feature = "span")]
+    pub fn stop(self, histogram: &Histogram) {
+        histogram.record(histogram.elapsed_since(self.start));
+    }
+
+    #[inline]
+    #[cfg(not(feature = "span"))]
+    pub fn stop(self, _histogram: &Histogram) {}
+}
+
+/// Span that only records when dropped during a panic, see [Span::on_panic].
+pub struct PanicSpan<'a> {
+    span: Option<Span<'a>>,
+}
+
+impl Drop for PanicSpan<'_> {
+    fn drop(&mut self) {
+        if let Some(span) = self.span.take() {
+            if !std::thread::panicking() {
+                span.cancel();
+            }
+        }
+    }
+}
+
+/// Monotonic clock used to time a [ClockSpan], implemented for closures returning the current time.
+pub trait ClockSource {
+    /// Current time in nanoseconds since an arbitrary epoch.
+    fn now_nanos(&self) -> u64;
+}
+
+impl<F: Fn() -> u64> ClockSource for F {
+    #[inline]
+    fn now_nanos(&self) -> u64 {
+        self()
+    }
+}
+
+/// Span timed by a user-supplied clock, see [Histogram::span_with_clock].
+pub struct ClockSpan<'a, C: ClockSource> {
+    histogram: &'a Histogram,
+    clock: &'a C,
+    start: u64,
+}
+
+impl<C: ClockSource> ClockSpan<'_, C> {
+    /// Ends the span without recording anything.
+    #[inline]
+    pub fn cancel(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl<C: ClockSource> Drop for ClockSpan<'_, C> {
+    fn drop(&mut self) {
+        self.histogram.record_span(self.start, self.clock.now_nanos());
+    }
+}
+
+/// Records the same duration into several histograms, see [Histogram::span_all].
+#[cfg(feature = "span")]
+pub struct MultiSpan<'a, const N: usize> {
+    histograms: [&'a Histogram; N],
+    start: Option<SpanStart>,
+}
+
+/// No-op multi span used when the `span` feature is disabled.
+#[cfg(not(feature = "span"))]
+pub struct MultiSpan<'a, const N: usize> {
+    _marker: std::marker::PhantomData<&'a ()>,
+}
+
+#[cfg(feature = "span")]
+impl<const N: usize> Drop for MultiSpan<'_, N> {
+    fn drop(&mut self) {
+        if let (Some(first), Some(start)) = (self.histograms.first(), self.start) {
+            let elapsed = first.elapsed_since(start);
+            for histogram in self.histograms {
+                histogram.record(elapsed);
+            }
+        }
+    }
+}
+
+impl<const N: usize> MultiSpan<'_, N> {
+    /// Ends the span without recording anything into any of the histograms.
+    #[inline]
+    // the no-op span without the `span` feature has nothing to forget
+    #[cfg_attr(not(feature = "span"), allow(clippy::forget_non_drop))]
+    pub fn cancel(self) {
+        std::mem::forget(self);
+    }
+}
+
+/// Future returned by [HistogramOps::poll_timed] that records the time spent polling the inner
+/// future (in the same unit as [Span]) once it completes.
+pub struct PollTimed<'a, F> {
+    future: F,
+    histogram: &'a Histogram,
+    #[cfg(feature = "span")]
+    active: u64,
+}
+
+impl<F: Future> Future for PollTimed<'_, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: the inner future is never moved out of `self`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        #[cfg(feature = "span")]
         {
-            let end_raw = self.histogram.clock.raw();
-            let elapsed = self.histogram.clock.delta_as_nanos(self.start_raw, end_raw);
-            self.histogram.record(elapsed);
+            let start = this.histogram.start_time();
+            let poll = future.poll(cx);
+            this.active = this.active.wrapping_add(this.histogram.elapsed_since(start));
+            if poll.is_ready() {
+                this.histogram.record(this.active);
+            }
+            poll
         }
-        #[cfg(not(feature = "rdtsc"))]
+        #[cfg(not(feature = "span"))]
         {
-            let elapsed = self.start_instant.elapsed();
-            let nanos = elapsed
-                .as_secs()
-                .wrapping_mul(1_000_000_000)
-                .wrapping_add(u64::from(elapsed.subsec_nanos()));
-            self.histogram.record(nanos);
+            let _ = this.histogram;
+            future.poll(cx)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestMetrics;
+
+    #[test]
+    fn record_span_counts_spans_ending_before_they_start() {
+        let metrics = TestMetrics::install();
+        let histogram = Histogram::new("record_span_latency", &[]);
+        histogram.record_span(1_000, 3_500);
+        histogram.record_span(3_500, 1_000);
+        histogram.record_span(2_000, 2_000);
+        assert_eq!(vec![2_500, 0], metrics.recorded_values("record_span_latency", &[]));
+        assert_eq!(Some(1), metrics.counter_value(CLOCK_ANOMALIES_MEASUREMENT, &[("stat", "clock_anomalies")]));
+    }
+
+    #[test]
+    fn clock_span_records_the_time_elapsed_on_its_clock() {
+        let metrics = TestMetrics::install();
+        let histogram = Histogram::new("clock_span_latency", &[]);
+        let now = std::cell::Cell::new(1_000);
+        let clock = || now.get();
+
+        let span = histogram.span_with_clock(&clock);
+        now.set(4_250);
+        drop(span);
+        assert_eq!(vec![3_250], metrics.recorded_values("clock_span_latency", &[]));
+
+        let span = histogram.span_with_clock(&clock);
+        now.set(9_000);
+        span.cancel();
+        assert_eq!(vec![3_250], metrics.recorded_values("clock_span_latency", &[]));
+    }
+
+    #[test]
+    fn record_f64_rounds_to_the_nearest_integer_by_default() {
+        let metrics = TestMetrics::install();
+        let histogram = Histogram::new("record_f64_ratio", &[]);
+        for value in [0.4, 0.5, 2.5, 41.7, -3.2, -0.0, f64::NAN, f64::INFINITY] {
+            histogram.record_f64(value);
+        }
+        // halfway values round away from zero, negative values and NaN saturate to zero
+        assert_eq!(vec![0, 1, 3, 42, 0, 0, 0, u64::MAX], metrics.recorded_values("record_f64_ratio", &[]));
+    }
+
+    #[test]
+    fn record_duration_records_nanoseconds_of_durations_over_a_second() {
+        let metrics = TestMetrics::install();
+        let histogram = Histogram::new("record_duration_latency", &[]);
+        histogram.record_duration(Duration::new(1, 500_000_000));
+        histogram.record_duration(Duration::from_secs(3_600));
+        histogram.record_duration(Duration::new(5, 7));
+        assert_eq!(
+            vec![1_500_000_000, 3_600_000_000_000, 5_000_000_007],
+            metrics.recorded_values("record_duration_latency", &[])
+        );
+        // beyond u64::MAX nanoseconds the duration wraps around rather than panicking
+        let just_over = Duration::from_secs(u64::MAX / 1_000_000_000 + 1);
+        assert_eq!(290_448_384, duration_as_nanos(just_over));
+    }
+}
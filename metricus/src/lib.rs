@@ -1,16 +1,47 @@
 #![doc = include_str!("../README.md")]
 
+mod binding;
 mod counter;
+mod gauge;
 mod histogram;
+#[cfg(feature = "metrics-rs")]
+mod metrics_rs;
+mod panic;
+mod snapshot;
+mod tagged;
+#[cfg(any(test, feature = "test-util"))]
+mod test_util;
+mod unit;
+
+// spans need a monotonic clock, which `Instant` does not provide on bare wasm where it panics at runtime
+#[cfg(all(feature = "span", target_arch = "wasm32", target_os = "unknown"))]
+compile_error!("the `span` feature requires a monotonic clock, which is not available on wasm32-unknown-unknown");
+
+// elsewhere quanta falls back to a regular clock, so the recorded values would not be cycles
+#[cfg(all(feature = "cycles", not(any(target_arch = "x86", target_arch = "x86_64"))))]
+compile_error!("the `cycles` feature requires a time stamp counter, which is only available on x86 and x86_64");
 
 use crate::access::get_metrics;
 // re-exports
-pub use counter::{Counter, CounterOps};
-pub use histogram::{Histogram, HistogramOps, Span};
+pub use counter::{Counter, CounterBuilder, CounterOps};
+pub use gauge::{CallbackGauge, Gauge, GaugeOps};
+pub use histogram::{
+    CLOCK_ANOMALIES_MEASUREMENT, ClockSource, ClockSpan, Histogram, HistogramBuilder, HistogramOps, MultiSpan,
+    PanicSpan, PollTimed, RawSpan, Span,
+};
+#[cfg(feature = "metrics-rs")]
+pub use metrics_rs::MetricsRsBackend;
+pub use panic::install_panic_counter;
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
+pub use snapshot::{MetricsDelta, MetricsSnapshot, SeriesKey};
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+#[doc(hidden)]
+pub use tagged::TaggedMetrics;
+#[cfg(any(test, feature = "test-util"))]
+pub use test_util::{ConcurrentTestMetrics, TestMetrics};
+pub use unit::{Bytes, Micros, Millis, Nanos, TypedHistogram, Unit};
 
 /// Metric id.
 pub type Id = u64;
@@ -19,6 +50,15 @@ pub type Tag<'a> = (&'a str, &'a str);
 /// Metrics tags expresses as array of key-value pairs.
 pub type Tags<'a> = &'a [Tag<'a>];
 
+/// Function applied to every value recorded by a histogram, see [Histogram::new_with_transform].
+pub type ValueTransform = Box<dyn Fn(u64) -> u64 + Send>;
+
+/// Name of the variant of an enum value, used to tag error counters by kind with
+/// `#[count_errors(by_variant)]`. Can be derived with `#[derive(metricus_macros::VariantName)]`.
+pub trait VariantName {
+    fn variant_name(&self) -> &'static str;
+}
+
 /// Returns empty tags.
 pub const fn empty_tags() -> Tags<'static> {
     &[]
@@ -32,6 +72,19 @@ pub trait Metrics {
 
     fn new_counter(&mut self, name: &str, tags: Tags) -> Id;
 
+    /// Like [Metrics::new_counter] but reports when the counter could not be registered, e.g. because
+    /// the backend is out of capacity. By default registration never fails.
+    fn try_new_counter(&mut self, name: &str, tags: Tags) -> Result<Id, RegisterError> {
+        Ok(self.new_counter(name, tags))
+    }
+
+    /// Like [Metrics::new_counter] for a name and tags that live for the rest of the program, see
+    /// [Counter::new_static]. Backends can keep the references rather than copying them into owned
+    /// storage. By default this forwards to [Metrics::new_counter].
+    fn new_counter_static(&mut self, name: &'static str, tags: Tags<'static>) -> Id {
+        self.new_counter(name, tags)
+    }
+
     fn delete_counter(&mut self, id: Id);
 
     fn increment_counter_by(&mut self, id: Id, delta: u64);
@@ -40,11 +93,120 @@ pub trait Metrics {
         self.increment_counter_by(id, 1)
     }
 
+    /// Decrease the counter by `delta`, for counters that go up and down such as active sessions.
+    /// Backends decide whether the counter saturates or wraps around at zero. By default the counter
+    /// saturates at zero: its value is read with [Metrics::read_counter], reset with
+    /// [Metrics::reset_counter] and incremented by the value less `delta`, which races with updates made
+    /// concurrently on other threads. Backends that do not keep counter values cannot be decremented by
+    /// default.
+    fn decrement_counter_by(&mut self, id: Id, delta: u64) {
+        if let Some(value) = self.read_counter(id) {
+            self.reset_counter(id);
+            self.increment_counter_by(id, value.saturating_sub(delta));
+        }
+    }
+
+    /// Set the counter back to zero, e.g. after its value has been collected by a pull-based system.
+    /// Resetting races with increments made concurrently on other threads. This is a no-op by default.
+    fn reset_counter(&mut self, _id: Id) {}
+
+    /// Current value of the counter, including all increments made so far. Returns `None` by default
+    /// for backends that do not keep counter values, and for unknown counters.
+    fn read_counter(&mut self, _id: Id) -> Option<u64> {
+        None
+    }
+
+    /// Create a gauge, i.e. a value that is set to the current reading of e.g. a queue length rather
+    /// than accumulated. Gauges are not supported by default, in which case the gauge is not registered.
+    fn new_gauge(&mut self, _name: &str, _tags: Tags) -> Id {
+        Id::default()
+    }
+
+    /// This is a no-op by default.
+    fn delete_gauge(&mut self, _id: Id) {}
+
+    /// Set the gauge to `value`, replacing the previous value. This is a no-op by default.
+    fn set_gauge(&mut self, _id: Id, _value: i64) {}
+
     fn new_histogram(&mut self, name: &str, tags: Tags) -> Id;
 
+    /// Like [Metrics::new_histogram] but reports when the histogram could not be registered, e.g.
+    /// because the backend is out of capacity. By default registration never fails.
+    fn try_new_histogram(&mut self, name: &str, tags: Tags) -> Result<Id, RegisterError> {
+        Ok(self.new_histogram(name, tags))
+    }
+
+    /// Create a histogram whose recorded values are passed through `transform` before being
+    /// summarized and exported. Backends should apply it away from the recording call site. By
+    /// default the transform is ignored and a plain histogram is created.
+    fn new_histogram_with_transform(&mut self, name: &str, tags: Tags, _transform: ValueTransform) -> Id {
+        self.new_histogram(name, tags)
+    }
+
+    /// Create a histogram whose recorded values are rounded to the nearest multiple of `resolution_ns`
+    /// before they are summarized, see [Histogram::new_with_resolution]. Backends that do not support
+    /// this ignore the resolution.
+    fn new_histogram_with_resolution(&mut self, name: &str, tags: Tags, _resolution_ns: u64) -> Id {
+        self.new_histogram(name, tags)
+    }
+
     fn delete_histogram(&mut self, id: Id);
 
     fn record(&mut self, id: Id, value: u64);
+
+    /// Record a fractional value, e.g. a ratio. By default the value is rounded to the nearest integer
+    /// and recorded with [Metrics::record], with negative values and NaN recorded as 0, for backends
+    /// that only summarize integers.
+    fn record_f64(&mut self, id: Id, value: f64) {
+        self.record(id, value.round() as u64)
+    }
+
+    /// Record several values at once, e.g. values collected in a tight loop, so that backends that
+    /// buffer or synchronize can take the whole slice in one call. By default each value is recorded
+    /// with [Metrics::record].
+    fn record_many(&mut self, id: Id, values: &[u64]) {
+        for value in values {
+            self.record(id, *value);
+        }
+    }
+
+    /// Discard all samples recorded so far by the histogram. This is a no-op by default.
+    fn clear_histogram(&mut self, _id: Id) {}
+
+    /// Reserve the declared ids for a metric set known up front, e.g. to pre-size storage.
+    ///
+    /// Backends that implement this must resolve any later [Metrics::new_counter] or
+    /// [Metrics::new_histogram] call with the same name and tags as a reserved metric to its
+    /// declared id, and must never hand out a reserved id to any other metric. Reserving a name and
+    /// tags combination that already has an id replaces it for subsequent calls only, metric objects
+    /// that were already created keep their id. This is a no-op by default.
+    fn reserve(&mut self, _metrics: &[PreAllocatedMetric]) {}
+
+    /// Capture the current state of all metrics, see [MetricsSnapshot]. Returns `None` by default
+    /// for backends that do not keep any state.
+    fn snapshot(&mut self) -> Option<MetricsSnapshot> {
+        None
+    }
+
+    /// Export all metrics recorded so far, blocking until done, e.g. before the process exits or before
+    /// taking a snapshot. Returns an error if the metrics could not be delivered. This is a no-op by default.
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Seed the backend with the state captured from another backend, see [replace_metrics_preserving].
+    /// By default each counter in the snapshot is registered and incremented by its value, and each
+    /// histogram is registered, so that metric objects created afterwards continue from there.
+    fn restore(&mut self, snapshot: &MetricsSnapshot) {
+        for (key, value) in &snapshot.counters {
+            let tags = key.tags();
+            let id = self.new_counter(&key.name, &tags);
+            self.increment_counter_by(id, *value);
+        }
+        for key in snapshot.histograms.keys() {
+            self.new_histogram(&key.name, &key.tags());
+        }
+    }
 }
 
 trait IntoHandle {
@@ -58,12 +220,31 @@ impl<T: Metrics + Sized> IntoHandle for T {
 
         let vtable = MetricsVTable {
             new_counter: new_counter_raw::<Self>,
+            try_new_counter: try_new_counter_raw::<Self>,
+            new_counter_static: new_counter_static_raw::<Self>,
             delete_counter: delete_counter_raw::<Self>,
             increment_counter: increment_counter_raw::<Self>,
             increment_counter_by: increment_counter_by_raw::<Self>,
+            decrement_counter_by: decrement_counter_by_raw::<Self>,
+            reset_counter: reset_counter_raw::<Self>,
+            read_counter: read_counter_raw::<Self>,
+            new_gauge: new_gauge_raw::<Self>,
+            delete_gauge: delete_gauge_raw::<Self>,
+            set_gauge: set_gauge_raw::<Self>,
             new_histogram: new_histogram_raw::<Self>,
+            try_new_histogram: try_new_histogram_raw::<Self>,
+            new_histogram_with_transform: new_histogram_with_transform_raw::<Self>,
+            new_histogram_with_resolution: new_histogram_with_resolution_raw::<Self>,
             delete_histogram: delete_histogram_raw::<Self>,
             record: record_raw::<Self>,
+            record_f64: record_f64_raw::<Self>,
+            record_many: record_many_raw::<Self>,
+            clear_histogram: clear_histogram_raw::<Self>,
+            reserve: reserve_raw::<Self>,
+            snapshot: snapshot_raw::<Self>,
+            flush: flush_raw::<Self>,
+            restore: restore_raw::<Self>,
+            drop: Some(drop_raw::<Self>),
         };
         MetricsHandle { ptr, vtable, name }
     }
@@ -75,6 +256,18 @@ fn new_counter_raw<T: Metrics>(ptr: *mut u8, name: &str, tags: Tags) -> Id {
     metrics.new_counter(name, tags)
 }
 
+#[inline]
+fn try_new_counter_raw<T: Metrics>(ptr: *mut u8, name: &str, tags: Tags) -> Result<Id, RegisterError> {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.try_new_counter(name, tags)
+}
+
+#[inline]
+fn new_counter_static_raw<T: Metrics>(ptr: *mut u8, name: &'static str, tags: Tags<'static>) -> Id {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.new_counter_static(name, tags)
+}
+
 #[inline]
 fn delete_counter_raw<T: Metrics>(ptr: *mut u8, id: Id) {
     let metrics = unsafe { &mut *(ptr as *mut T) };
@@ -87,30 +280,133 @@ fn increment_counter_by_raw<T: Metrics>(ptr: *mut u8, id: Id, delta: u64) {
     metrics.increment_counter_by(id, delta)
 }
 
+#[inline]
+fn decrement_counter_by_raw<T: Metrics>(ptr: *mut u8, id: Id, delta: u64) {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.decrement_counter_by(id, delta)
+}
+
 #[inline]
 fn increment_counter_raw<T: Metrics>(ptr: *mut u8, id: Id) {
     increment_counter_by_raw::<T>(ptr, id, 1)
 }
 
+#[inline]
+fn reset_counter_raw<T: Metrics>(ptr: *mut u8, id: Id) {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.reset_counter(id)
+}
+
+#[inline]
+fn read_counter_raw<T: Metrics>(ptr: *mut u8, id: Id) -> Option<u64> {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.read_counter(id)
+}
+
+#[inline]
+fn new_gauge_raw<T: Metrics>(ptr: *mut u8, name: &str, tags: Tags) -> Id {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.new_gauge(name, tags)
+}
+
+#[inline]
+fn delete_gauge_raw<T: Metrics>(ptr: *mut u8, id: Id) {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.delete_gauge(id)
+}
+
+#[inline]
+fn set_gauge_raw<T: Metrics>(ptr: *mut u8, id: Id, value: i64) {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.set_gauge(id, value)
+}
+
 #[inline]
 fn new_histogram_raw<T: Metrics>(ptr: *mut u8, name: &str, tags: Tags) -> Id {
     let metrics = unsafe { &mut *(ptr as *mut T) };
     metrics.new_histogram(name, tags)
 }
 
+#[inline]
+fn try_new_histogram_raw<T: Metrics>(ptr: *mut u8, name: &str, tags: Tags) -> Result<Id, RegisterError> {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.try_new_histogram(name, tags)
+}
+
+#[inline]
+fn new_histogram_with_transform_raw<T: Metrics>(ptr: *mut u8, name: &str, tags: Tags, transform: ValueTransform) -> Id {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.new_histogram_with_transform(name, tags, transform)
+}
+
+#[inline]
+fn new_histogram_with_resolution_raw<T: Metrics>(ptr: *mut u8, name: &str, tags: Tags, resolution_ns: u64) -> Id {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.new_histogram_with_resolution(name, tags, resolution_ns)
+}
+
 #[inline]
 fn delete_histogram_raw<T: Metrics>(ptr: *mut u8, id: Id) {
     let metrics = unsafe { &mut *(ptr as *mut T) };
     metrics.delete_histogram(id)
 }
 
+fn drop_raw<T: Metrics>(ptr: *mut u8) {
+    drop(unsafe { Box::from_raw(ptr as *mut T) })
+}
+
 #[inline]
 fn record_raw<T: Metrics>(ptr: *mut u8, id: Id, value: u64) {
     let metrics = unsafe { &mut *(ptr as *mut T) };
     metrics.record(id, value)
 }
 
-/// Pre-allocated metric consists of name, id and tags.
+#[inline]
+fn record_f64_raw<T: Metrics>(ptr: *mut u8, id: Id, value: f64) {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.record_f64(id, value)
+}
+
+#[inline]
+fn record_many_raw<T: Metrics>(ptr: *mut u8, id: Id, values: &[u64]) {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.record_many(id, values)
+}
+
+#[inline]
+fn clear_histogram_raw<T: Metrics>(ptr: *mut u8, id: Id) {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.clear_histogram(id)
+}
+
+#[inline]
+fn reserve_raw<T: Metrics>(ptr: *mut u8, metrics: &[PreAllocatedMetric]) {
+    let backend = unsafe { &mut *(ptr as *mut T) };
+    backend.reserve(metrics)
+}
+
+#[inline]
+fn snapshot_raw<T: Metrics>(ptr: *mut u8) -> Option<MetricsSnapshot> {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.snapshot()
+}
+
+#[inline]
+fn flush_raw<T: Metrics>(ptr: *mut u8) -> std::io::Result<()> {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.flush()
+}
+
+#[inline]
+fn restore_raw<T: Metrics>(ptr: *mut u8, snapshot: &MetricsSnapshot) {
+    let metrics = unsafe { &mut *(ptr as *mut T) };
+    metrics.restore(snapshot)
+}
+
+/// Pre-allocated metric consists of name, id and tags, and optionally the unit of its values (e.g.
+/// `bytes` or `nanoseconds`) for backends that can surface it, as the metrics themselves do not have
+/// an inherent notion of units. Histograms can also declare the upper bounds of the buckets their values
+/// are counted in, for backends that export bucketed histograms.
 #[serde_as]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
@@ -122,6 +418,8 @@ pub enum PreAllocatedMetric {
         #[serde_as(as = "HashMap<_, _>")]
         #[serde(default)]
         tags: Vec<(String, String)>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        unit: Option<String>,
     },
     Histogram {
         name: String,
@@ -129,6 +427,20 @@ pub enum PreAllocatedMetric {
         #[serde_as(as = "HashMap<_, _>")]
         #[serde(default)]
         tags: Vec<(String, String)>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        unit: Option<String>,
+        /// Inclusive upper bounds of the buckets, in ascending order.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        buckets: Option<Vec<u64>>,
+    },
+    Gauge {
+        name: String,
+        id: Id,
+        #[serde_as(as = "HashMap<_, _>")]
+        #[serde(default)]
+        tags: Vec<(String, String)>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        unit: Option<String>,
     },
 }
 
@@ -138,6 +450,7 @@ impl PreAllocatedMetric {
             name: name.to_owned(),
             id,
             tags: tags.iter().map(|tag| (tag.0.to_owned(), tag.1.to_owned())).collect(),
+            unit: None,
         }
     }
 
@@ -146,8 +459,82 @@ impl PreAllocatedMetric {
             name: name.to_owned(),
             id,
             tags: tags.iter().map(|tag| (tag.0.to_owned(), tag.1.to_owned())).collect(),
+            unit: None,
+            buckets: None,
+        }
+    }
+
+    pub fn gauge(name: &str, id: Id, tags: &[Tag]) -> Self {
+        PreAllocatedMetric::Gauge {
+            name: name.to_owned(),
+            id,
+            tags: tags.iter().map(|tag| (tag.0.to_owned(), tag.1.to_owned())).collect(),
+            unit: None,
+        }
+    }
+
+    /// Counter whose values are in the given `unit`, see [PreAllocatedMetric::counter].
+    pub fn counter_with_unit(name: &str, id: Id, tags: &[Tag], unit: &str) -> Self {
+        Self::counter(name, id, tags).with_unit(unit)
+    }
+
+    /// Histogram whose values are in the given `unit`, see [PreAllocatedMetric::histogram].
+    pub fn histogram_with_unit(name: &str, id: Id, tags: &[Tag], unit: &str) -> Self {
+        Self::histogram(name, id, tags).with_unit(unit)
+    }
+
+    /// Gauge whose values are in the given `unit`, see [PreAllocatedMetric::gauge].
+    pub fn gauge_with_unit(name: &str, id: Id, tags: &[Tag], unit: &str) -> Self {
+        Self::gauge(name, id, tags).with_unit(unit)
+    }
+
+    /// Histogram whose values are counted in buckets with the given inclusive upper `bounds`, see
+    /// [PreAllocatedMetric::histogram]. The bounds are sorted and deduplicated.
+    ///
+    /// ## Examples
+    ///
+    /// ```no_run
+    /// use metricus::PreAllocatedMetric;
+    ///
+    /// let metric = PreAllocatedMetric::histogram_with_buckets("latency", 1, &[], &[100, 1_000, 10_000]);
+    /// assert_eq!(Some(&[100, 1_000, 10_000][..]), metric.buckets());
+    /// ```
+    pub fn histogram_with_buckets(name: &str, id: Id, tags: &[Tag], bounds: &[u64]) -> Self {
+        let mut bounds = bounds.to_vec();
+        bounds.sort_unstable();
+        bounds.dedup();
+        let mut metric = Self::histogram(name, id, tags);
+        if let PreAllocatedMetric::Histogram { buckets, .. } = &mut metric {
+            *buckets = Some(bounds);
+        }
+        metric
+    }
+
+    /// Unit of the values, if known.
+    pub fn unit(&self) -> Option<&str> {
+        match self {
+            PreAllocatedMetric::Counter { unit, .. }
+            | PreAllocatedMetric::Histogram { unit, .. }
+            | PreAllocatedMetric::Gauge { unit, .. } => unit.as_deref(),
         }
     }
+
+    /// Upper bounds of the histogram buckets, if declared.
+    pub fn buckets(&self) -> Option<&[u64]> {
+        match self {
+            PreAllocatedMetric::Histogram { buckets, .. } => buckets.as_deref(),
+            PreAllocatedMetric::Counter { .. } | PreAllocatedMetric::Gauge { .. } => None,
+        }
+    }
+
+    fn with_unit(mut self, new_unit: &str) -> Self {
+        match &mut self {
+            PreAllocatedMetric::Counter { unit, .. }
+            | PreAllocatedMetric::Histogram { unit, .. }
+            | PreAllocatedMetric::Gauge { unit, .. } => *unit = Some(new_unit.to_owned()),
+        }
+        self
+    }
 }
 
 /// A trivial no-op backend for the "uninitialized" state.
@@ -170,6 +557,18 @@ impl Metrics for NoOpMetrics {
         // no-op
     }
 
+    fn new_gauge(&mut self, _name: &str, _tags: Tags) -> Id {
+        Id::default()
+    }
+
+    fn delete_gauge(&mut self, _id: Id) {
+        // no-op
+    }
+
+    fn set_gauge(&mut self, _id: Id, _value: i64) {
+        // no-op
+    }
+
     fn new_histogram(&mut self, _name: &str, _tags: Tags) -> Id {
         Id::default()
     }
@@ -187,12 +586,32 @@ const NO_OP_METRICS: NoOpMetrics = NoOpMetrics;
 
 const NO_OP_METRICS_VTABLE: MetricsVTable = MetricsVTable {
     new_counter: new_counter_raw::<NoOpMetrics>,
+    try_new_counter: try_new_counter_raw::<NoOpMetrics>,
+    new_counter_static: new_counter_static_raw::<NoOpMetrics>,
     delete_counter: delete_counter_raw::<NoOpMetrics>,
     increment_counter: increment_counter_raw::<NoOpMetrics>,
     increment_counter_by: increment_counter_by_raw::<NoOpMetrics>,
+    decrement_counter_by: decrement_counter_by_raw::<NoOpMetrics>,
+    reset_counter: reset_counter_raw::<NoOpMetrics>,
+    read_counter: read_counter_raw::<NoOpMetrics>,
+    new_gauge: new_gauge_raw::<NoOpMetrics>,
+    delete_gauge: delete_gauge_raw::<NoOpMetrics>,
+    set_gauge: set_gauge_raw::<NoOpMetrics>,
     new_histogram: new_histogram_raw::<NoOpMetrics>,
+    try_new_histogram: try_new_histogram_raw::<NoOpMetrics>,
+    new_histogram_with_transform: new_histogram_with_transform_raw::<NoOpMetrics>,
+    new_histogram_with_resolution: new_histogram_with_resolution_raw::<NoOpMetrics>,
     delete_histogram: delete_histogram_raw::<NoOpMetrics>,
     record: record_raw::<NoOpMetrics>,
+    record_f64: record_f64_raw::<NoOpMetrics>,
+    record_many: record_many_raw::<NoOpMetrics>,
+    clear_histogram: clear_histogram_raw::<NoOpMetrics>,
+    reserve: reserve_raw::<NoOpMetrics>,
+    snapshot: snapshot_raw::<NoOpMetrics>,
+    flush: flush_raw::<NoOpMetrics>,
+    restore: restore_raw::<NoOpMetrics>,
+    // the no-op backend is a constant that must not be freed
+    drop: None,
 };
 
 const NO_OP_METRICS_HANDLE: MetricsHandle = MetricsHandle {
@@ -210,29 +629,308 @@ static METRICS: MetricsHolder = MetricsHolder {
     handle: AtomicRef::new(&NO_OP_METRICS_HANDLE),
 };
 
-/// Set a new metrics backend. This must be called before any counters or histograms are
-/// created (including through macros), because metric objects cache the active handle.
-/// It should also be called before any worker threads start so hot-path loads can use
-/// relaxed ordering. Otherwise, all metrics calls will delegate to the `NoOpMetrics`.
+/// Set to `true` once any metric object has bound itself to the active backend.
+static METRICS_BOUND: AtomicBool = AtomicBool::new(false);
+
+/// Bumped whenever the backend is replaced, so that metric objects register again with the new one.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Set to `true` while recording is suspended, see [suspend].
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+
+/// Callback invoked for every counter and histogram registration, see [set_registration_observer].
+type RegistrationObserver = Box<dyn Fn(&str, Tags, Id) + Send + Sync>;
+
+/// Null until an observer is set with [set_registration_observer].
+static REGISTRATION_OBSERVER: AtomicPtr<RegistrationObserver> = AtomicPtr::new(std::ptr::null_mut());
+
+/// Set a new metrics backend. Until it is called all metrics calls delegate to the `NoOpMetrics`.
+/// It should be called before any counters or histograms are created (including through macros)
+/// and before any worker threads start. Enable the `late-init` feature if the backend has to be set
+/// while threads that record metrics are already running, at the cost of an acquire load on every update.
+///
+/// Metric objects cache the backend they were registered with, along with a generation that is bumped
+/// whenever the backend is replaced. An object created before this call registers itself again with
+/// the new backend on its next use, which costs a load of the generation and a branch on every update
+/// (the cached backend is loaded with acquire ordering, which is free on x86). Values it
+/// recorded with the previous backend are not carried over (see [replace_metrics_preserving]), and
+/// updates from other threads racing with the re-registration may still reach the previous backend.
+///
+/// Whether any metric object has already been created is tracked, and calling this function
+/// afterwards logs a warning. Use [try_set_metrics] to turn this into an error instead.
 pub fn set_metrics(metrics: impl Metrics) {
+    if METRICS_BOUND.load(Ordering::SeqCst) {
+        log::warn!(
+            "metrics backend '{}' set after metrics have been created, existing metrics will move over from '{}' and restart",
+            metrics.name(),
+            get_metrics().name
+        );
+    }
     METRICS
         .handle
         .set(Box::leak(Box::new(metrics.into_handle())), Ordering::SeqCst);
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Set a new metrics backend and hand back the previous one, so that it is freed once the returned
+/// [RetiredMetrics] is dropped rather than leaked as with [set_metrics], e.g. when every test installs
+/// its own backend. Returns `None` if the previous backend was the no-op backend.
+///
+/// # Safety
+///
+/// Metric objects register themselves again with the new backend on their next use, see [set_metrics],
+/// and are not deleted from the previous backend. A thread that is updating a metric while the backend
+/// is swapped may still call into the previous backend though. The caller must ensure that no other
+/// thread uses, creates or drops metric objects between the swap and dropping the returned value.
+///
+/// ## Examples
+///
+/// ```ignore
+/// let previous = unsafe { metricus::swap_metrics(TestBackend::new()) };
+/// // all metric objects of the previous backend have been dropped
+/// drop(previous);
+/// ```
+pub unsafe fn swap_metrics(metrics: impl Metrics) -> Option<RetiredMetrics> {
+    let previous = METRICS
+        .handle
+        .swap(Box::leak(Box::new(metrics.into_handle())), Ordering::SeqCst);
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+    RetiredMetrics::new(previous)
+}
+
+/// Restore the no-op backend, so that metric objects stop recording once they notice the change, see
+/// [set_metrics]. The previous backend is not freed, see [swap_metrics] to free it.
+pub fn reset_to_noop() {
+    METRICS.handle.set(&NO_OP_METRICS_HANDLE, Ordering::SeqCst);
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Backend replaced by [swap_metrics], which is freed when this is dropped.
+pub struct RetiredMetrics {
+    handle: &'static MetricsHandle,
+}
+
+impl RetiredMetrics {
+    fn new(handle: &'static MetricsHandle) -> Option<Self> {
+        handle.vtable.drop.map(|_| Self { handle })
+    }
+
+    /// Name of the backend, see [Metrics::name].
+    pub fn name(&self) -> &'static str {
+        self.handle.name
+    }
+}
+
+impl Drop for RetiredMetrics {
+    fn drop(&mut self) {
+        if let Some(drop) = self.handle.vtable.drop {
+            drop(self.handle.ptr);
+        }
+        // the handle was leaked when the backend was installed
+        drop(unsafe { Box::from_raw(self.handle as *const MetricsHandle as *mut MetricsHandle) });
+    }
+}
+
+/// Set a new metrics backend seeded with a snapshot of the state of the previous one, e.g. when the
+/// whole backend is replaced to reconfigure the exporter, so that counters do not reset to zero.
+/// The snapshot is restored into the new backend (see [Metrics::restore]) after it has been installed.
+///
+/// Counter values exported by the new backend continue from the values in the snapshot, provided the
+/// backend supports restoring them. Updates recorded with the previous backend after the snapshot was
+/// taken are lost. As with [set_metrics], metric objects created before the swap register again with
+/// the new backend on their next use, whether they carry on from the restored values depends on the
+/// backend registering them under the restored series. Histograms are only registered again, their recorded values are not carried over.
+///
+/// ## Examples
+///
+/// ```ignore
+/// let snapshot = metricus::snapshot().unwrap_or_default();
+/// metricus::replace_metrics_preserving(new_backend, &snapshot);
+/// ```
+pub fn replace_metrics_preserving(metrics: impl Metrics, snapshot: &MetricsSnapshot) {
+    set_metrics(metrics);
+    get_metrics().restore(snapshot);
+}
+
+/// Set a new metrics backend, unless any counter or histogram has already been created, in which
+/// case the backend is not installed and an error is returned. See [set_metrics] for details.
+pub fn try_set_metrics(metrics: impl Metrics) -> Result<(), MetricsAlreadyBound> {
+    if METRICS_BOUND.load(Ordering::SeqCst) {
+        return Err(MetricsAlreadyBound {
+            backend: get_metrics().name,
+        });
+    }
+    set_metrics(metrics);
+    Ok(())
+}
+
+/// Error returned by [try_set_metrics] when metric objects have already been bound to a backend.
+#[derive(Debug)]
+pub struct MetricsAlreadyBound {
+    backend: &'static str,
+}
+
+impl std::fmt::Display for MetricsAlreadyBound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "metrics have already been created using the '{}' backend", self.backend)
+    }
+}
+
+impl std::error::Error for MetricsAlreadyBound {}
+
+/// Error returned when a backend could not register a metric, see [Metrics::try_new_counter].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegisterError {
+    /// The backend cannot take any more metrics or registration requests at the moment.
+    CapacityExceeded,
+    /// The backend refused to register the metric for the given reason.
+    Rejected(String),
 }
 
+impl std::fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegisterError::CapacityExceeded => write!(f, "metrics backend is out of capacity"),
+            RegisterError::Rejected(reason) => write!(f, "metrics backend rejected the metric: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for RegisterError {}
+
 /// Get name of the active metrics backend.
 pub fn get_metrics_backend_name() -> &'static str {
     get_metrics().name
 }
 
+/// Reserve ids for metrics declared up front with the active backend, see [Metrics::reserve].
+/// Counters and histograms created afterwards with matching name and tags resolve to the declared ids.
+pub fn reserve_metrics(metrics: &[PreAllocatedMetric]) {
+    get_metrics().reserve(metrics)
+}
+
+/// Capture the current state of all metrics held by the active backend, or `None` if the backend
+/// does not support snapshots. Two snapshots can be compared with [MetricsSnapshot::diff].
+pub fn snapshot() -> Option<MetricsSnapshot> {
+    get_metrics().snapshot()
+}
+
+/// Export all metrics recorded so far with the active backend, blocking until done, see [Metrics::flush].
+pub fn flush() -> std::io::Result<()> {
+    get_metrics().flush()
+}
+
+/// Observe every counter and histogram registration with its name, tags and the id assigned by the
+/// active backend, e.g. to log registrations while tracking down a metric explosion. The observer is
+/// called on the registering thread right after the backend has registered the metric, including for
+/// metric objects registering again after the backend was replaced, but not for registrations that
+/// failed (see [Metrics::try_new_counter]).
+///
+/// Without an observer, registration pays for a single atomic load. Setting another observer replaces
+/// the previous one, which is not freed, as a concurrent registration may still be calling it.
+///
+/// ## Examples
+///
+/// ```
+/// metricus::set_registration_observer(|name, tags, id| println!("registered {name} {tags:?} as {id}"));
+/// ```
+pub fn set_registration_observer(observer: impl Fn(&str, Tags, Id) + Send + Sync + 'static) {
+    let observer: RegistrationObserver = Box::new(observer);
+    REGISTRATION_OBSERVER.store(Box::leak(Box::new(observer)), Ordering::Release);
+}
+
+/// Passes the registration to the observer, if any, see [set_registration_observer].
+#[inline]
+fn observe_registration(name: &str, tags: Tags, id: Id) -> Id {
+    let observer = REGISTRATION_OBSERVER.load(Ordering::Acquire);
+    if !observer.is_null() {
+        // observers are leaked so the pointer stays valid once set
+        unsafe { (*observer)(name, tags, id) }
+    }
+    id
+}
+
+/// Suspend recording of all metrics until [resume] is called, e.g. during warm-up or a batch backfill.
+/// While suspended, counter increments and histogram records (including spans) are dropped before they
+/// reach the backend, whereas creating and dropping metrics is unaffected.
+///
+/// Suspension is process-global, it applies to all threads and all metrics regardless of when they
+/// were created. Checking it costs a relaxed atomic load and a well predicted branch on every increment
+/// and record, also when recording is not suspended. Updates already handed to the backend are not
+/// discarded.
+///
+/// ## Examples
+///
+/// ```no_run
+/// metricus::suspend();
+/// // warm-up, nothing is recorded
+/// metricus::resume();
+/// ```
+pub fn suspend() {
+    SUSPENDED.store(true, Ordering::Relaxed);
+}
+
+/// Resume recording of metrics after [suspend].
+pub fn resume() {
+    SUSPENDED.store(false, Ordering::Relaxed);
+}
+
+/// Whether recording of metrics is currently suspended, see [suspend].
+#[inline]
+pub fn is_suspended() -> bool {
+    SUSPENDED.load(Ordering::Relaxed)
+}
+
+/// Increments the `counter` and then starts a span on the `histogram`, which records the elapsed
+/// time once dropped. This is meant for request entry points that count and time the same operation.
+/// The counter is incremented first, so the time spent incrementing it is not part of the span.
+///
+/// ## Examples
+///
+/// ```no_run
+/// use metricus::{observe_start, Counter, Histogram};
+///
+/// let requests = Counter::new("requests", &[]);
+/// let latency = Histogram::new("request_latency", &[]);
+/// {
+///     let _span = observe_start(&requests, &latency);
+///     // handle request...
+/// }
+/// ```
+#[inline]
+pub fn observe_start<'a>(counter: &impl CounterOps, histogram: &'a impl HistogramOps) -> Span<'a> {
+    counter.increment();
+    histogram.span()
+}
+
 struct MetricsVTable {
     new_counter: fn(*mut u8, &str, Tags) -> Id,
+    try_new_counter: fn(*mut u8, &str, Tags) -> Result<Id, RegisterError>,
+    new_counter_static: fn(*mut u8, &'static str, Tags<'static>) -> Id,
     delete_counter: fn(*mut u8, Id),
     increment_counter: fn(*mut u8, Id),
     increment_counter_by: fn(*mut u8, Id, u64),
+    decrement_counter_by: fn(*mut u8, Id, u64),
+    reset_counter: fn(*mut u8, Id),
+    read_counter: fn(*mut u8, Id) -> Option<u64>,
+    new_gauge: fn(*mut u8, &str, Tags) -> Id,
+    delete_gauge: fn(*mut u8, Id),
+    set_gauge: fn(*mut u8, Id, i64),
     new_histogram: fn(*mut u8, &str, Tags) -> Id,
+    try_new_histogram: fn(*mut u8, &str, Tags) -> Result<Id, RegisterError>,
+    new_histogram_with_transform: fn(*mut u8, &str, Tags, ValueTransform) -> Id,
+    new_histogram_with_resolution: fn(*mut u8, &str, Tags, u64) -> Id,
     delete_histogram: fn(*mut u8, Id),
     record: fn(*mut u8, Id, u64),
+    record_f64: fn(*mut u8, Id, f64),
+    record_many: fn(*mut u8, Id, &[u64]),
+    clear_histogram: fn(*mut u8, Id),
+    reserve: fn(*mut u8, &[PreAllocatedMetric]),
+    snapshot: fn(*mut u8) -> Option<MetricsSnapshot>,
+    flush: fn(*mut u8) -> std::io::Result<()>,
+    restore: fn(*mut u8, &MetricsSnapshot),
+    /// Frees the backend, `None` for the no-op backend.
+    drop: Option<fn(*mut u8)>,
 }
 
 /// Metrics backend handle.
@@ -250,7 +948,17 @@ unsafe impl Sync for MetricsHandle {}
 impl MetricsHandle {
     #[inline]
     fn new_counter(&self, name: &str, tags: Tags) -> Id {
-        (self.vtable.new_counter)(self.ptr, name, tags)
+        observe_registration(name, tags, (self.vtable.new_counter)(self.ptr, name, tags))
+    }
+
+    #[inline]
+    fn try_new_counter(&self, name: &str, tags: Tags) -> Result<Id, RegisterError> {
+        (self.vtable.try_new_counter)(self.ptr, name, tags).map(|id| observe_registration(name, tags, id))
+    }
+
+    #[inline]
+    fn new_counter_static(&self, name: &'static str, tags: Tags<'static>) -> Id {
+        observe_registration(name, tags, (self.vtable.new_counter_static)(self.ptr, name, tags))
     }
 
     #[inline]
@@ -260,17 +968,76 @@ impl MetricsHandle {
 
     #[inline]
     fn increment_counter_by(&self, id: Id, delta: u64) {
+        if is_suspended() {
+            return;
+        }
         (self.vtable.increment_counter_by)(self.ptr, id, delta)
     }
 
     #[inline]
     fn increment_counter(&self, id: Id) {
+        if is_suspended() {
+            return;
+        }
         (self.vtable.increment_counter)(self.ptr, id)
     }
 
+    #[inline]
+    fn decrement_counter_by(&self, id: Id, delta: u64) {
+        if is_suspended() {
+            return;
+        }
+        (self.vtable.decrement_counter_by)(self.ptr, id, delta)
+    }
+
+    #[inline]
+    fn reset_counter(&self, id: Id) {
+        (self.vtable.reset_counter)(self.ptr, id)
+    }
+
+    #[inline]
+    fn read_counter(&self, id: Id) -> Option<u64> {
+        (self.vtable.read_counter)(self.ptr, id)
+    }
+
+    #[inline]
+    fn new_gauge(&self, name: &str, tags: Tags) -> Id {
+        (self.vtable.new_gauge)(self.ptr, name, tags)
+    }
+
+    #[inline]
+    fn delete_gauge(&self, id: Id) {
+        (self.vtable.delete_gauge)(self.ptr, id)
+    }
+
+    #[inline]
+    fn set_gauge(&self, id: Id, value: i64) {
+        if is_suspended() {
+            return;
+        }
+        (self.vtable.set_gauge)(self.ptr, id, value)
+    }
+
     #[inline]
     fn new_histogram(&self, name: &str, tags: Tags) -> Id {
-        (self.vtable.new_histogram)(self.ptr, name, tags)
+        observe_registration(name, tags, (self.vtable.new_histogram)(self.ptr, name, tags))
+    }
+
+    #[inline]
+    fn try_new_histogram(&self, name: &str, tags: Tags) -> Result<Id, RegisterError> {
+        (self.vtable.try_new_histogram)(self.ptr, name, tags).map(|id| observe_registration(name, tags, id))
+    }
+
+    #[inline]
+    fn new_histogram_with_transform(&self, name: &str, tags: Tags, transform: ValueTransform) -> Id {
+        let id = (self.vtable.new_histogram_with_transform)(self.ptr, name, tags, transform);
+        observe_registration(name, tags, id)
+    }
+
+    #[inline]
+    fn new_histogram_with_resolution(&self, name: &str, tags: Tags, resolution_ns: u64) -> Id {
+        let id = (self.vtable.new_histogram_with_resolution)(self.ptr, name, tags, resolution_ns);
+        observe_registration(name, tags, id)
     }
 
     #[inline]
@@ -280,8 +1047,52 @@ impl MetricsHandle {
 
     #[inline]
     fn record(&self, id: Id, value: u64) {
+        if is_suspended() {
+            return;
+        }
         (self.vtable.record)(self.ptr, id, value)
     }
+
+    #[inline]
+    fn record_f64(&self, id: Id, value: f64) {
+        if is_suspended() {
+            return;
+        }
+        (self.vtable.record_f64)(self.ptr, id, value)
+    }
+
+    #[inline]
+    fn record_many(&self, id: Id, values: &[u64]) {
+        if is_suspended() {
+            return;
+        }
+        (self.vtable.record_many)(self.ptr, id, values)
+    }
+
+    #[inline]
+    fn clear_histogram(&self, id: Id) {
+        (self.vtable.clear_histogram)(self.ptr, id)
+    }
+
+    #[inline]
+    fn reserve(&self, metrics: &[PreAllocatedMetric]) {
+        (self.vtable.reserve)(self.ptr, metrics)
+    }
+
+    #[inline]
+    fn snapshot(&self) -> Option<MetricsSnapshot> {
+        (self.vtable.snapshot)(self.ptr)
+    }
+
+    #[inline]
+    fn flush(&self) -> std::io::Result<()> {
+        (self.vtable.flush)(self.ptr)
+    }
+
+    #[inline]
+    fn restore(&self, snapshot: &MetricsSnapshot) {
+        (self.vtable.restore)(self.ptr, snapshot)
+    }
 }
 
 struct AtomicRef<T> {
@@ -300,18 +1111,65 @@ impl<T> AtomicRef<T> {
         unsafe { &*self.ptr.load(ordering) }
     }
 
+    /// Same as `get(Ordering::Acquire)`, which makes the writes that initialised the referenced value
+    /// before it was set visible to the calling thread.
+    #[cfg_attr(not(feature = "late-init"), allow(dead_code))]
+    #[inline]
+    pub fn get_acquire(&self) -> &T {
+        self.get(Ordering::Acquire)
+    }
+
     #[inline]
     pub fn set(&self, new_ref: &T, ordering: Ordering) {
         self.ptr.store(new_ref as *const T as *mut T, ordering);
     }
+
+    #[inline]
+    pub fn swap(&self, new_ref: &T, ordering: Ordering) -> &T {
+        unsafe { &*self.ptr.swap(new_ref as *const T as *mut T, ordering) }
+    }
 }
 
+/// Access to the active backend on the hot path.
+///
+/// The backend is loaded with `Relaxed` ordering by default, which costs the same as a plain load on
+/// every platform. This relies on the documented contract that the backend is set before the threads
+/// that record metrics are started, as spawning a thread synchronises with it. A thread that was already
+/// running when the backend was set could otherwise observe the new handle before the writes that
+/// initialised the backend behind it.
+///
+/// With the `late-init` feature the backend is loaded with `Acquire` ordering instead, pairing with the
+/// `SeqCst` store in [crate::set_metrics], so that backends can be set or replaced after threads have
+/// started. This is free on x86, where every load has acquire semantics, but costs a barrier or a
+/// load-acquire instruction on weakly ordered architectures such as ARM on every metric update.
 mod access {
-    use crate::{METRICS, MetricsHandle};
+    use crate::{GENERATION, METRICS, METRICS_BOUND, MetricsHandle};
     use std::sync::atomic::Ordering;
 
+    /// Generation of the active backend, see [crate::set_metrics].
+    #[inline(always)]
+    pub fn generation() -> u64 {
+        GENERATION.load(Ordering::Relaxed)
+    }
+
+    #[cfg(not(feature = "late-init"))]
     #[inline(always)]
     pub fn get_metrics() -> &'static MetricsHandle {
         METRICS.handle.get(Ordering::Relaxed)
     }
+
+    #[cfg(feature = "late-init")]
+    #[inline(always)]
+    pub fn get_metrics() -> &'static MetricsHandle {
+        METRICS.handle.get_acquire()
+    }
+
+    /// Get the active handle on behalf of a metric object that is going to cache it.
+    #[inline]
+    pub fn bind_metrics() -> &'static MetricsHandle {
+        if !METRICS_BOUND.load(Ordering::Relaxed) {
+            METRICS_BOUND.store(true, Ordering::SeqCst);
+        }
+        get_metrics()
+    }
 }
@@ -0,0 +1,303 @@
+//! Backend forwarding metrics to the [metrics](https://docs.rs/metrics) crate (metrics-rs).
+
+use crate::{Id, Metrics, Tags};
+use metrics::{Label, SharedString, Unit};
+use std::collections::HashMap;
+
+/// Metrics backend that forwards counters and histograms to the recorder installed with the `metrics`
+/// crate (requires the `metrics-rs` feature), e.g. to expose them through a Prometheus exporter built
+/// on `metrics-rs`. The recorder must be installed before this backend is set with [crate::set_metrics].
+///
+/// Counters map to `metrics::counter!` and histograms to `metrics::histogram!`, with tags as labels.
+/// The metricus histogram API carries no unit, so units and descriptions are declared up front with
+/// [MetricsRsBackend::describe_counter] and [MetricsRsBackend::describe_histogram] and passed to the
+/// recorder (`metrics::describe_*!`) when the first metric of that name is registered.
+///
+/// The conversion is lossy in the following ways:
+/// - values are recorded into histograms as `f64`, which is exact up to 2^53 (values recorded with
+///   [crate::HistogramOps::record_f64] are passed on as they are),
+/// - values are passed on in the unit they were recorded in, e.g. spans record nanoseconds while
+///   Prometheus conventionally uses seconds, so the unit should be declared for the recorder to convert,
+/// - bucket boundaries are a property of the exporter rather than of the recorder in `metrics-rs`, so
+///   they are only stored with the backend and returned by [MetricsRsBackend::histogram_buckets] to
+///   configure the exporter with (e.g. `PrometheusBuilder::set_buckets_for_metric`),
+/// - snapshots are not supported, so [crate::snapshot] returns `None`.
+///
+/// ## Examples
+///
+/// ```ignore
+/// use metricus::MetricsRsBackend;
+/// use metrics::Unit;
+/// use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+///
+/// let backend = MetricsRsBackend::new()
+///     .describe_histogram("request_latency", Unit::Nanoseconds, "time spent handling a request")
+///     .with_histogram_buckets("request_latency", &[1e3, 1e4, 1e5, 1e6]);
+/// let mut builder = PrometheusBuilder::new();
+/// for (name, buckets) in backend.histogram_buckets() {
+///     builder = builder.set_buckets_for_metric(Matcher::Full(name.to_owned()), buckets)?;
+/// }
+/// builder.install()?;
+/// metricus::set_metrics(backend);
+/// ```
+#[derive(Default)]
+pub struct MetricsRsBackend {
+    next_id: Id,
+    counters: HashMap<Id, metrics::Counter>,
+    histograms: HashMap<Id, metrics::Histogram>,
+    descriptions: HashMap<String, Description>,
+    buckets: HashMap<String, Vec<f64>>,
+}
+
+struct Description {
+    unit: Option<Unit>,
+    description: SharedString,
+    /// Set once passed to the recorder.
+    described: bool,
+}
+
+impl MetricsRsBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares the unit and description of all counters with the given measurement name.
+    pub fn describe_counter(mut self, name: &str, unit: Unit, description: &str) -> Self {
+        self.describe(name, unit, description);
+        self
+    }
+
+    /// Declares the unit and description of all histograms with the given measurement name.
+    pub fn describe_histogram(mut self, name: &str, unit: Unit, description: &str) -> Self {
+        self.describe(name, unit, description);
+        self
+    }
+
+    /// Declares the bucket boundaries of all histograms with the given measurement name, to be passed
+    /// to the exporter, see [MetricsRsBackend::histogram_buckets].
+    pub fn with_histogram_buckets(mut self, name: &str, buckets: &[f64]) -> Self {
+        self.buckets.insert(name.to_owned(), buckets.to_vec());
+        self
+    }
+
+    /// Bucket boundaries declared with [MetricsRsBackend::with_histogram_buckets] by measurement name.
+    pub fn histogram_buckets(&self) -> impl Iterator<Item = (&str, &[f64])> {
+        self.buckets
+            .iter()
+            .map(|(name, buckets)| (name.as_str(), buckets.as_slice()))
+    }
+
+    fn describe(&mut self, name: &str, unit: Unit, description: &str) {
+        self.descriptions.insert(
+            name.to_owned(),
+            Description {
+                unit: Some(unit),
+                description: description.to_owned().into(),
+                described: false,
+            },
+        );
+    }
+
+    /// Passes the declared unit and description to the recorder the first time the name is used.
+    fn describe_once(&mut self, name: &str, describe: impl FnOnce(&dyn metrics::Recorder, &Description)) {
+        if let Some(description) = self.descriptions.get_mut(name) {
+            if !description.described {
+                description.described = true;
+                metrics::with_recorder(|recorder| describe(recorder, description));
+            }
+        }
+    }
+
+    fn next_id(&mut self) -> Id {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+}
+
+fn labels(tags: Tags) -> Vec<Label> {
+    tags.iter()
+        .map(|(key, value)| Label::new(key.to_string(), value.to_string()))
+        .collect()
+}
+
+impl Metrics for MetricsRsBackend {
+    fn name(&self) -> &'static str {
+        "metrics-rs"
+    }
+
+    fn new_counter(&mut self, name: &str, tags: Tags) -> Id {
+        self.describe_once(name, |recorder, description| {
+            recorder.describe_counter(name.to_owned().into(), description.unit, description.description.clone())
+        });
+        let id = self.next_id();
+        self.counters
+            .insert(id, metrics::counter!(name.to_owned(), labels(tags)));
+        id
+    }
+
+    fn delete_counter(&mut self, id: Id) {
+        self.counters.remove(&id);
+    }
+
+    fn increment_counter_by(&mut self, id: Id, delta: u64) {
+        if let Some(counter) = self.counters.get(&id) {
+            counter.increment(delta);
+        }
+    }
+
+    fn new_histogram(&mut self, name: &str, tags: Tags) -> Id {
+        self.describe_once(name, |recorder, description| {
+            recorder.describe_histogram(name.to_owned().into(), description.unit, description.description.clone())
+        });
+        let id = self.next_id();
+        self.histograms
+            .insert(id, metrics::histogram!(name.to_owned(), labels(tags)));
+        id
+    }
+
+    fn delete_histogram(&mut self, id: Id) {
+        self.histograms.remove(&id);
+    }
+
+    fn record(&mut self, id: Id, value: u64) {
+        if let Some(histogram) = self.histograms.get(&id) {
+            histogram.record(value as f64);
+        }
+    }
+
+    fn record_f64(&mut self, id: Id, value: f64) {
+        if let Some(histogram) = self.histograms.get(&id) {
+            histogram.record(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics::{GaugeFn, HistogramFn, Key, KeyName, Metadata, Recorder};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// Recorder keeping the series it registers by name and labels, plus the descriptions it was given.
+    #[derive(Default)]
+    struct Recorded {
+        counters: Mutex<HashMap<String, Arc<AtomicU64>>>,
+        histograms: Mutex<HashMap<String, Arc<Samples>>>,
+        descriptions: Mutex<Vec<(String, Option<Unit>, String)>>,
+    }
+
+    #[derive(Default)]
+    struct Samples(Mutex<Vec<f64>>);
+
+    impl HistogramFn for Samples {
+        fn record(&self, value: f64) {
+            self.0.lock().unwrap().push(value);
+        }
+    }
+
+    struct NoGauge;
+
+    impl GaugeFn for NoGauge {
+        fn increment(&self, _value: f64) {}
+
+        fn decrement(&self, _value: f64) {}
+
+        fn set(&self, _value: f64) {}
+    }
+
+    fn series(key: &Key) -> String {
+        let labels: Vec<_> = key
+            .labels()
+            .map(|label| format!("{}={}", label.key(), label.value()))
+            .collect();
+        format!("{}{{{}}}", key.name(), labels.join(","))
+    }
+
+    impl Recorded {
+        fn counter(&self, series: &str) -> u64 {
+            self.counters.lock().unwrap()[series].load(Ordering::Relaxed)
+        }
+
+        fn histogram(&self, series: &str) -> Vec<f64> {
+            self.histograms.lock().unwrap()[series].0.lock().unwrap().clone()
+        }
+
+        fn describe(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+            let entry = (key.as_str().to_owned(), unit, description.to_string());
+            self.descriptions.lock().unwrap().push(entry);
+        }
+    }
+
+    impl Recorder for Recorded {
+        fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+            self.describe(key, unit, description);
+        }
+
+        fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+            self.describe(key, unit, description);
+        }
+
+        fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+            self.describe(key, unit, description);
+        }
+
+        fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> metrics::Counter {
+            let counter = self.counters.lock().unwrap().entry(series(key)).or_default().clone();
+            metrics::Counter::from_arc(counter)
+        }
+
+        fn register_gauge(&self, _key: &Key, _metadata: &Metadata<'_>) -> metrics::Gauge {
+            metrics::Gauge::from_arc(Arc::new(NoGauge))
+        }
+
+        fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> metrics::Histogram {
+            let histogram = self.histograms.lock().unwrap().entry(series(key)).or_default().clone();
+            metrics::Histogram::from_arc(histogram)
+        }
+    }
+
+    #[test]
+    fn counters_and_histograms_are_forwarded_with_tags_as_labels() {
+        let recorder = Recorded::default();
+        let mut backend = MetricsRsBackend::new();
+        let (orders, latency) = metrics::with_local_recorder(&recorder, || {
+            let orders = backend.new_counter("orders", &[("venue", "lse")]);
+            let latency = backend.new_histogram("latency", &[]);
+            (orders, latency)
+        });
+
+        backend.increment_counter_by(orders, 3);
+        backend.increment_counter(orders);
+        backend.record(latency, 1_500);
+        backend.record_f64(latency, 0.25);
+        assert_eq!(4, recorder.counter("orders{venue=lse}"));
+        assert_eq!(vec![1_500.0, 0.25], recorder.histogram("latency{}"));
+
+        // updates of deleted metrics are dropped
+        backend.delete_counter(orders);
+        backend.increment_counter(orders);
+        assert_eq!(4, recorder.counter("orders{venue=lse}"));
+    }
+
+    #[test]
+    fn descriptions_are_passed_to_the_recorder_once_per_name() {
+        let recorder = Recorded::default();
+        let mut backend = MetricsRsBackend::new()
+            .describe_counter("orders", Unit::Count, "orders submitted")
+            .describe_histogram("latency", Unit::Nanoseconds, "time to acknowledge an order");
+        metrics::with_local_recorder(&recorder, || {
+            backend.new_counter("orders", &[("venue", "lse")]);
+            backend.new_counter("orders", &[("venue", "xetra")]);
+            backend.new_histogram("latency", &[]);
+            backend.new_histogram("fills", &[]);
+        });
+
+        let expected = vec![
+            ("orders".to_owned(), Some(Unit::Count), "orders submitted".to_owned()),
+            ("latency".to_owned(), Some(Unit::Nanoseconds), "time to acknowledge an order".to_owned()),
+        ];
+        assert_eq!(expected, *recorder.descriptions.lock().unwrap());
+    }
+}
@@ -0,0 +1,52 @@
+//! Counting panics before the process goes down.
+
+use crate::access::get_metrics;
+use crate::{Id, Tags};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Installs a panic hook that increments a counter on every panic and then flushes the active backend,
+/// so that the crash shows up in the exported metrics even if the panic takes the process down. The
+/// previous hook is called afterwards, so panic messages are still printed as before.
+///
+/// The counter is tagged with the given `tags` plus a `location` tag holding the `file:line` at which
+/// the panic occurred. The panicking thread is not included as thread names are often unique per
+/// worker and would make the number of series unbounded.
+///
+/// This must be called after [crate::set_metrics], as the counters are only registered once a panic
+/// occurs, using the backend active at that time. Flushing blocks the panicking thread until the
+/// backend has exported its metrics.
+///
+/// ## Examples
+///
+/// ```no_run
+/// metricus::install_panic_counter("panics", &[("service", "orders")]);
+/// ```
+pub fn install_panic_counter(measurement: &str, tags: Tags) {
+    let measurement = measurement.to_owned();
+    let tags: Vec<(String, String)> = tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    let ids = Mutex::new(HashMap::<String, Id>::new());
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let location = info
+            .location()
+            .map(|location| format!("{}:{}", location.file(), location.line()))
+            .unwrap_or_else(|| "unknown".to_owned());
+        // a poisoned lock means we panicked while counting a panic, so don't try again
+        if let Ok(mut ids) = ids.lock() {
+            let metrics = get_metrics();
+            let id = *ids.entry(location).or_insert_with_key(|location| {
+                let tags: Vec<_> = tags
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .chain([("location", location.as_str())])
+                    .collect();
+                metrics.new_counter(&measurement, &tags)
+            });
+            metrics.increment_counter(id);
+            // nothing can be done about a failed flush while panicking
+            let _ = metrics.flush();
+        }
+        previous(info);
+    }));
+}
@@ -0,0 +1,195 @@
+//! Point-in-time view of the metrics held by a backend.
+
+use crate::{Tag, Tags};
+use std::collections::HashMap;
+
+/// Identifies a series by its name and tags, with the tags ordered by key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SeriesKey {
+    pub name: String,
+    pub tags: Vec<(String, String)>,
+}
+
+impl SeriesKey {
+    pub fn new(name: &str, tags: Tags) -> Self {
+        let mut tags: Vec<_> = tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        tags.sort();
+        Self {
+            name: name.to_owned(),
+            tags,
+        }
+    }
+
+    /// Tags borrowed as [Tags].
+    pub fn tags(&self) -> Vec<Tag<'_>> {
+        self.tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect()
+    }
+}
+
+/// Values of all counters and sample counts of all histograms at the time the snapshot was taken,
+/// see [crate::snapshot()]. Histogram sample counts are cumulative since the histogram was registered,
+/// regardless of how often the backend exports and resets the recorded values.
+///
+/// ## Examples
+///
+/// Assert on how many times an operation incremented a counter.
+///
+/// ```no_run
+/// use metricus::{Counter, CounterOps};
+///
+/// let orders = Counter::new("orders", &[("side", "buy")]);
+/// let before = metricus::snapshot().unwrap();
+/// orders.increment_by(3);
+/// let after = metricus::snapshot().unwrap();
+/// let delta = before.diff(&after);
+/// assert_eq!(3, delta.counter("orders", &[("side", "buy")]));
+/// ```
+///
+/// Note that taking a snapshot may itself allocate, which shows up in allocation counters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub counters: HashMap<SeriesKey, u64>,
+    pub histograms: HashMap<SeriesKey, u64>,
+}
+
+impl MetricsSnapshot {
+    /// Value of the counter, if present.
+    pub fn counter(&self, name: &str, tags: Tags) -> Option<u64> {
+        self.counters.get(&SeriesKey::new(name, tags)).copied()
+    }
+
+    /// Number of samples recorded by the histogram, if present.
+    pub fn histogram_count(&self, name: &str, tags: Tags) -> Option<u64> {
+        self.histograms.get(&SeriesKey::new(name, tags)).copied()
+    }
+
+    /// Changes from this snapshot to the `other`, later, snapshot. Series that only exist in `other`
+    /// are treated as starting from zero and series that only exist in this snapshot are reported as
+    /// removed without a delta.
+    pub fn diff(&self, other: &MetricsSnapshot) -> MetricsDelta {
+        let mut delta = MetricsDelta::default();
+        for (key, value) in &other.counters {
+            let previous = self.counters.get(key).copied();
+            if previous.is_none() {
+                delta.added.push(key.clone());
+            }
+            delta
+                .counters
+                .insert(key.clone(), value.wrapping_sub(previous.unwrap_or_default()) as i64);
+        }
+        for (key, count) in &other.histograms {
+            let previous = self.histograms.get(key).copied();
+            if previous.is_none() {
+                delta.added.push(key.clone());
+            }
+            delta
+                .histograms
+                .insert(key.clone(), count.wrapping_sub(previous.unwrap_or_default()) as i64);
+        }
+        delta.removed.extend(
+            self.counters
+                .keys()
+                .filter(|key| !other.counters.contains_key(key))
+                .chain(self.histograms.keys().filter(|key| !other.histograms.contains_key(key)))
+                .cloned(),
+        );
+        delta.added.sort();
+        delta.removed.sort();
+        delta
+    }
+}
+
+/// Difference between two [MetricsSnapshot]s: counter value deltas and histogram sample count deltas
+/// per series, along with the series that were added or removed in between.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsDelta {
+    pub counters: HashMap<SeriesKey, i64>,
+    pub histograms: HashMap<SeriesKey, i64>,
+    pub added: Vec<SeriesKey>,
+    pub removed: Vec<SeriesKey>,
+}
+
+impl MetricsDelta {
+    /// Change of the counter value, zero if the counter did not change or is unknown.
+    pub fn counter(&self, name: &str, tags: Tags) -> i64 {
+        self.counters
+            .get(&SeriesKey::new(name, tags))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Number of samples recorded by the histogram in between, zero if unknown.
+    pub fn histogram_count(&self, name: &str, tags: Tags) -> i64 {
+        self.histograms
+            .get(&SeriesKey::new(name, tags))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(counters: &[(&str, u64)], histograms: &[(&str, u64)]) -> MetricsSnapshot {
+        let series = |metrics: &[(&str, u64)]| {
+            metrics
+                .iter()
+                .map(|(name, value)| (SeriesKey::new(name, &[("venue", "lse")]), *value))
+                .collect()
+        };
+        MetricsSnapshot {
+            counters: series(counters),
+            histograms: series(histograms),
+        }
+    }
+
+    fn keys(names: &[&str]) -> Vec<SeriesKey> {
+        names
+            .iter()
+            .map(|name| SeriesKey::new(name, &[("venue", "lse")]))
+            .collect()
+    }
+
+    #[test]
+    fn diff_reports_changed_added_and_removed_series() {
+        let before = snapshot(&[("orders", 5), ("cancels", 2), ("fills", 4)], &[("latency", 10), ("size", 3)]);
+        let after = snapshot(&[("orders", 8), ("cancels", 2), ("rejects", 1)], &[("latency", 12), ("queue", 7)]);
+        let delta = before.diff(&after);
+
+        assert_eq!(3, delta.counter("orders", &[("venue", "lse")]));
+        assert_eq!(0, delta.counter("cancels", &[("venue", "lse")]));
+        // added series start from zero
+        assert_eq!(1, delta.counter("rejects", &[("venue", "lse")]));
+        assert_eq!(2, delta.histogram_count("latency", &[("venue", "lse")]));
+        assert_eq!(7, delta.histogram_count("queue", &[("venue", "lse")]));
+        // removed series have no delta
+        assert!(!delta.counters.contains_key(&keys(&["fills"])[0]));
+        assert_eq!(0, delta.histogram_count("size", &[("venue", "lse")]));
+
+        assert_eq!(keys(&["queue", "rejects"]), delta.added);
+        assert_eq!(keys(&["fills", "size"]), delta.removed);
+    }
+
+    #[test]
+    fn diff_of_a_reset_counter_is_negative() {
+        let delta = snapshot(&[("orders", 5)], &[]).diff(&snapshot(&[("orders", 1)], &[]));
+        assert_eq!(-4, delta.counter("orders", &[("venue", "lse")]));
+        assert!(delta.added.is_empty() && delta.removed.is_empty());
+    }
+
+    #[test]
+    fn diff_of_equal_snapshots_is_all_zero() {
+        let snapshot = snapshot(&[("orders", 5)], &[("latency", 10)]);
+        let delta = snapshot.diff(&snapshot);
+        assert!(
+            delta
+                .counters
+                .values()
+                .chain(delta.histograms.values())
+                .all(|delta| *delta == 0)
+        );
+        assert_eq!(2, delta.counters.len() + delta.histograms.len());
+        assert!(delta.added.is_empty() && delta.removed.is_empty());
+    }
+}
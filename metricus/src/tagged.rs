@@ -0,0 +1,120 @@
+//! Metric objects created on demand for every distinct combination of tag values computed at call time,
+//! as generated by the `tag_arg` and `by_variant` macro arguments.
+
+use std::fmt::{Display, Write};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Metric objects keyed by the string representation of `N` tag values. The objects are leaked, as
+/// the statics generated by the macros that hold them live for the rest of the program anyway.
+///
+/// Lookups take a shared lock and compare the values with the stored ones without formatting them
+/// into new strings, so recording into an existing series does not allocate. Only the first use of a
+/// combination takes the exclusive lock to create the object. Entries are searched one by one, which
+/// is meant for the low-cardinality values the macros document.
+#[doc(hidden)]
+pub struct TaggedMetrics<M: 'static, const N: usize> {
+    metrics: RwLock<Entries<M, N>>,
+}
+
+/// Tag values along with the object created for them.
+type Entries<M, const N: usize> = Vec<([Box<str>; N], &'static M)>;
+
+impl<M: 'static, const N: usize> TaggedMetrics<M, N> {
+    pub const fn new() -> Self {
+        Self {
+            metrics: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Object for the given tag values, created with `create` from their string representation if this
+    /// is the first time they are used.
+    pub fn get_or_create(&self, values: [&dyn Display; N], create: impl FnOnce(&[&str; N]) -> M) -> &'static M {
+        if let Some(metric) = Self::find(&self.read(), &values) {
+            return metric;
+        }
+        let mut metrics = self.write();
+        // another thread may have created it in the meantime
+        if let Some(metric) = Self::find(&metrics, &values) {
+            return metric;
+        }
+        let owned = values.map(|value| value.to_string());
+        let metric: &'static M = Box::leak(Box::new(create(&owned.each_ref().map(String::as_str))));
+        metrics.push((owned.map(String::into_boxed_str), metric));
+        metric
+    }
+
+    fn find(metrics: &[([Box<str>; N], &'static M)], values: &[&dyn Display; N]) -> Option<&'static M> {
+        metrics
+            .iter()
+            .find(|(keys, _)| keys.iter().zip(values).all(|(key, value)| displays_as(*value, key)))
+            .map(|(_, metric)| *metric)
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Entries<M, N>> {
+        // a panic while creating a metric leaves the entries intact
+        self.metrics.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Entries<M, N>> {
+        self.metrics.write().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl<M: 'static, const N: usize> Default for TaggedMetrics<M, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether `value` is formatted as `expected`, without allocating.
+fn displays_as(value: &dyn Display, expected: &str) -> bool {
+    /// Consumes the expected string as the value is written, failing on the first mismatch.
+    struct Matcher<'a>(&'a str);
+
+    impl Write for Matcher<'_> {
+        fn write_str(&mut self, s: &str) -> std::fmt::Result {
+            match self.0.strip_prefix(s) {
+                Some(rest) => {
+                    self.0 = rest;
+                    Ok(())
+                }
+                None => Err(std::fmt::Error),
+            }
+        }
+    }
+
+    let mut matcher = Matcher(expected);
+    write!(matcher, "{value}").is_ok() && matcher.0.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn compares_the_formatted_value() {
+        assert!(displays_as(&42, "42"));
+        assert!(displays_as(&"buy", "buy"));
+        assert!(!displays_as(&42, "4"));
+        assert!(!displays_as(&4, "42"));
+        assert!(!displays_as(&"sell", "buy"));
+        assert!(displays_as(&"", ""));
+    }
+
+    #[test]
+    fn creates_each_combination_once() {
+        let metrics = TaggedMetrics::<String, 2>::new();
+        let created = Cell::new(0);
+        let create = |values: &[&str; 2]| {
+            created.set(created.get() + 1);
+            values.join("/")
+        };
+        let first = metrics.get_or_create([&"buy", &1], create);
+        assert_eq!("buy/1", first);
+        assert!(std::ptr::eq(first, metrics.get_or_create([&"buy", &1], create)));
+        assert_eq!("sell/1", metrics.get_or_create([&"sell", &1], create));
+        assert_eq!("buy/2", metrics.get_or_create([&"buy", &2], create));
+        assert_eq!(3, created.get());
+    }
+}
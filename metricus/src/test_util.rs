@@ -0,0 +1,609 @@
+//! In-memory backends for testing instrumented code.
+
+use crate::{Id, Metrics, MetricsSnapshot, SeriesKey, Tags, ValueTransform, set_metrics};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Metrics backend (requires the `test-util` feature) that keeps all counter and gauge values and every
+/// value recorded into histograms in memory, so that tests can assert on what instrumented code recorded.
+///
+/// The backend is shared, clones refer to the same state, so that a clone can be queried after the
+/// backend has been installed with [crate::set_metrics]. As metric objects (including the statics
+/// generated by the `#[counter]` and `#[span]` macros) move over to a newly installed backend and
+/// restart from zero, there should only be one per process. [TestMetrics::install] takes care of that
+/// by installing a single instance on first use and returning it to every caller. Tests running in parallel share it, so they should use distinct
+/// measurement names or tags, or run on a single test thread and call [TestMetrics::reset] in between.
+///
+/// Metrics are identified by name and tags, so creating a metric with the same name and tags again
+/// resolves to the same series. Values are kept when a metric object is dropped, so they can still be
+/// queried afterwards. Histogram transforms are applied when a value is recorded, whereas a resolution
+/// (see [crate::Histogram::new_with_resolution]) is ignored.
+///
+/// ## Examples
+///
+/// ```no_run
+/// use metricus::{Counter, CounterOps, Histogram, HistogramOps, TestMetrics};
+///
+/// let metrics = TestMetrics::install();
+/// metrics.reset();
+///
+/// let counter = Counter::new("orders", &[("side", "buy")]);
+/// counter.increment_by(3);
+/// let histogram = Histogram::new("latency", &[]);
+/// histogram.record(150);
+///
+/// assert_eq!(Some(3), metrics.counter_value("orders", &[("side", "buy")]));
+/// assert_eq!(vec![150], metrics.recorded_values("latency", &[]));
+/// ```
+#[derive(Clone, Default)]
+pub struct TestMetrics {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    ids: HashMap<SeriesKey, Id>,
+    counters: HashMap<Id, (SeriesKey, u64)>,
+    gauges: HashMap<Id, (SeriesKey, i64)>,
+    histograms: HashMap<Id, TestHistogram>,
+    next_id: Id,
+}
+
+struct TestHistogram {
+    key: SeriesKey,
+    values: Vec<u64>,
+    /// Values recorded since registration, not affected by clearing the histogram.
+    total: u64,
+    transform: Option<ValueTransform>,
+}
+
+impl State {
+    fn id(&mut self, key: &SeriesKey) -> Id {
+        if let Some(id) = self.ids.get(key) {
+            return *id;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.ids.insert(key.clone(), id);
+        id
+    }
+}
+
+impl TestMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Installs a backend with [crate::set_metrics] the first time this is called, and returns the
+    /// installed backend on every call.
+    pub fn install() -> TestMetrics {
+        static INSTALLED: OnceLock<TestMetrics> = OnceLock::new();
+        INSTALLED
+            .get_or_init(|| {
+                let metrics = TestMetrics::new();
+                set_metrics(metrics.clone());
+                metrics
+            })
+            .clone()
+    }
+
+    /// Current value of the counter, if it has been created.
+    pub fn counter_value(&self, name: &str, tags: Tags) -> Option<u64> {
+        let state = self.state();
+        let id = state.ids.get(&SeriesKey::new(name, tags))?;
+        state.counters.get(id).map(|(_, value)| *value)
+    }
+
+    /// Last value the gauge was set to, if it has been created.
+    pub fn gauge_value(&self, name: &str, tags: Tags) -> Option<i64> {
+        let state = self.state();
+        let id = state.ids.get(&SeriesKey::new(name, tags))?;
+        state.gauges.get(id).map(|(_, value)| *value)
+    }
+
+    /// Values recorded into the histogram since it was created or last cleared, in the order they were
+    /// recorded. Empty if the histogram has not been created.
+    pub fn recorded_values(&self, name: &str, tags: Tags) -> Vec<u64> {
+        let state = self.state();
+        state
+            .ids
+            .get(&SeriesKey::new(name, tags))
+            .and_then(|id| state.histograms.get(id))
+            .map(|histogram| histogram.values.clone())
+            .unwrap_or_default()
+    }
+
+    /// Resets all counters and gauges to zero and discards all recorded values, e.g. between tests.
+    /// Metrics stay registered, so that metric objects created before keep recording.
+    pub fn reset(&self) {
+        let mut state = self.state();
+        state.counters.values_mut().for_each(|(_, value)| *value = 0);
+        state.gauges.values_mut().for_each(|(_, value)| *value = 0);
+        state.histograms.values_mut().for_each(|histogram| {
+            histogram.values.clear();
+            histogram.total = 0;
+        });
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // a panicking test must not poison the state for the other tests
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Metrics for TestMetrics {
+    fn name(&self) -> &'static str {
+        "test"
+    }
+
+    fn new_counter(&mut self, name: &str, tags: Tags) -> Id {
+        let mut state = self.state();
+        let key = SeriesKey::new(name, tags);
+        let id = state.id(&key);
+        state.counters.entry(id).or_insert((key, 0));
+        id
+    }
+
+    fn delete_counter(&mut self, _id: Id) {}
+
+    fn increment_counter_by(&mut self, id: Id, delta: u64) {
+        if let Some((_, value)) = self.state().counters.get_mut(&id) {
+            *value = value.wrapping_add(delta);
+        }
+    }
+
+    /// Saturates at zero.
+    fn decrement_counter_by(&mut self, id: Id, delta: u64) {
+        if let Some((_, value)) = self.state().counters.get_mut(&id) {
+            *value = value.saturating_sub(delta);
+        }
+    }
+
+    fn reset_counter(&mut self, id: Id) {
+        if let Some((_, value)) = self.state().counters.get_mut(&id) {
+            *value = 0;
+        }
+    }
+
+    fn read_counter(&mut self, id: Id) -> Option<u64> {
+        self.state().counters.get(&id).map(|(_, value)| *value)
+    }
+
+    fn new_gauge(&mut self, name: &str, tags: Tags) -> Id {
+        let mut state = self.state();
+        let key = SeriesKey::new(name, tags);
+        let id = state.id(&key);
+        state.gauges.entry(id).or_insert((key, 0));
+        id
+    }
+
+    fn delete_gauge(&mut self, _id: Id) {}
+
+    fn set_gauge(&mut self, id: Id, value: i64) {
+        if let Some((_, current)) = self.state().gauges.get_mut(&id) {
+            *current = value;
+        }
+    }
+
+    fn new_histogram(&mut self, name: &str, tags: Tags) -> Id {
+        let mut state = self.state();
+        let key = SeriesKey::new(name, tags);
+        let id = state.id(&key);
+        state.histograms.entry(id).or_insert(TestHistogram {
+            key,
+            values: Vec::new(),
+            total: 0,
+            transform: None,
+        });
+        id
+    }
+
+    fn new_histogram_with_transform(&mut self, name: &str, tags: Tags, transform: ValueTransform) -> Id {
+        let id = self.new_histogram(name, tags);
+        if let Some(histogram) = self.state().histograms.get_mut(&id) {
+            histogram.transform = Some(transform);
+        }
+        id
+    }
+
+    fn delete_histogram(&mut self, _id: Id) {}
+
+    fn record(&mut self, id: Id, value: u64) {
+        self.record_many(id, &[value]);
+    }
+
+    fn record_many(&mut self, id: Id, values: &[u64]) {
+        if let Some(histogram) = self.state().histograms.get_mut(&id) {
+            for value in values {
+                let value = match &histogram.transform {
+                    Some(transform) => transform(*value),
+                    None => *value,
+                };
+                histogram.values.push(value);
+                histogram.total += 1;
+            }
+        }
+    }
+
+    fn clear_histogram(&mut self, id: Id) {
+        if let Some(histogram) = self.state().histograms.get_mut(&id) {
+            histogram.values.clear();
+        }
+    }
+
+    fn snapshot(&mut self) -> Option<MetricsSnapshot> {
+        let state = self.state();
+        Some(MetricsSnapshot {
+            counters: state
+                .counters
+                .values()
+                .map(|(key, value)| (key.clone(), *value))
+                .collect(),
+            histograms: state
+                .histograms
+                .values()
+                .map(|histogram| (histogram.key.clone(), histogram.total))
+                .collect(),
+        })
+    }
+}
+
+/// Metrics backend (requires the `test-util` feature) like [TestMetrics], for tests that update the same
+/// metrics from many threads at once. Counters are kept as atomics, so that threads incrementing them
+/// only share a read lock rather than queueing up on a single mutex as with [TestMetrics], which keeps
+/// the interleaving of the threads under test closer to that with a real backend.
+///
+/// Metrics can be registered from any thread too, ids are allocated from an atomic counter and are
+/// unique for the lifetime of the backend. Recording into a histogram still takes a lock per histogram,
+/// so that values are kept in the order they were recorded.
+///
+/// The backend is shared like [TestMetrics], clones refer to the same state. It is installed with
+/// [crate::set_metrics], which moves all metric objects over to it.
+///
+/// ## Examples
+///
+/// ```no_run
+/// use metricus::{ConcurrentTestMetrics, Counter, CounterOps};
+///
+/// let metrics = ConcurrentTestMetrics::new();
+/// metricus::set_metrics(metrics.clone());
+///
+/// let workers: Vec<_> = (0..8)
+///     .map(|_| {
+///         std::thread::spawn(|| {
+///             let counter = Counter::new("processed", &[]);
+///             for _ in 0..100_000 {
+///                 counter.increment();
+///             }
+///         })
+///     })
+///     .collect();
+/// workers.into_iter().for_each(|worker| worker.join().unwrap());
+///
+/// assert_eq!(Some(800_000), metrics.counter_value("processed", &[]));
+/// ```
+#[derive(Clone, Default)]
+pub struct ConcurrentTestMetrics {
+    state: Arc<ConcurrentState>,
+}
+
+#[derive(Default)]
+struct ConcurrentState {
+    ids: Mutex<HashMap<SeriesKey, Id>>,
+    next_id: AtomicU64,
+    counters: RwLock<HashMap<Id, (SeriesKey, AtomicU64)>>,
+    histograms: RwLock<HashMap<Id, ConcurrentHistogram>>,
+}
+
+struct ConcurrentHistogram {
+    key: SeriesKey,
+    /// Values recorded since registration, not affected by clearing the histogram.
+    total: AtomicU64,
+    recorded: Mutex<Recorded>,
+}
+
+#[derive(Default)]
+struct Recorded {
+    values: Vec<u64>,
+    transform: Option<ValueTransform>,
+}
+
+impl ConcurrentState {
+    fn id(&self, key: &SeriesKey) -> Id {
+        *lock(&self.ids)
+            .entry(key.clone())
+            .or_insert_with(|| self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn counters(&self) -> RwLockReadGuard<'_, HashMap<Id, (SeriesKey, AtomicU64)>> {
+        self.counters.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn counters_mut(&self) -> RwLockWriteGuard<'_, HashMap<Id, (SeriesKey, AtomicU64)>> {
+        self.counters.write().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn histograms(&self) -> RwLockReadGuard<'_, HashMap<Id, ConcurrentHistogram>> {
+        self.histograms.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn histograms_mut(&self) -> RwLockWriteGuard<'_, HashMap<Id, ConcurrentHistogram>> {
+        self.histograms.write().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Locks the mutex, a panicking test must not poison the state for the other tests.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl ConcurrentTestMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current value of the counter, if it has been created.
+    pub fn counter_value(&self, name: &str, tags: Tags) -> Option<u64> {
+        let id = *lock(&self.state.ids).get(&SeriesKey::new(name, tags))?;
+        self.state
+            .counters()
+            .get(&id)
+            .map(|(_, value)| value.load(Ordering::Relaxed))
+    }
+
+    /// Values recorded into the histogram since it was created or last cleared, in the order they were
+    /// recorded. Empty if the histogram has not been created.
+    pub fn recorded_values(&self, name: &str, tags: Tags) -> Vec<u64> {
+        let Some(id) = lock(&self.state.ids).get(&SeriesKey::new(name, tags)).copied() else {
+            return Vec::new();
+        };
+        self.state
+            .histograms()
+            .get(&id)
+            .map(|histogram| lock(&histogram.recorded).values.clone())
+            .unwrap_or_default()
+    }
+
+    /// Resets all counters to zero and discards all recorded values, e.g. between tests. Metrics stay
+    /// registered, so that metric objects created before keep recording.
+    pub fn reset(&self) {
+        self.state
+            .counters()
+            .values()
+            .for_each(|(_, value)| value.store(0, Ordering::Relaxed));
+        self.state.histograms().values().for_each(|histogram| {
+            lock(&histogram.recorded).values.clear();
+            histogram.total.store(0, Ordering::Relaxed);
+        });
+    }
+
+    fn with_counter(&self, id: Id, f: impl FnOnce(&AtomicU64)) {
+        if let Some((_, value)) = self.state.counters().get(&id) {
+            f(value);
+        }
+    }
+}
+
+impl Metrics for ConcurrentTestMetrics {
+    fn name(&self) -> &'static str {
+        "concurrent-test"
+    }
+
+    fn new_counter(&mut self, name: &str, tags: Tags) -> Id {
+        let key = SeriesKey::new(name, tags);
+        let id = self.state.id(&key);
+        self.state
+            .counters_mut()
+            .entry(id)
+            .or_insert_with(|| (key, AtomicU64::new(0)));
+        id
+    }
+
+    fn delete_counter(&mut self, _id: Id) {}
+
+    fn increment_counter_by(&mut self, id: Id, delta: u64) {
+        self.with_counter(id, |value| {
+            value.fetch_add(delta, Ordering::Relaxed);
+        });
+    }
+
+    /// Saturates at zero.
+    fn decrement_counter_by(&mut self, id: Id, delta: u64) {
+        self.with_counter(id, |value| {
+            let _ = value.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| Some(value.saturating_sub(delta)));
+        });
+    }
+
+    fn reset_counter(&mut self, id: Id) {
+        self.with_counter(id, |value| value.store(0, Ordering::Relaxed));
+    }
+
+    fn read_counter(&mut self, id: Id) -> Option<u64> {
+        self.state
+            .counters()
+            .get(&id)
+            .map(|(_, value)| value.load(Ordering::Relaxed))
+    }
+
+    fn new_histogram(&mut self, name: &str, tags: Tags) -> Id {
+        let key = SeriesKey::new(name, tags);
+        let id = self.state.id(&key);
+        self.state
+            .histograms_mut()
+            .entry(id)
+            .or_insert_with(|| ConcurrentHistogram {
+                key,
+                total: AtomicU64::new(0),
+                recorded: Mutex::default(),
+            });
+        id
+    }
+
+    fn new_histogram_with_transform(&mut self, name: &str, tags: Tags, transform: ValueTransform) -> Id {
+        let id = self.new_histogram(name, tags);
+        if let Some(histogram) = self.state.histograms().get(&id) {
+            lock(&histogram.recorded).transform = Some(transform);
+        }
+        id
+    }
+
+    fn delete_histogram(&mut self, _id: Id) {}
+
+    fn record(&mut self, id: Id, value: u64) {
+        self.record_many(id, &[value]);
+    }
+
+    fn record_many(&mut self, id: Id, values: &[u64]) {
+        if let Some(histogram) = self.state.histograms().get(&id) {
+            let mut recorded = lock(&histogram.recorded);
+            for value in values {
+                let value = match &recorded.transform {
+                    Some(transform) => transform(*value),
+                    None => *value,
+                };
+                recorded.values.push(value);
+            }
+            histogram.total.fetch_add(values.len() as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn clear_histogram(&mut self, id: Id) {
+        if let Some(histogram) = self.state.histograms().get(&id) {
+            lock(&histogram.recorded).values.clear();
+        }
+    }
+
+    fn snapshot(&mut self) -> Option<MetricsSnapshot> {
+        Some(MetricsSnapshot {
+            counters: self
+                .state
+                .counters()
+                .values()
+                .map(|(key, value)| (key.clone(), value.load(Ordering::Relaxed)))
+                .collect(),
+            histograms: self
+                .state
+                .histograms()
+                .values()
+                .map(|histogram| (histogram.key.clone(), histogram.total.load(Ordering::Relaxed)))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_are_kept_by_name_and_tags() {
+        let mut metrics = TestMetrics::new();
+        let buys = metrics.new_counter("orders", &[("side", "buy")]);
+        let sells = metrics.new_counter("orders", &[("side", "sell")]);
+        assert_ne!(buys, sells);
+        // registering again resolves to the same series, which outlives deleting the metric
+        assert_eq!(buys, metrics.new_counter("orders", &[("side", "buy")]));
+        metrics.increment_counter_by(buys, 3);
+        metrics.delete_counter(buys);
+        metrics.increment_counter(sells);
+        assert_eq!(Some(3), metrics.counter_value("orders", &[("side", "buy")]));
+        assert_eq!(Some(1), metrics.counter_value("orders", &[("side", "sell")]));
+        assert_eq!(None, metrics.counter_value("orders", &[]));
+
+        let latency = metrics.new_histogram_with_transform("latency", &[], Box::new(|value| value / 1_000));
+        metrics.record_many(latency, &[2_000, 5_000]);
+        metrics.record(latency, 1_000);
+        assert_eq!(vec![2, 5, 1], metrics.recorded_values("latency", &[]));
+        assert!(metrics.recorded_values("fills", &[]).is_empty());
+
+        // clearing discards the values but not the number recorded, which snapshots report
+        metrics.clear_histogram(latency);
+        assert!(metrics.recorded_values("latency", &[]).is_empty());
+        let snapshot = metrics.snapshot().unwrap();
+        assert_eq!(Some(3), snapshot.histogram_count("latency", &[]));
+        assert_eq!(Some(3), snapshot.counter("orders", &[("side", "buy")]));
+
+        metrics.reset();
+        assert_eq!(Some(0), metrics.counter_value("orders", &[("side", "buy")]));
+        metrics.record(latency, 4_000);
+        assert_eq!(vec![4], metrics.recorded_values("latency", &[]));
+    }
+
+    #[test]
+    fn clones_share_their_state_across_threads() {
+        let metrics = TestMetrics::new();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let mut metrics = metrics.clone();
+                scope.spawn(move || {
+                    let counter = metrics.new_counter("processed", &[]);
+                    for _ in 0..1_000 {
+                        metrics.increment_counter(counter);
+                    }
+                });
+            }
+        });
+        assert_eq!(Some(4_000), metrics.counter_value("processed", &[]));
+    }
+
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        let metrics = ConcurrentTestMetrics::new();
+        std::thread::scope(|scope| {
+            for thread in 0..8u64 {
+                let mut metrics = metrics.clone();
+                scope.spawn(move || {
+                    // every thread registers the metrics, which resolves to the same ids
+                    let counter = metrics.new_counter("processed", &[("stage", "parse")]);
+                    let histogram = metrics.new_histogram("batch_size", &[]);
+                    for _ in 0..100_000 {
+                        metrics.increment_counter(counter);
+                    }
+                    metrics.record(histogram, thread);
+                });
+            }
+        });
+        assert_eq!(Some(800_000), metrics.counter_value("processed", &[("stage", "parse")]));
+        let mut recorded = metrics.recorded_values("batch_size", &[]);
+        recorded.sort();
+        assert_eq!((0..8).collect::<Vec<_>>(), recorded);
+    }
+
+    #[test]
+    fn concurrent_metrics_registered_on_other_threads_can_be_reset() {
+        let metrics = ConcurrentTestMetrics::new();
+        std::thread::scope(|scope| {
+            for thread in 0..4u64 {
+                let mut metrics = metrics.clone();
+                scope.spawn(move || {
+                    // a series per thread, registered while the other threads update theirs
+                    let thread_tag = thread.to_string();
+                    let sessions = metrics.new_counter("sessions", &[("thread", &thread_tag)]);
+                    let latency = metrics.new_histogram_with_transform("latency", &[], Box::new(|value| value * 2));
+                    metrics.increment_counter_by(sessions, 10);
+                    metrics.decrement_counter_by(sessions, 3 + thread);
+                    metrics.record_many(latency, &[thread, thread]);
+                });
+            }
+        });
+        for thread in 0..4u64 {
+            let thread_tag = thread.to_string();
+            assert_eq!(Some(7 - thread), metrics.counter_value("sessions", &[("thread", &thread_tag)]));
+        }
+        let mut recorded = metrics.recorded_values("latency", &[]);
+        recorded.sort();
+        assert_eq!(vec![0, 0, 2, 2, 4, 4, 6, 6], recorded);
+
+        let mut handle = metrics.clone();
+        let latency = handle.new_histogram("latency", &[]);
+        handle.clear_histogram(latency);
+        assert!(metrics.recorded_values("latency", &[]).is_empty());
+        assert_eq!(Some(8), handle.snapshot().unwrap().histogram_count("latency", &[]));
+
+        metrics.reset();
+        assert_eq!(Some(0), metrics.counter_value("sessions", &[("thread", "0")]));
+        assert_eq!(Some(0), handle.snapshot().unwrap().histogram_count("latency", &[]));
+        assert_eq!(None, metrics.counter_value("sessions", &[]));
+    }
+}
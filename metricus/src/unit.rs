@@ -0,0 +1,176 @@
+//! Units of measurement for histograms enforced by the type system.
+
+use crate::{Histogram, HistogramOps, Tags};
+use std::marker::PhantomData;
+use std::time::Duration;
+
+/// Unit of measurement of the values recorded by a [TypedHistogram].
+pub trait Unit: Copy {
+    /// Raw value recorded by the histogram.
+    fn value(self) -> u64;
+}
+
+macro_rules! unit {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+        pub struct $name(pub u64);
+
+        impl Unit for $name {
+            #[inline]
+            fn value(self) -> u64 {
+                self.0
+            }
+        }
+
+        impl From<$name> for u64 {
+            #[inline]
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+    };
+}
+
+unit!(
+    /// Duration in nanoseconds.
+    Nanos
+);
+unit!(
+    /// Duration in microseconds.
+    Micros
+);
+unit!(
+    /// Duration in milliseconds.
+    Millis
+);
+unit!(
+    /// Size in bytes.
+    Bytes
+);
+
+impl From<Duration> for Nanos {
+    /// Saturates at `u64::MAX` nanoseconds.
+    #[inline]
+    fn from(duration: Duration) -> Self {
+        Nanos(duration.as_nanos().try_into().unwrap_or(u64::MAX))
+    }
+}
+
+impl From<Duration> for Micros {
+    /// Saturates at `u64::MAX` microseconds.
+    #[inline]
+    fn from(duration: Duration) -> Self {
+        Micros(duration.as_micros().try_into().unwrap_or(u64::MAX))
+    }
+}
+
+impl From<Duration> for Millis {
+    /// Saturates at `u64::MAX` milliseconds.
+    #[inline]
+    fn from(duration: Duration) -> Self {
+        Millis(duration.as_millis().try_into().unwrap_or(u64::MAX))
+    }
+}
+
+impl From<Micros> for Nanos {
+    /// Saturates at `u64::MAX` nanoseconds.
+    #[inline]
+    fn from(value: Micros) -> Self {
+        Nanos(value.0.saturating_mul(1_000))
+    }
+}
+
+impl From<Millis> for Nanos {
+    /// Saturates at `u64::MAX` nanoseconds.
+    #[inline]
+    fn from(value: Millis) -> Self {
+        Nanos(value.0.saturating_mul(1_000_000))
+    }
+}
+
+impl From<Millis> for Micros {
+    /// Saturates at `u64::MAX` microseconds.
+    #[inline]
+    fn from(value: Millis) -> Self {
+        Micros(value.0.saturating_mul(1_000))
+    }
+}
+
+/// A [Histogram] that only accepts values of a single [Unit], so that recording e.g. microseconds
+/// into a histogram of milliseconds fails to compile. Values are converted explicitly at the call site
+/// using the `From` conversions between units (e.g. `Millis::from(duration)`).
+///
+/// ## Migration
+///
+/// Replace `Histogram::new` with `TypedHistogram::<U>::new` and wrap the recorded values in the unit,
+/// e.g. `histogram.record(Millis(elapsed_ms))`. Call sites that pass plain integers will then fail to
+/// compile until they state their unit. The untyped histogram remains available through
+/// [TypedHistogram::untyped], for instance to use spans, which record nanoseconds.
+///
+/// ## Examples
+///
+/// ```no_run
+/// use metricus::{Bytes, Millis, TypedHistogram};
+/// use std::time::Duration;
+///
+/// let latency = TypedHistogram::<Millis>::new("request_latency_ms", &[]);
+/// latency.record(Millis(15));
+/// latency.record(Duration::from_micros(2500).into());
+///
+/// let size = TypedHistogram::<Bytes>::new("request_size", &[]);
+/// size.record(Bytes(512));
+/// ```
+#[derive(Debug)]
+pub struct TypedHistogram<U: Unit> {
+    histogram: Histogram,
+    _unit: PhantomData<fn(U)>,
+}
+
+impl<U: Unit> TypedHistogram<U> {
+    /// Creates a new histogram with the specified name and tags, see [Histogram::new].
+    pub fn new(name: &str, tags: Tags) -> Self {
+        Self::from_untyped(Histogram::new(name, tags))
+    }
+
+    /// Assigns a unit to an existing histogram.
+    pub fn from_untyped(histogram: Histogram) -> Self {
+        Self {
+            histogram,
+            _unit: PhantomData,
+        }
+    }
+
+    /// Records a value in the histogram's unit.
+    #[inline]
+    pub fn record(&self, value: U) {
+        self.histogram.record(value.value());
+    }
+
+    /// Underlying histogram, which records plain values.
+    pub fn untyped(&self) -> &Histogram {
+        &self.histogram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestMetrics;
+
+    #[test]
+    fn values_are_converted_between_units_before_being_recorded() {
+        let metrics = TestMetrics::install();
+        let latency = TypedHistogram::<Nanos>::new("typed_histogram_latency", &[]);
+        latency.record(Nanos(250));
+        latency.record(Micros(3).into());
+        latency.record(Millis(2).into());
+        latency.record(Duration::from_micros(1_500).into());
+        assert_eq!(vec![250, 3_000, 2_000_000, 1_500_000], metrics.recorded_values("typed_histogram_latency", &[]));
+
+        // coarser units truncate, and conversions saturate rather than overflow
+        assert_eq!(Millis(2), Duration::from_micros(2_999).into());
+        assert_eq!(Micros(u64::MAX), Millis(u64::MAX).into());
+        assert_eq!(Nanos(u64::MAX), Duration::from_secs(u64::MAX).into());
+    }
+}
@@ -0,0 +1,35 @@
+use metricus::{CallbackGauge, TestMetrics};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Callback returning the next value of `reading` each time it runs.
+fn incrementing(reading: &Arc<AtomicI64>) -> impl Fn() -> i64 + Send + Sync + 'static {
+    let reading = reading.clone();
+    move || reading.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+#[test]
+fn default_sample_interval_applies_to_gauges_without_their_own() {
+    let metrics = TestMetrics::install();
+    let reading = Arc::new(AtomicI64::new(0));
+    let default = CallbackGauge::new("default_interval", &[], incrementing(&reading));
+    let own = CallbackGauge::new("own_interval", &[], incrementing(&reading)).with_sample_interval(Duration::ZERO);
+
+    // also applies to gauges created before the default is set
+    CallbackGauge::set_default_sample_interval(Duration::from_secs(30));
+    assert_eq!(Duration::from_secs(30), default.sample_interval());
+    assert_eq!(Duration::ZERO, own.sample_interval());
+
+    let start = Instant::now();
+    assert_eq!(1, default.sample_at(start));
+    assert_eq!(1, default.sample_at(start + Duration::from_secs(29)));
+    assert_eq!(Some(1), metrics.gauge_value("default_interval", &[]));
+    assert_eq!(2, default.sample_at(start + Duration::from_secs(30)));
+    assert_eq!(Some(2), metrics.gauge_value("default_interval", &[]));
+
+    // the interval of the gauge takes precedence over the default
+    assert_eq!(3, own.sample_at(start));
+    assert_eq!(4, own.sample_at(start));
+    assert_eq!(Some(4), metrics.gauge_value("own_interval", &[]));
+}
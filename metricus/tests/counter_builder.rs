@@ -0,0 +1,25 @@
+use metricus::{Counter, CounterOps, TestMetrics};
+
+#[test]
+fn builder_registers_the_name_and_tags() {
+    let metrics = TestMetrics::install();
+    let route = String::from("/v1/orders");
+    let mut builder = Counter::builder("builder_requests")
+        .with_tag("service", "api")
+        .with_tags([("region", "eu")]);
+    builder.push_tag("route", route);
+    let tags = [("region", "eu"), ("route", "/v1/orders"), ("service", "api")];
+
+    let requests = builder.build();
+    requests.increment();
+    assert_eq!(Some(1), metrics.counter_value("builder_requests", &tags));
+
+    // building again creates another counter for the same series
+    let again = builder.try_build().unwrap();
+    again.increment_by(2);
+    assert_eq!(Some(3), metrics.counter_value("builder_requests", &tags));
+    // the order of the tags does not matter
+    let reordered = [("service", "api"), ("route", "/v1/orders"), ("region", "eu")];
+    assert_eq!(Some(3), metrics.counter_value("builder_requests", &reordered));
+    assert_eq!(None, metrics.counter_value("builder_requests", &[("service", "api")]));
+}
@@ -0,0 +1,12 @@
+use metricus::{Counter, CounterOps, TestMetrics};
+
+#[test]
+fn reset_sets_the_counter_back_to_zero() {
+    let metrics = TestMetrics::install();
+    let sessions = Counter::new("reset_sessions", &[]);
+    sessions.increment_by(5);
+    sessions.reset();
+    assert_eq!(Some(0), metrics.counter_value("reset_sessions", &[]));
+    sessions.increment();
+    assert_eq!(Some(1), metrics.counter_value("reset_sessions", &[]));
+}
@@ -0,0 +1,33 @@
+use metricus::{Histogram, HistogramOps, TestMetrics};
+
+#[test]
+fn record_cycles_records_the_raw_difference() {
+    let metrics = TestMetrics::install();
+    let histogram = Histogram::new("cycles_recorded", &[]);
+    histogram.record_cycles(1_000, 4_250);
+    histogram.record_cycles(4_250, 1_000);
+    assert_eq!(vec![3_250, 0], metrics.recorded_values("cycles_recorded", &[]));
+}
+
+#[test]
+fn span_records_cycles_rather_than_nanoseconds() {
+    let metrics = TestMetrics::install();
+    let histogram = Histogram::new("cycles_span", &[]);
+    let clock = quanta::Clock::new();
+
+    let before = clock.raw();
+    {
+        let _span = histogram.span();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let after = clock.raw();
+
+    // bounded by the cycles read around the span and, converted back to nanoseconds, covers the sleep
+    let recorded = metrics.recorded_values("cycles_span", &[]);
+    assert_eq!(1, recorded.len());
+    assert!(recorded[0] > 0 && recorded[0] <= after - before, "{} not within {}", recorded[0], after - before);
+    let elapsed_ns = clock.delta_as_nanos(before, after);
+    let cycles_per_ns = (after - before) as f64 / elapsed_ns as f64;
+    let recorded_ns = recorded[0] as f64 / cycles_per_ns;
+    assert!(recorded_ns >= 10_000_000.0, "{recorded_ns}ns is less than the time slept");
+}
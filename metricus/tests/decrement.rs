@@ -0,0 +1,13 @@
+use metricus::{Counter, CounterOps, TestMetrics};
+
+#[test]
+fn decrementing_below_zero_saturates() {
+    let metrics = TestMetrics::install();
+    let sessions = Counter::new("decrement_sessions", &[]);
+    sessions.increment_by(2);
+    sessions.decrement_by(5);
+    assert_eq!(Some(0), metrics.counter_value("decrement_sessions", &[]));
+    sessions.increment();
+    sessions.decrement();
+    assert_eq!(Some(0), metrics.counter_value("decrement_sessions", &[]));
+}
@@ -0,0 +1,25 @@
+use metricus::{Histogram, HistogramOps, TestMetrics};
+use std::cell::LazyCell;
+
+#[test]
+fn cleared_histogram_keeps_only_the_values_recorded_afterwards() {
+    let metrics = TestMetrics::install();
+    let latency = Histogram::new("clear_latency", &[]);
+    latency.record(1);
+    latency.record(2);
+    assert_eq!([1, 2], metrics.recorded_values("clear_latency", &[]).as_slice());
+
+    latency.clear();
+    assert!(metrics.recorded_values("clear_latency", &[]).is_empty());
+    latency.record(3);
+    assert_eq!([3], metrics.recorded_values("clear_latency", &[]).as_slice());
+}
+
+#[test]
+fn clear_is_forwarded_through_a_lazy_cell() {
+    let metrics = TestMetrics::install();
+    let latency = LazyCell::new(|| Histogram::new("clear_lazy_latency", &[]));
+    latency.record(5);
+    latency.clear();
+    assert!(metrics.recorded_values("clear_lazy_latency", &[]).is_empty());
+}
@@ -0,0 +1,32 @@
+use metricus::{Counter, CounterOps, TestMetrics};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier};
+use std::time::{Duration, Instant};
+
+#[test]
+fn worker_started_before_the_backend_is_set_records_into_it() {
+    let started = Arc::new(Barrier::new(2));
+    let stop = Arc::new(AtomicBool::new(false));
+    let worker = std::thread::spawn({
+        let (started, stop) = (started.clone(), stop.clone());
+        move || {
+            // bound to the no-op backend, and bound again once the worker sees the new one
+            let orders = Counter::new("late_orders", &[]);
+            started.wait();
+            while !stop.load(Ordering::Relaxed) {
+                orders.increment();
+                std::thread::yield_now();
+            }
+        }
+    });
+
+    started.wait();
+    let metrics = TestMetrics::install();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while metrics.counter_value("late_orders", &[]).unwrap_or_default() == 0 {
+        assert!(Instant::now() < deadline, "the worker never recorded into the backend set after it started");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    stop.store(true, Ordering::Relaxed);
+    worker.join().unwrap();
+}
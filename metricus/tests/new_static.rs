@@ -0,0 +1,77 @@
+use metricus::{Counter, CounterOps, Id, Metrics, Tags, set_metrics};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Counts the allocations made by the current thread.
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> u64 {
+    ALLOCATIONS.with(Cell::get)
+}
+
+/// Backend keeping nothing but a total, so registering does not allocate either.
+struct Totals(u64);
+
+impl Metrics for Totals {
+    fn name(&self) -> &'static str {
+        "totals"
+    }
+
+    fn new_counter(&mut self, _name: &str, _tags: Tags) -> Id {
+        0
+    }
+
+    fn new_counter_static(&mut self, _name: &'static str, _tags: Tags<'static>) -> Id {
+        0
+    }
+
+    fn delete_counter(&mut self, _id: Id) {}
+
+    fn increment_counter_by(&mut self, _id: Id, delta: u64) {
+        self.0 += delta;
+    }
+
+    fn new_histogram(&mut self, _name: &str, _tags: Tags) -> Id {
+        0
+    }
+
+    fn delete_histogram(&mut self, _id: Id) {}
+
+    fn record(&mut self, _id: Id, _value: u64) {}
+}
+
+#[test]
+fn creating_a_static_counter_does_not_allocate() {
+    set_metrics(Totals(0));
+
+    let before = allocations();
+    let requests = Counter::new_static("requests", &[("service", "api")]);
+    requests.increment();
+    drop(requests);
+    assert_eq!(0, allocations() - before);
+
+    // whereas the counter keeps a copy of borrowed names and tags, to register again with a new backend
+    let before = allocations();
+    let requests = Counter::new("requests", &[("service", "api")]);
+    requests.increment();
+    drop(requests);
+    assert!(allocations() > before);
+}
@@ -0,0 +1,24 @@
+use metricus::{Counter, Histogram, TestMetrics, observe_start};
+use std::time::Duration;
+
+#[test]
+fn observe_start_counts_the_call_and_records_the_span_when_dropped() {
+    let metrics = TestMetrics::install();
+    let requests = Counter::new("observe_start_requests", &[]);
+    let latency = Histogram::new("observe_start_latency", &[]);
+
+    {
+        let _span = observe_start(&requests, &latency);
+        // counted as soon as it starts, recorded only when the span ends
+        assert_eq!(Some(1), metrics.counter_value("observe_start_requests", &[]));
+        assert!(metrics.recorded_values("observe_start_latency", &[]).is_empty());
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let recorded = metrics.recorded_values("observe_start_latency", &[]);
+    assert_eq!(1, recorded.len());
+    assert!(recorded[0] >= 10_000_000, "{}ns is less than the time slept", recorded[0]);
+
+    drop(observe_start(&requests, &latency));
+    assert_eq!(Some(2), metrics.counter_value("observe_start_requests", &[]));
+    assert_eq!(2, metrics.recorded_values("observe_start_latency", &[]).len());
+}
@@ -0,0 +1,35 @@
+use metricus::{Counter, CounterOps, Histogram, HistogramOps, Id, TestMetrics, set_metrics};
+use std::sync::{Arc, Mutex};
+
+type Observed = Vec<(String, Vec<(String, String)>, Id)>;
+
+#[test]
+fn observer_sees_counter_histogram_and_rebind_registrations() {
+    let observed = Arc::new(Mutex::new(Observed::new()));
+    metricus::set_registration_observer({
+        let observed = observed.clone();
+        move |name, tags, id| {
+            let tags = tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            observed.lock().unwrap().push((name.to_owned(), tags, id));
+        }
+    });
+    let take = || std::mem::take(&mut *observed.lock().unwrap());
+    let venue = || vec![("venue".to_owned(), "lse".to_owned())];
+
+    set_metrics(TestMetrics::new());
+    let orders = Counter::new("orders", &[("venue", "lse")]);
+    let latency = Histogram::new("latency", &[]);
+    assert_eq!(vec![("orders".to_owned(), venue(), 0), ("latency".to_owned(), vec![], 1)], take());
+
+    // metric objects register again with a new backend on their next use
+    let metrics = TestMetrics::new();
+    set_metrics(metrics.clone());
+    latency.record(10);
+    orders.increment();
+    assert_eq!(vec![("latency".to_owned(), vec![], 0), ("orders".to_owned(), venue(), 1)], take());
+    assert_eq!(Some(1), metrics.counter_value("orders", &[("venue", "lse")]));
+
+    // registrations the backend already knows are observed too
+    let _orders = Counter::new("orders", &[("venue", "lse")]);
+    assert_eq!(vec![("orders".to_owned(), venue(), 1)], take());
+}
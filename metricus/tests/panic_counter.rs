@@ -0,0 +1,26 @@
+use metricus::TestMetrics;
+use std::panic::catch_unwind;
+
+fn fail() -> ! {
+    panic!("order rejected")
+}
+const FAIL_LINE: u32 = line!() - 2;
+
+#[test]
+fn panics_are_counted_per_location() {
+    let metrics = TestMetrics::install();
+    metricus::install_panic_counter("panics", &[("service", "orders")]);
+    let location = |line: u32| format!("{}:{line}", file!());
+
+    assert!(catch_unwind(|| fail()).is_err());
+    assert!(catch_unwind(|| fail()).is_err());
+    let other_line = line!() + 1;
+    assert!(catch_unwind(|| panic!("book not found")).is_err());
+
+    let fail_location = location(FAIL_LINE);
+    let tags = [("service", "orders"), ("location", fail_location.as_str())];
+    assert_eq!(Some(2), metrics.counter_value("panics", &tags));
+    let other_location = location(other_line);
+    let tags = [("service", "orders"), ("location", other_location.as_str())];
+    assert_eq!(Some(1), metrics.counter_value("panics", &tags));
+}
@@ -0,0 +1,22 @@
+use metricus::{Histogram, TestMetrics};
+use std::time::Duration;
+
+#[test]
+fn raw_span_records_the_time_until_it_is_stopped() {
+    let metrics = TestMetrics::install();
+    let started = Histogram::new("raw_span_started", &[]);
+    let stopped = Histogram::new("raw_span_stopped", &[]);
+
+    let span = started.start_span();
+    std::thread::sleep(Duration::from_millis(10));
+    assert!(metrics.recorded_values("raw_span_started", &[]).is_empty());
+
+    // stopped on another thread and into another histogram than the one that started it
+    std::thread::scope(|scope| {
+        scope.spawn(|| span.stop(&stopped));
+    });
+    assert!(metrics.recorded_values("raw_span_started", &[]).is_empty());
+    let recorded = metrics.recorded_values("raw_span_stopped", &[]);
+    assert_eq!(1, recorded.len());
+    assert!(recorded[0] >= 10_000_000, "{}ns is less than the time slept", recorded[0]);
+}
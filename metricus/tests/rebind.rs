@@ -0,0 +1,110 @@
+use metricus::{Counter, CounterOps, Histogram, HistogramOps, Id, Metrics, Tags, set_metrics};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+type Registered = Vec<(String, Vec<(String, String)>)>;
+
+/// Backend handing out ids from `base` and recording the ids it is updated with.
+struct RangeMetrics {
+    base: Id,
+    next: Id,
+    registered: Arc<Mutex<Registered>>,
+    updated: Arc<Mutex<Vec<Id>>>,
+}
+
+impl RangeMetrics {
+    fn new(base: Id) -> Self {
+        Self {
+            base,
+            next: 0,
+            registered: Arc::default(),
+            updated: Arc::default(),
+        }
+    }
+
+    fn register(&mut self, name: &str, tags: Tags) -> Id {
+        let tags = tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        self.registered.lock().unwrap().push((name.to_owned(), tags));
+        self.next += 1;
+        self.base + self.next - 1
+    }
+}
+
+impl Metrics for RangeMetrics {
+    fn name(&self) -> &'static str {
+        "range"
+    }
+
+    fn new_counter(&mut self, name: &str, tags: Tags) -> Id {
+        self.register(name, tags)
+    }
+
+    fn delete_counter(&mut self, _id: Id) {}
+
+    fn increment_counter_by(&mut self, id: Id, _delta: u64) {
+        self.updated.lock().unwrap().push(id);
+    }
+
+    fn new_histogram(&mut self, name: &str, tags: Tags) -> Id {
+        self.register(name, tags)
+    }
+
+    fn delete_histogram(&mut self, _id: Id) {}
+
+    fn record(&mut self, id: Id, _value: u64) {
+        self.updated.lock().unwrap().push(id);
+    }
+}
+
+#[test]
+fn metrics_register_again_with_each_new_backend() {
+    let counter = Counter::new("rebind_orders", &[("venue", "lse"), ("side", "buy")]);
+    let histogram = Histogram::new("rebind_latency", &[]);
+
+    let first = RangeMetrics::new(1_000);
+    let (first_registered, first_updated) = (first.registered.clone(), first.updated.clone());
+    set_metrics(first);
+    counter.increment();
+    histogram.record(1);
+    let expected: Registered = vec![
+        (
+            "rebind_orders".to_owned(),
+            vec![
+                ("venue".to_owned(), "lse".to_owned()),
+                ("side".to_owned(), "buy".to_owned()),
+            ],
+        ),
+        ("rebind_latency".to_owned(), vec![]),
+    ];
+    assert_eq!(expected, *first_registered.lock().unwrap());
+    assert_eq!(vec![1_000, 1_001], *first_updated.lock().unwrap());
+
+    // replace the backend while other threads keep updating the counter, none of them may pair the id
+    // registered with one backend with the other backend
+    let second = RangeMetrics::new(2_000);
+    let (second_registered, second_updated) = (second.registered.clone(), second.updated.clone());
+    let stop = AtomicBool::new(false);
+    thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                while !stop.load(Ordering::Relaxed) {
+                    counter.increment();
+                }
+            });
+        }
+        thread::sleep(Duration::from_millis(10));
+        set_metrics(second);
+        thread::sleep(Duration::from_millis(10));
+        stop.store(true, Ordering::Relaxed);
+    });
+    histogram.record(1);
+
+    assert!(first_updated.lock().unwrap().iter().skip(2).all(|id| *id == 1_000));
+    let second_updated = second_updated.lock().unwrap();
+    assert!(second_updated.contains(&2_000));
+    assert!(second_updated.iter().all(|id| *id == 2_000 || *id == 2_001));
+    // each metric registered exactly once with the new backend, with the same name and tags
+    assert_eq!(expected, *second_registered.lock().unwrap());
+}
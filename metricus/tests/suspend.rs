@@ -0,0 +1,38 @@
+use metricus::{Counter, CounterOps, Gauge, GaugeOps, Histogram, HistogramOps, TestMetrics};
+
+#[test]
+fn updates_while_suspended_are_dropped() {
+    let metrics = TestMetrics::install();
+    let orders = Counter::new("suspend_orders", &[]);
+    let latency = Histogram::new("suspend_latency", &[]);
+    let queue = Gauge::new("suspend_queue", &[]);
+    orders.increment_by(2);
+    latency.record(10);
+    queue.set(3);
+
+    metricus::suspend();
+    assert!(metricus::is_suspended());
+    orders.increment();
+    orders.increment_by(5);
+    orders.decrement();
+    latency.record(20);
+    drop(latency.span());
+    queue.set(7);
+    // metrics created while suspended are registered as usual
+    let fills = Counter::new("suspend_fills", &[]);
+    fills.increment();
+    metricus::resume();
+
+    assert_eq!(Some(2), metrics.counter_value("suspend_orders", &[]));
+    assert_eq!([10], metrics.recorded_values("suspend_latency", &[]).as_slice());
+    assert_eq!(Some(3), metrics.gauge_value("suspend_queue", &[]));
+    assert_eq!(Some(0), metrics.counter_value("suspend_fills", &[]));
+
+    // and recording picks up again once resumed
+    orders.increment();
+    fills.increment();
+    latency.record(30);
+    assert_eq!(Some(3), metrics.counter_value("suspend_orders", &[]));
+    assert_eq!(Some(1), metrics.counter_value("suspend_fills", &[]));
+    assert_eq!([10, 30], metrics.recorded_values("suspend_latency", &[]).as_slice());
+}
@@ -0,0 +1,102 @@
+use metricus::{Counter, CounterOps, Id, Metrics, Tags, reset_to_noop, swap_metrics};
+use std::sync::{Arc, Mutex};
+
+type Log = Arc<Mutex<Vec<String>>>;
+
+/// Backend logging every call it receives, and when it is freed, into a log shared between backends.
+struct LoggingMetrics {
+    name: &'static str,
+    log: Log,
+}
+
+impl LoggingMetrics {
+    fn new(name: &'static str, log: &Log) -> Self {
+        Self { name, log: log.clone() }
+    }
+
+    fn log(&self, call: &str) {
+        self.log.lock().unwrap().push(format!("{} {call}", self.name));
+    }
+}
+
+impl Drop for LoggingMetrics {
+    fn drop(&mut self) {
+        self.log("dropped");
+    }
+}
+
+impl Metrics for LoggingMetrics {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn new_counter(&mut self, name: &str, _tags: Tags) -> Id {
+        self.log(&format!("new_counter {name}"));
+        1
+    }
+
+    fn delete_counter(&mut self, _id: Id) {
+        self.log("delete_counter");
+    }
+
+    fn increment_counter_by(&mut self, _id: Id, _delta: u64) {
+        self.log("increment");
+    }
+
+    fn new_histogram(&mut self, _name: &str, _tags: Tags) -> Id {
+        1
+    }
+
+    fn delete_histogram(&mut self, _id: Id) {}
+
+    fn record(&mut self, _id: Id, _value: u64) {}
+}
+
+/// Serializes the tests, as each of them replaces the global backend.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+#[test]
+fn metrics_never_reach_a_retired_backend_once_it_is_freed() {
+    let _serial = SERIAL.lock().unwrap();
+    let log = Log::default();
+    drop(unsafe { swap_metrics(LoggingMetrics::new("first", &log)) });
+    let counter = Counter::new("orders", &[]);
+    let idle = Counter::new("fills", &[]);
+    counter.increment();
+
+    let retired = unsafe { swap_metrics(LoggingMetrics::new("second", &log)) }.unwrap();
+    assert_eq!("first", retired.name());
+    drop(retired);
+    // neither using nor dropping the counters calls into the freed backend
+    counter.increment();
+    drop(counter);
+    drop(idle);
+
+    let expected = [
+        "first new_counter orders",
+        "first new_counter fills",
+        "first increment",
+        "first dropped",
+        "second new_counter orders",
+        "second increment",
+        "second delete_counter",
+    ];
+    assert_eq!(expected.as_slice(), log.lock().unwrap().as_slice());
+}
+
+#[test]
+fn metrics_stop_recording_after_reset_to_noop() {
+    let _serial = SERIAL.lock().unwrap();
+    let log = Log::default();
+    drop(unsafe { swap_metrics(LoggingMetrics::new("reset", &log)) });
+    let counter = Counter::new("orders", &[]);
+    counter.increment();
+
+    reset_to_noop();
+    counter.increment();
+    drop(counter);
+
+    // the backend is not freed either, as it is not handed back
+    let expected = ["reset new_counter orders", "reset increment"];
+    assert_eq!(expected.as_slice(), log.lock().unwrap().as_slice());
+}
@@ -0,0 +1,15 @@
+use metricus::{ConcurrentTestMetrics, Counter, CounterOps, TestMetrics};
+
+#[test]
+fn backend_cannot_be_set_once_a_metric_has_been_created() {
+    let metrics = TestMetrics::new();
+    metricus::try_set_metrics(metrics.clone()).unwrap();
+    let orders = Counter::new("orders", &[]);
+    orders.increment();
+
+    let err = metricus::try_set_metrics(ConcurrentTestMetrics::new()).unwrap_err();
+    assert_eq!("metrics have already been created using the 'test' backend", err.to_string());
+    // the backend in use is kept
+    orders.increment();
+    assert_eq!(Some(2), metrics.counter_value("orders", &[]));
+}
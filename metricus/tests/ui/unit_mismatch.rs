@@ -0,0 +1,7 @@
+use metricus::{Micros, Millis, TypedHistogram};
+
+fn main() {
+    let latency = TypedHistogram::<Millis>::new("request_latency_ms", &[]);
+    latency.record(Micros(2500));
+    latency.record(15);
+}
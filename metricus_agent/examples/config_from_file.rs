@@ -2,9 +2,10 @@ use metricus_agent::MetricsAgent;
 use metricus_agent::config::MetricsConfig;
 use metricus_allocator::{CountingAllocator, enable_allocator_instrumentation};
 use metricus_macros::{counter, span};
+use std::alloc::System;
 
 #[global_allocator]
-static GLOBAL: CountingAllocator = CountingAllocator;
+static GLOBAL: CountingAllocator = CountingAllocator::new(System);
 
 #[counter(measurement = "counters", tags(key1 = "value1", key2 = "value2"))]
 fn foo() {}
@@ -1,16 +1,25 @@
 use crate::affinity::Affinity;
-use crate::config::MetricsConfig;
-use crate::exporter::Exporter;
+use crate::buffer::UpdateConsumer;
+use crate::config::{
+    CounterReporting, DEFAULT_QUANTILES, HistogramKind, MetricsConfig, OverflowPolicy, TagFilter, TimestampResolution,
+    WarmupPolicy,
+};
+use crate::exporter::{Exporter, ExporterInfo};
+use crate::stats::{self, AggregatorStats};
+#[cfg(feature = "tdigest")]
+use crate::tdigest::TDigest;
 use crate::{ControlEvent, Error, OwnedTags, UpdateEvent};
 use log::error;
-use metricus::Id;
+use metricus::{Id, MetricsSnapshot, SeriesKey, ValueTransform};
 #[cfg(feature = "rtrb")]
 use rtrb::Consumer;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::io::Write;
+use std::sync::Arc;
 #[cfg(not(feature = "rtrb"))]
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::TryRecvError;
+use std::sync::mpsc::{Receiver, SyncSender};
 use std::thread::JoinHandle;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
@@ -18,45 +27,80 @@ pub type Counters = HashMap<Id, Counter>;
 pub type Histograms = HashMap<Id, Histogram>;
 
 pub struct MetricsAggregator {
-    #[cfg(feature = "rtrb")]
-    rx_upd: Consumer<UpdateEvent>,
+    rx_reg: Receiver<UpdateConsumer>,
     #[cfg(feature = "rtrb")]
     rx_cnc: Consumer<ControlEvent>,
     #[cfg(not(feature = "rtrb"))]
-    rx_upd: Receiver<UpdateEvent>,
-    #[cfg(not(feature = "rtrb"))]
     rx_cnc: Receiver<ControlEvent>,
+    buffers: Vec<UpdateConsumer>,
     exporter: Exporter,
     counters: Counters,
     histograms: Histograms,
     next_flush_time_ns: u64,
     flush_interval_ns: u64,
+    /// End of the warmup, cleared once the warmup has elapsed.
+    warmup_end_ns: Option<u64>,
+    warmup_policy: WarmupPolicy,
+    settings: MetricSettings,
+    self_metrics: bool,
+    stats: Option<AggregatorStats>,
+    events_processed: u64,
+    pending_snapshots: Vec<SyncSender<MetricsSnapshot>>,
+    pending_counter_reads: Vec<(Id, SyncSender<Option<u64>>)>,
+    pending_flushes: Vec<SyncSender<()>>,
+    /// Set once the agent sending control events has been dropped.
+    agent_dropped: bool,
 }
 
 impl MetricsAggregator {
     pub fn new(
-        #[cfg(feature = "rtrb")] rx_upd: Consumer<UpdateEvent>,
+        rx_reg: Receiver<UpdateConsumer>,
         #[cfg(feature = "rtrb")] rx_cnc: Consumer<ControlEvent>,
-        #[cfg(not(feature = "rtrb"))] rx_upd: Receiver<UpdateEvent>,
         #[cfg(not(feature = "rtrb"))] rx_cnc: Receiver<ControlEvent>,
         exporter: Exporter,
         flush_interval: Duration,
+        settings: MetricSettings,
+        self_metrics: bool,
     ) -> Self {
         Self {
-            rx_upd,
+            rx_reg,
             rx_cnc,
+            buffers: Vec::new(),
             exporter,
             counters: Default::default(),
             histograms: Default::default(),
             flush_interval_ns: flush_interval.as_nanos() as u64,
             next_flush_time_ns: current_time_ns() + flush_interval.as_nanos() as u64,
+            warmup_end_ns: None,
+            warmup_policy: WarmupPolicy::default(),
+            settings,
+            self_metrics,
+            stats: None,
+            events_processed: 0,
+            pending_snapshots: Vec::new(),
+            pending_counter_reads: Vec::new(),
+            pending_flushes: Vec::new(),
+            agent_dropped: false,
+        }
+    }
+
+    /// Defers exporting until the `warmup` has elapsed from now, see [MetricsConfig::warmup].
+    pub fn with_warmup(self, warmup: Option<Duration>, warmup_policy: WarmupPolicy) -> Self {
+        self.with_warmup_from(current_time_ns(), warmup, warmup_policy)
+    }
+
+    /// Defers exporting until the `warmup` has elapsed from `start` (in nanoseconds since the Unix epoch).
+    fn with_warmup_from(self, start: u64, warmup: Option<Duration>, warmup_policy: WarmupPolicy) -> Self {
+        Self {
+            warmup_end_ns: warmup.map(|warmup| start + warmup.as_nanos() as u64),
+            warmup_policy,
+            ..self
         }
     }
 
     pub fn start_on_thread(
-        #[cfg(feature = "rtrb")] rx_upd: Consumer<UpdateEvent>,
+        rx_reg: Receiver<UpdateConsumer>,
         #[cfg(feature = "rtrb")] rx_cnc: Consumer<ControlEvent>,
-        #[cfg(not(feature = "rtrb"))] rx_upd: Receiver<UpdateEvent>,
         #[cfg(not(feature = "rtrb"))] rx_cnc: Receiver<ControlEvent>,
         config: MetricsConfig,
     ) -> JoinHandle<()> {
@@ -66,17 +110,34 @@ impl MetricsAggregator {
                 let affinity = Affinity::try_from(config.clone()).unwrap();
                 affinity.pin_current_thread_to_core();
 
-                let exporter = config
+                let settings = MetricSettings::from(&config);
+                let exporter: Exporter = config
                     .exporter
                     .try_into()
                     .inspect_err(|e| error!("unable to create exporter: {e}"))
                     .unwrap();
-                let mut aggregator = MetricsAggregator::new(rx_upd, rx_cnc, exporter, config.flush_interval);
+                ExporterInfo::set_active(exporter.info());
+                let mut aggregator = MetricsAggregator::new(
+                    rx_reg,
+                    rx_cnc,
+                    exporter,
+                    config.flush_interval,
+                    settings,
+                    config.self_metrics,
+                )
+                .with_warmup(config.warmup, config.warmup_policy);
                 loop {
                     aggregator
                         .poll()
                         .inspect_err(|e| error!("error when polling aggregator: {e}"))
                         .unwrap();
+                    if aggregator.agent_dropped {
+                        // publish what was recorded since the last flush before the thread exits
+                        if let Err(e) = aggregator.flush_metrics(current_time_ns()) {
+                            error!("error when flushing metrics on shutdown: {e}");
+                        }
+                        break;
+                    }
                     std::thread::sleep(Duration::from_millis(1));
                 }
             })
@@ -86,7 +147,13 @@ impl MetricsAggregator {
     #[inline]
     fn poll(&mut self) -> crate::Result<()> {
         self.process_events()?;
-        let now = current_time_ns();
+        self.tick(current_time_ns())
+    }
+
+    /// Ends the warmup and publishes if due at `now` (in nanoseconds since the Unix epoch).
+    #[inline]
+    fn tick(&mut self, now: u64) -> crate::Result<()> {
+        self.maybe_end_warmup(now);
         if now > self.next_flush_time_ns {
             self.flush_metrics(now)?;
             self.next_flush_time_ns = now + self.flush_interval_ns;
@@ -97,50 +164,149 @@ impl MetricsAggregator {
     #[cfg(feature = "rtrb")]
     #[inline]
     fn process_events(&mut self) -> crate::Result<()> {
+        // check before draining so that no events pushed before the agent was dropped are lost
+        self.agent_dropped = self.rx_cnc.is_abandoned();
         if let Ok(chunk) = self.rx_cnc.read_chunk(self.rx_cnc.slots()) {
             for event in chunk {
-                Self::handle_control_event(&mut self.counters, &mut self.histograms, event)?;
+                Self::handle_control_event(
+                    &mut self.counters,
+                    &mut self.histograms,
+                    &self.settings,
+                    &mut self.pending_snapshots,
+                    &mut self.pending_counter_reads,
+                    &mut self.pending_flushes,
+                    event,
+                )?;
             }
         }
-        if let Ok(chunk) = self.rx_upd.read_chunk(self.rx_upd.slots()) {
-            for event in chunk {
-                Self::handle_update_event(&mut self.counters, &mut self.histograms, event)?;
+        self.buffers.extend(self.rx_reg.try_iter());
+        let mut index = 0;
+        while index < self.buffers.len() {
+            // check before draining so that no events pushed prior to thread exit are lost
+            let abandoned = self.buffers[index].is_abandoned();
+            let rx_upd = &mut self.buffers[index];
+            if let Ok(chunk) = rx_upd.read_chunk(rx_upd.slots()) {
+                self.events_processed += chunk.len() as u64;
+                for event in chunk {
+                    Self::handle_update_event(&mut self.counters, &mut self.histograms, event)?;
+                }
+            }
+            if abandoned {
+                self.buffers.swap_remove(index);
+            } else {
+                index += 1;
             }
         }
-        Ok(())
+        self.send_snapshots();
+        self.acknowledge_flushes()
     }
 
     #[cfg(not(feature = "rtrb"))]
     #[inline]
     fn process_events(&mut self) -> crate::Result<()> {
-        for event in self.rx_cnc.try_iter() {
-            Self::handle_control_event(&mut self.counters, &mut self.histograms, event)?;
+        loop {
+            let event = match self.rx_cnc.try_recv() {
+                Ok(event) => event,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.agent_dropped = true;
+                    break;
+                }
+            };
+            Self::handle_control_event(
+                &mut self.counters,
+                &mut self.histograms,
+                &self.settings,
+                &mut self.pending_snapshots,
+                &mut self.pending_counter_reads,
+                &mut self.pending_flushes,
+                event,
+            )?;
         }
-        for event in self.rx_upd.try_iter() {
-            Self::handle_update_event(&mut self.counters, &mut self.histograms, event)?;
+        self.buffers.extend(self.rx_reg.try_iter());
+        let mut index = 0;
+        while index < self.buffers.len() {
+            let abandoned = loop {
+                match self.buffers[index].try_recv() {
+                    Ok(event) => {
+                        self.events_processed += 1;
+                        Self::handle_update_event(&mut self.counters, &mut self.histograms, event)?
+                    }
+                    Err(TryRecvError::Empty) => break false,
+                    Err(TryRecvError::Disconnected) => break true,
+                }
+            };
+            if abandoned {
+                self.buffers.swap_remove(index);
+            } else {
+                index += 1;
+            }
         }
-        Ok(())
+        self.send_snapshots();
+        self.acknowledge_flushes()
     }
 
     #[inline]
     fn handle_control_event(
         counters: &mut Counters,
         histograms: &mut Histograms,
+        settings: &MetricSettings,
+        snapshots: &mut Vec<SyncSender<MetricsSnapshot>>,
+        counter_reads: &mut Vec<(Id, SyncSender<Option<u64>>)>,
+        flushes: &mut Vec<SyncSender<()>>,
         event: ControlEvent,
     ) -> crate::Result<()> {
         match event {
             ControlEvent::CounterCreate(id, name, tags) => {
-                counters.entry(id).or_insert_with(|| Counter::new(name, tags));
+                counters.entry(id).or_insert_with(|| {
+                    let exporter_info = stats::is_exporter_info(&name, &tags);
+                    let mut counter = Counter::new(name, tags, settings);
+                    if exporter_info {
+                        counter.reporting = Some(CounterReporting::Cumulative);
+                    }
+                    counter
+                });
             }
             ControlEvent::CounterDelete(id) => {
                 counters.remove(&id);
             }
             ControlEvent::HistogramCreate(id, name, tags) => {
-                histograms.entry(id).or_insert_with(|| Histogram::new(name, tags));
+                histograms
+                    .entry(id)
+                    .or_insert_with(|| Histogram::new(name, tags, settings));
             }
             ControlEvent::HistogramDelete(id) => {
                 histograms.remove(&id);
             }
+            ControlEvent::HistogramTransform(id, transform) => {
+                if let Some(histogram) = histograms.get_mut(&id) {
+                    histogram.transform = Some(transform.0);
+                }
+            }
+            ControlEvent::HistogramResolution(id, resolution) => {
+                if let Some(histogram) = histograms.get_mut(&id) {
+                    histogram.resolution = resolution;
+                }
+            }
+            ControlEvent::HistogramBuckets(id, bounds) => {
+                if let Some(histogram) = histograms.get_mut(&id) {
+                    histogram.buckets = Some(Buckets::new(bounds));
+                }
+            }
+            ControlEvent::CounterRestore(id, value) => {
+                if let Some(counter) = counters.get_mut(&id) {
+                    counter.value = value;
+                    counter.previous = value;
+                }
+            }
+            ControlEvent::HistogramRestore(id, total) => {
+                if let Some(histogram) = histograms.get_mut(&id) {
+                    histogram.total = total;
+                }
+            }
+            ControlEvent::CounterRead(id, tx) => counter_reads.push((id, tx)),
+            ControlEvent::Snapshot(tx) => snapshots.push(tx),
+            ControlEvent::Flush(tx) => flushes.push(tx),
         }
         Ok(())
     }
@@ -157,9 +323,25 @@ impl MetricsAggregator {
                     counter.increment(delta);
                 }
             }
+            UpdateEvent::CounterDecrement(id, delta) => {
+                if let Some(counter) = counters.get_mut(&id) {
+                    counter.value = counter.value.saturating_sub(delta);
+                }
+            }
             UpdateEvent::HistogramRecord(id, value) => {
                 if let Some(histogram) = histograms.get_mut(&id) {
-                    histogram.inner.record(value).map_err(Error::other)?;
+                    histogram.record(value).map_err(Error::other)?;
+                }
+            }
+            UpdateEvent::HistogramClear(id) => {
+                if let Some(histogram) = histograms.get_mut(&id) {
+                    histogram.clear();
+                }
+            }
+            UpdateEvent::CounterReset(id) => {
+                if let Some(counter) = counters.get_mut(&id) {
+                    counter.value = 0;
+                    counter.previous = 0;
                 }
             }
         }
@@ -167,60 +349,543 @@ impl MetricsAggregator {
     }
 
     #[inline]
+    /// Answers snapshot and counter value requests, which is deferred until the buffers have been drained
+    /// so that the response includes the updates recorded before it was requested.
+    #[cold]
+    fn send_snapshots(&mut self) {
+        for (id, tx) in self.pending_counter_reads.drain(..) {
+            let _ = tx.try_send(self.counters.get(&id).map(|counter| counter.value));
+        }
+        if self.pending_snapshots.is_empty() {
+            return;
+        }
+        let snapshot = MetricsSnapshot {
+            counters: self
+                .counters
+                .values()
+                .map(|counter| (counter.meta_data.series_key(), counter.value))
+                .collect(),
+            histograms: self
+                .histograms
+                .values()
+                .map(|histogram| (histogram.meta_data.series_key(), histogram.total))
+                .collect(),
+        };
+        for tx in self.pending_snapshots.drain(..) {
+            let _ = tx.try_send(snapshot.clone());
+        }
+    }
+
+    /// Publishes on request, deferred like [MetricsAggregator::send_snapshots], and acknowledges
+    /// the requests once done.
+    #[cold]
+    fn acknowledge_flushes(&mut self) -> crate::Result<()> {
+        if self.pending_flushes.is_empty() {
+            return Ok(());
+        }
+        self.flush_metrics(current_time_ns())?;
+        for tx in self.pending_flushes.drain(..) {
+            let _ = tx.try_send(());
+        }
+        Ok(())
+    }
+
+    /// Applies the warmup policy once the warmup has elapsed.
+    fn maybe_end_warmup(&mut self, now: u64) {
+        match self.warmup_end_ns {
+            Some(warmup_end_ns) if now >= warmup_end_ns => {
+                self.warmup_end_ns = None;
+                if self.warmup_policy == WarmupPolicy::Reset {
+                    self.counters.iter_mut().for_each(|(_, counter)| {
+                        counter.value = 0;
+                        counter.previous = 0;
+                    });
+                    self.histograms.iter_mut().for_each(|(_, histogram)| histogram.clear());
+                }
+            }
+            _ => {}
+        }
+    }
+
     fn flush_metrics(&mut self, timestamp: u64) -> crate::Result<()> {
-        self.exporter.publish_counters(&self.counters, timestamp)?;
-        self.exporter.publish_histograms(&self.histograms, timestamp)?;
-        // clear histograms
+        // keep aggregating without exporting until the warmup has elapsed
+        if self.warmup_end_ns.is_some() {
+            return Ok(());
+        }
         self.histograms
             .iter_mut()
-            .for_each(|(_, histogram)| histogram.inner.clear());
+            .for_each(|(_, histogram)| histogram.inner.compress());
+        if self.self_metrics {
+            // created lazily so that the proxies bind to the agent rather than the no-op backend
+            let stats = self
+                .stats
+                .get_or_insert_with(|| AggregatorStats::new(&self.exporter.info()));
+            stats.events_processed(self.events_processed);
+            self.events_processed = 0;
+            {
+                let _span = stats.publish_span();
+                self.exporter.publish(&self.counters, &self.histograms, timestamp)?;
+            }
+            stats.unsent_datagrams(self.exporter.take_unsent_datagrams());
+        } else {
+            self.exporter.publish(&self.counters, &self.histograms, timestamp)?;
+        }
+        // remember exported counter values and clear histograms
+        self.counters
+            .iter_mut()
+            .for_each(|(_, counter)| counter.previous = counter.value);
+        self.histograms.iter_mut().for_each(|(_, histogram)| histogram.clear());
         Ok(())
     }
 }
 
+/// Settings applied to every metric registered with the aggregator.
+#[derive(Debug, Clone, Default)]
+pub struct MetricSettings {
+    sample_limit: Option<SampleLimit>,
+    histogram_kind: HistogramKind,
+    container_tags: OwnedTags,
+    tag_renames: HashMap<String, String>,
+    quantiles: Quantiles,
+    counter_reporting: Option<CounterReporting>,
+    timestamp_resolution: Option<TimestampResolution>,
+    filter: TagFilter,
+}
+
+impl From<&MetricsConfig> for MetricSettings {
+    fn from(config: &MetricsConfig) -> Self {
+        Self {
+            sample_limit: config.histogram_max_samples.map(|max_samples| SampleLimit {
+                max_samples,
+                policy: config.histogram_overflow_policy,
+            }),
+            histogram_kind: config.histogram_kind,
+            container_tags: config.container_tags.clone(),
+            tag_renames: config.tag_renames.clone(),
+            quantiles: config.quantiles.as_deref().map(Quantiles::new).unwrap_or_default(),
+            counter_reporting: config.counter_reporting,
+            timestamp_resolution: config.timestamp_resolution,
+            filter: config.filter.clone(),
+        }
+    }
+}
+
+/// Quantile exported by the line protocol and Prometheus encoders, with its names rendered once.
+#[derive(Debug)]
+struct Quantile {
+    value: f64,
+    /// Line protocol field name, e.g. `p999` for 0.999.
+    field: String,
+    /// Prometheus `quantile` label value, e.g. `0.999`.
+    label: String,
+}
+
+impl Quantile {
+    fn new(value: f64) -> Self {
+        let value = value.clamp(0.0, 1.0);
+        let label = value.to_string();
+        let field = match label.strip_prefix("0.") {
+            // keep at least two digits so that 0.5 is exported as p50
+            Some(digits) if digits.len() == 1 => format!("p{digits}0"),
+            Some(digits) => format!("p{digits}"),
+            None => format!("p{}", value as u64 * 100),
+        };
+        Self { value, field, label }
+    }
+}
+
+/// Quantiles shared by all histograms, see [MetricsConfig::quantiles].
+#[derive(Debug, Clone)]
+struct Quantiles(Arc<[Quantile]>);
+
+impl Quantiles {
+    fn new(values: &[f64]) -> Self {
+        Self(values.iter().copied().map(Quantile::new).collect())
+    }
+}
+
+impl Default for Quantiles {
+    fn default() -> Self {
+        Self::new(&DEFAULT_QUANTILES)
+    }
+}
+
 #[derive(Serialize)]
 pub struct Counter {
     value: u64,
+    /// Value as of the last publish.
+    #[serde(skip)]
+    previous: u64,
+    /// Overrides the representation the encoder exports by default.
+    #[serde(skip)]
+    reporting: Option<CounterReporting>,
     #[serde(flatten)]
     meta_data: MetaData,
 }
 
 impl Counter {
-    fn new(name: String, tags: OwnedTags) -> Self {
+    pub(crate) fn new(name: String, tags: OwnedTags, settings: &MetricSettings) -> Self {
         Self {
             value: 0,
-            meta_data: MetaData::new(name, tags),
+            previous: 0,
+            reporting: settings.counter_reporting,
+            meta_data: MetaData::new(name, tags, settings),
         }
     }
 
-    fn increment(&mut self, delta: u64) {
+    pub(crate) fn increment(&mut self, delta: u64) {
         self.value += delta;
     }
+
+    /// Change since the last publish, negative if the counter was decremented.
+    fn delta(&self) -> i64 {
+        self.value.wrapping_sub(self.previous) as i64
+    }
+
+    /// Whether the change since the last publish is exported rather than the running total, which is
+    /// up to the encoder (`default`) unless configured.
+    fn reports_delta(&self, default: CounterReporting) -> bool {
+        self.reporting.unwrap_or(default) == CounterReporting::Delta
+    }
+}
+
+/// Caps the number of samples a histogram accepts per flush interval.
+#[derive(Debug, Clone, Copy)]
+pub struct SampleLimit {
+    max_samples: u64,
+    policy: OverflowPolicy,
+}
+
+/// Summary of the values recorded by a histogram, see [HistogramKind].
+enum Summary {
+    Hdr(hdrhistogram::Histogram<u64>),
+    #[cfg(feature = "tdigest")]
+    TDigest(TDigest),
+}
+
+impl Summary {
+    fn new(kind: HistogramKind) -> Self {
+        match kind {
+            HistogramKind::Hdr => Summary::Hdr(hdrhistogram::Histogram::<u64>::new(3).unwrap()), // will never fail
+            #[cfg(feature = "tdigest")]
+            HistogramKind::TDigest => Summary::TDigest(TDigest::new()),
+        }
+    }
+
+    #[inline]
+    fn record_n(&mut self, value: u64, count: u64) -> Result<(), hdrhistogram::RecordError> {
+        match self {
+            Summary::Hdr(inner) => inner.record_n(value, count),
+            #[cfg(feature = "tdigest")]
+            Summary::TDigest(inner) => {
+                inner.record_n(value, count);
+                Ok(())
+            }
+        }
+    }
+
+    /// Brings the summary up to date before it is queried.
+    fn compress(&mut self) {
+        match self {
+            Summary::Hdr(_) => {}
+            #[cfg(feature = "tdigest")]
+            Summary::TDigest(inner) => inner.compress(),
+        }
+    }
+
+    fn len(&self) -> u64 {
+        match self {
+            Summary::Hdr(inner) => inner.len(),
+            #[cfg(feature = "tdigest")]
+            Summary::TDigest(inner) => inner.len(),
+        }
+    }
+
+    fn min(&self) -> u64 {
+        match self {
+            Summary::Hdr(inner) => inner.min(),
+            #[cfg(feature = "tdigest")]
+            Summary::TDigest(inner) => inner.min(),
+        }
+    }
+
+    fn max(&self) -> u64 {
+        match self {
+            Summary::Hdr(inner) => inner.max(),
+            #[cfg(feature = "tdigest")]
+            Summary::TDigest(inner) => inner.max(),
+        }
+    }
+
+    fn mean(&self) -> f64 {
+        match self {
+            Summary::Hdr(inner) => inner.mean(),
+            #[cfg(feature = "tdigest")]
+            Summary::TDigest(inner) => inner.mean(),
+        }
+    }
+
+    fn value_at_quantile(&self, quantile: f64) -> u64 {
+        match self {
+            Summary::Hdr(inner) => inner.value_at_quantile(quantile),
+            #[cfg(feature = "tdigest")]
+            Summary::TDigest(inner) => inner.value_at_quantile(quantile),
+        }
+    }
+
+    /// Visits each distinct recorded value (or centroid) along with its count.
+    fn for_each_value(&self, mut f: impl FnMut(u64, u64) -> std::io::Result<()>) -> std::io::Result<()> {
+        match self {
+            Summary::Hdr(inner) => {
+                for value in inner.iter_recorded() {
+                    f(value.value_iterated_to(), value.count_at_value())?;
+                }
+            }
+            #[cfg(feature = "tdigest")]
+            Summary::TDigest(inner) => {
+                for centroid in inner.centroids() {
+                    f(centroid.mean.round() as u64, centroid.weight)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Summary::Hdr(inner) => inner.clear(),
+            #[cfg(feature = "tdigest")]
+            Summary::TDigest(inner) => inner.clear(),
+        }
+    }
+}
+
+/// Number of values recorded in each bucket of a histogram, see [metricus::PreAllocatedMetric::Histogram].
+struct Buckets {
+    /// Inclusive upper bounds in ascending order.
+    bounds: Box<[u64]>,
+    /// Values recorded per bucket, with values above the last bound in the trailing entry.
+    counts: Box<[u64]>,
+}
+
+impl Buckets {
+    fn new(mut bounds: Vec<u64>) -> Self {
+        bounds.sort_unstable();
+        bounds.dedup();
+        let counts = vec![0; bounds.len() + 1].into_boxed_slice();
+        Self {
+            bounds: bounds.into_boxed_slice(),
+            counts,
+        }
+    }
+
+    #[inline]
+    fn record_n(&mut self, value: u64, count: u64) {
+        let index = self.bounds.partition_point(|bound| *bound < value);
+        self.counts[index] += count;
+    }
+
+    /// Visits each upper bound along with the number of values less than or equal to it, with `None`
+    /// standing for the unbounded last bucket.
+    fn for_each_cumulative(&self, mut f: impl FnMut(Option<u64>, u64) -> std::io::Result<()>) -> std::io::Result<()> {
+        let mut cumulative = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            cumulative += count;
+            f(self.bounds.get(index).copied(), cumulative)?;
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.counts.fill(0);
+    }
 }
 
 pub struct Histogram {
-    inner: hdrhistogram::Histogram<u64>,
+    inner: Summary,
     meta_data: MetaData,
+    sample_limit: Option<SampleLimit>,
+    /// Samples seen since the last clear, including the ones not recorded due to the limit.
+    seen: u64,
+    /// Samples dropped since the last clear because of the limit.
+    dropped: u64,
+    /// Current downsampling factor.
+    stride: u64,
+    /// Applied to each value before it is recorded.
+    transform: Option<ValueTransform>,
+    /// Values are rounded to the nearest multiple of this after the transform, unless it is 0 or 1.
+    resolution: u64,
+    /// Samples seen since registration, never cleared.
+    total: u64,
+    quantiles: Quantiles,
+    /// Bucket counts, if bounds were declared for the histogram.
+    buckets: Option<Buckets>,
 }
 
 impl Histogram {
-    fn new(name: String, tags: OwnedTags) -> Self {
+    pub(crate) fn new(name: String, tags: OwnedTags, settings: &MetricSettings) -> Self {
         Self {
-            inner: hdrhistogram::Histogram::<u64>::new(3).unwrap(), // will never fail
-            meta_data: MetaData::new(name, tags),
+            inner: Summary::new(settings.histogram_kind),
+            meta_data: MetaData::new(name, tags, settings),
+            sample_limit: settings.sample_limit,
+            seen: 0,
+            dropped: 0,
+            stride: 1,
+            transform: None,
+            resolution: 1,
+            total: 0,
+            quantiles: settings.quantiles.clone(),
+            buckets: None,
         }
     }
+
+    #[inline]
+    pub(crate) fn record(&mut self, value: u64) -> Result<(), hdrhistogram::RecordError> {
+        self.total += 1;
+        let value = match &self.transform {
+            Some(transform) => transform(value),
+            None => value,
+        };
+        let value = if self.resolution > 1 {
+            let rounded = value.saturating_add(self.resolution / 2) / self.resolution;
+            rounded.saturating_mul(self.resolution)
+        } else {
+            value
+        };
+        let Some(limit) = self.sample_limit else {
+            return self.record_n(value, 1);
+        };
+        self.seen += 1;
+        if self.seen <= limit.max_samples {
+            return self.record_n(value, 1);
+        }
+        match limit.policy {
+            OverflowPolicy::Drop => {
+                self.dropped += 1;
+                Ok(())
+            }
+            OverflowPolicy::Downsample => {
+                if self.seen > limit.max_samples.saturating_mul(self.stride) {
+                    self.stride = self.stride.saturating_mul(2);
+                }
+                if self.seen % self.stride == 0 {
+                    self.record_n(value, self.stride)
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn record_n(&mut self, value: u64, count: u64) -> Result<(), hdrhistogram::RecordError> {
+        if let Some(buckets) = &mut self.buckets {
+            buckets.record_n(value, count);
+        }
+        self.inner.record_n(value, count)
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear();
+        if let Some(buckets) = &mut self.buckets {
+            buckets.clear();
+        }
+        self.seen = 0;
+        self.dropped = 0;
+        self.stride = 1;
+    }
 }
 
 #[derive(Serialize)]
 struct MetaData {
     name: String,
     tags: OwnedTags,
+    /// Measurement and tags rendered once at registration as `name,k1=v1,k2=v2`. Tags are immutable
+    /// after registration so the encoders can splice this in on every publish instead of writing each
+    /// tag separately, which for metrics with several tags is the bulk of the per-line encoding work.
+    #[serde(skip)]
+    series: String,
+    /// Tags and container tags rendered once at registration as `k1:v1,k2:v2` for the DogStatsD encoder.
+    #[serde(skip)]
+    statsd_tags: String,
+    /// Name and labels rendered once at registration for the Prometheus encoder, with invalid
+    /// characters in the name and label names replaced and the label values escaped.
+    #[serde(skip)]
+    prometheus_name: String,
+    /// Labels rendered as `k1="v1",k2="v2"`, without braces so that more labels can be appended.
+    #[serde(skip)]
+    prometheus_labels: String,
+    /// Series as registered, before tag renames, used to identify the metric in snapshots.
+    #[serde(skip)]
+    key: SeriesKey,
+    /// Whether the metric passes the configured [TagFilter], metrics that do not are never encoded.
+    #[serde(skip)]
+    exported: bool,
+    /// Overrides the timestamp resolution the encoder writes by default.
+    #[serde(skip)]
+    timestamp_resolution: Option<TimestampResolution>,
 }
 
 impl MetaData {
-    fn new(name: String, tags: OwnedTags) -> Self {
-        Self { name, tags }
+    fn new(name: String, mut tags: OwnedTags, settings: &MetricSettings) -> Self {
+        let key = SeriesKey {
+            name: name.clone(),
+            // tags added by the agent rather than by whoever created the metric
+            tags: tags
+                .iter()
+                .filter(|(key, _)| key != "type" && key != "unit")
+                .cloned()
+                .collect(),
+        };
+        let exported = settings.filter.matches(&tags);
+        if !settings.tag_renames.is_empty() {
+            for (key, _) in tags.iter_mut() {
+                if let Some(renamed) = settings.tag_renames.get(key) {
+                    key.clone_from(renamed);
+                }
+            }
+            tags.sort();
+        }
+        let mut series = name.clone();
+        for tag in tags.iter() {
+            series.push(',');
+            series.push_str(&tag.0);
+            series.push('=');
+            series.push_str(&tag.1);
+        }
+        let statsd_tags = tags
+            .iter()
+            .chain(settings.container_tags.iter())
+            .map(|(k, v)| format!("{k}:{v}"))
+            .collect::<Vec<_>>()
+            .join(",");
+        let prometheus_name = Prometheus::sanitize(&name, true);
+        let prometheus_labels = tags
+            .iter()
+            .map(|(k, v)| format!("{}=\"{}\"", Prometheus::sanitize(k, false), Prometheus::escape(v)))
+            .collect::<Vec<_>>()
+            .join(",");
+        Self {
+            name,
+            tags,
+            series,
+            statsd_tags,
+            prometheus_name,
+            prometheus_labels,
+            key,
+            exported,
+            timestamp_resolution: settings.timestamp_resolution,
+        }
+    }
+
+    fn series_key(&self) -> SeriesKey {
+        self.key.clone()
+    }
+
+    /// Converts a timestamp in nanoseconds to the configured resolution, or to the resolution the
+    /// encoder writes by default (`default`) unless configured.
+    fn timestamp(&self, timestamp_ns: u64, default: TimestampResolution) -> u64 {
+        self.timestamp_resolution.unwrap_or(default).convert(timestamp_ns)
     }
 }
 
@@ -228,21 +893,80 @@ impl MetaData {
 #[serde(rename_all = "snake_case")]
 pub enum Encoder {
     LineProtocol,
+    /// Newline-delimited JSON, one object per metric. Counters are encoded as
+    /// `{"timestamp":..,"value":..,"name":..,"tags":[["k","v"],..]}` and histograms as
+    /// `{"timestamp":..,"name":..,"tags":[["k","v"],..],"count":..,"min":..,"max":..,"mean":..}` followed
+    /// by a field per quantile named as in the line protocol (e.g. `"p99"`), `"dropped_samples"` when
+    /// samples were dropped because of the sample limit, and `"buckets":[[bound,count],..]` with the
+    /// number of values up to each bound for histograms with declared buckets (see
+    /// [metricus::PreAllocatedMetric::histogram_with_buckets]). Timestamps are in nanoseconds (unless
+    /// configured otherwise with [MetricsConfig::timestamp_resolution]).
     Json,
+    /// DogStatsD datagrams. Counters are sent as `|c` with the increase since the last publish (unless
+    /// configured otherwise with [MetricsConfig::counter_reporting]) and histograms as `|d` distributions,
+    /// so that Datadog computes percentiles globally across hosts rather than per host. Each recorded
+    /// value is sent once with a sample rate standing for its count.
+    /// Distributions require Datadog Agent 6 or newer.
+    DogStatsd,
+    /// Prometheus text exposition format. Counters are sent with their cumulative value (unless configured
+    /// otherwise with [MetricsConfig::counter_reporting]) and histograms as summaries, i.e. a line per
+    /// quantile along with `_sum` and `_count` lines, or for histograms with declared buckets (see
+    /// [metricus::PreAllocatedMetric::histogram_with_buckets]) a cumulative `_bucket` line per bound
+    /// instead of the quantiles. Timestamps are in milliseconds (unless configured
+    /// otherwise with [MetricsConfig::timestamp_resolution]). `# TYPE` lines are not emitted, as the same
+    /// metric name can occur with different tags in a single publish.
+    Prometheus,
+    /// StatsD datagrams. Counters are sent as `|c` with the increase since the last publish (unless
+    /// configured otherwise with [MetricsConfig::counter_reporting]) and histograms as `|h` with each
+    /// recorded value sent once, with a sample rate standing for its count.
+    /// Tags are sent in the DogStatsD `|#k:v` format, unless `plain` is set for StatsD servers that do
+    /// not support tags, e.g. `encoder: !statsd {}` with tags and `encoder: !statsd { plain: true }`
+    /// without.
+    Statsd {
+        #[serde(default)]
+        plain: bool,
+    },
 }
 
 impl Encoder {
+    /// Name of the encoder as used in the config.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoder::LineProtocol => "line_protocol",
+            Encoder::Json => "json",
+            Encoder::DogStatsd => "dog_statsd",
+            Encoder::Prometheus => "prometheus",
+            Encoder::Statsd { .. } => "statsd",
+        }
+    }
+
+    /// Encodes the counter into `dst`, or nothing if it is filtered out, see [TagFilter]. The `timestamp` is
+    /// in nanoseconds since the Unix epoch, see [MetricsConfig::timestamp_resolution] for what is written.
     pub fn encode_counter(&self, counter: &Counter, timestamp: u64, dst: &mut impl Write) -> std::io::Result<()> {
+        if !counter.meta_data.exported {
+            return Ok(());
+        }
         match self {
             Encoder::LineProtocol => LineProtocol::encode_counter(counter, timestamp, dst),
             Encoder::Json => Json::encode_counter(counter, timestamp, dst),
+            Encoder::DogStatsd => Statsd::DOG_STATSD.encode_counter(counter, dst),
+            Encoder::Prometheus => Prometheus::encode_counter(counter, timestamp, dst),
+            Encoder::Statsd { plain } => Statsd::new(*plain).encode_counter(counter, dst),
         }
     }
 
+    /// Encodes the histogram into `dst`, or nothing if it is filtered out, see [TagFilter]. The `timestamp`
+    /// is in nanoseconds since the Unix epoch, as for [Encoder::encode_counter].
     pub fn encode_histogram(&self, histogram: &Histogram, timestamp: u64, dst: &mut impl Write) -> std::io::Result<()> {
+        if !histogram.meta_data.exported {
+            return Ok(());
+        }
         match self {
             Encoder::LineProtocol => LineProtocol::encode_histogram(histogram, timestamp, dst),
-            Encoder::Json => Ok(()),
+            Encoder::Json => Json::encode_histogram(histogram, timestamp, dst),
+            Encoder::DogStatsd => Statsd::DOG_STATSD.encode_histogram(histogram, dst),
+            Encoder::Prometheus => Prometheus::encode_histogram(histogram, timestamp, dst),
+            Encoder::Statsd { plain } => Statsd::new(*plain).encode_histogram(histogram, dst),
         }
     }
 }
@@ -251,20 +975,19 @@ struct LineProtocol;
 
 impl LineProtocol {
     fn encode_counter(counter: &Counter, timestamp: u64, dst: &mut impl Write) -> std::io::Result<()> {
-        // measurement
-        dst.write_all(counter.meta_data.name.as_bytes())?;
-        // tags
-        for tag in counter.meta_data.tags.iter() {
-            dst.write_all(b",")?;
-            dst.write_all(tag.0.as_bytes())?;
-            dst.write_all(b"=")?;
-            dst.write_all(tag.1.as_bytes())?;
-        }
+        // measurement and tags
+        dst.write_all(counter.meta_data.series.as_bytes())?;
         // field
         dst.write_all(b" value=")?;
-        dst.write_all(itoa::Buffer::new().format(counter.value).as_bytes())?;
-        dst.write_all(b"u ")?;
+        if counter.reports_delta(CounterReporting::Cumulative) {
+            dst.write_all(itoa::Buffer::new().format(counter.delta()).as_bytes())?;
+            dst.write_all(b"i ")?;
+        } else {
+            dst.write_all(itoa::Buffer::new().format(counter.value).as_bytes())?;
+            dst.write_all(b"u ")?;
+        }
         // timestamp
+        let timestamp = counter.meta_data.timestamp(timestamp, TimestampResolution::Nanos);
         dst.write_all(itoa::Buffer::new().format(timestamp).as_bytes())?;
         // new line
         dst.write_all(b"\n")?;
@@ -272,15 +995,8 @@ impl LineProtocol {
     }
 
     fn encode_histogram(histogram: &Histogram, timestamp: u64, dst: &mut impl Write) -> std::io::Result<()> {
-        // measurement
-        dst.write_all(histogram.meta_data.name.as_bytes())?;
-        // tags
-        for tag in histogram.meta_data.tags.iter() {
-            dst.write_all(b",")?;
-            dst.write_all(tag.0.as_bytes())?;
-            dst.write_all(b"=")?;
-            dst.write_all(tag.1.as_bytes())?;
-        }
+        // measurement and tags
+        dst.write_all(histogram.meta_data.series.as_bytes())?;
         // fields
         dst.write_all(b" count=")?;
         dst.write_all(itoa::Buffer::new().format(histogram.inner.len()).as_bytes())?;
@@ -290,50 +1006,48 @@ impl LineProtocol {
         dst.write_all(itoa::Buffer::new().format(histogram.inner.max()).as_bytes())?;
         dst.write_all(b"u,mean=")?;
         dst.write_all(dtoa::Buffer::new().format(histogram.inner.mean()).as_bytes())?;
-        dst.write_all(b",p50=")?;
-        dst.write_all(
-            itoa::Buffer::new()
-                .format(histogram.inner.value_at_quantile(0.50))
-                .as_bytes(),
-        )?;
-        dst.write_all(b"u,p75=")?;
-        dst.write_all(
-            itoa::Buffer::new()
-                .format(histogram.inner.value_at_quantile(0.75))
-                .as_bytes(),
-        )?;
-        dst.write_all(b"u,p90=")?;
-        dst.write_all(
-            itoa::Buffer::new()
-                .format(histogram.inner.value_at_quantile(0.90))
-                .as_bytes(),
-        )?;
-        dst.write_all(b"u,p95=")?;
-        dst.write_all(
-            itoa::Buffer::new()
-                .format(histogram.inner.value_at_quantile(0.95))
-                .as_bytes(),
-        )?;
-        dst.write_all(b"u,p99=")?;
-        dst.write_all(
-            itoa::Buffer::new()
-                .format(histogram.inner.value_at_quantile(0.99))
-                .as_bytes(),
-        )?;
-        dst.write_all(b"u,p999=")?;
-        dst.write_all(
-            itoa::Buffer::new()
-                .format(histogram.inner.value_at_quantile(0.999))
-                .as_bytes(),
-        )?;
-        dst.write_all(b"u,p9999=")?;
-        dst.write_all(
-            itoa::Buffer::new()
-                .format(histogram.inner.value_at_quantile(0.9999))
-                .as_bytes(),
-        )?;
-        dst.write_all(b"u ")?;
+        for quantile in histogram.quantiles.0.iter() {
+            dst.write_all(b",")?;
+            dst.write_all(quantile.field.as_bytes())?;
+            dst.write_all(b"=")?;
+            dst.write_all(
+                itoa::Buffer::new()
+                    .format(histogram.inner.value_at_quantile(quantile.value))
+                    .as_bytes(),
+            )?;
+            dst.write_all(b"u")?;
+        }
+        if histogram.dropped > 0 {
+            dst.write_all(b",dropped_samples=")?;
+            dst.write_all(itoa::Buffer::new().format(histogram.dropped).as_bytes())?;
+            dst.write_all(b"u")?;
+        }
+        if let Some(buckets) = &histogram.buckets {
+            buckets.for_each_cumulative(|bound, count| {
+                let Some(bound) = bound else { return Ok(()) };
+                dst.write_all(b",le_")?;
+                dst.write_all(itoa::Buffer::new().format(bound).as_bytes())?;
+                dst.write_all(b"=")?;
+                dst.write_all(itoa::Buffer::new().format(count).as_bytes())?;
+                dst.write_all(b"u")
+            })?;
+        }
+        #[cfg(feature = "tdigest")]
+        if let Summary::TDigest(inner) = &histogram.inner {
+            dst.write_all(b",centroids=\"")?;
+            for (i, centroid) in inner.centroids().iter().enumerate() {
+                if i > 0 {
+                    dst.write_all(b" ")?;
+                }
+                dst.write_all(dtoa::Buffer::new().format(centroid.mean).as_bytes())?;
+                dst.write_all(b":")?;
+                dst.write_all(itoa::Buffer::new().format(centroid.weight).as_bytes())?;
+            }
+            dst.write_all(b"\"")?;
+        }
+        dst.write_all(b" ")?;
         // timestamp
+        let timestamp = histogram.meta_data.timestamp(timestamp, TimestampResolution::Nanos);
         dst.write_all(itoa::Buffer::new().format(timestamp).as_bytes())?;
         // new line
         dst.write_all(b"\n")?;
@@ -341,20 +1055,256 @@ impl LineProtocol {
     }
 }
 
+/// StatsD datagrams as sent by [Encoder::DogStatsd] and [Encoder::Statsd], which only differ in the type
+/// histograms are sent as and in whether tags are sent.
+struct Statsd {
+    histogram_type: &'static [u8],
+    tags: bool,
+}
+
+impl Statsd {
+    const DOG_STATSD: Statsd = Statsd {
+        histogram_type: b"|d",
+        tags: true,
+    };
+
+    const fn new(plain: bool) -> Self {
+        Statsd {
+            histogram_type: b"|h",
+            tags: !plain,
+        }
+    }
+
+    fn encode_counter(&self, counter: &Counter, dst: &mut impl Write) -> std::io::Result<()> {
+        dst.write_all(counter.meta_data.name.as_bytes())?;
+        dst.write_all(b":")?;
+        write_counter_value(counter, CounterReporting::Delta, dst)?;
+        dst.write_all(b"|c")?;
+        self.encode_tags(&counter.meta_data, dst)?;
+        dst.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn encode_histogram(&self, histogram: &Histogram, dst: &mut impl Write) -> std::io::Result<()> {
+        histogram.inner.for_each_value(|value, count| {
+            dst.write_all(histogram.meta_data.name.as_bytes())?;
+            dst.write_all(b":")?;
+            dst.write_all(itoa::Buffer::new().format(value).as_bytes())?;
+            dst.write_all(self.histogram_type)?;
+            if count > 1 {
+                // the sample rate makes the server count this value `count` times
+                dst.write_all(b"|@")?;
+                dst.write_all(dtoa::Buffer::new().format(1.0 / count as f64).as_bytes())?;
+            }
+            self.encode_tags(&histogram.meta_data, dst)?;
+            dst.write_all(b"\n")?;
+            Ok(())
+        })
+    }
+
+    fn encode_tags(&self, meta_data: &MetaData, dst: &mut impl Write) -> std::io::Result<()> {
+        if self.tags && !meta_data.statsd_tags.is_empty() {
+            dst.write_all(b"|#")?;
+            dst.write_all(meta_data.statsd_tags.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+struct Prometheus;
+
+impl Prometheus {
+    fn encode_counter(counter: &Counter, timestamp: u64, dst: &mut impl Write) -> std::io::Result<()> {
+        Self::encode_series(&counter.meta_data, "", None, dst)?;
+        dst.write_all(b" ")?;
+        write_counter_value(counter, CounterReporting::Cumulative, dst)?;
+        let timestamp = counter.meta_data.timestamp(timestamp, TimestampResolution::Millis);
+        Self::encode_timestamp(timestamp, dst)
+    }
+
+    fn encode_histogram(histogram: &Histogram, timestamp: u64, dst: &mut impl Write) -> std::io::Result<()> {
+        let meta_data = &histogram.meta_data;
+        let timestamp = meta_data.timestamp(timestamp, TimestampResolution::Millis);
+        if let Some(buckets) = &histogram.buckets {
+            buckets.for_each_cumulative(|bound, count| {
+                let mut buffer = itoa::Buffer::new();
+                let bound = bound.map_or("+Inf", |bound| buffer.format(bound));
+                Self::encode_series(meta_data, "_bucket", Some(("le", bound)), dst)?;
+                dst.write_all(b" ")?;
+                dst.write_all(itoa::Buffer::new().format(count).as_bytes())?;
+                Self::encode_timestamp(timestamp, dst)
+            })?;
+        } else {
+            for quantile in histogram.quantiles.0.iter() {
+                Self::encode_series(meta_data, "", Some(("quantile", &quantile.label)), dst)?;
+                dst.write_all(b" ")?;
+                dst.write_all(
+                    itoa::Buffer::new()
+                        .format(histogram.inner.value_at_quantile(quantile.value))
+                        .as_bytes(),
+                )?;
+                Self::encode_timestamp(timestamp, dst)?;
+            }
+        }
+        let count = histogram.inner.len();
+        Self::encode_series(meta_data, "_sum", None, dst)?;
+        dst.write_all(b" ")?;
+        dst.write_all(
+            dtoa::Buffer::new()
+                .format(histogram.inner.mean() * count as f64)
+                .as_bytes(),
+        )?;
+        Self::encode_timestamp(timestamp, dst)?;
+        Self::encode_series(meta_data, "_count", None, dst)?;
+        dst.write_all(b" ")?;
+        dst.write_all(itoa::Buffer::new().format(count).as_bytes())?;
+        Self::encode_timestamp(timestamp, dst)
+    }
+
+    /// Writes the metric name with `suffix` and its labels, followed by the extra `label` (e.g. the
+    /// `quantile` or the `le` bucket bound) if any.
+    fn encode_series(
+        meta_data: &MetaData,
+        suffix: &str,
+        label: Option<(&str, &str)>,
+        dst: &mut impl Write,
+    ) -> std::io::Result<()> {
+        dst.write_all(meta_data.prometheus_name.as_bytes())?;
+        dst.write_all(suffix.as_bytes())?;
+        if meta_data.prometheus_labels.is_empty() && label.is_none() {
+            return Ok(());
+        }
+        dst.write_all(b"{")?;
+        dst.write_all(meta_data.prometheus_labels.as_bytes())?;
+        if let Some((name, value)) = label {
+            if !meta_data.prometheus_labels.is_empty() {
+                dst.write_all(b",")?;
+            }
+            dst.write_all(name.as_bytes())?;
+            dst.write_all(b"=\"")?;
+            dst.write_all(value.as_bytes())?;
+            dst.write_all(b"\"")?;
+        }
+        dst.write_all(b"}")
+    }
+
+    fn encode_timestamp(timestamp: u64, dst: &mut impl Write) -> std::io::Result<()> {
+        dst.write_all(b" ")?;
+        dst.write_all(itoa::Buffer::new().format(timestamp).as_bytes())?;
+        dst.write_all(b"\n")
+    }
+
+    /// Replaces characters that are not allowed in metric names (or label names, which do not allow
+    /// colons) with underscores.
+    fn sanitize(name: &str, metric_name: bool) -> String {
+        let mut sanitized: String = name
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => c,
+                ':' if metric_name => c,
+                _ => '_',
+            })
+            .collect();
+        if sanitized.is_empty() || sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+            sanitized.insert(0, '_');
+        }
+        sanitized
+    }
+
+    /// Escapes backslashes, double quotes and line feeds in label values.
+    fn escape(value: &str) -> String {
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '"' => escaped.push_str("\\\""),
+                '\n' => escaped.push_str("\\n"),
+                _ => escaped.push(c),
+            }
+        }
+        escaped
+    }
+}
+
 struct Json;
 
 impl Json {
     fn encode_counter(counter: &Counter, timestamp: u64, dst: &mut impl Write) -> std::io::Result<()> {
+        let timestamp = counter.meta_data.timestamp(timestamp, TimestampResolution::Nanos);
         serde_json::to_writer(&mut *dst, &CounterWithTimestamp::new(counter, timestamp))
             .map_err(std::io::Error::other)
             .and_then(|_| dst.write_all(b"\n"))
     }
+
+    fn encode_histogram(histogram: &Histogram, timestamp: u64, dst: &mut impl Write) -> std::io::Result<()> {
+        let timestamp = histogram.meta_data.timestamp(timestamp, TimestampResolution::Nanos);
+        serde_json::to_writer(&mut *dst, &HistogramWithTimestamp { timestamp, histogram })
+            .map_err(std::io::Error::other)
+            .and_then(|_| dst.write_all(b"\n"))
+    }
 }
 
-#[derive(Serialize)]
+/// Compact human-readable summary of the largest counters and busiest histograms, one line per kind.
+pub struct LogSummary;
+
+impl LogSummary {
+    /// Top `top_n` counters by value as `series value (change since last publish)`.
+    pub fn counters(counters: &Counters, top_n: usize) -> Option<String> {
+        let mut top: Vec<_> = counters.values().filter(|counter| counter.meta_data.exported).collect();
+        top.sort_unstable_by(|a, b| {
+            b.value
+                .cmp(&a.value)
+                .then_with(|| a.meta_data.series.cmp(&b.meta_data.series))
+        });
+        let total = top.len();
+        top.truncate(top_n);
+        let summary = top
+            .iter()
+            .map(|counter| format!("{} {} ({:+})", counter.meta_data.series, counter.value, counter.delta()))
+            .collect::<Vec<_>>()
+            .join("; ");
+        (!summary.is_empty()).then(|| format!("{} counters, top {}: {}", total, top.len(), summary))
+    }
+
+    /// Top `top_n` histograms by number of samples recorded during the interval, histograms without
+    /// samples are left out.
+    pub fn histograms(histograms: &Histograms, top_n: usize) -> Option<String> {
+        let total = histograms
+            .values()
+            .filter(|histogram| histogram.meta_data.exported)
+            .count();
+        let mut top: Vec<_> = histograms
+            .values()
+            .filter(|histogram| histogram.meta_data.exported && histogram.inner.len() > 0)
+            .collect();
+        top.sort_unstable_by(|a, b| {
+            b.inner
+                .len()
+                .cmp(&a.inner.len())
+                .then_with(|| a.meta_data.series.cmp(&b.meta_data.series))
+        });
+        top.truncate(top_n);
+        let summary = top
+            .iter()
+            .map(|histogram| {
+                format!(
+                    "{} count={} p50={} p99={} max={}",
+                    histogram.meta_data.series,
+                    histogram.inner.len(),
+                    histogram.inner.value_at_quantile(0.5),
+                    histogram.inner.value_at_quantile(0.99),
+                    histogram.inner.max()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+        (!summary.is_empty()).then(|| format!("{} histograms, top {}: {}", total, top.len(), summary))
+    }
+}
+
+/// Counter as encoded by [Json], with the value in the configured representation.
 struct CounterWithTimestamp<'a> {
     timestamp: u64,
-    #[serde(flatten)]
     counter: &'a Counter,
 }
 
@@ -364,6 +1314,668 @@ impl<'a> CounterWithTimestamp<'a> {
     }
 }
 
+impl Serialize for CounterWithTimestamp<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let counter = self.counter;
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("timestamp", &self.timestamp)?;
+        if counter.reports_delta(CounterReporting::Cumulative) {
+            map.serialize_entry("value", &counter.delta())?;
+        } else {
+            map.serialize_entry("value", &counter.value)?;
+        }
+        map.serialize_entry("name", &counter.meta_data.name)?;
+        map.serialize_entry("tags", &counter.meta_data.tags)?;
+        map.end()
+    }
+}
+
+/// Writes the running total or the change since the last publish, see [Counter::reports_delta].
+fn write_counter_value(counter: &Counter, default: CounterReporting, dst: &mut impl Write) -> std::io::Result<()> {
+    if counter.reports_delta(default) {
+        dst.write_all(itoa::Buffer::new().format(counter.delta()).as_bytes())
+    } else {
+        dst.write_all(itoa::Buffer::new().format(counter.value).as_bytes())
+    }
+}
+
+/// Summary of a histogram as encoded by [Json], with a field per configured quantile.
+struct HistogramWithTimestamp<'a> {
+    timestamp: u64,
+    histogram: &'a Histogram,
+}
+
+impl Serialize for HistogramWithTimestamp<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let histogram = self.histogram;
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("timestamp", &self.timestamp)?;
+        map.serialize_entry("name", &histogram.meta_data.name)?;
+        map.serialize_entry("tags", &histogram.meta_data.tags)?;
+        map.serialize_entry("count", &histogram.inner.len())?;
+        map.serialize_entry("min", &histogram.inner.min())?;
+        map.serialize_entry("max", &histogram.inner.max())?;
+        map.serialize_entry("mean", &histogram.inner.mean())?;
+        for quantile in histogram.quantiles.0.iter() {
+            map.serialize_entry(&quantile.field, &histogram.inner.value_at_quantile(quantile.value))?;
+        }
+        if histogram.dropped > 0 {
+            map.serialize_entry("dropped_samples", &histogram.dropped)?;
+        }
+        if let Some(buckets) = &histogram.buckets {
+            let mut cumulative = Vec::with_capacity(buckets.bounds.len());
+            let _ = buckets.for_each_cumulative(|bound, count| {
+                cumulative.extend(bound.map(|bound| (bound, count)));
+                Ok(())
+            });
+            map.serialize_entry("buckets", &cumulative)?;
+        }
+        map.end()
+    }
+}
+
 fn current_time_ns() -> u64 {
     SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::BufferRegistry;
+    use crate::config::{ExporterSource, FileConfig};
+
+    const SECOND: u64 = 1_000_000_000;
+
+    /// Aggregator publishing every second into a file in the temp directory, along with the file's path.
+    /// Time only passes when the test calls [MetricsAggregator::tick].
+    fn aggregator(name: &str, encoder: Encoder, settings: MetricSettings) -> (MetricsAggregator, String) {
+        let path = std::env::temp_dir().join(format!("metricus_{name}_{}.txt", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let exporter = Exporter::try_from(ExporterSource::File(FileConfig {
+            path: path.clone(),
+            encoder,
+            sync_on_publish: false,
+            compression: None,
+        }))
+        .unwrap();
+        let (_, rx_reg) = std::sync::mpsc::channel();
+        #[cfg(feature = "rtrb")]
+        let (_, rx_cnc) = rtrb::RingBuffer::new(16);
+        #[cfg(not(feature = "rtrb"))]
+        let (_, rx_cnc) = std::sync::mpsc::sync_channel(16);
+        let aggregator = MetricsAggregator::new(rx_reg, rx_cnc, exporter, Duration::from_secs(1), settings, false);
+        (aggregator, path)
+    }
+
+    /// Aggregator draining the buffers created by the returned registry, each holding up to `capacity`
+    /// events.
+    fn aggregator_with_buffers(name: &str, capacity: usize) -> (MetricsAggregator, BufferRegistry) {
+        let (mut aggregator, _) = aggregator(name, Encoder::LineProtocol, MetricSettings::default());
+        let (tx_reg, rx_reg) = std::sync::mpsc::channel();
+        aggregator.rx_reg = rx_reg;
+        (aggregator, BufferRegistry::new(capacity, tx_reg))
+    }
+
+    /// Lines published so far, without the timestamps.
+    fn published(path: &str) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| line.rsplit_once(' ').map_or(line, |(line, _)| line).to_owned())
+            .collect()
+    }
+
+    fn create_counter(aggregator: &mut MetricsAggregator, id: Id, name: &str) {
+        let counter = Counter::new(name.to_owned(), vec![], &aggregator.settings);
+        aggregator.counters.insert(id, counter);
+    }
+
+    fn increment(aggregator: &mut MetricsAggregator, id: Id, delta: u64) {
+        let event = UpdateEvent::CounterIncrement(id, delta);
+        MetricsAggregator::handle_update_event(&mut aggregator.counters, &mut aggregator.histograms, event).unwrap();
+    }
+
+    #[test]
+    fn pending_events_are_drained_and_the_buffer_deregistered_on_thread_exit() {
+        let (mut aggregator, buffers) = aggregator_with_buffers("thread_exit", 16);
+        create_counter(&mut aggregator, 1, "orders");
+        // the buffer is dropped along with the thread's locals before `join` returns
+        std::thread::spawn(move || {
+            for _ in 0..3 {
+                buffers.push(UpdateEvent::CounterIncrement(1, 1));
+            }
+        })
+        .join()
+        .unwrap();
+
+        aggregator.process_events().unwrap();
+        assert_eq!(3, aggregator.counters[&1].value);
+        assert!(aggregator.buffers.is_empty());
+    }
+
+    #[test]
+    fn events_are_dropped_while_the_buffer_is_full() {
+        let (mut aggregator, buffers) = aggregator_with_buffers("full_buffer", 4);
+        create_counter(&mut aggregator, 1, "orders");
+        for _ in 0..10 {
+            buffers.push(UpdateEvent::CounterIncrement(1, 1));
+        }
+        aggregator.process_events().unwrap();
+        assert_eq!(4, aggregator.counters[&1].value);
+        assert_eq!(1, aggregator.buffers.len());
+
+        // events are accepted again once the aggregator has caught up
+        buffers.push(UpdateEvent::CounterIncrement(1, 1));
+        aggregator.process_events().unwrap();
+        assert_eq!(5, aggregator.counters[&1].value);
+    }
+
+    /// Nanosecond timestamp the encoding tests publish at, 1_700_000_000_000 in milliseconds.
+    const TIMESTAMP: u64 = 1_700_000_000 * SECOND;
+
+    fn tags(tags: &[(&str, &str)]) -> OwnedTags {
+        tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn settings_with_quantiles(quantiles: &[f64]) -> MetricSettings {
+        MetricSettings {
+            quantiles: Quantiles::new(quantiles),
+            ..MetricSettings::default()
+        }
+    }
+
+    fn encode_counter(encoder: &Encoder, counter: &Counter) -> String {
+        let mut dst = Vec::new();
+        encoder.encode_counter(counter, TIMESTAMP, &mut dst).unwrap();
+        String::from_utf8(dst).unwrap()
+    }
+
+    fn encode_histogram(encoder: &Encoder, histogram: &Histogram) -> String {
+        let mut dst = Vec::new();
+        encoder.encode_histogram(histogram, TIMESTAMP, &mut dst).unwrap();
+        String::from_utf8(dst).unwrap()
+    }
+
+    #[test]
+    fn line_protocol_splices_the_series_rendered_at_registration() {
+        let order_tags = tags(&[("side", "buy"), ("venue", "lse")]);
+        let mut counter = Counter::new("orders".to_owned(), order_tags, &MetricSettings::default());
+        assert_eq!("orders,side=buy,venue=lse", counter.meta_data.series);
+        assert_eq!("side:buy,venue:lse", counter.meta_data.statsd_tags);
+        assert_eq!("side=\"buy\",venue=\"lse\"", counter.meta_data.prometheus_labels);
+
+        // only the value and the timestamp change from one publish to the next
+        counter.increment(3);
+        let expected = "orders,side=buy,venue=lse value=3u 1700000000000000000\n";
+        assert_eq!(expected, encode_counter(&Encoder::LineProtocol, &counter));
+        counter.increment(2);
+        let expected = "orders,side=buy,venue=lse value=5u 1700000000000000000\n";
+        assert_eq!(expected, encode_counter(&Encoder::LineProtocol, &counter));
+
+        let settings = settings_with_quantiles(&[0.5]);
+        let mut histogram = Histogram::new("latency".to_owned(), tags(&[("venue", "lse")]), &settings);
+        for value in [10, 20, 30] {
+            histogram.record(value).unwrap();
+        }
+        let expected = "latency,venue=lse count=3u,min=10u,max=30u,mean=20.0,p50=20u 1700000000000000000\n";
+        assert_eq!(expected, encode_histogram(&Encoder::LineProtocol, &histogram));
+    }
+
+    #[test]
+    fn hdr_quantiles_are_exported_as_line_protocol_fields() {
+        let settings = settings_with_quantiles(&[0.5, 0.9, 0.99, 0.999, 1.0]);
+        let mut histogram = Histogram::new("latency".to_owned(), vec![], &settings);
+        // values below 2048 are recorded exactly with the default precision
+        for value in 1..=1_000 {
+            histogram.record(value).unwrap();
+        }
+        let expected = "latency count=1000u,min=1u,max=1000u,mean=500.5,\
+            p50=500u,p90=900u,p99=990u,p999=999u,p100=1000u 1700000000000000000\n";
+        assert_eq!(expected, encode_histogram(&Encoder::LineProtocol, &histogram));
+    }
+
+    #[test]
+    fn prometheus_counter_matches_fixture() {
+        let tags = tags(&[("note", "line\nbreak"), ("path", "C:\\orders"), ("venue", "\"lse\"")]);
+        let mut counter = Counter::new("orders.filled".to_owned(), tags, &MetricSettings::default());
+        counter.increment(3);
+        let expected =
+            concat!(r#"orders_filled{note="line\nbreak",path="C:\\orders",venue="\"lse\""} 3 1700000000000"#, "\n");
+        assert_eq!(expected, encode_counter(&Encoder::Prometheus, &counter));
+
+        let counter = Counter::new("0-orders".to_owned(), vec![], &MetricSettings::default());
+        assert_eq!("_0_orders 0 1700000000000\n", encode_counter(&Encoder::Prometheus, &counter));
+    }
+
+    #[test]
+    fn prometheus_summary_matches_fixture() {
+        let settings = settings_with_quantiles(&[0.5, 0.99]);
+        let mut histogram = Histogram::new("latency".to_owned(), tags(&[("venue", "lse")]), &settings);
+        for value in [10, 20, 30] {
+            histogram.record(value).unwrap();
+        }
+        let expected = "\
+latency{venue=\"lse\",quantile=\"0.5\"} 20 1700000000000
+latency{venue=\"lse\",quantile=\"0.99\"} 30 1700000000000
+latency_sum{venue=\"lse\"} 60.0 1700000000000
+latency_count{venue=\"lse\"} 3 1700000000000
+";
+        assert_eq!(expected, encode_histogram(&Encoder::Prometheus, &histogram));
+    }
+
+    #[test]
+    fn prometheus_buckets_match_fixture() {
+        let mut histogram = Histogram::new("latency".to_owned(), vec![], &MetricSettings::default());
+        histogram.buckets = Some(Buckets::new(vec![25, 15]));
+        for value in [10, 15, 20, 30] {
+            histogram.record(value).unwrap();
+        }
+        let expected = "\
+latency_bucket{le=\"15\"} 2 1700000000000
+latency_bucket{le=\"25\"} 3 1700000000000
+latency_bucket{le=\"+Inf\"} 4 1700000000000
+latency_sum 75.0 1700000000000
+latency_count 4 1700000000000
+";
+        assert_eq!(expected, encode_histogram(&Encoder::Prometheus, &histogram));
+    }
+
+    #[test]
+    fn statsd_counter_with_two_tags() {
+        let tags = tags(&[("side", "buy"), ("venue", "lse")]);
+        let mut counter = Counter::new("orders".to_owned(), tags, &MetricSettings::default());
+        counter.increment(7);
+        let tagged = "orders:7|c|#side:buy,venue:lse\n";
+        assert_eq!(tagged, encode_counter(&Encoder::Statsd { plain: false }, &counter));
+        assert_eq!(tagged, encode_counter(&Encoder::DogStatsd, &counter));
+        assert_eq!("orders:7|c\n", encode_counter(&Encoder::Statsd { plain: true }, &counter));
+    }
+
+    #[test]
+    fn statsd_histogram_with_two_tags() {
+        let tags = tags(&[("side", "buy"), ("venue", "lse")]);
+        let mut histogram = Histogram::new("latency".to_owned(), tags, &MetricSettings::default());
+        for value in [10, 20, 20] {
+            histogram.record(value).unwrap();
+        }
+        let expected = "latency:10|h|#side:buy,venue:lse\nlatency:20|h|@0.5|#side:buy,venue:lse\n";
+        assert_eq!(expected, encode_histogram(&Encoder::Statsd { plain: false }, &histogram));
+        let expected = "latency:10|h\nlatency:20|h|@0.5\n";
+        assert_eq!(expected, encode_histogram(&Encoder::Statsd { plain: true }, &histogram));
+    }
+
+    #[test]
+    fn dog_statsd_histogram_is_a_distribution() {
+        let tags = tags(&[("side", "buy"), ("venue", "lse")]);
+        let mut histogram = Histogram::new("latency".to_owned(), tags, &MetricSettings::default());
+        for value in [10, 20, 20, 30, 30, 30, 30] {
+            histogram.record(value).unwrap();
+        }
+        let expected = "latency:10|d|#side:buy,venue:lse\n\
+                        latency:20|d|@0.5|#side:buy,venue:lse\n\
+                        latency:30|d|@0.25|#side:buy,venue:lse\n";
+        assert_eq!(expected, encode_histogram(&Encoder::DogStatsd, &histogram));
+    }
+
+    #[test]
+    fn statsd_tags_include_container_tags() {
+        let settings = MetricSettings {
+            container_tags: tags(&[("pod", "gateway-0")]),
+            ..MetricSettings::default()
+        };
+        let mut counter = Counter::new("orders".to_owned(), tags(&[("venue", "lse")]), &settings);
+        counter.increment(2);
+        let mut histogram = Histogram::new("latency".to_owned(), vec![], &settings);
+        histogram.record(10).unwrap();
+
+        assert_eq!("orders:2|c|#venue:lse,pod:gateway-0\n", encode_counter(&Encoder::DogStatsd, &counter));
+        assert_eq!("latency:10|d|#pod:gateway-0\n", encode_histogram(&Encoder::DogStatsd, &histogram));
+        assert_eq!("latency:10|h|#pod:gateway-0\n", encode_histogram(&Encoder::Statsd { plain: false }, &histogram));
+        // the series the other encoders write are left as they are
+        assert_eq!("orders,venue=lse", counter.meta_data.series);
+        assert_eq!("orders:2|c\n", encode_counter(&Encoder::Statsd { plain: true }, &counter));
+    }
+
+    #[test]
+    fn renamed_tags_are_exported_while_the_series_key_keeps_the_original_tags() {
+        let settings = MetricSettings {
+            tag_renames: HashMap::from([("fn_name".to_owned(), "operation".to_owned())]),
+            ..MetricSettings::default()
+        };
+        let mut counter = Counter::new("orders".to_owned(), tags(&[("fn_name", "place"), ("venue", "lse")]), &settings);
+        counter.increment(2);
+
+        assert_eq!(
+            format!("orders,operation=place,venue=lse value=2u {TIMESTAMP}\n"),
+            encode_counter(&Encoder::LineProtocol, &counter)
+        );
+        assert_eq!("orders:2|c|#operation:place,venue:lse\n", encode_counter(&Encoder::DogStatsd, &counter));
+        let expected = SeriesKey {
+            name: "orders".to_owned(),
+            tags: tags(&[("fn_name", "place"), ("venue", "lse")]),
+        };
+        assert_eq!(expected, counter.meta_data.series_key());
+    }
+
+    #[test]
+    fn log_summary_lists_the_top_counters_and_histograms() {
+        let settings = MetricSettings::default();
+        let mut counters = Counters::new();
+        for (id, name, previous, value) in [(0, "fills", 0, 5), (1, "orders", 5, 9), (2, "cancels", 0, 9)] {
+            let mut counter = Counter::new(name.to_owned(), tags(&[("venue", "lse")]), &settings);
+            counter.increment(value);
+            counter.previous = previous;
+            counters.insert(id, counter);
+        }
+        // ties are ordered by series
+        let expected = "3 counters, top 2: cancels,venue=lse 9 (+9); orders,venue=lse 9 (+4)";
+        assert_eq!(Some(expected.to_owned()), LogSummary::counters(&counters, 2));
+        assert_eq!(None, LogSummary::counters(&Counters::new(), 2));
+
+        let mut histograms = Histograms::new();
+        for (id, name, values) in [
+            (0, "latency", &[10, 20, 30][..]),
+            (1, "size", &[100][..]),
+            (2, "idle", &[][..]),
+        ] {
+            let mut histogram = Histogram::new(name.to_owned(), vec![], &settings);
+            for value in values {
+                histogram.record(*value).unwrap();
+            }
+            histograms.insert(id, histogram);
+        }
+        // histograms without samples count towards the total but are not listed
+        let expected =
+            "3 histograms, top 2: latency count=3 p50=20 p99=30 max=30; size count=1 p50=100 p99=100 max=100";
+        assert_eq!(Some(expected.to_owned()), LogSummary::histograms(&histograms, 10));
+        histograms.values_mut().for_each(Histogram::clear);
+        assert_eq!(None, LogSummary::histograms(&histograms, 10));
+    }
+
+    #[test]
+    fn json_counter_matches_fixture() {
+        let tags = tags(&[("side", "buy"), ("venue", "lse")]);
+        let mut counter = Counter::new("orders".to_owned(), tags, &MetricSettings::default());
+        counter.increment(3);
+        let expected = concat!(
+            r#"{"timestamp":1700000000000000000,"value":3,"name":"orders","#,
+            r#""tags":[["side","buy"],["venue","lse"]]}"#,
+            "\n"
+        );
+        assert_eq!(expected, encode_counter(&Encoder::Json, &counter));
+    }
+
+    #[test]
+    fn json_histogram_matches_fixture() {
+        let settings = settings_with_quantiles(&[0.5, 0.99]);
+        let mut histogram = Histogram::new("latency".to_owned(), tags(&[("venue", "lse")]), &settings);
+        histogram.buckets = Some(Buckets::new(vec![15, 25]));
+        for value in [10, 20, 30] {
+            histogram.record(value).unwrap();
+        }
+        let expected = concat!(
+            r#"{"timestamp":1700000000000000000,"name":"latency","tags":[["venue","lse"]],"#,
+            r#""count":3,"min":10,"max":30,"mean":20.0,"p50":20,"p99":30,"buckets":[[15,1],[25,2]]}"#,
+            "\n"
+        );
+        assert_eq!(expected, encode_histogram(&Encoder::Json, &histogram));
+    }
+
+    #[test]
+    fn publishes_once_per_window_after_the_flush_time_has_passed() {
+        let (mut aggregator, path) = aggregator("window", Encoder::LineProtocol, MetricSettings::default());
+        let start = 1_000 * SECOND;
+        aggregator.next_flush_time_ns = start;
+        create_counter(&mut aggregator, 0, "window_orders");
+        increment(&mut aggregator, 0, 5);
+
+        // the window ends strictly after the flush time
+        aggregator.tick(start).unwrap();
+        assert!(published(&path).is_empty());
+        aggregator.tick(start + 1).unwrap();
+        assert_eq!(["window_orders value=5u"], published(&path).as_slice());
+
+        // and the next one a flush interval after the previous publish
+        increment(&mut aggregator, 0, 2);
+        aggregator.tick(start + 1 + SECOND).unwrap();
+        assert_eq!(1, published(&path).len());
+        aggregator.tick(start + 2 + SECOND).unwrap();
+        assert_eq!(["window_orders value=5u", "window_orders value=7u"], published(&path).as_slice());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn consecutive_flushes_export_totals_or_changes() {
+        let cases = [
+            (None, ["5u", "8u", "4u"]),
+            (Some(CounterReporting::Cumulative), ["5u", "8u", "4u"]),
+            (Some(CounterReporting::Delta), ["5i", "3i", "-4i"]),
+        ];
+        for (reporting, values) in cases {
+            let settings = MetricSettings {
+                counter_reporting: reporting,
+                ..MetricSettings::default()
+            };
+            let (mut aggregator, path) = aggregator("reporting", Encoder::LineProtocol, settings);
+            let start = 1_000 * SECOND;
+            aggregator.next_flush_time_ns = start;
+            create_counter(&mut aggregator, 0, "reporting_orders");
+
+            increment(&mut aggregator, 0, 5);
+            aggregator.tick(start + 1).unwrap();
+            increment(&mut aggregator, 0, 3);
+            aggregator.tick(start + SECOND + 2).unwrap();
+            let event = UpdateEvent::CounterDecrement(0, 4);
+            MetricsAggregator::handle_update_event(&mut aggregator.counters, &mut aggregator.histograms, event)
+                .unwrap();
+            aggregator.tick(start + 2 * SECOND + 3).unwrap();
+
+            let expected = values.map(|value| format!("reporting_orders value={value}"));
+            assert_eq!(expected.as_slice(), published(&path), "with {reporting:?}");
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn only_counters_matching_the_filter_are_exported() {
+        let filters = [
+            (vec![tags(&[("env", "prod")])], vec![], vec!["requests,env=prod value=1u"]),
+            (vec![], vec![tags(&[("env", "dev")])], vec!["requests,env=prod value=1u"]),
+            (
+                vec![tags(&[("env", "prod")]), tags(&[("env", "dev"), ("region", "eu")])],
+                vec![],
+                vec!["requests,env=prod value=1u"],
+            ),
+            (vec![], vec![], vec!["requests,env=dev value=1u", "requests,env=prod value=1u"]),
+        ];
+        for (include, exclude, expected) in filters {
+            let settings = MetricSettings {
+                filter: TagFilter { include, exclude },
+                ..MetricSettings::default()
+            };
+            let (mut aggregator, path) = aggregator("filter", Encoder::LineProtocol, settings);
+            for (id, env) in [(0, "dev"), (1, "prod")] {
+                let counter = Counter::new("requests".to_owned(), tags(&[("env", env)]), &aggregator.settings);
+                aggregator.counters.insert(id, counter);
+                increment(&mut aggregator, id, 1);
+            }
+            aggregator.next_flush_time_ns = 0;
+            aggregator.tick(1).unwrap();
+
+            let mut lines = published(&path);
+            lines.sort();
+            assert_eq!(expected, lines, "with {:?}", aggregator.settings.filter);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn timestamps_are_written_in_the_configured_resolution() {
+        let timestamp = TIMESTAMP + 123_456_789;
+        let cases = [
+            (TimestampResolution::Seconds, "1700000000"),
+            (TimestampResolution::Millis, "1700000000123"),
+            (TimestampResolution::Micros, "1700000000123456"),
+            (TimestampResolution::Nanos, "1700000000123456789"),
+        ];
+        for (resolution, expected) in cases {
+            let settings = MetricSettings {
+                timestamp_resolution: Some(resolution),
+                ..MetricSettings::default()
+            };
+            let counter = Counter::new("orders".to_owned(), vec![], &settings);
+            let mut histogram = Histogram::new("latency".to_owned(), vec![], &settings);
+            histogram.record(1).unwrap();
+            for encoder in [Encoder::LineProtocol, Encoder::Prometheus] {
+                let mut dst = Vec::new();
+                encoder.encode_counter(&counter, timestamp, &mut dst).unwrap();
+                encoder.encode_histogram(&histogram, timestamp, &mut dst).unwrap();
+                for line in String::from_utf8(dst).unwrap().lines() {
+                    assert!(line.ends_with(&format!(" {expected}")), "{line} with {resolution:?}");
+                }
+            }
+            let mut dst = Vec::new();
+            Encoder::Json.encode_counter(&counter, timestamp, &mut dst).unwrap();
+            let json: serde_json::Value = serde_json::from_slice(&dst).unwrap();
+            assert_eq!(expected, json["timestamp"].to_string(), "with {resolution:?}");
+        }
+    }
+
+    #[test]
+    fn nothing_is_exported_before_the_warmup_has_elapsed() {
+        for (policy, first_export) in [(WarmupPolicy::Reset, 0), (WarmupPolicy::CarryForward, 5)] {
+            let (aggregator, path) = aggregator("warmup", Encoder::LineProtocol, MetricSettings::default());
+            let start = 1_000 * SECOND;
+            let mut aggregator = aggregator.with_warmup_from(start, Some(Duration::from_secs(10)), policy);
+            aggregator.next_flush_time_ns = start;
+            create_counter(&mut aggregator, 0, "warmup_orders");
+            increment(&mut aggregator, 0, 5);
+
+            for second in 1..10 {
+                aggregator.tick(start + second * SECOND + 1).unwrap();
+            }
+            // explicit flushes are held back too
+            aggregator.flush_metrics(start + 9 * SECOND).unwrap();
+            assert!(published(&path).is_empty(), "exported during the warmup with {policy:?}");
+
+            // the first regular flush after the warmup exports
+            aggregator.tick(start + 10 * SECOND + 2).unwrap();
+            increment(&mut aggregator, 0, 3);
+            aggregator.tick(start + 11 * SECOND + 3).unwrap();
+            let expected = [
+                format!("warmup_orders value={first_export}u"),
+                format!("warmup_orders value={}u", first_export + 3),
+            ];
+            assert_eq!(expected.as_slice(), published(&path), "with {policy:?}");
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn values_are_rounded_to_the_nearest_multiple_of_the_resolution() {
+        let recorded = |resolution: u64, value: u64| {
+            let mut histogram = Histogram::new("latency".to_owned(), vec![], &MetricSettings::default());
+            histogram.resolution = resolution;
+            histogram.record(value).unwrap();
+            histogram.inner.max()
+        };
+        // halves are rounded up
+        let cases = [
+            (0, 0),
+            (49, 0),
+            (50, 100),
+            (100, 100),
+            (149, 100),
+            (150, 200),
+            (1_949, 1_900),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(expected, recorded(100, value), "{value} with a resolution of 100");
+        }
+        assert_eq!(2, recorded(2, 1));
+        // a resolution of 0 or 1 leaves values unchanged
+        assert_eq!(149, recorded(0, 149));
+        assert_eq!(149, recorded(1, 149));
+    }
+
+    fn histogram_with_sample_limit(policy: OverflowPolicy) -> Histogram {
+        let settings = MetricSettings {
+            sample_limit: Some(SampleLimit {
+                max_samples: 100,
+                policy,
+            }),
+            ..MetricSettings::default()
+        };
+        Histogram::new("latency".to_owned(), vec![], &settings)
+    }
+
+    #[test]
+    fn samples_past_the_limit_are_dropped_and_reported() {
+        let mut histogram = histogram_with_sample_limit(OverflowPolicy::Drop);
+        for value in 1..=1_000 {
+            histogram.record(value).unwrap();
+        }
+        // only the first samples of the interval are kept
+        assert_eq!(100, histogram.inner.len());
+        assert_eq!(100, histogram.inner.max());
+        assert_eq!(900, histogram.dropped);
+        assert!(encode_histogram(&Encoder::LineProtocol, &histogram).contains(",dropped_samples=900u"));
+
+        histogram.clear();
+        histogram.record(5).unwrap();
+        assert_eq!((1, 0), (histogram.inner.len(), histogram.dropped));
+        assert!(!encode_histogram(&Encoder::LineProtocol, &histogram).contains("dropped_samples"));
+    }
+
+    #[test]
+    fn samples_past_the_limit_are_downsampled_with_weights() {
+        let mut histogram = histogram_with_sample_limit(OverflowPolicy::Downsample);
+        for value in 1..=1_000 {
+            histogram.record(value).unwrap();
+        }
+        // every 2nd sample is kept with a weight of 2 past 100 samples, every 4th with a weight of 4 past 200
+        // and so on, so the count stays within a stride (16 by the end) of the number of samples
+        assert_eq!(992, histogram.inner.len());
+        assert_eq!(0, histogram.dropped);
+        let median = histogram.inner.value_at_quantile(0.5);
+        assert!(median.abs_diff(500) <= 16, "median {median} too far from 500");
+        assert_eq!(992, histogram.inner.max());
+
+        // the stride starts over after a clear
+        histogram.clear();
+        for value in 1..=100 {
+            histogram.record(value).unwrap();
+        }
+        assert_eq!((100, 100), (histogram.inner.len(), histogram.inner.value_at_quantile(1.0)));
+    }
+
+    #[cfg(feature = "tdigest")]
+    #[test]
+    fn tdigest_quantiles_are_close_to_the_exact_quantiles() {
+        const COUNT: u64 = 100_000;
+        let settings = MetricSettings {
+            histogram_kind: HistogramKind::TDigest,
+            ..MetricSettings::default()
+        };
+        let mut histogram = Histogram::new("latency".to_owned(), vec![], &settings);
+        // every value from 1 to COUNT once, in an order that is neither ascending nor descending
+        for i in 0..COUNT {
+            histogram.record(i * 7_919 % COUNT + 1).unwrap();
+        }
+        histogram.inner.compress();
+
+        assert_eq!(COUNT, histogram.inner.len());
+        assert_eq!((1, COUNT), (histogram.inner.min(), histogram.inner.max()));
+        for quantile in [0.5, 0.9, 0.99, 0.999] {
+            let exact = quantile * COUNT as f64;
+            let estimate = histogram.inner.value_at_quantile(quantile) as f64;
+            // as every value occurs once, the distance to the exact value is the error in rank
+            let error = (estimate - exact).abs() / COUNT as f64;
+            assert!(error < 0.001, "p{quantile} estimated as {estimate} rather than {exact}");
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Per-thread update event buffers.
+//!
+//! Every application thread that records a metric gets its own bounded single-producer buffer
+//! the first time it does so. The consumer side of that buffer is handed over to the aggregator,
+//! which drains all registered buffers on each poll. This way recording is an uncontended append
+//! into thread-local state rather than an update on a channel shared by all threads.
+//!
+//! Each buffer holds at most `event_channel_size` events, so the memory used per recording thread
+//! is bounded by `event_channel_size * size_of::<UpdateEvent>()`. Once the buffer is full further
+//! events from that thread are dropped until the aggregator catches up.
+//!
+//! When a thread exits its buffer is dropped, the aggregator drains whatever is still pending and
+//! then deregisters it.
+//!
+//! A thread keeps a buffer per agent instance it records into, so that switching back and forth
+//! between instances (e.g. while the agent is re-initialized) does not register a new buffer on each
+//! switch. Buffers of instances that have since been dropped are removed the next time the thread
+//! registers a buffer.
+
+use crate::UpdateEvent;
+#[cfg(feature = "rtrb")]
+use rtrb::{Consumer, Producer};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+#[cfg(not(feature = "rtrb"))]
+use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::{Arc, Weak};
+
+#[cfg(feature = "rtrb")]
+pub type UpdateProducer = Producer<UpdateEvent>;
+#[cfg(feature = "rtrb")]
+pub type UpdateConsumer = Consumer<UpdateEvent>;
+#[cfg(not(feature = "rtrb"))]
+pub type UpdateProducer = SyncSender<UpdateEvent>;
+#[cfg(not(feature = "rtrb"))]
+pub type UpdateConsumer = Receiver<UpdateEvent>;
+
+/// Used to tell apart buffers that belong to different agent instances.
+static NEXT_REGISTRY_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Buffers of the current thread, one per registry it has pushed into.
+    static LOCAL_BUFFERS: RefCell<Vec<LocalBuffer>> = const { RefCell::new(Vec::new()) };
+}
+
+struct LocalBuffer {
+    registry_id: usize,
+    /// Dropped along with the registry, after which the buffer is no longer drained.
+    registry: Weak<()>,
+    tx: UpdateProducer,
+}
+
+/// Creates thread-local buffers on demand and registers them with the aggregator.
+pub struct BufferRegistry {
+    id: usize,
+    capacity: usize,
+    tx_reg: Sender<UpdateConsumer>,
+    alive: Arc<()>,
+}
+
+impl BufferRegistry {
+    pub fn new(capacity: usize, tx_reg: Sender<UpdateConsumer>) -> Self {
+        Self {
+            id: NEXT_REGISTRY_ID.fetch_add(1, Ordering::Relaxed),
+            capacity,
+            tx_reg,
+            alive: Arc::new(()),
+        }
+    }
+
+    /// Appends the event to the buffer owned by the current thread, creating and registering
+    /// the buffer first if needed. Events are dropped if the buffer is full, if the thread is
+    /// being torn down or if called re-entrantly (e.g. from within an instrumented allocator
+    /// while the buffer itself is being created).
+    #[inline]
+    pub fn push(&self, event: UpdateEvent) {
+        let _ = LOCAL_BUFFERS.try_with(|local| {
+            if let Ok(mut local) = local.try_borrow_mut() {
+                match local.iter_mut().find(|buffer| buffer.registry_id == self.id) {
+                    Some(buffer) => Self::push_to(&mut buffer.tx, event),
+                    None => {
+                        local.retain(|buffer| buffer.registry.strong_count() > 0);
+                        let mut tx = self.register();
+                        Self::push_to(&mut tx, event);
+                        local.push(LocalBuffer {
+                            registry_id: self.id,
+                            registry: Arc::downgrade(&self.alive),
+                            tx,
+                        });
+                    }
+                }
+            }
+        });
+    }
+
+    #[inline]
+    fn push_to(tx: &mut UpdateProducer, event: UpdateEvent) {
+        #[cfg(feature = "rtrb")]
+        let _ = tx.push(event);
+        #[cfg(not(feature = "rtrb"))]
+        let _ = tx.try_send(event);
+    }
+
+    #[cold]
+    fn register(&self) -> UpdateProducer {
+        #[cfg(feature = "rtrb")]
+        let (tx, rx) = rtrb::RingBuffer::new(self.capacity);
+        #[cfg(not(feature = "rtrb"))]
+        let (tx, rx) = std::sync::mpsc::sync_channel(self.capacity);
+        let _ = self.tx_reg.send(rx);
+        tx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registered(buffers: &std::sync::mpsc::Receiver<UpdateConsumer>) -> usize {
+        buffers.try_iter().count()
+    }
+
+    #[test]
+    fn alternating_registries_register_a_buffer_once_each() {
+        let (tx_first, rx_first) = std::sync::mpsc::channel();
+        let (tx_second, rx_second) = std::sync::mpsc::channel();
+        let first = BufferRegistry::new(16, tx_first);
+        let second = BufferRegistry::new(16, tx_second);
+        for _ in 0..3 {
+            first.push(UpdateEvent::CounterIncrement(1, 1));
+            second.push(UpdateEvent::CounterIncrement(1, 1));
+        }
+        assert_eq!(1, registered(&rx_first));
+        assert_eq!(1, registered(&rx_second));
+    }
+
+    #[test]
+    fn buffers_of_dropped_registries_are_removed() {
+        let local_buffers = || LOCAL_BUFFERS.with(|local| local.borrow().len());
+        let (tx_reg, _rx_reg) = std::sync::mpsc::channel();
+        let dropped = BufferRegistry::new(16, tx_reg.clone());
+        let kept = BufferRegistry::new(16, tx_reg.clone());
+        dropped.push(UpdateEvent::CounterIncrement(1, 1));
+        kept.push(UpdateEvent::CounterIncrement(1, 1));
+        assert_eq!(2, local_buffers());
+
+        drop(dropped);
+        let added = BufferRegistry::new(16, tx_reg);
+        added.push(UpdateEvent::CounterIncrement(1, 1));
+        assert_eq!(2, local_buffers());
+    }
+}
@@ -1,6 +1,6 @@
 use crate::OwnedTags;
 use crate::aggregator::Encoder;
-use duration_str::deserialize_duration;
+use duration_str::{deserialize_duration, deserialize_option_duration};
 use metricus::PreAllocatedMetric;
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
@@ -19,18 +19,85 @@ pub struct MetricsConfig {
     #[serde(deserialize_with = "deserialize_duration")]
     #[serde(default = "get_default_flush_interval")]
     pub flush_interval: Duration,
+    /// Period after the agent starts during which metrics are aggregated but not exported, so that one-time
+    /// initialization costs do not skew the exported values. Explicit flushes are not exported either. Once the
+    /// warmup has elapsed, metrics are exported at the next regular flush, with the warmup data handled
+    /// according to `warmup_policy`. Disabled by default.
+    #[serde(default, deserialize_with = "deserialize_option_duration")]
+    pub warmup: Option<Duration>,
+    /// What happens to the metrics aggregated during the warmup once it has elapsed. Defaults to discarding them.
+    #[serde(default)]
+    pub warmup_policy: WarmupPolicy,
     /// Default tags that will be added to all metrics.
     #[serde_as(as = "HashMap<_, _>")]
     #[serde(default)]
     pub default_tags: OwnedTags,
-    /// Event channel size between the metrics agent and aggregator. This defaults to 1 million.
+    /// Container tags appended to every metric by the DogStatsD encoder (and the StatsD encoder unless
+    /// it is `plain`), e.g. to let Datadog apply them server-side when aggregating distributions across
+    /// hosts. Ignored by the other encoders.
+    #[serde_as(as = "HashMap<_, _>")]
+    #[serde(default)]
+    pub container_tags: OwnedTags,
+    /// Tag keys renamed when metrics are encoded for export, e.g. `fn_name: operation`, so that the exported
+    /// data follows the naming conventions of the downstream without re-tagging in code. Renames apply to
+    /// metric tags and `default_tags` (but not to `container_tags`) and are not chained, so `a: b` and `b: c`
+    /// export `a` as `b`. Metrics are still aggregated and identified in snapshots by their original tags.
+    /// If a rename results in duplicate keys, both tags are exported.
+    #[serde(default)]
+    pub tag_renames: HashMap<String, String>,
+    /// Sample rates of individual metrics, see [SampleRateConfig]. Metrics without a matching rule keep
+    /// every update. Empty by default.
+    #[serde(default)]
+    pub sample_rates: Vec<SampleRateConfig>,
+    /// Tag rules selecting the metrics that are exported, see [TagFilter]. Every metric is exported by
+    /// default.
+    #[serde(default)]
+    pub filter: TagFilter,
+    /// Capacity of the event buffer created for each application thread that records metrics.
+    /// The memory held per recording thread is bounded by this capacity (each event takes 24 bytes).
+    /// This defaults to 1 million.
     #[serde(default = "get_default_event_channel_size")]
     pub event_channel_size: usize,
     /// Metrics exporter type.
     #[serde(default)]
     pub exporter: ExporterSource,
+    /// Metrics registered with fixed ids at startup. Metrics declaring a unit are exported with a `unit`
+    /// tag (a label for Prometheus), unless they already have a tag with that key.
     #[serde(default)]
     pub pre_allocated_metrics: Vec<PreAllocatedMetric>,
+    /// Maximum number of samples a single histogram accepts per flush interval. Once reached, further
+    /// samples are handled according to `histogram_overflow_policy` and the exported percentiles become
+    /// approximate. Unlimited by default.
+    #[serde(default)]
+    pub histogram_max_samples: Option<u64>,
+    /// What to do with histogram samples past `histogram_max_samples`. Defaults to dropping them.
+    #[serde(default)]
+    pub histogram_overflow_policy: OverflowPolicy,
+    /// Data structure used to summarize histogram values. Defaults to HDR histograms.
+    #[serde(default)]
+    pub histogram_kind: HistogramKind,
+    /// Quantiles of each histogram exported by the line protocol encoder (as fields named after the
+    /// quantile, e.g. `p999` for 0.999) and the Prometheus encoder (as `quantile` labels), computed by the
+    /// agent so that only the quantiles rather than the recorded values are exported. Values are clamped
+    /// to the range from 0 to 1. Defaults to [DEFAULT_QUANTILES].
+    #[serde(default)]
+    pub quantiles: Option<Vec<f64>>,
+    /// Whether counters are exported with their running total or with the change since the last publish,
+    /// see [CounterReporting]. By default each encoder uses the representation its downstream expects:
+    /// the StatsD and DogStatsD encoders export deltas, the other encoders running totals.
+    #[serde(default)]
+    pub counter_reporting: Option<CounterReporting>,
+    /// Unit of the timestamps written by the line protocol, JSON and Prometheus encoders, see
+    /// [TimestampResolution]. By default the line protocol and JSON encoders write nanoseconds and the
+    /// Prometheus encoder milliseconds. The StatsD and DogStatsD encoders do not write timestamps.
+    #[serde(default)]
+    pub timestamp_resolution: Option<TimestampResolution>,
+    /// Enables aggregator self-instrumentation: counters of processed update events and of datagrams
+    /// that could not be sent, and a histogram of publish durations, registered under the reserved
+    /// `EVENTS_PROCESSED_COUNTER_ID`, `UNSENT_DATAGRAMS_COUNTER_ID` and `PUBLISH_DURATION_HISTOGRAM_ID`
+    /// ids, which are part of [crate::RESERVED_IDS]. Disabled by default.
+    #[serde(default)]
+    pub self_metrics: bool,
     /// CPU id for the metrics aggregator thread. Cannot be used with [MetricsConfig:aggregator_affinity_cpu_index] `aggregator_affinity_cpu_index`.
     #[serde(default)]
     pub aggregator_affinity_cpu_id: Option<usize>,
@@ -60,6 +127,207 @@ impl MetricsConfig {
             ..self
         }
     }
+
+    /// Overrides the exporter with the `METRICUS_*` environment variables that are set, e.g. to point a
+    /// container at another collector without editing the config file:
+    ///
+    /// - `METRICUS_EXPORTER`: exporter type, one of [available_exporters]. If it differs from the
+    ///   configured type, the exporter is built from the environment variables alone with the remaining
+    ///   settings at their defaults.
+    /// - `METRICUS_ENCODER`: encoder, one of [available_encoders] (`statsd` sends tags).
+    /// - `METRICUS_UDP_ADDR`, `METRICUS_TCP_ADDR`: `host:port` of the `udp` or `tcp` exporter.
+    /// - `METRICUS_FILE_PATH`: path of the `file` exporter.
+    /// - `METRICUS_UNIX_SOCKET_PATH`: path of the `unix_stream` or `unix_datagram` exporter.
+    ///
+    /// Values that cannot be parsed, and variables that do not apply to the resulting exporter (e.g.
+    /// `METRICUS_UDP_ADDR` with a `file` exporter), are reported as errors rather than ignored.
+    ///
+    /// ```no_run
+    /// use metricus_agent::config::MetricsConfig;
+    ///
+    /// // METRICUS_EXPORTER=udp METRICUS_UDP_ADDR=10.0.0.5:8125 METRICUS_ENCODER=statsd
+    /// let config = MetricsConfig::from_file("metrics.yml")?.with_env_overrides()?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn with_env_overrides(mut self) -> crate::Result<MetricsConfig> {
+        let overrides = EnvOverrides::from_env()?;
+        self.exporter = overrides.apply(self.exporter)?;
+        Ok(self)
+    }
+}
+
+/// Exporter settings read from the environment, see [MetricsConfig::with_env_overrides].
+#[derive(Default)]
+struct EnvOverrides {
+    exporter: Option<String>,
+    encoder: Option<Encoder>,
+    udp_addr: Option<(String, u16)>,
+    tcp_addr: Option<(String, u16)>,
+    file_path: Option<String>,
+    unix_socket_path: Option<String>,
+}
+
+impl EnvOverrides {
+    const EXPORTER: &'static str = "METRICUS_EXPORTER";
+    const ENCODER: &'static str = "METRICUS_ENCODER";
+    const UDP_ADDR: &'static str = "METRICUS_UDP_ADDR";
+    const TCP_ADDR: &'static str = "METRICUS_TCP_ADDR";
+    const FILE_PATH: &'static str = "METRICUS_FILE_PATH";
+    const UNIX_SOCKET_PATH: &'static str = "METRICUS_UNIX_SOCKET_PATH";
+
+    fn from_env() -> crate::Result<Self> {
+        Self::from_vars(|key| match std::env::var(key) {
+            Ok(value) => Ok(Some(value)),
+            Err(std::env::VarError::NotPresent) => Ok(None),
+            Err(err) => Err(crate::Error::other(format!("invalid {key}: {err}"))),
+        })
+    }
+
+    fn from_vars(var: impl Fn(&str) -> crate::Result<Option<String>>) -> crate::Result<Self> {
+        let exporter = var(Self::EXPORTER)?;
+        if let Some(exporter) = &exporter {
+            if !available_exporters().contains(&exporter.as_str()) {
+                return Err(crate::Error::other(format!(
+                    "invalid {}: unknown exporter '{exporter}', expected one of {}",
+                    Self::EXPORTER,
+                    available_exporters().join(", ")
+                )));
+            }
+        }
+        Ok(Self {
+            exporter,
+            encoder: var(Self::ENCODER)?.map(|encoder| parse_encoder(&encoder)).transpose()?,
+            udp_addr: var(Self::UDP_ADDR)?
+                .map(|addr| parse_addr(Self::UDP_ADDR, &addr))
+                .transpose()?,
+            tcp_addr: var(Self::TCP_ADDR)?
+                .map(|addr| parse_addr(Self::TCP_ADDR, &addr))
+                .transpose()?,
+            file_path: var(Self::FILE_PATH)?,
+            unix_socket_path: var(Self::UNIX_SOCKET_PATH)?,
+        })
+    }
+
+    fn apply(self, exporter: ExporterSource) -> crate::Result<ExporterSource> {
+        let mut exporter = match self.exporter.as_deref() {
+            Some(kind) if kind != exporter.kind() => self.build(kind)?,
+            _ => exporter,
+        };
+        let kind = exporter.kind();
+        let not_applicable =
+            |key: &str| crate::Error::other(format!("{key} is set but does not apply to the '{kind}' exporter"));
+        if let Some(encoder) = self.encoder {
+            match &mut exporter {
+                ExporterSource::Udp(UdpConfig { encoder: target, .. })
+                | ExporterSource::Tcp(TcpConfig { encoder: target, .. })
+                | ExporterSource::File(FileConfig { encoder: target, .. })
+                | ExporterSource::UnixStream(UnixSocketConfig { encoder: target, .. })
+                | ExporterSource::UnixDatagram(UnixSocketConfig { encoder: target, .. })
+                | ExporterSource::Stdout(ConsoleConfig { encoder: target })
+                | ExporterSource::Stderr(ConsoleConfig { encoder: target }) => *target = encoder,
+                ExporterSource::SplitFile(config) => {
+                    config.counters.encoder = encoder.clone();
+                    config.histograms.encoder = encoder;
+                }
+                ExporterSource::NoOp | ExporterSource::Log(_) => return Err(not_applicable(Self::ENCODER)),
+            }
+        }
+        if let Some((host, port)) = self.udp_addr {
+            let ExporterSource::Udp(config) = &mut exporter else {
+                return Err(not_applicable(Self::UDP_ADDR));
+            };
+            config.host = host;
+            config.port = port;
+        }
+        if let Some((host, port)) = self.tcp_addr {
+            let ExporterSource::Tcp(config) = &mut exporter else {
+                return Err(not_applicable(Self::TCP_ADDR));
+            };
+            config.host = host;
+            config.port = port;
+        }
+        if let Some(path) = self.file_path {
+            let ExporterSource::File(config) = &mut exporter else {
+                return Err(not_applicable(Self::FILE_PATH));
+            };
+            config.path = path;
+        }
+        if let Some(path) = self.unix_socket_path {
+            let (ExporterSource::UnixStream(config) | ExporterSource::UnixDatagram(config)) = &mut exporter else {
+                return Err(not_applicable(Self::UNIX_SOCKET_PATH));
+            };
+            config.path = path;
+        }
+        Ok(exporter)
+    }
+
+    /// Exporter of the given type with the settings that are not set by the environment at their
+    /// defaults, so that the exporter is deserialized like one from a config file.
+    fn build(&self, kind: &str) -> crate::Result<ExporterSource> {
+        let missing = |key: &str| crate::Error::other(format!("{key} is required for the '{kind}' exporter"));
+        let encoder = || self.encoder.clone().ok_or_else(|| missing(Self::ENCODER));
+        let exporter = match kind {
+            "no_op" => ExporterSource::NoOp,
+            "udp" | "tcp" => {
+                let (key, addr) = match kind {
+                    "udp" => (Self::UDP_ADDR, &self.udp_addr),
+                    _ => (Self::TCP_ADDR, &self.tcp_addr),
+                };
+                let (host, port) = addr.clone().ok_or_else(|| missing(key))?;
+                let config = serde_json::json!({ "host": host, "port": port, "encoder": encoder()? });
+                from_json(kind, config)?
+            }
+            "file" => {
+                let path = self.file_path.clone().ok_or_else(|| missing(Self::FILE_PATH))?;
+                from_json(kind, serde_json::json!({ "path": path, "encoder": encoder()? }))?
+            }
+            "unix_stream" | "unix_datagram" => {
+                let path = self
+                    .unix_socket_path
+                    .clone()
+                    .ok_or_else(|| missing(Self::UNIX_SOCKET_PATH))?;
+                from_json(kind, serde_json::json!({ "path": path, "encoder": encoder()? }))?
+            }
+            "log" => ExporterSource::Log(LogConfig::default()),
+            "stdout" => ExporterSource::Stdout(ConsoleConfig { encoder: encoder()? }),
+            "stderr" => ExporterSource::Stderr(ConsoleConfig { encoder: encoder()? }),
+            _ => {
+                return Err(crate::Error::other(format!(
+                    "the '{kind}' exporter cannot be configured with environment variables"
+                )));
+            }
+        };
+        Ok(exporter)
+    }
+}
+
+fn from_json(kind: &str, config: serde_json::Value) -> crate::Result<ExporterSource> {
+    serde_json::from_value(serde_json::json!({ "type": kind, "config": config })).map_err(crate::Error::other)
+}
+
+fn parse_encoder(encoder: &str) -> crate::Result<Encoder> {
+    let value = match encoder {
+        "statsd" => serde_json::json!({ "statsd": {} }),
+        _ => serde_json::Value::from(encoder),
+    };
+    serde_json::from_value(value).map_err(|_| {
+        crate::Error::other(format!(
+            "invalid {}: unknown encoder '{encoder}', expected one of {}",
+            EnvOverrides::ENCODER,
+            available_encoders().join(", ")
+        ))
+    })
+}
+
+/// Splits `host:port`, where an IPv6 host is enclosed in brackets, e.g. `[::1]:8125`. The brackets are
+/// kept, as the host and port are joined again to resolve the address.
+fn parse_addr(key: &str, addr: &str) -> crate::Result<(String, u16)> {
+    let invalid = || crate::Error::other(format!("invalid {key}: expected host:port, got '{addr}'"));
+    let (host, port) = addr.rsplit_once(':').ok_or_else(invalid)?;
+    if host.is_empty() || host.contains(':') && !(host.starts_with('[') && host.ends_with(']')) {
+        return Err(invalid());
+    }
+    Ok((host.to_owned(), port.parse().map_err(|_| invalid())?))
 }
 
 impl FromStr for MetricsConfig {
@@ -70,6 +338,21 @@ impl FromStr for MetricsConfig {
     }
 }
 
+/// Quantiles exported unless configured otherwise, see [MetricsConfig::quantiles].
+pub const DEFAULT_QUANTILES: [f64; 7] = [0.5, 0.75, 0.9, 0.95, 0.99, 0.999, 0.9999];
+
+const fn get_default_max_datagram_size() -> usize {
+    8192
+}
+
+const fn get_default_max_udp_datagram_size() -> usize {
+    1432
+}
+
+const fn get_default_buffer_capacity() -> usize {
+    1024
+}
+
 const fn get_default_event_channel_size() -> usize {
     1024 * 1024
 }
@@ -78,6 +361,163 @@ const fn get_default_flush_interval() -> Duration {
     Duration::from_secs(10)
 }
 
+/// Keeps only a fraction of the updates of the matching metrics, to reduce the recording and aggregation
+/// work for the noisiest metrics. A metric matches if its measurement name is equal to `measurement` and
+/// it has all of the `tags` (if any). The first matching rule applies, so more specific rules should be
+/// listed first.
+///
+/// With a rate of `r` an update is kept with probability `r`, rounded so that one in `1/r` updates is kept
+/// on average. Kept counter increments are multiplied by `1/r`, so counter values stay correct on average,
+/// but grow in steps of `1/r` times the increment and deviate from the true value by a relative error of
+/// about `1/sqrt(r * n)` after `n` increments. Histogram quantiles are unaffected on average, but are computed
+/// from `r` times as many samples with the corresponding loss of accuracy at the tails, and the exported
+/// histogram count is `r` times the number of recorded values.
+///
+/// ```yaml
+/// sample_rates:
+///   - measurement: order_book_update_latency
+///     rate: 0.01
+///   - measurement: cache_lookups
+///     tags:
+///       cache: l1
+///     rate: 0.1
+/// ```
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SampleRateConfig {
+    pub measurement: String,
+    #[serde_as(as = "HashMap<_, _>")]
+    #[serde(default)]
+    pub tags: OwnedTags,
+    /// Fraction of updates kept, between 0 (exclusive) and 1.
+    pub rate: f64,
+}
+
+impl SampleRateConfig {
+    pub(crate) fn matches(&self, name: &str, tags: &OwnedTags) -> bool {
+        self.measurement == name && self.tags.iter().all(|tag| tags.contains(tag))
+    }
+
+    /// Number of updates each kept update stands for.
+    pub(crate) fn stride(&self) -> u64 {
+        (1.0 / self.rate).round().max(1.0) as u64
+    }
+}
+
+/// Selects the metrics that are exported by their tags, e.g. to send only a subset of the registered
+/// metrics to a destination. Each rule is a set of tags that a metric matches if it has all of them
+/// with equal values. A metric is exported if it matches any of the `include` rules (or there are
+/// none) and none of the `exclude` rules. Rules match the tags as registered, including `default_tags`
+/// and the `type` tag (`counter` or `histogram`), before `tag_renames` are applied.
+///
+/// Filtered out metrics are still aggregated, so they keep showing up in snapshots and counter reads,
+/// but are skipped by every exporter. Whether a metric is exported is decided once when it is registered.
+///
+/// ```yaml
+/// filter:
+///   include:
+///     - env: prod
+///   exclude:
+///     - env: prod
+///       component: debug
+/// ```
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TagFilter {
+    #[serde_as(as = "Vec<HashMap<_, _>>")]
+    #[serde(default)]
+    pub include: Vec<OwnedTags>,
+    #[serde_as(as = "Vec<HashMap<_, _>>")]
+    #[serde(default)]
+    pub exclude: Vec<OwnedTags>,
+}
+
+impl TagFilter {
+    pub(crate) fn matches(&self, tags: &OwnedTags) -> bool {
+        let matches = |rule: &OwnedTags| rule.iter().all(|tag| tags.contains(tag));
+        (self.include.is_empty() || self.include.iter().any(matches)) && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Representation of the exported counter values, see [MetricsConfig::counter_reporting].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CounterReporting {
+    /// Running total since the counter was registered, e.g. for Prometheus.
+    Cumulative,
+    /// Change since the last publish, negative if the counter was decremented, e.g. for StatsD. The line
+    /// protocol encoder then writes the value as a signed integer (`i`) rather than unsigned (`u`).
+    Delta,
+}
+
+/// Unit of the exported timestamps, see [MetricsConfig::timestamp_resolution]. Timestamps are taken in
+/// nanoseconds since the Unix epoch and truncated to the resolution, e.g. to match the `precision` an
+/// InfluxDB write endpoint expects.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampResolution {
+    Seconds,
+    Millis,
+    Micros,
+    Nanos,
+}
+
+impl TimestampResolution {
+    /// Converts a timestamp in nanoseconds to this resolution.
+    pub fn convert(self, timestamp_ns: u64) -> u64 {
+        match self {
+            TimestampResolution::Seconds => timestamp_ns / 1_000_000_000,
+            TimestampResolution::Millis => timestamp_ns / 1_000_000,
+            TimestampResolution::Micros => timestamp_ns / 1_000,
+            TimestampResolution::Nanos => timestamp_ns,
+        }
+    }
+}
+
+/// Handling of the metrics aggregated during the `warmup` once it has elapsed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WarmupPolicy {
+    /// Reset counters to zero and clear histograms, so that the exported values only cover the time
+    /// after the warmup. Counter values in snapshots are reset too, while histogram sample counts in
+    /// snapshots are cumulative and keep the warmup samples.
+    #[default]
+    Reset,
+    /// Keep the aggregated values, so that the first export after the warmup includes everything
+    /// recorded since startup.
+    CarryForward,
+}
+
+/// Policy applied to histogram samples recorded past the configured `histogram_max_samples`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Discard the samples and report how many were discarded as `dropped_samples`.
+    #[default]
+    Drop,
+    /// Keep every n-th sample weighted by n, doubling n each time the number of samples seen
+    /// doubles past the limit. The sample count stays approximately correct while the amount
+    /// of recording work is bounded.
+    Downsample,
+}
+
+/// Data structure used by the aggregator to summarize histogram values.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HistogramKind {
+    /// HDR histogram with 3 significant digits. Quantiles have a fixed relative error of 0.1% and
+    /// memory grows with the logarithm of the range of recorded values.
+    #[default]
+    Hdr,
+    /// Merging t-digest (requires the `tdigest` feature). Memory is bounded by about 100 centroids
+    /// plus a buffer of 1024 values however widely the values are spread, which suits values spanning
+    /// many orders of magnitude. Quantile error is smallest at the tails and largest around the median.
+    /// The line protocol encoder additionally exports the centroids as `centroids="mean:weight ..."`
+    /// and the DogStatsD encoder sends them as weighted values, so that digests can be merged server-side.
+    #[cfg(feature = "tdigest")]
+    TDigest,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum Format {
@@ -85,6 +525,34 @@ pub enum Format {
     Json,
 }
 
+/// Exporter types, as used for `exporter.type` in the config, supported by this build. Tooling can use
+/// this to validate a config before starting the agent. None of the exporters or encoders depend on
+/// cargo features at the moment, so the lists are the same for every build.
+///
+/// ```
+/// assert!(metricus_agent::available_exporters().contains(&"udp"));
+/// assert!(metricus_agent::available_encoders().contains(&"line_protocol"));
+/// ```
+pub fn available_exporters() -> &'static [&'static str] {
+    &[
+        "no_op",
+        "udp",
+        "tcp",
+        "file",
+        "split_file",
+        "unix_stream",
+        "unix_datagram",
+        "log",
+        "stdout",
+        "stderr",
+    ]
+}
+
+/// Encoders, as used for `encoder` in the exporter config, supported by this build.
+pub fn available_encoders() -> &'static [&'static str] {
+    &["line_protocol", "json", "dog_statsd", "prometheus", "statsd"]
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type", content = "config")]
@@ -92,9 +560,32 @@ pub enum ExporterSource {
     #[default]
     NoOp,
     Udp(UdpConfig),
+    Tcp(TcpConfig),
     File(FileConfig),
+    SplitFile(SplitFileConfig),
     UnixStream(UnixSocketConfig),
     UnixDatagram(UnixSocketConfig),
+    Log(LogConfig),
+    Stdout(ConsoleConfig),
+    Stderr(ConsoleConfig),
+}
+
+impl ExporterSource {
+    /// Exporter type as used for `exporter.type` in the config.
+    fn kind(&self) -> &'static str {
+        match self {
+            ExporterSource::NoOp => "no_op",
+            ExporterSource::Udp(_) => "udp",
+            ExporterSource::Tcp(_) => "tcp",
+            ExporterSource::File(_) => "file",
+            ExporterSource::SplitFile(_) => "split_file",
+            ExporterSource::UnixStream(_) => "unix_stream",
+            ExporterSource::UnixDatagram(_) => "unix_datagram",
+            ExporterSource::Log(_) => "log",
+            ExporterSource::Stdout(_) => "stdout",
+            ExporterSource::Stderr(_) => "stderr",
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -102,6 +593,28 @@ pub struct UdpConfig {
     pub host: String,
     pub port: u16,
     pub encoder: Encoder,
+    /// Interval at which a hostname target is resolved again, reconnecting the socket if the address
+    /// has changed (e.g. a collector behind a rotating DNS name). IP targets are never re-resolved.
+    /// Disabled by default.
+    #[serde(default, deserialize_with = "deserialize_option_duration")]
+    pub resolve_interval: Option<Duration>,
+    /// Local address the socket is bound to, e.g. `0.0.0.0:0` to send to a remote collector or the
+    /// address of a specific interface. Must be of the same family (IPv4 or IPv6) as the target. Defaults
+    /// to `127.0.0.1:0` for IPv4 targets and `[::1]:0` for IPv6 targets.
+    #[serde(default)]
+    pub bind: Option<SocketAddr>,
+    /// Prefix each datagram with a sequence number, see [UnixSocketConfig::sequence]. Disabled by default.
+    #[serde(default)]
+    pub sequence: bool,
+    /// Maximum size of a datagram, see [UnixSocketConfig::max_datagram_size]. Defaults to 1432, so that
+    /// datagrams fit into a single Ethernet frame and are not fragmented (or dropped) on the way to a
+    /// remote collector. Can be raised for local targets, e.g. up to 8192 for a collector on loopback.
+    #[serde(default = "get_default_max_udp_datagram_size")]
+    pub max_datagram_size: usize,
+    /// Initial capacity in bytes of the exporter's buffer, see [UnixSocketConfig::buffer_capacity].
+    /// Defaults to 1024.
+    #[serde(default = "get_default_buffer_capacity")]
+    pub buffer_capacity: usize,
 }
 
 impl ToSocketAddrs for UdpConfig {
@@ -112,14 +625,291 @@ impl ToSocketAddrs for UdpConfig {
     }
 }
 
+/// Writes metrics to a remote collector over a TCP connection, for reliable delivery. If the connection
+/// is lost (`BrokenPipe` or `ConnectionReset`), the metrics of that publish are dropped and the exporter
+/// reconnects before a later publish, backing off exponentially up to `max_reconnect_backoff` between
+/// attempts. The initial connection must succeed for the agent to start.
+///
+/// ```yaml
+/// exporter:
+///   type: tcp
+///   config:
+///     host: collector.internal
+///     port: 8094
+///     encoder: line_protocol
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TcpConfig {
+    pub host: String,
+    pub port: u16,
+    pub encoder: Encoder,
+    /// Maximum time between reconnection attempts. Defaults to 10 seconds.
+    #[serde(
+        default = "get_default_max_reconnect_backoff",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub max_reconnect_backoff: Duration,
+}
+
+impl ToSocketAddrs for TcpConfig {
+    type Iter = vec::IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> std::io::Result<Self::Iter> {
+        format!("{}:{}", self.host, self.port).to_socket_addrs()
+    }
+}
+
+const fn get_default_max_reconnect_backoff() -> Duration {
+    Duration::from_secs(10)
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FileConfig {
     pub path: String,
     pub encoder: Encoder,
+    /// Call `fsync` (`File::sync_data`) after each publish so that exported metrics survive a crash or
+    /// power loss. Every publish then waits for the storage device, which can take milliseconds and
+    /// limits how short the flush interval can be. Disabled by default.
+    #[serde(default)]
+    pub sync_on_publish: bool,
+    /// Compress the file as it is written, see [Compression]. Disabled by default.
+    #[serde(default)]
+    pub compression: Option<Compression>,
+}
+
+/// Compression applied by the file and unix stream exporters as metrics are written.
+///
+/// ```yaml
+/// exporter:
+///   type: file
+///   config:
+///     path: metrics/metrics.lp.gz
+///     encoder: line_protocol
+///     compression: gzip
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    /// Gzip stream, readable with e.g. `zcat`. Every publish flushes the compressor, so the metrics
+    /// written so far can be decompressed while the file is still being written, at the expense of a
+    /// lower compression ratio for short flush intervals. The gzip trailer is written when the exporter
+    /// is dropped, i.e. when the agent is dropped after being replaced with [metricus::swap_metrics];
+    /// until then, readers report an unexpected end of file after the last complete publish. A unix
+    /// stream exporter starts a new gzip stream on every (re)connection.
+    Gzip,
+}
+
+/// Writes counters and histograms into two separate files, each with its own encoder.
+///
+/// ```yaml
+/// exporter:
+///   type: split_file
+///   config:
+///     counters:
+///       path: metrics/counters.jsonl
+///       encoder: json
+///     histograms:
+///       path: metrics/histograms.lp
+///       encoder: line_protocol
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SplitFileConfig {
+    pub counters: FileConfig,
+    pub histograms: FileConfig,
+}
+
+/// Writes metrics to the standard output or error stream, e.g. to look at them during development.
+///
+/// ```yaml
+/// exporter:
+///   type: stdout
+///   config:
+///     encoder: line_protocol
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConsoleConfig {
+    pub encoder: Encoder,
+}
+
+/// Logs a summary of the top metrics on every publish, for services without a metrics collector.
+/// Summaries are logged at `info` level with the `metricus_agent::summary` target ([crate::LOG_SUMMARY_TARGET]),
+/// one line for counters (by value) and one for histograms (by number of samples recorded during the interval).
+///
+/// ```yaml
+/// exporter:
+///   type: log
+///   config:
+///     top_n: 5
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogConfig {
+    /// Maximum number of counters and of histograms included in each summary. Defaults to 10.
+    #[serde(default = "get_default_top_n")]
+    pub top_n: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            top_n: get_default_top_n(),
+        }
+    }
+}
+
+const fn get_default_top_n() -> usize {
+    10
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UnixSocketConfig {
     pub path: String,
     pub encoder: Encoder,
+    /// Prefix each datagram with a sequence number so that the receiver can detect lost and reordered
+    /// datagrams. The sequence number is written as 8 bytes in big-endian order ahead of the encoded
+    /// metrics and increases by one for every datagram, including the ones that could not be sent
+    /// (which are counted by the `unsent_datagrams` self-metric). Receivers must strip the header before
+    /// decoding the payload. Only applies to datagram exporters. Disabled by default.
+    #[serde(default)]
+    pub sequence: bool,
+    /// Maximum size in bytes of a datagram. Metrics that do not fit are sent in further datagrams, an
+    /// encoded metric is never split across datagrams. Must not exceed the limit of the socket (e.g. the
+    /// `net.core.wmem_default` sysctl for unix datagram sockets on Linux) or sends fail with `EMSGSIZE`.
+    /// Only applies to datagram exporters. Defaults to 8192.
+    #[serde(default = "get_default_max_datagram_size")]
+    pub max_datagram_size: usize,
+    /// Initial capacity in bytes of the exporter's buffer. The buffer grows as needed while publishing,
+    /// so setting this to the size of the largest datagram (i.e. `max_datagram_size`, or more if single
+    /// encoded metrics can be larger) avoids reallocations mid-publish, while a smaller capacity saves
+    /// memory when few metrics are published. Only applies to datagram exporters. Defaults to 1024.
+    #[serde(default = "get_default_buffer_capacity")]
+    pub buffer_capacity: usize,
+    /// Maximum time between attempts to reconnect to the socket once the connection has been lost,
+    /// e.g. because the listener restarted, see [TcpConfig]. Only applies to the stream exporter.
+    /// Defaults to 10 seconds.
+    #[serde(
+        default = "get_default_max_reconnect_backoff",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub max_reconnect_backoff: Duration,
+    /// Compress the stream as it is written, see [Compression]. Only applies to the stream exporter.
+    /// Disabled by default.
+    #[serde(default)]
+    pub compression: Option<Compression>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(vars: &[(&str, &str)]) -> crate::Result<EnvOverrides> {
+        EnvOverrides::from_vars(|key| Ok(vars.iter().find(|(k, _)| *k == key).map(|(_, v)| v.to_string())))
+    }
+
+    fn apply(base: &str, vars: &[(&str, &str)]) -> crate::Result<ExporterSource> {
+        overrides(vars)?.apply(serde_yaml::from_str(base).unwrap())
+    }
+
+    fn error(base: &str, vars: &[(&str, &str)]) -> String {
+        apply(base, vars).unwrap_err().to_string()
+    }
+
+    const FILE: &str = "type: file\nconfig:\n  path: metrics.lp\n  encoder: line_protocol\n";
+    const UDP: &str = "type: udp\nconfig:\n  host: collector\n  port: 8125\n  encoder: dog_statsd\n";
+
+    #[test]
+    fn switches_to_the_exporter_built_from_the_environment() {
+        let vars = [
+            ("METRICUS_EXPORTER", "udp"),
+            ("METRICUS_UDP_ADDR", "10.0.0.5:8125"),
+            ("METRICUS_ENCODER", "statsd"),
+        ];
+        let ExporterSource::Udp(config) = apply(FILE, &vars).unwrap() else {
+            panic!("expected a udp exporter");
+        };
+        assert_eq!(("10.0.0.5", 8125), (config.host.as_str(), config.port));
+        assert!(matches!(config.encoder, Encoder::Statsd { plain: false }));
+        assert_eq!(get_default_max_udp_datagram_size(), config.max_datagram_size);
+    }
+
+    #[test]
+    fn patches_the_configured_exporter() {
+        let ExporterSource::Udp(config) = apply(UDP, &[("METRICUS_UDP_ADDR", "[::1]:9125")]).unwrap() else {
+            panic!("expected a udp exporter");
+        };
+        assert_eq!(("[::1]", 9125), (config.host.as_str(), config.port));
+        assert!(matches!(config.encoder, Encoder::DogStatsd));
+
+        let vars = [
+            ("METRICUS_EXPORTER", "file"),
+            ("METRICUS_FILE_PATH", "/tmp/metrics.json"),
+            ("METRICUS_ENCODER", "json"),
+        ];
+        let ExporterSource::File(config) = apply(FILE, &vars).unwrap() else {
+            panic!("expected a file exporter");
+        };
+        assert_eq!("/tmp/metrics.json", config.path);
+        assert!(matches!(config.encoder, Encoder::Json));
+    }
+
+    #[test]
+    fn buffer_capacity_defaults_to_1024() {
+        let ExporterSource::Udp(config) = serde_yaml::from_str(UDP).unwrap() else {
+            panic!("expected a udp exporter");
+        };
+        assert_eq!(1024, config.buffer_capacity);
+        let unix = "type: unix_datagram\nconfig:\n  path: /tmp/metrics.sock\n  encoder: dog_statsd\n";
+        let ExporterSource::UnixDatagram(config) = serde_yaml::from_str(unix).unwrap() else {
+            panic!("expected a unix datagram exporter");
+        };
+        assert_eq!(1024, config.buffer_capacity);
+
+        let ExporterSource::Udp(config) = serde_yaml::from_str(&format!("{UDP}  buffer_capacity: 65536\n")).unwrap()
+        else {
+            panic!("expected a udp exporter");
+        };
+        assert_eq!(65536, config.buffer_capacity);
+    }
+
+    #[test]
+    fn keeps_the_configured_exporter_without_variables() {
+        let ExporterSource::File(config) = apply(FILE, &[]).unwrap() else {
+            panic!("expected a file exporter");
+        };
+        assert_eq!("metrics.lp", config.path);
+    }
+
+    #[test]
+    fn rejects_malformed_and_inapplicable_values() {
+        assert!(error(FILE, &[("METRICUS_EXPORTER", "carrier_pigeon")]).contains("unknown exporter 'carrier_pigeon'"));
+        assert!(error(FILE, &[("METRICUS_ENCODER", "xml")]).contains("unknown encoder 'xml'"));
+        for addr in ["10.0.0.5", "::1:8125", "collector:port", ":8125", "collector:70000"] {
+            let err = error(UDP, &[("METRICUS_UDP_ADDR", addr)]);
+            assert!(err.contains("invalid METRICUS_UDP_ADDR"), "{addr} accepted: {err}");
+        }
+        let err = error(FILE, &[("METRICUS_UDP_ADDR", "10.0.0.5:8125")]);
+        assert!(err.contains("METRICUS_UDP_ADDR is set but does not apply to the 'file' exporter"), "{err}");
+        let err = error(FILE, &[("METRICUS_EXPORTER", "udp"), ("METRICUS_ENCODER", "statsd")]);
+        assert!(err.contains("METRICUS_UDP_ADDR is required for the 'udp' exporter"), "{err}");
+    }
+
+    #[test]
+    fn reads_the_environment() {
+        // the only test that sets these variables, and nothing else in the process reads them
+        unsafe {
+            std::env::set_var("METRICUS_EXPORTER", "tcp");
+            std::env::set_var("METRICUS_TCP_ADDR", "collector.internal:2003");
+            std::env::set_var("METRICUS_ENCODER", "line_protocol");
+        }
+        let config = MetricsConfig::default().with_env_overrides();
+        unsafe {
+            std::env::remove_var("METRICUS_EXPORTER");
+            std::env::remove_var("METRICUS_TCP_ADDR");
+            std::env::remove_var("METRICUS_ENCODER");
+        }
+        let ExporterSource::Tcp(config) = config.unwrap().exporter else {
+            panic!("expected a tcp exporter");
+        };
+        assert_eq!(("collector.internal", 2003), (config.host.as_str(), config.port));
+        assert!(matches!(config.encoder, Encoder::LineProtocol));
+    }
 }
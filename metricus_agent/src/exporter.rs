@@ -1,23 +1,62 @@
-use crate::aggregator::{Counters, Encoder, Histograms};
-use crate::config::{ExporterSource, FileConfig, UdpConfig, UnixSocketConfig};
-use log::warn;
-use metricus::Id;
-use std::collections::HashMap;
+use crate::aggregator::{Counters, Encoder, Histograms, LogSummary};
+use crate::config::{
+    Compression, ConsoleConfig, ExporterSource, FileConfig, LogConfig, SplitFileConfig, TcpConfig, UdpConfig,
+    UnixSocketConfig,
+};
+use flate2::write::GzEncoder;
+use log::{info, warn};
 use std::fs::{File, create_dir_all};
-use std::io::{BufWriter, ErrorKind, Write};
-use std::net::UdpSocket;
+use std::io::{BufWriter, ErrorKind, Stderr, Stdout, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
 use std::os::unix::net::{UnixDatagram, UnixStream};
 use std::path::Path;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
 
 type FileExporter = StreamExporter<File>;
-type UnixStreamExporter = StreamExporter<UnixStream>;
+type StdoutExporter = StreamExporter<Stdout>;
+type StderrExporter = StreamExporter<Stderr>;
+type TcpExporter = ReconnectingExporter<TcpConfig>;
+type UnixStreamExporter = ReconnectingExporter<UnixSocketConfig>;
 
 pub enum Exporter {
     NoOp,
     Udp(UdpExporter),
+    Tcp(TcpExporter),
     File(FileExporter),
+    SplitFile(SplitFileExporter),
     UnixStream(UnixStreamExporter),
     UnixDatagram(UnixDatagramExporter),
+    Log(LogExporter),
+    Stdout(StdoutExporter),
+    Stderr(StderrExporter),
+}
+
+/// Exporter and encoders used by the running agent, see [crate::MetricsAgent::exporter_info].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExporterInfo {
+    /// Exporter type as used in the config, e.g. `udp`.
+    pub exporter: &'static str,
+    /// Encoders as used in the config, one per file for the `split_file` exporter (counters first).
+    pub encoders: Vec<&'static str>,
+    /// Address the metrics are sent to or path of the file or socket they are written to, if any.
+    pub target: Option<String>,
+}
+
+/// Info of the exporter created most recently.
+static ACTIVE_EXPORTER_INFO: RwLock<Option<ExporterInfo>> = RwLock::new(None);
+
+impl ExporterInfo {
+    pub(crate) fn set_active(info: ExporterInfo) {
+        *ACTIVE_EXPORTER_INFO.write().unwrap_or_else(|err| err.into_inner()) = Some(info);
+    }
+
+    pub(crate) fn active() -> Option<ExporterInfo> {
+        ACTIVE_EXPORTER_INFO
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
 }
 
 impl TryFrom<ExporterSource> for Exporter {
@@ -27,97 +66,326 @@ impl TryFrom<ExporterSource> for Exporter {
         match source {
             ExporterSource::NoOp => Ok(Exporter::NoOp),
             ExporterSource::Udp(config) => Ok(Exporter::Udp(UdpExporter::try_from(config)?)),
+            ExporterSource::Tcp(config) => Ok(Exporter::Tcp(TcpExporter::try_from(config)?)),
             ExporterSource::File(config) => Ok(Exporter::File(FileExporter::try_from(config)?)),
+            ExporterSource::SplitFile(config) => Ok(Exporter::SplitFile(SplitFileExporter::try_from(config)?)),
             ExporterSource::UnixStream(config) => Ok(Exporter::UnixStream(UnixStreamExporter::try_from(config)?)),
             ExporterSource::UnixDatagram(config) => Ok(Exporter::UnixDatagram(UnixDatagramExporter::try_from(config)?)),
+            ExporterSource::Log(config) => Ok(Exporter::Log(LogExporter::from(config))),
+            ExporterSource::Stdout(config) => Ok(Exporter::Stdout(StdoutExporter::from(config))),
+            ExporterSource::Stderr(config) => Ok(Exporter::Stderr(StderrExporter::from(config))),
         }
     }
 }
 
 impl Exporter {
-    pub fn publish_counters(&mut self, counters: &Counters, timestamp: u64) -> std::io::Result<()> {
+    pub fn info(&self) -> ExporterInfo {
+        let (exporter, encoders, target) = match self {
+            Exporter::NoOp => ("no_op", vec![], None),
+            Exporter::Udp(exporter) => ("udp", vec![exporter.encoder.name()], Some(exporter.peer.to_string())),
+            Exporter::Tcp(exporter) => ("tcp", vec![exporter.encoder.name()], Some(exporter.target.describe())),
+            Exporter::File(exporter) => ("file", vec![exporter.encoder.name()], Some(exporter.target.clone())),
+            Exporter::SplitFile(exporter) => (
+                "split_file",
+                vec![exporter.counters.encoder.name(), exporter.histograms.encoder.name()],
+                Some(format!("{},{}", exporter.counters.target, exporter.histograms.target)),
+            ),
+            Exporter::UnixStream(exporter) => {
+                ("unix_stream", vec![exporter.encoder.name()], Some(exporter.target.describe()))
+            }
+            Exporter::UnixDatagram(exporter) => {
+                ("unix_datagram", vec![exporter.encoder.name()], Some(exporter.path.clone()))
+            }
+            Exporter::Log(_) => ("log", vec![], Some(LOG_SUMMARY_TARGET.to_owned())),
+            Exporter::Stdout(exporter) => ("stdout", vec![exporter.encoder.name()], Some(exporter.target.clone())),
+            Exporter::Stderr(exporter) => ("stderr", vec![exporter.encoder.name()], Some(exporter.target.clone())),
+        };
+        ExporterInfo {
+            exporter,
+            encoders,
+            target,
+        }
+    }
+
+    /// Publishes counters and histograms together. Datagram exporters pack both into the same datagrams
+    /// and stream exporters flush once, rather than once per metric type, which halves the number of
+    /// `send`/`write` calls per publish when all metrics fit into a single datagram or write buffer. With
+    /// a datagram size of 8192, 50 counters and 10 histograms take 1 `send` call instead of 2, as measured
+    /// by the `unix_datagram_sends_counters_and_histograms_together` test.
+    pub fn publish(&mut self, counters: &Counters, histograms: &Histograms, timestamp: u64) -> std::io::Result<()> {
         match self {
             Exporter::NoOp => Ok(()),
-            Exporter::Udp(exporter) => exporter.publish_counters(counters, timestamp),
-            Exporter::File(exporter) => exporter.publish_counters(counters, timestamp),
-            Exporter::UnixStream(exporter) => exporter.publish_counters(counters, timestamp),
-            Exporter::UnixDatagram(exporter) => exporter.publish_counters(counters, timestamp),
+            Exporter::Udp(exporter) => exporter.publish(counters, histograms, timestamp),
+            Exporter::Tcp(exporter) => exporter.publish(counters, histograms, timestamp),
+            Exporter::File(exporter) => exporter.publish(counters, histograms, timestamp),
+            Exporter::SplitFile(exporter) => {
+                exporter.counters.publish_counters(counters, timestamp)?;
+                exporter.histograms.publish_histograms(histograms, timestamp)
+            }
+            Exporter::UnixStream(exporter) => exporter.publish(counters, histograms, timestamp),
+            Exporter::UnixDatagram(exporter) => exporter.publish(counters, histograms, timestamp),
+            Exporter::Log(exporter) => {
+                exporter.publish_counters(counters)?;
+                exporter.publish_histograms(histograms)
+            }
+            Exporter::Stdout(exporter) => exporter.publish(counters, histograms, timestamp),
+            Exporter::Stderr(exporter) => exporter.publish(counters, histograms, timestamp),
         }
     }
 
-    pub fn publish_histograms(&mut self, histograms: &Histograms, timestamp: u64) -> std::io::Result<()> {
+    /// Writes out any data still buffered by the exporter. Every publish already flushes what it wrote,
+    /// so this only has an effect after a publish failed midway, and is called when the exporter is
+    /// dropped, e.g. when the agent shuts down.
+    pub fn flush(&mut self) -> std::io::Result<()> {
         match self {
-            Exporter::NoOp => Ok(()),
-            Exporter::Udp(exporter) => exporter.publish_histograms(histograms, timestamp),
-            Exporter::File(exporter) => exporter.publish_histograms(histograms, timestamp),
-            Exporter::UnixStream(exporter) => exporter.publish_histograms(histograms, timestamp),
-            Exporter::UnixDatagram(exporter) => exporter.publish_histograms(histograms, timestamp),
+            Exporter::NoOp | Exporter::Log(_) => Ok(()),
+            Exporter::Udp(exporter) => exporter.flush(),
+            Exporter::Tcp(exporter) => exporter.flush(),
+            Exporter::File(exporter) => exporter.flush(),
+            Exporter::SplitFile(exporter) => {
+                exporter.counters.flush()?;
+                exporter.histograms.flush()
+            }
+            Exporter::UnixStream(exporter) => exporter.flush(),
+            Exporter::UnixDatagram(exporter) => exporter.flush(),
+            Exporter::Stdout(exporter) => exporter.flush(),
+            Exporter::Stderr(exporter) => exporter.flush(),
+        }
+    }
+
+    /// Number of datagrams that could not be sent since the last call.
+    pub fn take_unsent_datagrams(&mut self) -> u64 {
+        match self {
+            Exporter::Udp(exporter) => std::mem::take(&mut exporter.unsent),
+            Exporter::UnixDatagram(exporter) => std::mem::take(&mut exporter.unsent),
+            _ => 0,
+        }
+    }
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        let result = match self {
+            Exporter::File(exporter) => exporter.finish(),
+            Exporter::SplitFile(exporter) => exporter.counters.finish().and(exporter.histograms.finish()),
+            Exporter::UnixStream(exporter) => exporter.finish(),
+            _ => self.flush(),
+        };
+        if let Err(err) = result {
+            warn!("Failed to flush metrics exporter: [{}]", err);
+        }
+    }
+}
+
+/// Sends a single datagram.
+type SendDatagram<'a> = dyn FnMut(&[u8]) -> std::io::Result<()> + 'a;
+
+/// Accumulates encoded metrics into datagrams of bounded size, optionally prefixed with a sequence number.
+struct DatagramBuffer {
+    buffer: Vec<u8>,
+    max_size: usize,
+    sequence: Option<u64>,
+}
+
+impl DatagramBuffer {
+    fn new(max_size: usize, capacity: usize, sequence: bool) -> Self {
+        Self {
+            buffer: Vec::with_capacity(capacity),
+            max_size,
+            sequence: sequence.then_some(0),
+        }
+    }
+
+    fn header_len(&self) -> usize {
+        if self.sequence.is_some() { size_of::<u64>() } else { 0 }
+    }
+
+    /// Starts a new batch of metrics. Metrics pushed into the batch are sent in as many datagrams as needed
+    /// to stay within the size limit. An encoded metric is never split, so a single metric larger than the
+    /// limit is sent in a datagram of its own.
+    fn begin(&mut self) {
+        self.buffer.clear();
+        self.buffer.resize(self.header_len(), 0);
+    }
+
+    /// Encodes a metric into the current datagram, sending the datagram first if the metric does not fit.
+    fn push<E>(&mut self, encode: E, send: &mut SendDatagram) -> std::io::Result<()>
+    where
+        E: FnOnce(&mut Vec<u8>) -> std::io::Result<()>,
+    {
+        let header_len = self.header_len();
+        let start = self.buffer.len();
+        encode(&mut self.buffer)?;
+        if self.buffer.len() > self.max_size && start > header_len {
+            // send what fits and carry the last metric over to the next datagram
+            self.send(start, send)?;
+            self.buffer.drain(header_len..start);
+        }
+        Ok(())
+    }
+
+    /// Sends the last datagram of the batch, if it holds any metrics.
+    fn finish(&mut self, send: &mut SendDatagram) -> std::io::Result<()> {
+        if self.buffer.len() > self.header_len() {
+            self.send(self.buffer.len(), send)?;
         }
+        self.buffer.clear();
+        Ok(())
+    }
+
+    fn send(&mut self, len: usize, send: &mut SendDatagram) -> std::io::Result<()> {
+        if let Some(sequence) = &mut self.sequence {
+            self.buffer[..size_of::<u64>()].copy_from_slice(&sequence.to_be_bytes());
+            *sequence = sequence.wrapping_add(1);
+        }
+        send(&self.buffer[..len])
+    }
+}
+
+/// Encodes counters followed by histograms into the current batch of datagrams.
+fn encode_all(
+    datagrams: &mut DatagramBuffer,
+    encoder: &Encoder,
+    counters: &Counters,
+    histograms: &Histograms,
+    timestamp: u64,
+    send: &mut SendDatagram,
+) -> std::io::Result<()> {
+    for counter in counters.values() {
+        datagrams.push(|buffer| encoder.encode_counter(counter, timestamp, buffer), send)?;
+    }
+    for histogram in histograms.values() {
+        datagrams.push(|buffer| encoder.encode_histogram(histogram, timestamp, buffer), send)?;
     }
+    Ok(())
 }
 
 pub struct UdpExporter {
     socket: UdpSocket,
-    buffer: Vec<u8>,
+    datagrams: DatagramBuffer,
     encoder: Encoder,
+    target: UdpConfig,
+    bind: SocketAddr,
+    peer: SocketAddr,
+    resolve_interval: Option<Duration>,
+    next_resolve: Instant,
+    send_failures: u64,
+    unsent: u64,
 }
 
 impl TryFrom<UdpConfig> for UdpExporter {
     type Error = std::io::Error;
 
     fn try_from(config: UdpConfig) -> Result<Self, Self::Error> {
-        let socket = UdpSocket::bind("127.0.0.1:0")?;
-        socket.connect(&config)?;
+        let peer = resolve(&config, config.bind)?;
+        let bind = config.bind.unwrap_or(match peer {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::LOCALHOST, 0)),
+        });
+        let socket = UdpSocket::bind(bind)?;
+        socket.connect(peer)?;
+        // ip targets never change so there is no point in resolving them again
+        let resolve_interval = match config.host.parse::<IpAddr>() {
+            Ok(_) => None,
+            Err(_) => config.resolve_interval,
+        };
         Ok(Self {
             socket,
-            buffer: Vec::with_capacity(1024),
-            encoder: config.encoder,
+            datagrams: DatagramBuffer::new(config.max_datagram_size, config.buffer_capacity, config.sequence),
+            encoder: config.encoder.clone(),
+            bind,
+            peer,
+            resolve_interval,
+            next_resolve: Instant::now() + resolve_interval.unwrap_or_default(),
+            send_failures: 0,
+            unsent: 0,
+            target: config,
         })
     }
 }
 
+/// Resolves the target to its first address, or to the first address of the same family as `bind` if given.
+fn resolve(target: &impl ToSocketAddrs, bind: Option<SocketAddr>) -> std::io::Result<SocketAddr> {
+    let mut addrs = target.to_socket_addrs()?;
+    match bind {
+        None => addrs
+            .next()
+            .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "unable to resolve udp target")),
+        Some(bind) => addrs.find(|addr| addr.is_ipv4() == bind.is_ipv4()).ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("udp target has no address of the same family as bind address {bind}"),
+            )
+        }),
+    }
+}
+
 impl UdpExporter {
-    fn publish_metrics<T, F>(&mut self, items: &HashMap<Id, T>, timestamp: u64, encode: F) -> std::io::Result<()>
-    where
-        F: Fn(&Encoder, &T, u64, &mut Vec<u8>) -> std::io::Result<()>,
-    {
-        if items.is_empty() {
-            return Ok(());
+    /// Resolves the target hostname again once the resolve interval has elapsed and reconnects
+    /// the socket if the address has changed. Resolution failures keep the current address.
+    fn maybe_resolve(&mut self) {
+        if let Some(resolve_interval) = self.resolve_interval {
+            let now = Instant::now();
+            if now >= self.next_resolve {
+                self.next_resolve = now + resolve_interval;
+                match resolve(&self.target, Some(self.bind)) {
+                    Ok(peer) if peer != self.peer => match self.socket.connect(peer) {
+                        Ok(()) => {
+                            info!("udp target {} changed from {} to {}", self.target.host, self.peer, peer);
+                            self.peer = peer;
+                        }
+                        Err(err) => warn!("Failed to reconnect udp socket to {}: [{}]", peer, err),
+                    },
+                    Ok(_) => {}
+                    Err(err) => warn!("Failed to resolve udp target {}: [{}]", self.target.host, err),
+                }
+            }
         }
+    }
 
-        for item in items.values() {
-            encode(&self.encoder, item, timestamp, &mut self.buffer)?;
+    fn publish(&mut self, counters: &Counters, histograms: &Histograms, timestamp: u64) -> std::io::Result<()> {
+        if counters.is_empty() && histograms.is_empty() {
+            return Ok(());
         }
 
-        // we can ignore connection refused in case the udp listener is temporarily unavailable
-        if let Err(err) = self.socket.send(&self.buffer) {
-            match err.kind() {
-                ErrorKind::ConnectionRefused => warn!("Failed to send metrics via udp: [{}]", err),
-                _ => Err(err)?,
-            }
-        }
+        self.maybe_resolve();
 
-        self.buffer.clear();
-        Ok(())
-    }
-    fn publish_counters(&mut self, counters: &Counters, timestamp: u64) -> std::io::Result<()> {
-        self.publish_metrics(counters, timestamp, |encoder, item, timestamp, buffer| {
-            encoder.encode_counter(item, timestamp, buffer)
-        })
+        let socket = &self.socket;
+        let send_failures = &mut self.send_failures;
+        let unsent = &mut self.unsent;
+        let mut send = |datagram: &[u8]| {
+            // we can ignore connection refused in case the udp listener is temporarily unavailable
+            if let Err(err) = socket.send(datagram) {
+                match err.kind() {
+                    ErrorKind::ConnectionRefused => {
+                        *send_failures += 1;
+                        *unsent += 1;
+                        warn!("Failed to send metrics via udp: [{}] ({} failures so far)", err, send_failures)
+                    }
+                    _ => Err(err)?,
+                }
+            }
+            Ok(())
+        };
+        self.datagrams.begin();
+        encode_all(&mut self.datagrams, &self.encoder, counters, histograms, timestamp, &mut send)?;
+        self.datagrams.finish(&mut send)
     }
 
-    fn publish_histograms(&mut self, histograms: &Histograms, timestamp: u64) -> std::io::Result<()> {
-        self.publish_metrics(histograms, timestamp, |encoder, item, timestamp, buffer| {
-            encoder.encode_histogram(item, timestamp, buffer)
-        })
+    /// Sends the metrics left in the datagram buffer by a failed publish.
+    fn flush(&mut self) -> std::io::Result<()> {
+        let socket = &self.socket;
+        self.datagrams
+            .finish(&mut |datagram: &[u8]| socket.send(datagram).map(|_| ()))
     }
 }
 
 pub struct UnixDatagramExporter {
     socket: UnixDatagram,
-    buffer: Vec<u8>,
+    datagrams: DatagramBuffer,
     encoder: Encoder,
     path: String,
+    unsent: u64,
 }
 
 impl TryFrom<UnixSocketConfig> for UnixDatagramExporter {
@@ -127,55 +395,390 @@ impl TryFrom<UnixSocketConfig> for UnixDatagramExporter {
         let socket = UnixDatagram::unbound()?;
         Ok(Self {
             socket,
-            buffer: Vec::with_capacity(1024),
+            datagrams: DatagramBuffer::new(config.max_datagram_size, config.buffer_capacity, config.sequence),
             encoder: config.encoder,
             path: config.path,
+            unsent: 0,
         })
     }
 }
 
 impl UnixDatagramExporter {
-    fn publish_metrics<T, F>(&mut self, items: &HashMap<Id, T>, timestamp: u64, encode: F) -> std::io::Result<()>
+    fn publish(&mut self, counters: &Counters, histograms: &Histograms, timestamp: u64) -> std::io::Result<()> {
+        if counters.is_empty() && histograms.is_empty() {
+            return Ok(());
+        }
+
+        let socket = &self.socket;
+        let path = &self.path;
+        let unsent = &mut self.unsent;
+        let mut send = |datagram: &[u8]| {
+            // we can ignore file not found in case the listener unix socket is temporarily unavailable
+            if let Err(err) = socket.send_to(datagram, path) {
+                if let ErrorKind::NotFound = err.kind() {
+                    *unsent += 1;
+                    warn!("Failed to send metrics via unix datagram: [{}]", err);
+                } else {
+                    return Err(err);
+                }
+            }
+            Ok(())
+        };
+        self.datagrams.begin();
+        encode_all(&mut self.datagrams, &self.encoder, counters, histograms, timestamp, &mut send)?;
+        self.datagrams.finish(&mut send)
+    }
+
+    /// Sends the metrics left in the datagram buffer by a failed publish.
+    fn flush(&mut self) -> std::io::Result<()> {
+        let (socket, path) = (&self.socket, &self.path);
+        self.datagrams
+            .finish(&mut |datagram: &[u8]| socket.send_to(datagram, path).map(|_| ()))
+    }
+}
+
+/// Schedules reconnection attempts of a stream exporter, doubling the delay after every failed attempt.
+struct Reconnect {
+    backoff: Duration,
+    max_backoff: Duration,
+    next_attempt: Instant,
+    failures: u64,
+}
+
+impl Reconnect {
+    const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+    fn new(max_backoff: Duration) -> Self {
+        Self {
+            backoff: Self::INITIAL_BACKOFF,
+            max_backoff,
+            next_attempt: Instant::now(),
+            failures: 0,
+        }
+    }
+
+    /// Whether the connection has been lost with an error that a new connection may resolve.
+    fn is_disconnect(err: &std::io::Error) -> bool {
+        matches!(err.kind(), ErrorKind::BrokenPipe | ErrorKind::ConnectionReset)
+    }
+
+    fn is_due(&self) -> bool {
+        Instant::now() >= self.next_attempt
+    }
+
+    /// Records a lost connection or failed attempt and returns the number of failures so far.
+    fn failed(&mut self) -> u64 {
+        self.failures += 1;
+        self.next_attempt = Instant::now() + self.backoff;
+        self.backoff = (self.backoff * 2).min(self.max_backoff);
+        self.failures
+    }
+
+    fn connected(&mut self) {
+        self.backoff = Self::INITIAL_BACKOFF;
+    }
+}
+
+/// Target of a [ReconnectingExporter].
+pub trait StreamTarget {
+    type Stream: Write + SyncData;
+
+    /// Exporter type as used in the config, for logging.
+    const KIND: &'static str;
+
+    fn connect(&self) -> std::io::Result<Self::Stream>;
+
+    fn encoder(&self) -> &Encoder;
+
+    fn max_reconnect_backoff(&self) -> Duration;
+
+    /// Compression applied to every connection, none by default.
+    fn compression(&self) -> Option<Compression> {
+        None
+    }
+
+    /// Address or path connected to.
+    fn describe(&self) -> String;
+}
+
+impl StreamTarget for TcpConfig {
+    type Stream = TcpStream;
+    const KIND: &'static str = "tcp";
+
+    fn connect(&self) -> std::io::Result<Self::Stream> {
+        TcpStream::connect(resolve(self, None)?)
+    }
+
+    fn encoder(&self) -> &Encoder {
+        &self.encoder
+    }
+
+    fn max_reconnect_backoff(&self) -> Duration {
+        self.max_reconnect_backoff
+    }
+
+    fn describe(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+impl StreamTarget for UnixSocketConfig {
+    type Stream = UnixStream;
+    const KIND: &'static str = "unix stream";
+
+    fn connect(&self) -> std::io::Result<Self::Stream> {
+        UnixStream::connect(&self.path)
+    }
+
+    fn encoder(&self) -> &Encoder {
+        &self.encoder
+    }
+
+    fn max_reconnect_backoff(&self) -> Duration {
+        self.max_reconnect_backoff
+    }
+
+    fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    fn describe(&self) -> String {
+        self.path.clone()
+    }
+}
+
+/// Writes metrics over a connected stream (TCP or unix socket), reconnecting once the connection has
+/// been lost. Metrics published while disconnected are dropped.
+pub struct ReconnectingExporter<T: StreamTarget> {
+    stream: Option<StreamExporter<T::Stream>>,
+    encoder: Encoder,
+    target: T,
+    reconnect: Reconnect,
+}
+
+impl<T: StreamTarget> ReconnectingExporter<T> {
+    /// Connects to the target, the initial connection must succeed.
+    fn new(target: T) -> std::io::Result<Self> {
+        let stream = target.connect()?;
+        Ok(Self {
+            stream: Some(StreamExporter::new(
+                stream,
+                target.encoder().clone(),
+                target.describe(),
+                target.compression(),
+            )),
+            encoder: target.encoder().clone(),
+            reconnect: Reconnect::new(target.max_reconnect_backoff()),
+            target,
+        })
+    }
+
+    fn maybe_reconnect(&mut self) {
+        if self.stream.is_some() || !self.reconnect.is_due() {
+            return;
+        }
+        match self.target.connect() {
+            Ok(stream) => {
+                info!("Reconnected to {} target {}", T::KIND, self.target.describe());
+                self.reconnect.connected();
+                self.stream = Some(StreamExporter::new(
+                    stream,
+                    self.encoder.clone(),
+                    self.target.describe(),
+                    self.target.compression(),
+                ));
+            }
+            Err(err) => {
+                let failures = self.reconnect.failed();
+                warn!(
+                    "Failed to reconnect to {} target {}: [{}] ({} failures so far)",
+                    T::KIND,
+                    self.target.describe(),
+                    err,
+                    failures
+                );
+            }
+        }
+    }
+
+    fn with_stream<F>(&mut self, publish: F) -> std::io::Result<()>
     where
-        F: Fn(&Encoder, &T, u64, &mut Vec<u8>) -> std::io::Result<()>,
+        F: FnOnce(&mut StreamExporter<T::Stream>) -> std::io::Result<()>,
     {
-        if items.is_empty() {
+        self.maybe_reconnect();
+        let Some(stream) = &mut self.stream else {
             return Ok(());
+        };
+        match publish(stream) {
+            Err(err) if Reconnect::is_disconnect(&err) => {
+                let failures = self.reconnect.failed();
+                warn!(
+                    "Lost connection to {} target {}: [{}] ({} failures so far)",
+                    T::KIND,
+                    self.target.describe(),
+                    err,
+                    failures
+                );
+                // discard the buffered metrics rather than writing them into the broken connection on drop
+                if let Some(stream) = self.stream.take() {
+                    stream.writer.discard();
+                }
+                Ok(())
+            }
+            result => result,
         }
+    }
 
-        for item in items.values() {
-            encode(&self.encoder, item, timestamp, &mut self.buffer)?;
+    fn publish(&mut self, counters: &Counters, histograms: &Histograms, timestamp: u64) -> std::io::Result<()> {
+        if counters.is_empty() && histograms.is_empty() {
+            return Ok(());
         }
+        self.with_stream(|stream| stream.publish(counters, histograms, timestamp))
+    }
 
-        // we can ignore file not found in case the listener unix socket is temporarily unavailable
-        if let Err(err) = self.socket.send_to(&self.buffer, &self.path) {
-            if let ErrorKind::NotFound = err.kind() {
-                warn!("Failed to send metrics via unix datagram: [{}]", err);
-            } else {
-                return Err(err);
-            }
+    /// Flushes the connection, if connected, without attempting to reconnect.
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.stream {
+            Some(stream) => stream.flush(),
+            None => Ok(()),
         }
+    }
 
-        self.buffer.clear();
-        Ok(())
+    /// Finishes the connection, if connected, see [StreamExporter::finish].
+    fn finish(&mut self) -> std::io::Result<()> {
+        match &mut self.stream {
+            Some(stream) => stream.finish(),
+            None => Ok(()),
+        }
     }
+}
 
-    fn publish_counters(&mut self, counters: &Counters, timestamp: u64) -> std::io::Result<()> {
-        self.publish_metrics(counters, timestamp, |encoder, item, timestamp, buffer| {
-            encoder.encode_counter(item, timestamp, buffer)
-        })
+impl TryFrom<TcpConfig> for TcpExporter {
+    type Error = std::io::Error;
+
+    fn try_from(config: TcpConfig) -> Result<Self, Self::Error> {
+        Self::new(config)
     }
+}
 
-    fn publish_histograms(&mut self, histograms: &Histograms, timestamp: u64) -> std::io::Result<()> {
-        self.publish_metrics(histograms, timestamp, |encoder, item, timestamp, buffer| {
-            encoder.encode_histogram(item, timestamp, buffer)
-        })
+impl TryFrom<UnixSocketConfig> for UnixStreamExporter {
+    type Error = std::io::Error;
+
+    fn try_from(config: UnixSocketConfig) -> Result<Self, Self::Error> {
+        Self::new(config)
     }
 }
 
 pub struct StreamExporter<S: Write> {
-    writer: BufWriter<S>,
+    writer: StreamWriter<S>,
     encoder: Encoder,
+    sync_on_publish: bool,
+    /// Path of the file or socket written to.
+    target: String,
+}
+
+/// Buffered writer of a [StreamExporter], optionally compressing what is written.
+enum StreamWriter<S: Write> {
+    Plain(BufWriter<S>),
+    Gzip(GzEncoder<BufWriter<S>>),
+}
+
+impl<S: Write> StreamWriter<S> {
+    fn new(stream: S, compression: Option<Compression>) -> Self {
+        let writer = BufWriter::new(stream);
+        match compression {
+            None => StreamWriter::Plain(writer),
+            Some(Compression::Gzip) => StreamWriter::Gzip(GzEncoder::new(writer, flate2::Compression::default())),
+        }
+    }
+
+    fn get_ref(&self) -> &S {
+        match self {
+            StreamWriter::Plain(writer) => writer.get_ref(),
+            StreamWriter::Gzip(encoder) => encoder.get_ref().get_ref(),
+        }
+    }
+
+    /// Writes the end of the compressed stream, if compressing, and flushes. Nothing can be written after.
+    fn finish(&mut self) -> std::io::Result<()> {
+        match self {
+            StreamWriter::Plain(writer) => writer.flush(),
+            StreamWriter::Gzip(encoder) => {
+                encoder.try_finish()?;
+                encoder.get_mut().flush()
+            }
+        }
+    }
+
+    /// Drops the writer without writing out the buffered data.
+    fn discard(self) {
+        match self {
+            StreamWriter::Plain(writer) => {
+                let _ = writer.into_parts();
+            }
+            // the encoder cannot be taken apart without finishing the stream, so its trailer is written
+            // on drop and fails on the broken connection
+            StreamWriter::Gzip(encoder) => drop(encoder),
+        }
+    }
+}
+
+impl<S: Write> Write for StreamWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            StreamWriter::Plain(writer) => writer.write(buf),
+            StreamWriter::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        match self {
+            StreamWriter::Plain(writer) => writer.write_all(buf),
+            StreamWriter::Gzip(encoder) => encoder.write_all(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            StreamWriter::Plain(writer) => writer.flush(),
+            StreamWriter::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Makes written data durable, on top of flushing it.
+pub trait SyncData {
+    fn sync_data(&self) -> std::io::Result<()>;
+}
+
+impl SyncData for File {
+    fn sync_data(&self) -> std::io::Result<()> {
+        File::sync_data(self)
+    }
+}
+
+impl SyncData for TcpStream {
+    fn sync_data(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SyncData for Stdout {
+    fn sync_data(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SyncData for Stderr {
+    fn sync_data(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SyncData for UnixStream {
+    fn sync_data(&self) -> std::io::Result<()> {
+        Ok(())
+    }
 }
 
 impl TryFrom<FileConfig> for StreamExporter<File> {
@@ -190,38 +793,741 @@ impl TryFrom<FileConfig> for StreamExporter<File> {
         }
         let file = File::create(path)?;
         Ok(Self {
-            writer: BufWriter::new(file),
+            writer: StreamWriter::new(file, config.compression),
             encoder: config.encoder,
+            sync_on_publish: config.sync_on_publish,
+            target: config.path,
         })
     }
 }
 
-impl TryFrom<UnixSocketConfig> for StreamExporter<UnixStream> {
+impl From<ConsoleConfig> for StdoutExporter {
+    fn from(config: ConsoleConfig) -> Self {
+        Self::new(std::io::stdout(), config.encoder, "stdout".to_owned(), None)
+    }
+}
+
+impl From<ConsoleConfig> for StderrExporter {
+    fn from(config: ConsoleConfig) -> Self {
+        Self::new(std::io::stderr(), config.encoder, "stderr".to_owned(), None)
+    }
+}
+
+/// Routes counters and histograms to two separate files.
+pub struct SplitFileExporter {
+    counters: FileExporter,
+    histograms: FileExporter,
+}
+
+impl TryFrom<SplitFileConfig> for SplitFileExporter {
     type Error = std::io::Error;
 
-    fn try_from(config: UnixSocketConfig) -> Result<Self, Self::Error> {
-        let stream = UnixStream::connect(config.path)?;
+    fn try_from(config: SplitFileConfig) -> Result<Self, Self::Error> {
         Ok(Self {
-            writer: BufWriter::new(stream),
-            encoder: config.encoder,
+            counters: FileExporter::try_from(config.counters)?,
+            histograms: FileExporter::try_from(config.histograms)?,
         })
     }
 }
 
-impl<S: Write> StreamExporter<S> {
+impl<S: Write + SyncData> StreamExporter<S> {
+    fn new(stream: S, encoder: Encoder, target: String, compression: Option<Compression>) -> Self {
+        Self {
+            writer: StreamWriter::new(stream, compression),
+            encoder,
+            sync_on_publish: false,
+            target,
+        }
+    }
+
     fn publish_counters(&mut self, counters: &Counters, timestamp: u64) -> std::io::Result<()> {
         for counter in counters.values() {
             self.encoder.encode_counter(counter, timestamp, &mut self.writer)?;
         }
-        self.writer.flush()?;
-        Ok(())
+        self.flush()
     }
 
     fn publish_histograms(&mut self, histograms: &Histograms, timestamp: u64) -> std::io::Result<()> {
         for histogram in histograms.values() {
             self.encoder.encode_histogram(histogram, timestamp, &mut self.writer)?;
         }
+        self.flush()
+    }
+
+    fn publish(&mut self, counters: &Counters, histograms: &Histograms, timestamp: u64) -> std::io::Result<()> {
+        for counter in counters.values() {
+            self.encoder.encode_counter(counter, timestamp, &mut self.writer)?;
+        }
+        for histogram in histograms.values() {
+            self.encoder.encode_histogram(histogram, timestamp, &mut self.writer)?;
+        }
+        self.flush()
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
         self.writer.flush()?;
+        if self.sync_on_publish {
+            self.writer.get_ref().sync_data()?;
+        }
         Ok(())
     }
+
+    /// Flushes and, if compressing, writes the end of the compressed stream. Called when the exporter is
+    /// dropped, nothing can be published after.
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.finish()?;
+        if self.sync_on_publish {
+            self.writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+/// Log target of the summaries logged by the log exporter, see [LogConfig].
+pub const LOG_SUMMARY_TARGET: &str = "metricus_agent::summary";
+
+/// Logs a summary of the top metrics at `info` level, see [LogConfig].
+pub struct LogExporter {
+    top_n: usize,
+}
+
+impl From<LogConfig> for LogExporter {
+    fn from(config: LogConfig) -> Self {
+        Self { top_n: config.top_n }
+    }
+}
+
+impl LogExporter {
+    fn publish_counters(&mut self, counters: &Counters) -> std::io::Result<()> {
+        if let Some(summary) = LogSummary::counters(counters, self.top_n) {
+            info!(target: LOG_SUMMARY_TARGET, "{}", summary);
+        }
+        Ok(())
+    }
+
+    fn publish_histograms(&mut self, histograms: &Histograms) -> std::io::Result<()> {
+        if let Some(summary) = LogSummary::histograms(histograms, self.top_n) {
+            info!(target: LOG_SUMMARY_TARGET, "{}", summary);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregator::{Counter, Histogram, MetricSettings};
+    use metricus::Id;
+
+    /// Counters `counter_0` to `counter_{count - 1}`, each incremented by one.
+    fn counters(count: Id) -> Counters {
+        let settings = MetricSettings::default();
+        (0..count)
+            .map(|id| {
+                let tags = vec![("venue".to_owned(), "lse".to_owned())];
+                let mut counter = Counter::new(format!("counter_{id}"), tags, &settings);
+                counter.increment(1);
+                (id, counter)
+            })
+            .collect()
+    }
+
+    /// Histograms `histogram_0` to `histogram_{count - 1}`, each with a single value recorded.
+    fn histograms(count: Id) -> Histograms {
+        let settings = MetricSettings::default();
+        (0..count)
+            .map(|id| {
+                let tags = vec![("venue".to_owned(), "lse".to_owned())];
+                let mut histogram = Histogram::new(format!("histogram_{id}"), tags, &settings);
+                histogram.record(100).unwrap();
+                (id, histogram)
+            })
+            .collect()
+    }
+
+    fn udp_config(host: &str, port: u16, bind: Option<SocketAddr>) -> UdpConfig {
+        UdpConfig {
+            host: host.to_owned(),
+            port,
+            encoder: Encoder::LineProtocol,
+            resolve_interval: None,
+            bind,
+            sequence: false,
+            max_datagram_size: 1432,
+            buffer_capacity: 1024,
+        }
+    }
+
+    /// Unique path for a unix socket in the temp directory, removed if left over from a previous run.
+    fn socket_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("metricus_{name}_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    fn unix_config(path: &str, max_datagram_size: usize) -> UnixSocketConfig {
+        UnixSocketConfig {
+            path: path.to_owned(),
+            encoder: Encoder::LineProtocol,
+            sequence: false,
+            max_datagram_size,
+            buffer_capacity: 1024,
+            max_reconnect_backoff: Duration::from_millis(10),
+            compression: None,
+        }
+    }
+
+    fn file_exporter(name: &str, compression: Option<Compression>) -> (Exporter, String) {
+        let path = std::env::temp_dir().join(format!("metricus_{name}_{}.txt", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let config = FileConfig {
+            path: path.clone(),
+            encoder: Encoder::LineProtocol,
+            sync_on_publish: false,
+            compression,
+        };
+        (Exporter::try_from(ExporterSource::File(config)).unwrap(), path)
+    }
+
+    #[test]
+    fn file_is_readable_after_flush() {
+        let (mut exporter, path) = file_exporter("flush", None);
+        exporter.publish(&counters(2), &Histograms::new(), 7).unwrap();
+        exporter.flush().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        // counters are published in no particular order
+        let mut lines = written.lines().collect::<Vec<_>>();
+        lines.sort();
+        assert_eq!(["counter_0,venue=lse value=1u 7", "counter_1,venue=lse value=1u 7"], lines.as_slice());
+        drop(exporter);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    impl SyncData for Vec<u8> {
+        fn sync_data(&self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Stream that records how many bytes had been written whenever it was synced.
+    #[derive(Default)]
+    struct SyncedStream {
+        written: Vec<u8>,
+        synced_at: std::cell::RefCell<Vec<usize>>,
+    }
+
+    impl Write for SyncedStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SyncData for SyncedStream {
+        fn sync_data(&self) -> std::io::Result<()> {
+            self.synced_at.borrow_mut().push(self.written.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sync_on_publish_syncs_once_everything_published_is_flushed() {
+        let mut exporter =
+            StreamExporter::new(SyncedStream::default(), Encoder::LineProtocol, "buffer".to_owned(), None);
+        exporter.publish(&counters(1), &Histograms::new(), 7).unwrap();
+        assert!(exporter.writer.get_ref().synced_at.borrow().is_empty());
+
+        exporter.sync_on_publish = true;
+        exporter.publish(&counters(1), &Histograms::new(), 8).unwrap();
+        exporter.publish_histograms(&histograms(1), 9).unwrap();
+        let stream = exporter.writer.get_ref();
+        let written = String::from_utf8(stream.written.clone()).unwrap();
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(3, lines.len(), "{written}");
+        // each sync comes after the lines of its publish have left the buffer
+        let after_second = lines[..2].iter().map(|line| line.len() + 1).sum::<usize>();
+        assert_eq!(vec![after_second, written.len()], *stream.synced_at.borrow());
+
+        // and for files the published lines are on disk without flushing the exporter
+        let path = std::env::temp_dir().join(format!("metricus_sync_{}.txt", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let config = FileConfig {
+            path: path.clone(),
+            encoder: Encoder::LineProtocol,
+            sync_on_publish: true,
+            compression: None,
+        };
+        let mut exporter = Exporter::try_from(ExporterSource::File(config)).unwrap();
+        exporter.publish(&counters(1), &Histograms::new(), 7).unwrap();
+        assert_eq!("counter_0,venue=lse value=1u 7\n", std::fs::read_to_string(&path).unwrap());
+        drop(exporter);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn console_exporters_are_configured_with_an_encoder() {
+        for (kind, encoder) in [("stdout", "json"), ("stderr", "line_protocol")] {
+            let config = format!("type: {kind}\nconfig:\n  encoder: {encoder}\n");
+            let exporter = Exporter::try_from(serde_yaml::from_str::<ExporterSource>(&config).unwrap()).unwrap();
+            let expected = ExporterInfo {
+                exporter: kind,
+                encoders: vec![encoder],
+                target: Some(kind.to_owned()),
+            };
+            assert_eq!(expected, exporter.info());
+        }
+    }
+
+    #[test]
+    fn stream_exporter_writes_one_encoded_line_per_metric() {
+        // the console exporters are stream exporters over stdout/stderr, written to a buffer here instead
+        let mut exporter = StreamExporter::new(Vec::new(), Encoder::LineProtocol, "buffer".to_owned(), None);
+        exporter.publish(&counters(1), &histograms(1), 7).unwrap();
+        let written = String::from_utf8(exporter.writer.get_ref().clone()).unwrap();
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(2, lines.len(), "{written}");
+        assert_eq!("counter_0,venue=lse value=1u 7", lines[0]);
+        assert!(lines[1].starts_with("histogram_0,venue=lse "), "{written}");
+        assert!(lines[1].ends_with(" 7"), "{written}");
+    }
+
+    #[test]
+    fn split_file_writes_counters_and_histograms_to_separate_files() {
+        let path = |kind: &str| {
+            let path = std::env::temp_dir().join(format!("metricus_split_{kind}_{}.txt", std::process::id()));
+            path.to_string_lossy().into_owned()
+        };
+        let (counters_path, histograms_path) = (path("counters"), path("histograms"));
+        let file_config = |path: &str, encoder| FileConfig {
+            path: path.to_owned(),
+            encoder,
+            sync_on_publish: false,
+            compression: None,
+        };
+        let config = SplitFileConfig {
+            counters: file_config(&counters_path, Encoder::Json),
+            histograms: file_config(&histograms_path, Encoder::LineProtocol),
+        };
+        let mut exporter = Exporter::try_from(ExporterSource::SplitFile(config)).unwrap();
+        exporter.publish(&counters(2), &histograms(1), 7).unwrap();
+        exporter.flush().unwrap();
+
+        let counters = std::fs::read_to_string(&counters_path).unwrap();
+        let mut names = counters
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["name"].to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(["\"counter_0\"", "\"counter_1\""], names.as_slice());
+        let histograms = std::fs::read_to_string(&histograms_path).unwrap();
+        assert_eq!(1, histograms.lines().count());
+        assert!(histograms.starts_with("histogram_0,venue=lse "), "{histograms}");
+        drop(exporter);
+        std::fs::remove_file(&counters_path).unwrap();
+        std::fs::remove_file(&histograms_path).unwrap();
+    }
+
+    #[test]
+    fn unix_datagram_splits_metrics_across_datagrams() {
+        let path = socket_path("datagram_chunks");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        receiver.set_nonblocking(true).unwrap();
+
+        // few enough datagrams to fit into the receive queue (`net.unix.max_dgram_qlen`, 10 by default on
+        // Linux) as sends block once it is full
+        let mut exporter = UnixDatagramExporter::try_from(unix_config(&path, 256)).unwrap();
+        exporter.publish(&counters(30), &Histograms::new(), 0).unwrap();
+
+        let mut datagram = [0; 4096];
+        let mut lines = Vec::new();
+        let mut datagrams = 0;
+        while let Ok(len) = receiver.recv(&mut datagram) {
+            assert!(len <= 256, "datagram of {len} bytes exceeds the limit");
+            let payload = std::str::from_utf8(&datagram[..len]).unwrap();
+            // metrics are never split across datagrams
+            assert!(payload.ends_with('\n'));
+            lines.extend(payload.lines().map(|line| line.split(',').next().unwrap().to_owned()));
+            datagrams += 1;
+        }
+        assert!(datagrams > 1, "expected multiple datagrams, got {datagrams}");
+        lines.sort();
+        let mut expected = (0..30).map(|id| format!("counter_{id}")).collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(expected, lines);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unix_datagram_sends_counters_and_histograms_together() {
+        let path = socket_path("datagram_combined");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        receiver.set_nonblocking(true).unwrap();
+        let mut exporter = UnixDatagramExporter::try_from(unix_config(&path, 8192)).unwrap();
+        let mut datagram = [0; 8192];
+        let mut received = || {
+            let mut payloads = Vec::new();
+            while let Ok(len) = receiver.recv(&mut datagram) {
+                payloads.push(std::str::from_utf8(&datagram[..len]).unwrap().to_owned());
+            }
+            payloads
+        };
+
+        // publishing each metric type on its own takes a send per type
+        exporter.publish(&counters(50), &Histograms::new(), 0).unwrap();
+        exporter.publish(&Counters::new(), &histograms(10), 0).unwrap();
+        assert_eq!(2, received().len());
+
+        // whereas publishing them together packs both into a single datagram
+        exporter.publish(&counters(50), &histograms(10), 0).unwrap();
+        let payloads = received();
+        assert_eq!(1, payloads.len());
+        let lines = payloads[0].lines().collect::<Vec<_>>();
+        assert_eq!(50, lines.iter().filter(|line| line.starts_with("counter_")).count());
+        assert_eq!(10, lines.iter().filter(|line| line.starts_with("histogram_")).count());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Content of the gzip stream read from `compressed`, which must have been finished.
+    fn decompress(compressed: impl std::io::Read) -> String {
+        use std::io::Read;
+
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(compressed)
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        decompressed
+    }
+
+    #[test]
+    fn compressed_file_decompresses_to_the_published_metrics() {
+        let (mut exporter, path) = file_exporter("flush_gzip", Some(Compression::Gzip));
+        exporter.publish(&counters(1), &Histograms::new(), 7).unwrap();
+        exporter.publish(&counters(1), &Histograms::new(), 8).unwrap();
+        // the end of the compressed stream is written on drop
+        drop(exporter);
+        assert_eq!(
+            "counter_0,venue=lse value=1u 7\ncounter_0,venue=lse value=1u 8\n",
+            decompress(File::open(&path).unwrap())
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unix_stream_starts_a_new_compressed_stream_on_reconnect() {
+        use std::io::{BufRead, BufReader};
+        use std::os::unix::net::UnixListener;
+
+        let path = socket_path("stream_gzip");
+        let listener = UnixListener::bind(&path).unwrap();
+        let config = UnixSocketConfig {
+            compression: Some(Compression::Gzip),
+            ..unix_config(&path, 0)
+        };
+        let mut exporter = UnixStreamExporter::try_from(config).unwrap();
+        let (connection, _) = listener.accept().unwrap();
+        exporter.publish(&counters(1), &Histograms::new(), 7).unwrap();
+        // each publish flushes the compressor, so the metrics can be decompressed before the stream ends
+        let mut line = String::new();
+        BufReader::new(flate2::read::GzDecoder::new(&connection))
+            .read_line(&mut line)
+            .unwrap();
+        assert_eq!("counter_0,venue=lse value=1u 7\n", line);
+
+        drop(connection);
+        drop(listener);
+        std::fs::remove_file(&path).unwrap();
+        exporter.publish(&counters(1), &Histograms::new(), 8).unwrap();
+        assert!(exporter.stream.is_none());
+
+        let listener = UnixListener::bind(&path).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let connection = loop {
+            exporter.publish(&counters(1), &Histograms::new(), 9).unwrap();
+            if let Ok((connection, _)) = listener.accept() {
+                break connection;
+            }
+            assert!(Instant::now() < deadline, "exporter did not reconnect");
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        exporter.finish().unwrap();
+        drop(exporter);
+        connection.set_nonblocking(false).unwrap();
+        // the new connection carries a complete gzip stream of its own, with a header and a trailer
+        assert_eq!("counter_0,venue=lse value=1u 9\n", decompress(&connection));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unix_stream_resumes_publishing_after_listener_restart() {
+        use std::io::{BufRead, BufReader};
+        use std::os::unix::net::UnixListener;
+
+        let path = socket_path("stream_reconnect");
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut exporter = UnixStreamExporter::try_from(unix_config(&path, 0)).unwrap();
+        let (connection, _) = listener.accept().unwrap();
+        exporter.publish(&counters(1), &Histograms::new(), 0).unwrap();
+        let mut line = String::new();
+        BufReader::new(&connection).read_line(&mut line).unwrap();
+        assert!(line.starts_with("counter_0,venue=lse"), "unexpected line {line:?}");
+
+        // the listener goes away, publishing fails on the broken pipe without returning an error
+        drop(connection);
+        drop(listener);
+        std::fs::remove_file(&path).unwrap();
+        exporter.publish(&counters(1), &Histograms::new(), 0).unwrap();
+        assert!(exporter.stream.is_none());
+
+        // and resumes once the listener is back and the reconnect backoff has elapsed
+        let listener = UnixListener::bind(&path).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let connection = loop {
+            exporter.publish(&counters(2), &Histograms::new(), 0).unwrap();
+            if let Ok((connection, _)) = listener.accept() {
+                break connection;
+            }
+            assert!(Instant::now() < deadline, "exporter did not reconnect");
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        exporter.publish(&counters(2), &Histograms::new(), 0).unwrap();
+        connection.set_nonblocking(false).unwrap();
+        let mut reader = BufReader::new(&connection);
+        let mut names = Vec::new();
+        for _ in 0..2 {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            names.push(line.split(',').next().unwrap().to_owned());
+        }
+        names.sort();
+        assert_eq!(["counter_0", "counter_1"], names.as_slice());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tcp_publishes_and_reconnects_after_the_connection_is_lost() {
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = TcpConfig {
+            host: "127.0.0.1".to_owned(),
+            port: listener.local_addr().unwrap().port(),
+            encoder: Encoder::LineProtocol,
+            max_reconnect_backoff: Duration::from_millis(10),
+        };
+        let mut exporter = TcpExporter::try_from(config).unwrap();
+        let (connection, _) = listener.accept().unwrap();
+        exporter.publish(&counters(1), &histograms(1), 7).unwrap();
+        let mut reader = BufReader::new(&connection);
+        let mut lines = Vec::new();
+        for _ in 0..2 {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            lines.push(line.split(',').next().unwrap().to_owned());
+        }
+        lines.sort();
+        assert_eq!(["counter_0", "histogram_0"], lines.as_slice());
+
+        // the peer closing the connection is only noticed once a write fails, which is not returned as an error
+        drop(reader);
+        drop(connection);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while exporter.stream.is_some() {
+            exporter.publish(&counters(1), &Histograms::new(), 8).unwrap();
+            assert!(Instant::now() < deadline, "exporter did not notice the lost connection");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        // and publishing resumes on a new connection once the reconnect backoff has elapsed
+        listener.set_nonblocking(true).unwrap();
+        let connection = loop {
+            exporter.publish(&counters(1), &Histograms::new(), 9).unwrap();
+            if let Ok((connection, _)) = listener.accept() {
+                break connection;
+            }
+            assert!(Instant::now() < deadline, "exporter did not reconnect");
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        connection.set_nonblocking(false).unwrap();
+        // the connection is established before it is accepted, so the publish that reconnected is delivered
+        let mut line = String::new();
+        BufReader::new(&connection).read_line(&mut line).unwrap();
+        assert_eq!("counter_0,venue=lse value=1u 9\n", line);
+    }
+
+    #[test]
+    fn datagram_buffer_is_sized_with_the_configured_capacity() {
+        let exporter = UdpExporter::try_from(UdpConfig {
+            buffer_capacity: 65536,
+            ..udp_config("127.0.0.1", 9, None)
+        })
+        .unwrap();
+        assert!(exporter.datagrams.buffer.capacity() >= 65536);
+
+        let exporter = UnixDatagramExporter::try_from(UnixSocketConfig {
+            buffer_capacity: 16,
+            ..unix_config(&socket_path("buffer_capacity"), 8192)
+        })
+        .unwrap();
+        assert!((16..8192).contains(&exporter.datagrams.buffer.capacity()));
+    }
+
+    #[test]
+    fn udp_splits_metrics_across_datagrams() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        let config = UdpConfig {
+            max_datagram_size: 256,
+            ..udp_config("127.0.0.1", port, None)
+        };
+        let mut exporter = UdpExporter::try_from(config).unwrap();
+        exporter.publish(&counters(100), &Histograms::new(), 0).unwrap();
+
+        let mut datagram = [0; 4096];
+        let mut lines = Vec::new();
+        let mut datagrams = 0;
+        while let Ok(len) = receiver.recv(&mut datagram) {
+            assert!(len <= 256, "datagram of {len} bytes exceeds the limit");
+            let payload = std::str::from_utf8(&datagram[..len]).unwrap();
+            // metrics are never split across datagrams
+            assert!(payload.ends_with('\n'));
+            lines.extend(payload.lines().map(|line| line.split(',').next().unwrap().to_owned()));
+            datagrams += 1;
+        }
+        assert!(datagrams > 1, "expected multiple datagrams, got {datagrams}");
+        lines.sort();
+        let mut expected = (0..100).map(|id| format!("counter_{id}")).collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(expected, lines);
+    }
+
+    #[test]
+    fn udp_datagrams_are_numbered_in_sequence_across_publishes() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        let config = UdpConfig {
+            sequence: true,
+            max_datagram_size: 256,
+            ..udp_config("127.0.0.1", port, None)
+        };
+        let mut exporter = UdpExporter::try_from(config).unwrap();
+        exporter.publish(&counters(30), &Histograms::new(), 0).unwrap();
+        exporter.publish(&counters(30), &Histograms::new(), 1).unwrap();
+
+        let mut datagram = [0; 4096];
+        let mut sequences = Vec::new();
+        while let Ok(len) = receiver.recv(&mut datagram) {
+            // the 8 byte big endian sequence number is followed by whole metrics
+            sequences.push(u64::from_be_bytes(datagram[..8].try_into().unwrap()));
+            assert!(std::str::from_utf8(&datagram[8..len]).unwrap().ends_with('\n'));
+        }
+        assert!(sequences.len() > 2, "expected multiple datagrams per publish, got {}", sequences.len());
+        assert_eq!((0..sequences.len() as u64).collect::<Vec<_>>(), sequences);
+    }
+
+    #[test]
+    fn udp_keeps_its_socket_while_the_receiver_is_unavailable() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let target = receiver.local_addr().unwrap();
+        let mut exporter = UdpExporter::try_from(udp_config("127.0.0.1", target.port(), None)).unwrap();
+        let mut datagram = [0; 1500];
+        exporter.publish(&counters(1), &Histograms::new(), 0).unwrap();
+        let (_, source) = receiver.recv_from(&mut datagram).unwrap();
+
+        // sends are refused once the port is unreachable, which is counted rather than returned as an error
+        drop(receiver);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while exporter.send_failures == 0 {
+            exporter.publish(&counters(1), &Histograms::new(), 0).unwrap();
+            assert!(Instant::now() < deadline, "sends were not refused");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(exporter.send_failures, exporter.unsent);
+
+        // and the same socket delivers again once a receiver is back on the port
+        let receiver = UdpSocket::bind(target).unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        exporter.publish(&counters(1), &Histograms::new(), 0).unwrap();
+        let (len, resent_from) = receiver.recv_from(&mut datagram).unwrap();
+        assert_eq!(source, resent_from);
+        assert!(
+            std::str::from_utf8(&datagram[..len])
+                .unwrap()
+                .starts_with("counter_0,venue=lse")
+        );
+    }
+
+    #[test]
+    fn udp_reconnects_when_the_target_resolves_to_a_new_address() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        let config = UdpConfig {
+            resolve_interval: Some(Duration::ZERO),
+            ..udp_config("localhost", port, Some("127.0.0.1:0".parse().unwrap()))
+        };
+        let mut exporter = UdpExporter::try_from(config).unwrap();
+        let source = exporter.socket.local_addr().unwrap();
+
+        // point the exporter at a stale address, as if the target had moved since it was last resolved
+        let stale = UdpSocket::bind("127.0.0.1:0").unwrap();
+        exporter.peer = stale.local_addr().unwrap();
+        exporter.socket.connect(exporter.peer).unwrap();
+        exporter.publish(&counters(1), &Histograms::new(), 0).unwrap();
+
+        let mut datagram = [0; 1500];
+        let (len, from) = receiver.recv_from(&mut datagram).unwrap();
+        assert_eq!(source, from);
+        assert!(
+            std::str::from_utf8(&datagram[..len])
+                .unwrap()
+                .starts_with("counter_0,venue=lse")
+        );
+        assert_eq!(receiver.local_addr().unwrap(), exporter.peer);
+        stale.set_nonblocking(true).unwrap();
+        assert!(stale.recv(&mut datagram).is_err());
+    }
+
+    #[test]
+    fn udp_binds_to_any_ipv6_address_for_ipv6_target() {
+        let receiver = UdpSocket::bind("[::1]:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let port = receiver.local_addr().unwrap().port();
+
+        let config = udp_config("[::1]", port, Some("[::]:0".parse().unwrap()));
+        let mut exporter = UdpExporter::try_from(config).unwrap();
+        assert!(exporter.socket.local_addr().unwrap().is_ipv6());
+        exporter.publish(&counters(1), &Histograms::new(), 0).unwrap();
+
+        let mut datagram = [0; 1500];
+        let len = receiver.recv(&mut datagram).unwrap();
+        assert!(
+            std::str::from_utf8(&datagram[..len])
+                .unwrap()
+                .starts_with("counter_0,venue=lse")
+        );
+    }
+
+    #[test]
+    fn udp_defaults_to_bind_address_of_target_family() {
+        let receiver = UdpSocket::bind("[::1]:0").unwrap();
+        let config = udp_config("[::1]", receiver.local_addr().unwrap().port(), None);
+        let exporter = UdpExporter::try_from(config).unwrap();
+        assert_eq!(Some(Ipv6Addr::LOCALHOST.into()), exporter.socket.local_addr().ok().map(|addr| addr.ip()));
+    }
+
+    #[test]
+    fn udp_rejects_bind_address_of_other_family() {
+        let config = udp_config("[::1]", 8125, Some("0.0.0.0:0".parse().unwrap()));
+        let err = UdpExporter::try_from(config).err().unwrap();
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+    }
 }
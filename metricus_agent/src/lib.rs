@@ -2,21 +2,39 @@
 
 mod affinity;
 mod aggregator;
+mod buffer;
 pub mod config;
 mod error;
 mod exporter;
+mod sampling;
+mod stats;
+#[cfg(feature = "tdigest")]
+mod tdigest;
 
 use crate::aggregator::MetricsAggregator;
+use crate::buffer::BufferRegistry;
 use crate::config::MetricsConfig;
-use metricus::{Id, Metrics, PreAllocatedMetric, Tag, Tags, set_metrics};
+use crate::sampling::Sampling;
+use crate::stats::AggregatorStats;
+use metricus::{
+    Id, Metrics, MetricsSnapshot, PreAllocatedMetric, RegisterError, Tag, Tags, ValueTransform,
+    replace_metrics_preserving, set_metrics,
+};
 #[cfg(feature = "rtrb")]
 use rtrb::Producer;
+use std::sync::mpsc::RecvTimeoutError;
 #[cfg(not(feature = "rtrb"))]
 use std::sync::mpsc::SyncSender;
 
 // re-exports
+pub use config::{available_encoders, available_exporters};
 pub use error::{Error, Result};
-use std::collections::HashMap;
+pub use exporter::{ExporterInfo, LOG_SUMMARY_TARGET};
+pub use stats::{
+    EVENTS_PROCESSED_COUNTER_ID, PUBLISH_DURATION_HISTOGRAM_ID, RESERVED_IDS, UNSENT_DATAGRAMS_COUNTER_ID,
+};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 
 type OwnedTag = (String, String);
 type OwnedTags = Vec<OwnedTag>;
@@ -41,18 +59,20 @@ impl ToOwnedTag for Tag<'_> {
     }
 }
 
+/// Metrics backend aggregating counters and histograms on a background thread, which publishes them
+/// with the configured exporter. Gauges, including callback gauges, are not supported and produce no
+/// output.
 pub struct MetricsAgent {
-    #[cfg(feature = "rtrb")]
-    tx_upd: Producer<UpdateEvent>,
     #[cfg(feature = "rtrb")]
     tx_cnc: Producer<ControlEvent>,
     #[cfg(not(feature = "rtrb"))]
-    tx_upd: SyncSender<UpdateEvent>,
-    #[cfg(not(feature = "rtrb"))]
     tx_cnc: SyncSender<ControlEvent>,
+    buffers: BufferRegistry,
     default_tags: OwnedTags,
     next_id: Id,
     metric_key_to_id: HashMap<MetricKey, Id>,
+    reserved_ids: HashSet<Id>,
+    sampling: Sampling,
 }
 
 impl MetricsAgent {
@@ -63,46 +83,107 @@ impl MetricsAgent {
 
     /// Init agent with user supplied config.
     pub fn init_with_config(config: MetricsConfig) -> Result<()> {
-        #[cfg(feature = "rtrb")]
-        let (tx_upd, rx_upd) = rtrb::RingBuffer::new(config.event_channel_size);
+        set_metrics(Self::start(config));
+        Ok(())
+    }
+
+    /// Replace the active backend with a new agent using the supplied config, seeded with a snapshot
+    /// of the current backend so that counters continue from their current values rather than starting
+    /// from zero, see [metricus::replace_metrics_preserving]. The previous agent's aggregator keeps
+    /// running, so metric objects created before the swap should be dropped and created again.
+    pub fn reinit_with_config(config: MetricsConfig) -> Result<()> {
+        let snapshot = metricus::snapshot().unwrap_or_default();
+        replace_metrics_preserving(Self::start(config), &snapshot);
+        Ok(())
+    }
+
+    /// Exporter type, encoders and target of the running agent, or `None` until the aggregator thread
+    /// has created the exporter shortly after initialisation. After [MetricsAgent::reinit_with_config]
+    /// this describes the new agent. The UDP target is the address resolved when the exporter was
+    /// created, later re-resolutions (see `resolve_interval`) are not reflected.
+    ///
+    /// This can be called from any thread, it takes a read lock that is only contended while an exporter
+    /// is being created, and returns a copy that is not updated afterwards. With `self_metrics` enabled
+    /// the same information is also exported as the `metrics_agent` counter tagged `stat=exporter_info`.
+    pub fn exporter_info() -> Option<ExporterInfo> {
+        ExporterInfo::active()
+    }
+
+    /// Publishes the metrics recorded so far and restores the no-op backend, e.g. before the process
+    /// exits. The aggregator publishes every `flush_interval` (see [MetricsConfig::flush_interval]),
+    /// so without a final flush the updates recorded since the last one would not be exported. Metric
+    /// objects stop recording once they notice the change of backend, see [metricus::reset_to_noop].
+    ///
+    /// The aggregator also publishes one last time and exits when the agent is dropped, which is the
+    /// case when the agent is replaced with [metricus::swap_metrics] and the returned value is dropped.
+    ///
+    /// The no-op backend is restored even if the final publish fails, in which case the error is returned.
+    pub fn shutdown() -> std::io::Result<()> {
+        let result = metricus::flush();
+        metricus::reset_to_noop();
+        result
+    }
+
+    fn start(config: MetricsConfig) -> Self {
         #[cfg(feature = "rtrb")]
         let (tx_cnc, rx_cnc) = rtrb::RingBuffer::new(1024);
         #[cfg(not(feature = "rtrb"))]
-        let (tx_upd, rx_upd) = std::sync::mpsc::sync_channel(config.event_channel_size);
-        #[cfg(not(feature = "rtrb"))]
         let (tx_cnc, rx_cnc) = std::sync::mpsc::sync_channel(1024);
+        let (tx_reg, rx_reg) = std::sync::mpsc::channel();
 
         // launch aggregator on background thread
-        let _ = MetricsAggregator::start_on_thread(rx_upd, rx_cnc, config.clone());
+        let _ = MetricsAggregator::start_on_thread(rx_reg, rx_cnc, config.clone());
 
-        let mut agent = MetricsAgent::new(tx_upd, tx_cnc, config.default_tags);
+        let buffers = BufferRegistry::new(config.event_channel_size, tx_reg);
+        let mut agent = MetricsAgent::new(tx_cnc, buffers, config.default_tags, Sampling::new(config.sample_rates));
+        agent.reserve(&config.pre_allocated_metrics);
         for metric in config.pre_allocated_metrics {
             agent.register_metric_with_id(metric);
         }
+        if config.self_metrics {
+            let metrics = AggregatorStats::metrics();
+            agent.reserve(&metrics);
+            for metric in metrics {
+                agent.register_metric_with_id(metric);
+            }
+        }
 
-        set_metrics(agent);
-        Ok(())
+        agent
     }
 
     #[cfg(feature = "rtrb")]
-    fn new(tx_upd: Producer<UpdateEvent>, tx_cnc: Producer<ControlEvent>, default_tags: OwnedTags) -> Self {
+    fn new(
+        tx_cnc: Producer<ControlEvent>,
+        buffers: BufferRegistry,
+        default_tags: OwnedTags,
+        sampling: Sampling,
+    ) -> Self {
         Self {
-            tx_upd,
             tx_cnc,
+            buffers,
             default_tags,
             next_id: 0,
             metric_key_to_id: Default::default(),
+            reserved_ids: Default::default(),
+            sampling,
         }
     }
 
     #[cfg(not(feature = "rtrb"))]
-    fn new(tx_upd: SyncSender<UpdateEvent>, tx_cnc: SyncSender<ControlEvent>, default_tags: OwnedTags) -> Self {
+    fn new(
+        tx_cnc: SyncSender<ControlEvent>,
+        buffers: BufferRegistry,
+        default_tags: OwnedTags,
+        sampling: Sampling,
+    ) -> Self {
         Self {
-            tx_upd,
             tx_cnc,
+            buffers,
             default_tags,
             next_id: 0,
             metric_key_to_id: Default::default(),
+            reserved_ids: Default::default(),
+            sampling,
         }
     }
 
@@ -112,26 +193,57 @@ impl MetricsAgent {
             .metric_key_to_id
             .entry(MetricKey::new(name, tags))
             .or_insert_with(|| {
+                while self.reserved_ids.contains(&self.next_id) {
+                    self.next_id += 1;
+                }
                 let id = self.next_id;
                 self.next_id += 1;
                 id
             })
     }
 
+    fn reserve_id(&mut self, name: &str, id: Id, tags: OwnedTags) {
+        self.reserved_ids.insert(id);
+        self.metric_key_to_id.insert(MetricKey::new(name, tags), id);
+    }
+
     #[inline]
     fn send_control_event(&mut self, event: ControlEvent) {
+        let _ = self.try_send_control_event(event);
+    }
+
+    #[inline]
+    fn try_send_control_event(&mut self, event: ControlEvent) -> std::result::Result<(), RegisterError> {
         #[cfg(feature = "rtrb")]
-        let _ = self.tx_cnc.push(event);
+        return self.tx_cnc.push(event).map_err(|_| RegisterError::CapacityExceeded);
         #[cfg(not(feature = "rtrb"))]
-        let _ = self.tx_cnc.try_send(event);
+        return self.tx_cnc.try_send(event).map_err(|err| match err {
+            std::sync::mpsc::TrySendError::Full(_) => RegisterError::CapacityExceeded,
+            std::sync::mpsc::TrySendError::Disconnected(_) => {
+                RegisterError::Rejected("aggregator is not running".to_string())
+            }
+        });
+    }
+
+    fn counter_create_event(&mut self, name: &str, tags: Tags) -> (Id, ControlEvent) {
+        let mut tags = tags.to_owned_tags();
+        self.enrich_with_counter_tags(&mut tags);
+        let id = self.assign_next_id(name, tags.clone());
+        self.sampling.register(id, name, &tags);
+        (id, ControlEvent::CounterCreate(id, name.to_owned(), tags))
+    }
+
+    fn histogram_create_event(&mut self, name: &str, tags: Tags) -> (Id, ControlEvent) {
+        let mut tags = tags.to_owned_tags();
+        self.enrich_with_histogram_tags(&mut tags);
+        let id = self.assign_next_id(name, tags.clone());
+        self.sampling.register(id, name, &tags);
+        (id, ControlEvent::HistogramCreate(id, name.to_owned(), tags))
     }
 
     #[inline]
     fn send_update_event(&mut self, event: UpdateEvent) {
-        #[cfg(feature = "rtrb")]
-        let _ = self.tx_upd.push(event);
-        #[cfg(not(feature = "rtrb"))]
-        let _ = self.tx_upd.try_send(event);
+        self.buffers.push(event);
     }
 
     fn enrich_with_counter_tags(&self, tags: &mut OwnedTags) {
@@ -150,14 +262,34 @@ impl MetricsAgent {
 
     fn register_metric_with_id(&mut self, metric: PreAllocatedMetric) {
         match metric {
-            PreAllocatedMetric::Counter { name, id, mut tags } => {
+            PreAllocatedMetric::Counter {
+                name,
+                id,
+                mut tags,
+                unit,
+            } => {
                 self.enrich_with_counter_tags(&mut tags);
+                add_unit_tag(&mut tags, unit);
+                self.sampling.register_pre_allocated(id, &name, &tags);
                 self.send_control_event(ControlEvent::CounterCreate(id, name, tags))
             }
-            PreAllocatedMetric::Histogram { name, id, mut tags } => {
+            PreAllocatedMetric::Histogram {
+                name,
+                id,
+                mut tags,
+                unit,
+                buckets,
+            } => {
                 self.enrich_with_histogram_tags(&mut tags);
-                self.send_control_event(ControlEvent::HistogramCreate(id, name, tags))
+                add_unit_tag(&mut tags, unit);
+                self.sampling.register_pre_allocated(id, &name, &tags);
+                self.send_control_event(ControlEvent::HistogramCreate(id, name, tags));
+                if let Some(buckets) = buckets {
+                    self.send_control_event(ControlEvent::HistogramBuckets(id, buckets));
+                }
             }
+            // gauges are not supported by the agent
+            PreAllocatedMetric::Gauge { .. } => {}
         }
     }
 }
@@ -168,27 +300,62 @@ impl Metrics for MetricsAgent {
     }
 
     fn new_counter(&mut self, name: &str, tags: Tags) -> Id {
-        let mut tags = tags.to_owned_tags();
-        self.enrich_with_counter_tags(&mut tags);
-        let id = self.assign_next_id(name, tags.clone());
-        self.send_control_event(ControlEvent::CounterCreate(id, name.to_owned(), tags));
+        let (id, event) = self.counter_create_event(name, tags);
+        self.send_control_event(event);
         id
     }
 
+    /// Fails with [RegisterError::CapacityExceeded] when the queue of registration requests to the
+    /// aggregator is full.
+    fn try_new_counter(&mut self, name: &str, tags: Tags) -> std::result::Result<Id, RegisterError> {
+        let (id, event) = self.counter_create_event(name, tags);
+        self.try_send_control_event(event).map(|_| id)
+    }
+
     fn delete_counter(&mut self, id: Id) {
         self.send_control_event(ControlEvent::CounterDelete(id));
     }
 
     #[inline]
     fn increment_counter_by(&mut self, id: Id, delta: u64) {
-        self.send_update_event(UpdateEvent::CounterIncrement(id, delta));
+        if let Some(weight) = self.sampling.sample(id) {
+            self.send_update_event(UpdateEvent::CounterIncrement(id, delta.saturating_mul(weight)));
+        }
+    }
+
+    /// Saturates at zero. Exporters that send the change since the last publish (e.g. StatsD) send a
+    /// negative change if the counter was decremented by more than it was incremented in between.
+    #[inline]
+    fn decrement_counter_by(&mut self, id: Id, delta: u64) {
+        if let Some(weight) = self.sampling.sample(id) {
+            self.send_update_event(UpdateEvent::CounterDecrement(id, delta.saturating_mul(weight)));
+        }
     }
 
     fn new_histogram(&mut self, name: &str, tags: Tags) -> Id {
-        let mut tags = tags.to_owned_tags();
-        self.enrich_with_histogram_tags(&mut tags);
-        let id = self.assign_next_id(name, tags.clone());
-        self.send_control_event(ControlEvent::HistogramCreate(id, name.to_owned(), tags));
+        let (id, event) = self.histogram_create_event(name, tags);
+        self.send_control_event(event);
+        id
+    }
+
+    /// Fails with [RegisterError::CapacityExceeded] when the queue of registration requests to the
+    /// aggregator is full.
+    fn try_new_histogram(&mut self, name: &str, tags: Tags) -> std::result::Result<Id, RegisterError> {
+        let (id, event) = self.histogram_create_event(name, tags);
+        self.try_send_control_event(event).map(|_| id)
+    }
+
+    fn new_histogram_with_transform(&mut self, name: &str, tags: Tags, transform: ValueTransform) -> Id {
+        let id = self.new_histogram(name, tags);
+        self.send_control_event(ControlEvent::HistogramTransform(id, Transform(transform)));
+        id
+    }
+
+    fn new_histogram_with_resolution(&mut self, name: &str, tags: Tags, resolution_ns: u64) -> Id {
+        let id = self.new_histogram(name, tags);
+        if resolution_ns > 1 {
+            self.send_control_event(ControlEvent::HistogramResolution(id, resolution_ns));
+        }
         id
     }
 
@@ -198,7 +365,97 @@ impl Metrics for MetricsAgent {
 
     #[inline]
     fn record(&mut self, id: Id, value: u64) {
-        self.send_update_event(UpdateEvent::HistogramRecord(id, value));
+        if self.sampling.sample(id).is_some() {
+            self.send_update_event(UpdateEvent::HistogramRecord(id, value));
+        }
+    }
+
+    fn clear_histogram(&mut self, id: Id) {
+        self.send_update_event(UpdateEvent::HistogramClear(id));
+    }
+
+    /// The reset is sent along with the increments of the calling thread, so it applies after the
+    /// increments the thread made before. The next export reports the counter from zero.
+    fn reset_counter(&mut self, id: Id) {
+        self.send_update_event(UpdateEvent::CounterReset(id));
+    }
+
+    /// Blocks until the aggregator has processed all update events recorded so far and returns the
+    /// resulting counter value. Returns `None` for unknown counters or if the aggregator does not respond
+    /// within a second.
+    fn read_counter(&mut self, id: Id) -> Option<u64> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.send_control_event(ControlEvent::CounterRead(id, tx));
+        rx.recv_timeout(Duration::from_secs(1)).ok().flatten()
+    }
+
+    /// Blocks until the aggregator has processed all update events recorded so far and returns the
+    /// resulting state. The `type` tag is omitted from the series. Returns `None` if the aggregator
+    /// does not respond within a second.
+    fn snapshot(&mut self) -> Option<MetricsSnapshot> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.send_control_event(ControlEvent::Snapshot(tx));
+        rx.recv_timeout(Duration::from_secs(1)).ok()
+    }
+
+    /// Blocks until the aggregator has processed all update events recorded so far and published the
+    /// metrics, or for at most a second, in which case a `TimedOut` error is returned. The regular flush
+    /// schedule is not affected.
+    fn flush(&mut self) -> std::io::Result<()> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.send_control_event(ControlEvent::Flush(tx));
+        rx.recv_timeout(Duration::from_secs(1)).map_err(|err| match err {
+            RecvTimeoutError::Timeout => {
+                std::io::Error::new(std::io::ErrorKind::TimedOut, "aggregator did not publish within a second")
+            }
+            RecvTimeoutError::Disconnected => std::io::Error::other("aggregator is not running"),
+        })
+    }
+
+    /// Counters continue from the restored values as if they had been recorded by this agent, and are
+    /// considered already published, so the DogStatsD encoder does not send them again as increments.
+    /// The sample counts of histograms in snapshots continue from the restored counts as well.
+    fn restore(&mut self, snapshot: &MetricsSnapshot) {
+        for (key, value) in &snapshot.counters {
+            let (id, event) = self.counter_create_event(&key.name, &key.tags());
+            self.send_control_event(event);
+            self.send_control_event(ControlEvent::CounterRestore(id, *value));
+        }
+        for (key, total) in &snapshot.histograms {
+            let (id, event) = self.histogram_create_event(&key.name, &key.tags());
+            self.send_control_event(event);
+            self.send_control_event(ControlEvent::HistogramRestore(id, *total));
+        }
+    }
+
+    fn reserve(&mut self, metrics: &[PreAllocatedMetric]) {
+        self.metric_key_to_id.reserve(metrics.len());
+        self.reserved_ids.reserve(metrics.len());
+        for metric in metrics {
+            match metric.clone() {
+                PreAllocatedMetric::Counter { name, id, mut tags, .. } => {
+                    self.enrich_with_counter_tags(&mut tags);
+                    self.reserve_id(&name, id, tags);
+                }
+                PreAllocatedMetric::Histogram { name, id, mut tags, .. } => {
+                    self.enrich_with_histogram_tags(&mut tags);
+                    self.reserve_id(&name, id, tags);
+                }
+                // gauges are not supported by the agent
+                PreAllocatedMetric::Gauge { .. } => {}
+            }
+        }
+    }
+}
+
+/// Tags a pre-allocated metric with the unit of its values, so that every encoder exports it, unless
+/// the metric already has a `unit` tag.
+fn add_unit_tag(tags: &mut OwnedTags, unit: Option<String>) {
+    if let Some(unit) = unit {
+        if !tags.iter().any(|(key, _)| key == "unit") {
+            tags.push(("unit".to_owned(), unit));
+            tags.sort();
+        }
     }
 }
 
@@ -208,12 +465,41 @@ enum ControlEvent {
     CounterDelete(Id),
     HistogramCreate(Id, String, OwnedTags),
     HistogramDelete(Id),
+    HistogramTransform(Id, Transform),
+    /// Sets the resolution values of a histogram are rounded to.
+    HistogramResolution(Id, u64),
+    /// Sets the upper bounds of the buckets values of a histogram are counted in.
+    HistogramBuckets(Id, Vec<u64>),
+    /// Sets the value of a counter restored from a snapshot of another backend.
+    CounterRestore(Id, u64),
+    /// Sets the total sample count of a histogram restored from a snapshot of another backend.
+    HistogramRestore(Id, u64),
+    /// Requests the value of a counter, which is sent back once all update events pending at the time
+    /// are processed.
+    CounterRead(Id, std::sync::mpsc::SyncSender<Option<u64>>),
+    /// Requests a snapshot, which is sent back once all update events pending at the time are processed.
+    Snapshot(std::sync::mpsc::SyncSender<MetricsSnapshot>),
+    /// Requests a publish, which is acknowledged once all update events pending at the time are processed
+    /// and the metrics have been published.
+    Flush(std::sync::mpsc::SyncSender<()>),
+}
+
+/// Value transform applied by the aggregator when folding values into a histogram.
+struct Transform(ValueTransform);
+
+impl std::fmt::Debug for Transform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Transform")
+    }
 }
 
 #[derive(Debug)]
 enum UpdateEvent {
     CounterIncrement(Id, u64),
+    CounterDecrement(Id, u64),
     HistogramRecord(Id, u64),
+    HistogramClear(Id),
+    CounterReset(Id),
 }
 
 #[derive(Eq, PartialEq, Hash, Clone)]
@@ -230,3 +516,243 @@ impl MetricKey {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// Starts an agent that is not installed as the backend, with room for all the updates of a test.
+    fn start(config: &str) -> MetricsAgent {
+        let config = format!("exporter:\n  type: no_op\nevent_channel_size: 65536\n{config}");
+        MetricsAgent::start(config.parse().unwrap())
+    }
+
+    #[test]
+    fn sampled_counter_increments_are_compensated() {
+        let mut agent = start("sample_rates:\n  - measurement: noisy\n    rate: 0.25\n");
+        let noisy = agent.new_counter("noisy", &[]);
+        let rare = agent.new_counter("rare", &[]);
+        for _ in 0..20_000 {
+            agent.increment_counter_by(noisy, 5);
+            agent.increment_counter_by(rare, 5);
+        }
+        let value = agent.read_counter(noisy).unwrap();
+        // every kept increment stands for 4, relative error of about 1/sqrt(0.25 * 20000), i.e. 1.4%
+        assert_eq!(0, value % 20);
+        assert!(value.abs_diff(100_000) < 10_000, "{value} too far from 100000");
+        assert_eq!(Some(100_000), agent.read_counter(rare));
+    }
+
+    #[test]
+    fn decrementing_below_zero_saturates() {
+        let mut agent = start("");
+        let sessions = agent.new_counter("sessions", &[]);
+        agent.increment_counter_by(sessions, 2);
+        agent.decrement_counter_by(sessions, 5);
+        assert_eq!(Some(0), agent.read_counter(sessions));
+        agent.increment_counter_by(sessions, 4);
+        agent.decrement_counter_by(sessions, 1);
+        assert_eq!(Some(3), agent.read_counter(sessions));
+    }
+
+    #[test]
+    fn histograms_count_recorded_values_in_declared_buckets() {
+        let path = std::env::temp_dir().join(format!("metricus_buckets_{}.txt", std::process::id()));
+        let config = format!(
+            "exporter:\n  type: file\n  config:\n    path: {}\n    encoder: json\n\
+             event_channel_size: 1024\n\
+             pre_allocated_metrics:\n\
+             \x20 - {{ type: histogram, name: latency, id: 1, buckets: [1000, 100, 10000] }}\n\
+             \x20 - {{ type: histogram, name: size, id: 2 }}\n",
+            path.display()
+        );
+        let config: MetricsConfig = config.parse().unwrap();
+        // kept as given, the aggregator sorts them
+        assert_eq!(Some(&[1_000, 100, 10_000][..]), config.pre_allocated_metrics[0].buckets());
+        assert_eq!(None, config.pre_allocated_metrics[1].buckets());
+
+        let mut agent = MetricsAgent::start(config);
+        for value in [50, 100, 101, 999, 5_000, 20_000] {
+            agent.record(1, value);
+            agent.record(2, value);
+        }
+        agent.flush().unwrap();
+
+        let published = std::fs::read_to_string(&path).unwrap();
+        let histograms: HashMap<_, _> = published
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .map(|histogram| (histogram["name"].as_str().unwrap().to_owned(), histogram))
+            .collect();
+        // cumulative counts of the values up to each bound, 20000 is only counted in the total
+        assert_eq!(serde_json::json!([[100, 2], [1_000, 4], [10_000, 5]]), histograms["latency"]["buckets"]);
+        assert_eq!(6, histograms["latency"]["count"]);
+        assert!(histograms["size"].get("buckets").is_none());
+        drop(agent);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn histogram_transform_is_applied_to_recorded_values() {
+        let path = std::env::temp_dir().join(format!("metricus_transform_{}.txt", std::process::id()));
+        let config = format!(
+            "exporter:\n  type: file\n  config:\n    path: {}\n    encoder: json\n\
+             event_channel_size: 1024\n",
+            path.display()
+        );
+        let mut agent = MetricsAgent::start(config.parse().unwrap());
+        let scaled = agent.new_histogram_with_transform("latency_scaled", &[], Box::new(|nanos| nanos / 100));
+        let nanos = agent.new_histogram("latency_ns", &[]);
+        // small enough for the histograms to keep the exact values
+        for value in [150, 250, 1_000] {
+            agent.record(scaled, value);
+            agent.record(nanos, value);
+        }
+        agent.flush().unwrap();
+
+        let published = std::fs::read_to_string(&path).unwrap();
+        let histograms: HashMap<_, _> = published
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .map(|histogram| (histogram["name"].as_str().unwrap().to_owned(), histogram))
+            .collect();
+        let summary = |name: &str| {
+            let histogram = &histograms[name];
+            [&histogram["count"], &histogram["min"], &histogram["max"]].map(|field| field.as_u64().unwrap())
+        };
+        assert_eq!([3, 1, 10], summary("latency_scaled"));
+        // other histograms record the values as they are
+        assert_eq!([3, 150, 1_000], summary("latency_ns"));
+        drop(agent);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn dropping_the_agent_publishes_one_last_time() {
+        let path = std::env::temp_dir().join(format!("metricus_final_flush_{}.txt", std::process::id()));
+        let config = format!(
+            "exporter:\n  type: file\n  config:\n    path: {}\n    encoder: line_protocol\n\
+             event_channel_size: 1024\nflush_interval: 1h\n",
+            path.display()
+        );
+        let mut agent = MetricsAgent::start(config.parse().unwrap());
+        let orders = agent.new_counter("orders", &[]);
+        agent.increment_counter_by(orders, 3);
+        drop(agent);
+
+        // the aggregator publishes and exits on its own thread once it notices that the agent is gone
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let published = std::fs::read_to_string(&path).unwrap_or_default();
+            if let Some(line) = published.lines().next() {
+                assert!(line.starts_with("orders,type=counter value=3u "), "{line}");
+                break;
+            }
+            assert!(Instant::now() < deadline, "nothing published on shutdown");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn units_of_pre_allocated_metrics_are_exported_as_tags() {
+        let path = std::env::temp_dir().join(format!("metricus_units_{}.txt", std::process::id()));
+        let config = format!(
+            "exporter:\n  type: file\n  config:\n    path: {}\n    encoder: line_protocol\n\
+             event_channel_size: 1024\n\
+             pre_allocated_metrics:\n\
+             \x20 - {{ type: counter, name: allocated, id: 1, unit: bytes }}\n\
+             \x20 - {{ type: counter, name: retries, id: 2, tags: {{ unit: attempts }}, unit: bytes }}\n\
+             \x20 - {{ type: histogram, name: latency, id: 3, unit: nanoseconds }}\n",
+            path.display()
+        );
+        let mut agent = MetricsAgent::start(config.parse().unwrap());
+        agent.increment_counter_by(1, 5);
+        agent.increment_counter_by(2, 1);
+        agent.record(3, 100);
+        agent.flush().unwrap();
+
+        let published = std::fs::read_to_string(&path).unwrap();
+        let mut series: Vec<_> = published.lines().filter_map(|line| line.split(' ').next()).collect();
+        series.sort();
+        let expected = [
+            "allocated,type=counter,unit=bytes",
+            "latency,type=histogram,unit=nanoseconds",
+            // the tag given explicitly takes precedence
+            "retries,type=counter,unit=attempts",
+        ];
+        assert_eq!(expected.as_slice(), series);
+        drop(agent);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn metrics_created_after_reserving_resolve_to_the_reserved_ids() {
+        let mut agent = start(
+            "pre_allocated_metrics:\n\
+             \x20 - { type: counter, name: orders, id: 0, tags: { venue: lse } }\n\
+             \x20 - { type: histogram, name: latency, id: 1 }\n",
+        );
+        assert_eq!(0, agent.new_counter("orders", &[("venue", "lse")]));
+        assert_eq!(1, agent.new_histogram("latency", &[]));
+        // other series, including the same name with other tags, get ids past the reserved ones
+        assert_eq!(2, agent.new_counter("orders", &[("venue", "xetra")]));
+        assert_eq!(3, agent.new_counter("cancels", &[]));
+
+        agent.increment_counter_by(0, 5);
+        let orders = agent.new_counter("orders", &[("venue", "lse")]);
+        agent.increment_counter(orders);
+        assert_eq!(Some(6), agent.read_counter(0));
+    }
+
+    #[test]
+    fn registering_fails_while_the_registration_queue_is_full() {
+        // nothing drains the queue, as if the aggregator had fallen behind
+        #[cfg(feature = "rtrb")]
+        let (tx_cnc, _rx_cnc) = rtrb::RingBuffer::new(2);
+        #[cfg(not(feature = "rtrb"))]
+        let (tx_cnc, _rx_cnc) = std::sync::mpsc::sync_channel(2);
+        let (tx_reg, _rx_reg) = std::sync::mpsc::channel();
+        let buffers = BufferRegistry::new(1024, tx_reg);
+        let mut agent = MetricsAgent::new(tx_cnc, buffers, Vec::new(), Sampling::new(Vec::new()));
+
+        assert!(agent.try_new_counter("orders", &[]).is_ok());
+        assert!(agent.try_new_histogram("latency", &[]).is_ok());
+        assert_eq!(Err(RegisterError::CapacityExceeded), agent.try_new_counter("cancels", &[]));
+        assert_eq!(Err(RegisterError::CapacityExceeded), agent.try_new_histogram("fills", &[]));
+    }
+
+    #[test]
+    fn reserved_series_are_published_only_with_self_metrics() {
+        for self_metrics in [false, true] {
+            let path = std::env::temp_dir().join(format!("metricus_self_{self_metrics}_{}.txt", std::process::id()));
+            let config = format!(
+                "exporter:\n  type: file\n  config:\n    path: {}\n    encoder: line_protocol\n\
+                 event_channel_size: 1024\nself_metrics: {self_metrics}\n",
+                path.display()
+            );
+            let mut agent = MetricsAgent::start(config.parse().unwrap());
+            let orders = agent.new_counter("orders", &[]);
+            agent.increment_counter(orders);
+            agent.flush().unwrap();
+
+            let published = std::fs::read_to_string(&path).unwrap();
+            let mut series: Vec<_> = published.lines().filter_map(|line| line.split(' ').next()).collect();
+            series.sort();
+            let expected: &[&str] = if self_metrics {
+                &[
+                    "metrics_agent,stat=events_processed,type=counter",
+                    "metrics_agent,stat=publish_duration,type=histogram",
+                    "metrics_agent,stat=unsent_datagrams,type=counter",
+                    "orders,type=counter",
+                ]
+            } else {
+                &["orders,type=counter"]
+            };
+            assert_eq!(expected, series, "self_metrics: {self_metrics}");
+            drop(agent);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
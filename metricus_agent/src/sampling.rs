@@ -0,0 +1,243 @@
+//! Per-metric sampling of update events.
+//!
+//! Sample rates are matched against a metric once, when it is registered, and remembered by id. Recording
+//! a sampled metric then costs a lookup of that id and a draw from a thread-local random number generator,
+//! metrics without a matching rule skip the lookup entirely if no rules are configured.
+//!
+//! Metrics are registered from any thread while other threads record, so the strides of ids assigned
+//! by the agent are kept in a table of atomics that only ever grows, rather than in a map that would be
+//! rehashed under the readers. Pre-allocated metrics may use any id and are registered before the agent
+//! is installed, so their strides are kept in a map that does not change afterwards.
+
+use crate::OwnedTags;
+use crate::config::SampleRateConfig;
+use log::warn;
+use metricus::Id;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+
+thread_local! {
+    static RNG: Cell<u64> = Cell::new(seed());
+}
+
+/// Number of ids per segment of the stride table.
+const SEGMENT_LEN: usize = 1024;
+/// Number of segments of the stride table, which covers ids up to `SEGMENT_LEN * SEGMENTS`.
+const SEGMENTS: usize = 1024;
+
+/// Strides of a range of ids, zero for ids without a matching rule.
+type Segment = [AtomicU64; SEGMENT_LEN];
+
+/// Keeps one in `n` update events of the metrics matched by the configured rules.
+pub struct Sampling {
+    rules: Vec<SampleRateConfig>,
+    /// Strides by id, a segment is only allocated once a metric within it matches a rule.
+    segments: Box<[AtomicPtr<Segment>]>,
+    /// Strides of pre-allocated metrics with ids beyond the table, fixed once the agent has started.
+    pre_allocated: HashMap<Id, u64>,
+}
+
+impl Sampling {
+    pub fn new(rules: Vec<SampleRateConfig>) -> Self {
+        let segments = match rules.is_empty() {
+            true => Box::default(),
+            false => (0..SEGMENTS).map(|_| AtomicPtr::new(null_mut())).collect(),
+        };
+        Self {
+            rules,
+            segments,
+            pre_allocated: HashMap::new(),
+        }
+    }
+
+    /// Applies the first rule matching the metric, if any, to its id. Can be called while other threads
+    /// sample, ids beyond the table keep every update.
+    pub fn register(&self, id: Id, name: &str, tags: &OwnedTags) {
+        if let Some(stride) = self.matching_stride(name, tags) {
+            if !self.set_stride(id, stride) {
+                warn!("Sample rate of metric {name} with id {id} is ignored, as the id is out of range");
+            }
+        }
+    }
+
+    /// Applies the first rule matching a pre-allocated metric, if any, to its id. Must be called before
+    /// the agent is installed, as it is not synchronised with [Sampling::sample].
+    pub fn register_pre_allocated(&mut self, id: Id, name: &str, tags: &OwnedTags) {
+        if let Some(stride) = self.matching_stride(name, tags) {
+            if !self.set_stride(id, stride) {
+                self.pre_allocated.insert(id, stride);
+            }
+        }
+    }
+
+    /// Returns the weight of the event if it should be kept, i.e. the number of events it stands for.
+    #[inline]
+    pub fn sample(&self, id: Id) -> Option<u64> {
+        if self.rules.is_empty() {
+            return Some(1);
+        }
+        match self.stride(id) {
+            0 | 1 => Some(1),
+            stride => (next_random() % stride == 0).then_some(stride),
+        }
+    }
+
+    fn matching_stride(&self, name: &str, tags: &OwnedTags) -> Option<u64> {
+        let rule = self.rules.iter().find(|rule| rule.matches(name, tags))?;
+        Some(rule.stride()).filter(|stride| *stride > 1)
+    }
+
+    /// Stride of the id, zero if it has none.
+    #[inline]
+    fn stride(&self, id: Id) -> u64 {
+        let Some((segment, index)) = Self::position(id) else {
+            return self.pre_allocated.get(&id).copied().unwrap_or(0);
+        };
+        let segment = self.segments[segment].load(Ordering::Acquire);
+        if segment.is_null() {
+            return 0;
+        }
+        // SAFETY: segments are never freed while the sampling is alive
+        unsafe { (*segment)[index].load(Ordering::Acquire) }
+    }
+
+    /// Sets the stride of an id within the table, returns false if the id is beyond it.
+    fn set_stride(&self, id: Id, stride: u64) -> bool {
+        let Some((segment, index)) = Self::position(id) else {
+            return false;
+        };
+        let slot = &self.segments[segment];
+        let mut current = slot.load(Ordering::Acquire);
+        if current.is_null() {
+            let new = Box::into_raw(Box::new([const { AtomicU64::new(0) }; SEGMENT_LEN]));
+            current = match slot.compare_exchange(null_mut(), new, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => new,
+                Err(existing) => {
+                    // SAFETY: the new segment was never shared
+                    drop(unsafe { Box::from_raw(new) });
+                    existing
+                }
+            };
+        }
+        // SAFETY: segments are never freed while the sampling is alive
+        unsafe { (*current)[index].store(stride, Ordering::Release) };
+        true
+    }
+
+    /// Segment and index within the segment of an id, if it is covered by the table.
+    #[inline]
+    fn position(id: Id) -> Option<(usize, usize)> {
+        let id = usize::try_from(id).ok().filter(|id| *id < SEGMENT_LEN * SEGMENTS)?;
+        Some((id / SEGMENT_LEN, id % SEGMENT_LEN))
+    }
+}
+
+impl Drop for Sampling {
+    fn drop(&mut self) {
+        for slot in self.segments.iter_mut() {
+            let segment = *slot.get_mut();
+            if !segment.is_null() {
+                // SAFETY: allocated with `Box::into_raw` and no longer shared
+                drop(unsafe { Box::from_raw(segment) });
+            }
+        }
+    }
+}
+
+/// xorshift64*, good enough to decide which events to keep.
+#[inline]
+fn next_random() -> u64 {
+    RNG.with(|rng| {
+        let mut x = rng.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        rng.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    })
+}
+
+fn seed() -> u64 {
+    // stack addresses differ per thread, mixed with the time to differ per run
+    let local = 0u8;
+    let address = &local as *const u8 as u64;
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    (address ^ time.rotate_left(32)) | 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn rule(measurement: &str, rate: f64) -> SampleRateConfig {
+        SampleRateConfig {
+            measurement: measurement.to_owned(),
+            tags: vec![],
+            rate,
+        }
+    }
+
+    #[test]
+    fn unmatched_metrics_keep_every_update() {
+        let sampling = Sampling::new(vec![rule("noisy", 0.25)]);
+        sampling.register(1, "rare", &vec![]);
+        assert!((0..10_000).all(|_| sampling.sample(1) == Some(1)));
+        // unregistered ids and ids beyond the table are not sampled either
+        assert!((0..10_000).all(|_| sampling.sample(2) == Some(1)));
+        assert_eq!(Some(1), sampling.sample(Id::MAX));
+    }
+
+    #[test]
+    fn kept_increments_are_weighted_by_the_stride() {
+        let sampling = Sampling::new(vec![rule("noisy", 0.25)]);
+        sampling.register(1, "noisy", &vec![]);
+        let (updates, delta) = (100_000, 5);
+        let total: u64 = (0..updates)
+            .filter_map(|_| sampling.sample(1))
+            .map(|weight| delta * weight)
+            .sum();
+        assert_eq!(0, total % (4 * delta));
+        // relative error of about 1/sqrt(rate * updates), i.e. 0.6%
+        let expected = updates * delta;
+        assert!(total.abs_diff(expected) < expected / 20, "{total} too far from {expected}");
+    }
+
+    #[test]
+    fn pre_allocated_ids_beyond_the_table_are_sampled() {
+        let mut sampling = Sampling::new(vec![rule("noisy", 0.5)]);
+        sampling.register_pre_allocated(Id::MAX - 1, "noisy", &vec![]);
+        assert_eq!(2, sampling.stride(Id::MAX - 1));
+        assert!(
+            (0..1_000)
+                .filter_map(|_| sampling.sample(Id::MAX - 1))
+                .all(|weight| weight == 2)
+        );
+    }
+
+    #[test]
+    fn metrics_are_registered_while_other_threads_sample() {
+        let sampling = Sampling::new(vec![rule("noisy", 0.1)]);
+        let ids = (SEGMENT_LEN * 4) as Id;
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for id in 0..ids {
+                    sampling.register(id, "noisy", &vec![]);
+                }
+            });
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for id in (0..ids).cycle().take(100_000) {
+                        assert!(matches!(sampling.sample(id), None | Some(1) | Some(10)));
+                    }
+                });
+            }
+        });
+        assert!((0..ids).all(|id| sampling.stride(id) == 10));
+    }
+}
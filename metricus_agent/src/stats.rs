@@ -0,0 +1,98 @@
+//! Self-instrumentation of the metrics aggregator.
+//!
+//! When `self_metrics` is enabled in the config, the aggregator reports the number of update events
+//! it has processed, how long each publish (encode + send) takes and how many datagrams could not be
+//! sent. These metrics are registered up front under reserved ids (see [AggregatorStats::metrics] and
+//! [RESERVED_IDS]) and recorded through the regular `Counter`/`Histogram` proxies, so they flow through
+//! the same per-thread buffer as any other metric.
+//! Recording them never re-enters the aggregator directly, the events are simply picked up on the next
+//! poll (and are themselves included in the processed events count).
+//!
+//! The exporter in use is reported as an info metric, a counter with value 1 tagged with the exporter
+//! type, encoders and target, so that it can be checked from the collector that the config took effect.
+//! The info metric is always reported as a running total, see [is_exporter_info].
+
+use crate::exporter::ExporterInfo;
+use metricus::{Counter, CounterOps, Histogram, Id, PreAllocatedMetric, Span};
+
+/// Ids reserved for the metrics of the agent itself, from `Id::MAX - 2999` to `Id::MAX - 2000`. Ids given
+/// to pre-allocated metrics or to `counter_with_id`/`histogram_with_id` must be outside of this range,
+/// otherwise they are mixed up with the agent's own metrics.
+pub const RESERVED_IDS: std::ops::RangeInclusive<Id> = Id::MAX - 2999..=Id::MAX - 2000;
+
+/// Reserved id of the counter tracking the number of update events processed by the aggregator.
+pub const EVENTS_PROCESSED_COUNTER_ID: Id = Id::MAX - 2002;
+/// Reserved id of the histogram tracking publish duration in nanoseconds.
+pub const PUBLISH_DURATION_HISTOGRAM_ID: Id = Id::MAX - 2001;
+/// Reserved id of the counter tracking the number of datagrams the exporter failed to send.
+pub const UNSENT_DATAGRAMS_COUNTER_ID: Id = Id::MAX - 2000;
+
+/// Whether the counter is the exporter info metric, which the aggregator reports as a running total
+/// regardless of `counter_reporting`. It is only incremented once, so its delta would be 1 on the first
+/// publish and 0 from then on.
+pub fn is_exporter_info(name: &str, tags: &[(String, String)]) -> bool {
+    name == "metrics_agent"
+        && tags
+            .iter()
+            .any(|(key, value)| key == "stat" && value == "exporter_info")
+}
+
+pub struct AggregatorStats {
+    events_processed: Counter,
+    publish_duration: Histogram,
+    unsent_datagrams: Counter,
+    _exporter_info: Counter,
+}
+
+impl AggregatorStats {
+    /// Pre-allocated metrics used by the aggregator self-instrumentation.
+    pub fn metrics() -> Vec<PreAllocatedMetric> {
+        vec![
+            PreAllocatedMetric::counter("metrics_agent", EVENTS_PROCESSED_COUNTER_ID, &[("stat", "events_processed")]),
+            PreAllocatedMetric::histogram(
+                "metrics_agent",
+                PUBLISH_DURATION_HISTOGRAM_ID,
+                &[("stat", "publish_duration")],
+            ),
+            PreAllocatedMetric::counter("metrics_agent", UNSENT_DATAGRAMS_COUNTER_ID, &[("stat", "unsent_datagrams")]),
+        ]
+    }
+
+    /// Must only be called once the agent has been set as the metrics backend, as the proxies
+    /// cache the active handle.
+    pub fn new(exporter: &ExporterInfo) -> Self {
+        let encoders = exporter.encoders.join(",");
+        let target = exporter.target.as_deref().unwrap_or_default();
+        let exporter_info = Counter::new(
+            "metrics_agent",
+            &[
+                ("stat", "exporter_info"),
+                ("exporter", exporter.exporter),
+                ("encoder", &encoders),
+                ("target", target),
+            ],
+        );
+        exporter_info.increment();
+        Self {
+            events_processed: Counter::new_with_id(EVENTS_PROCESSED_COUNTER_ID),
+            publish_duration: Histogram::new_with_id(PUBLISH_DURATION_HISTOGRAM_ID),
+            unsent_datagrams: Counter::new_with_id(UNSENT_DATAGRAMS_COUNTER_ID),
+            _exporter_info: exporter_info,
+        }
+    }
+
+    #[inline]
+    pub fn events_processed(&self, count: u64) {
+        self.events_processed.increment_by(count);
+    }
+
+    #[inline]
+    pub fn unsent_datagrams(&self, count: u64) {
+        self.unsent_datagrams.increment_by(count);
+    }
+
+    #[inline]
+    pub fn publish_span(&self) -> Span<'_> {
+        metricus::HistogramOps::span(&self.publish_duration)
+    }
+}
@@ -0,0 +1,154 @@
+//! Merging t-digest used as an alternative histogram summary.
+//!
+//! Values are appended to an insertion buffer that is merged into a sorted list of centroids once
+//! full, or before the digest is queried. Centroid sizes are bounded by the `k1` scale function
+//! (`k(q) = compression / (2π) * asin(2q - 1)`), which keeps centroids near the tails small so that
+//! extreme quantiles stay accurate while the number of centroids never exceeds about `compression`.
+
+use std::f64::consts::PI;
+
+/// Controls the accuracy/memory trade-off, the digest holds at most around this many centroids.
+const COMPRESSION: f64 = 100.0;
+/// Number of values buffered before they are merged into the centroids.
+const BUFFER_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Centroid {
+    pub mean: f64,
+    pub weight: u64,
+}
+
+pub struct TDigest {
+    centroids: Vec<Centroid>,
+    buffer: Vec<Centroid>,
+    count: u64,
+    sum: f64,
+    min: u64,
+    max: u64,
+}
+
+impl TDigest {
+    pub fn new() -> Self {
+        Self {
+            centroids: Vec::with_capacity(COMPRESSION as usize * 2),
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+            count: 0,
+            sum: 0.0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    #[inline]
+    pub fn record_n(&mut self, value: u64, count: u64) {
+        if count == 0 {
+            return;
+        }
+        self.buffer.push(Centroid {
+            mean: value as f64,
+            weight: count,
+        });
+        self.count += count;
+        self.sum += value as f64 * count as f64;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        if self.buffer.len() == BUFFER_SIZE {
+            self.compress();
+        }
+    }
+
+    /// Merges buffered values into the centroids.
+    pub fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        self.buffer.append(&mut self.centroids);
+        self.buffer.sort_unstable_by(|a, b| a.mean.total_cmp(&b.mean));
+
+        let total = self.count as f64;
+        let mut merged_weight = 0.0;
+        let mut current = self.buffer[0];
+        for &next in &self.buffer[1..] {
+            let q_left = merged_weight / total;
+            let q_right = (merged_weight + (current.weight + next.weight) as f64) / total;
+            if k(q_right) - k(q_left) <= 1.0 {
+                let weight = current.weight + next.weight;
+                current.mean += (next.mean - current.mean) * next.weight as f64 / weight as f64;
+                current.weight = weight;
+            } else {
+                merged_weight += current.weight as f64;
+                self.centroids.push(current);
+                current = next;
+            }
+        }
+        self.centroids.push(current);
+        self.buffer.clear();
+    }
+
+    /// Estimated value at quantile `q`. Buffered values are only taken into account after [TDigest::compress].
+    pub fn value_at_quantile(&self, q: f64) -> u64 {
+        let Some(first) = self.centroids.first() else {
+            return 0;
+        };
+        if self.centroids.len() == 1 {
+            return first.mean.round() as u64;
+        }
+        let target = q.clamp(0.0, 1.0) * self.count as f64;
+        // interpolate between the centers of adjacent centroids, using min and max as the outer bounds
+        let mut previous_center = 0.0;
+        let mut previous_mean = self.min as f64;
+        let mut cumulative = 0.0;
+        for centroid in &self.centroids {
+            let center = cumulative + centroid.weight as f64 / 2.0;
+            if target < center {
+                let ratio = (target - previous_center) / (center - previous_center);
+                let value = previous_mean + (centroid.mean - previous_mean) * ratio;
+                return value.round() as u64;
+            }
+            previous_center = center;
+            previous_mean = centroid.mean;
+            cumulative += centroid.weight as f64;
+        }
+        let ratio = (target - previous_center) / (cumulative - previous_center);
+        let value = previous_mean + (self.max as f64 - previous_mean) * ratio;
+        value.round().min(self.max as f64) as u64
+    }
+
+    pub fn centroids(&self) -> &[Centroid] {
+        &self.centroids
+    }
+
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> u64 {
+        if self.count == 0 { 0 } else { self.min }
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum / self.count as f64
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.centroids.clear();
+        self.buffer.clear();
+        self.count = 0;
+        self.sum = 0.0;
+        self.min = u64::MAX;
+        self.max = 0;
+    }
+}
+
+#[inline]
+fn k(q: f64) -> f64 {
+    COMPRESSION / (2.0 * PI) * (2.0 * q - 1.0).asin()
+}
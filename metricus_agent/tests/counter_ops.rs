@@ -0,0 +1,45 @@
+use metricus::{Counter, CounterOps};
+use metricus_agent::MetricsAgent;
+use std::sync::Once;
+
+fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| MetricsAgent::init_with_config("flush_interval: 1h\n".parse().unwrap()).unwrap());
+}
+
+#[test]
+fn reset_sets_the_counter_back_to_zero() {
+    init();
+    let sessions = Counter::new("reset_sessions", &[]);
+    sessions.increment_by(5);
+    sessions.reset();
+    assert_eq!(Some(0), sessions.value());
+    sessions.increment_by(2);
+    assert_eq!(Some(2), sessions.value());
+}
+
+#[test]
+fn value_includes_the_updates_of_all_threads() {
+    init();
+    let orders = Counter::new("value_orders", &[]);
+    assert_eq!(Some(0), orders.value());
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for _ in 0..1_000 {
+                    orders.increment();
+                }
+            });
+        }
+    });
+    // the aggregator processes the updates still pending before reading the value
+    assert_eq!(Some(4_000), orders.value());
+    orders.decrement_by(1_500);
+    assert_eq!(Some(2_500), orders.value());
+}
+
+#[test]
+fn value_of_an_unknown_counter_is_none() {
+    init();
+    assert_eq!(None, Counter::new_with_id(123_456).value());
+}
@@ -0,0 +1,39 @@
+use metricus_agent::{ExporterInfo, MetricsAgent};
+
+#[test]
+fn exporter_info_reads_1_on_every_publish_with_delta_reporting() {
+    let path = std::env::temp_dir().join(format!("metricus_exporter_info_{}.txt", std::process::id()));
+    let path = path.to_string_lossy().into_owned();
+    let config = format!(
+        "flush_interval: 1h\ncounter_reporting: delta\nself_metrics: true\n\
+         exporter:\n  type: file\n  config:\n    path: {path}\n    encoder: line_protocol\n"
+    );
+    MetricsAgent::init_with_config(config.parse().unwrap()).unwrap();
+
+    // the info metric is registered on the first publish and exported from the second one on
+    for _ in 0..3 {
+        metricus::flush().unwrap();
+    }
+    let expected = ExporterInfo {
+        exporter: "file",
+        encoders: vec!["line_protocol"],
+        target: Some(path.clone()),
+    };
+    assert_eq!(Some(expected), MetricsAgent::exporter_info());
+
+    let published = std::fs::read_to_string(&path).unwrap();
+    let values: Vec<_> = published
+        .lines()
+        .filter(|line| line.contains("stat=exporter_info"))
+        .map(|line| line.split(' ').nth(1).unwrap())
+        .collect();
+    assert_eq!(["value=1u", "value=1u"], values.as_slice());
+    let series = published
+        .lines()
+        .find(|line| line.contains("stat=exporter_info"))
+        .unwrap();
+    assert!(series.starts_with("metrics_agent,encoder=line_protocol,exporter=file,stat=exporter_info,"), "{series}");
+    assert!(series.contains(&format!("target={path}")), "{series}");
+    MetricsAgent::shutdown().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
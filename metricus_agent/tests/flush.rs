@@ -0,0 +1,31 @@
+use metricus::{Counter, CounterOps, Histogram, HistogramOps};
+use metricus_agent::MetricsAgent;
+
+#[test]
+fn published_metrics_are_in_the_file_once_flush_returns() {
+    // the no-op backend has nothing to deliver
+    metricus::flush().unwrap();
+
+    let path = std::env::temp_dir().join(format!("metricus_flush_{}.txt", std::process::id()));
+    let path = path.to_string_lossy().into_owned();
+    let config = format!(
+        "flush_interval: 1h\nexporter:\n  type: file\n  config:\n    path: {path}\n    encoder: line_protocol\n"
+    );
+    MetricsAgent::init_with_config(config.parse().unwrap()).unwrap();
+    let orders = Counter::new("orders", &[]);
+    let latency = Histogram::new("latency", &[]);
+    orders.increment_by(3);
+    latency.record(100);
+
+    // no publish is due for an hour, so what is in the file was published by the flush
+    metricus::flush().unwrap();
+    let published = std::fs::read_to_string(&path).unwrap();
+    let mut series: Vec<_> = published.lines().filter_map(|line| line.split_once(' ')).collect();
+    series.sort();
+    assert_eq!(2, series.len(), "{published}");
+    assert_eq!("latency,type=histogram", series[0].0);
+    assert!(series[0].1.starts_with("count=1u,"), "{published}");
+    assert_eq!(("orders,type=counter", "value=3u"), (series[1].0, series[1].1.split(' ').next().unwrap()));
+    MetricsAgent::shutdown().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
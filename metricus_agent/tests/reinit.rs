@@ -0,0 +1,32 @@
+use metricus::{Counter, CounterOps};
+use metricus_agent::MetricsAgent;
+
+#[test]
+fn counters_continue_from_the_snapshot_after_reinit() {
+    let path = std::env::temp_dir().join(format!("metricus_reinit_{}.txt", std::process::id()));
+    let path = path.to_string_lossy().into_owned();
+    MetricsAgent::init_with_config("flush_interval: 1h\n".parse().unwrap()).unwrap();
+    let orders = Counter::new("orders", &[("venue", "lse")]);
+    orders.increment_by(5);
+
+    let config = format!(
+        "flush_interval: 1h\nexporter:\n  type: file\n  config:\n    path: {path}\n    encoder: line_protocol\n"
+    );
+    MetricsAgent::reinit_with_config(config.parse().unwrap()).unwrap();
+    // created again as recommended, registering under the restored series
+    let restored = Counter::new("orders", &[("venue", "lse")]);
+    drop(orders);
+    restored.increment_by(2);
+    assert_eq!(Some(7), restored.value());
+
+    metricus::flush().unwrap();
+    let published = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<_> = published
+        .lines()
+        .filter_map(|line| line.rsplit_once(' '))
+        .map(|(line, _)| line)
+        .collect();
+    assert_eq!(["orders,type=counter,venue=lse value=7u"], lines.as_slice());
+    MetricsAgent::shutdown().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
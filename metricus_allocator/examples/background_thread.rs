@@ -1,7 +1,8 @@
 use metricus_allocator::{CountingAllocator, enable_allocator_instrumentation};
+use std::alloc::System;
 
 #[global_allocator]
-static GLOBAL: CountingAllocator = CountingAllocator;
+static GLOBAL: CountingAllocator = CountingAllocator::new(System);
 
 fn foo() -> usize {
     Vec::<u8>::with_capacity(1024).capacity()
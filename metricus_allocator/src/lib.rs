@@ -1,14 +1,30 @@
 #![doc = include_str!("../README.md")]
 
-use metricus::{Counter, CounterOps, Id, PreAllocatedMetric};
-use std::alloc::{GlobalAlloc, Layout};
-use std::cell::Cell;
-use std::sync::LazyLock;
+use metricus::{Counter, CounterOps, Gauge, GaugeOps, Id, PreAllocatedMetric};
+#[cfg(feature = "size-histogram")]
+use metricus::{Histogram, HistogramOps};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::thread::ThreadId;
 
 const ALLOC_COUNTER_ID: Id = Id::MAX - 1004;
 const ALLOC_BYTES_COUNTER_ID: Id = Id::MAX - 1003;
 const DEALLOC_COUNTER_ID: Id = Id::MAX - 1002;
 const DEALLOC_BYTES_COUNTER_ID: Id = Id::MAX - 1001;
+const REALLOC_COUNTER_ID: Id = Id::MAX - 1006;
+const REALLOC_BYTES_COUNTER_ID: Id = Id::MAX - 1005;
+#[cfg(feature = "size-histogram")]
+const ALLOC_SIZE_HISTOGRAM_ID: Id = Id::MAX - 1007;
+const LIVE_BYTES_GAUGE_ID: Id = Id::MAX - 1008;
+const PEAK_BYTES_GAUGE_ID: Id = Id::MAX - 1009;
+
+/// Maximum number of distinct allocation zones, see [set_allocation_zone].
+pub const MAX_ALLOCATION_ZONES: usize = 16;
+/// Maximum number of threads listed by [instrumented_threads], and of threads with their own counters,
+/// see [enable_per_thread_allocator_instrumentation].
+pub const MAX_INSTRUMENTED_THREADS: usize = 256;
 
 const fn get_aligned_size(layout: Layout) -> usize {
     let alignment_mask: usize = layout.align() - 1;
@@ -16,80 +32,265 @@ const fn get_aligned_size(layout: Layout) -> usize {
 }
 
 /// This allocator will use instrumentation to count the number of allocations and de-allocations
-/// occurring in the program. All calls to allocate (and free) memory are delegated to the concrete
-/// allocator (`std::alloc::System` by default). Once the allocator has been registered as
-/// `global_allocator` you need to call [enable_allocator_instrumentation] from each thread that
-/// wants to include its allocation and de-allocation metrics.
+/// occurring in the program. Reallocations are counted separately, along with the number of bytes by
+/// which the allocations grew or shrank, rather than as an allocation followed by a de-allocation. All
+/// calls to allocate (and free) memory are delegated to the inner allocator `A`, which is
+/// `std::alloc::System` unless specified otherwise, e.g. a custom arena allocator. Once the allocator has been registered as `global_allocator` you need to call
+/// [enable_allocator_instrumentation] from each thread that wants to include its allocation and
+/// de-allocation metrics.
 ///
 /// ```no_run
 /// use metricus_allocator::CountingAllocator;
+/// use std::alloc::System;
 ///
 /// #[global_allocator]
-/// static GLOBAL: CountingAllocator = CountingAllocator;
+/// static GLOBAL: CountingAllocator = CountingAllocator::new(System);
 /// ```
-pub struct CountingAllocator;
+///
+/// With the `jemalloc` or `mimalloc` feature, [DefaultCountingAllocator] delegates to the selected
+/// allocator, as does [JemallocCountingAllocator] or [MimallocCountingAllocator] respectively.
+///
+/// ```no_run
+/// use metricus_allocator::DefaultCountingAllocator;
+///
+/// #[global_allocator]
+/// static GLOBAL: DefaultCountingAllocator = DefaultCountingAllocator::DEFAULT;
+/// ```
+pub struct CountingAllocator<A = System> {
+    inner: A,
+}
+
+/// Counting allocator delegating to jemalloc.
+#[cfg(feature = "jemalloc")]
+pub type JemallocCountingAllocator = CountingAllocator<jemallocator::Jemalloc>;
+
+/// Counting allocator delegating to mimalloc.
+#[cfg(feature = "mimalloc")]
+pub type MimallocCountingAllocator = CountingAllocator<mimalloc::MiMalloc>;
+
+/// Allocator selected by the `jemalloc` or `mimalloc` feature, or the system allocator if neither or
+/// both of them are enabled.
+#[cfg(all(feature = "jemalloc", not(feature = "mimalloc")))]
+pub type DefaultAllocator = jemallocator::Jemalloc;
+#[cfg(all(feature = "jemalloc", not(feature = "mimalloc")))]
+const DEFAULT_ALLOCATOR: DefaultAllocator = jemallocator::Jemalloc;
+
+/// Allocator selected by the `jemalloc` or `mimalloc` feature, or the system allocator if neither or
+/// both of them are enabled.
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+pub type DefaultAllocator = mimalloc::MiMalloc;
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+const DEFAULT_ALLOCATOR: DefaultAllocator = mimalloc::MiMalloc;
+
+/// Allocator selected by the `jemalloc` or `mimalloc` feature, or the system allocator if neither or
+/// both of them are enabled.
+#[cfg(not(any(
+    all(feature = "jemalloc", not(feature = "mimalloc")),
+    all(feature = "mimalloc", not(feature = "jemalloc"))
+)))]
+pub type DefaultAllocator = System;
+#[cfg(not(any(
+    all(feature = "jemalloc", not(feature = "mimalloc")),
+    all(feature = "mimalloc", not(feature = "jemalloc"))
+)))]
+const DEFAULT_ALLOCATOR: DefaultAllocator = System;
+
+/// Counting allocator delegating to the allocator selected by the features, see [DefaultAllocator].
+pub type DefaultCountingAllocator = CountingAllocator<DefaultAllocator>;
 
 #[allow(static_mut_refs)]
-unsafe impl GlobalAlloc for CountingAllocator {
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        // provide metrics only if instrumentation has been enabled for this thread
-        if INSTRUMENTATION_ENABLED.get() {
-            COUNTERS.alloc_count.increment();
-            COUNTERS.alloc_bytes.increment_by(get_aligned_size(layout) as u64);
-        }
+        record(|counters| {
+            counters.alloc_count.increment();
+            counters.alloc_bytes.increment_by(get_aligned_size(layout) as u64);
+            #[cfg(feature = "size-histogram")]
+            counters.alloc_size.record(get_aligned_size(layout) as u64);
+            add_live_bytes(get_aligned_size(layout) as isize);
+        });
 
-        // delegate to the appropriate allocator
-        #[cfg(all(feature = "jemalloc", not(feature = "mimalloc")))]
-        {
-            return unsafe { jemallocator::Jemalloc.alloc(layout) };
-        }
-        #[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
-        {
-            return unsafe { mimalloc::MiMalloc.alloc(layout) };
-        }
-        unsafe { std::alloc::System.alloc(layout) }
+        unsafe { self.inner.alloc(layout) }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        // provide metrics only if instrumentation has been enabled for this thread
-        if INSTRUMENTATION_ENABLED.get() {
-            COUNTERS.dealloc_count.increment();
-            COUNTERS.dealloc_bytes.increment_by(get_aligned_size(layout) as u64);
-        }
+        record(|counters| {
+            counters.dealloc_count.increment();
+            counters.dealloc_bytes.increment_by(get_aligned_size(layout) as u64);
+            add_live_bytes(-(get_aligned_size(layout) as isize));
+        });
 
-        // delegate to the appropriate allocator
-        #[cfg(all(feature = "jemalloc", not(feature = "mimalloc")))]
-        {
-            unsafe {
-                jemallocator::Jemalloc.dealloc(ptr, layout);
-            }
-            return;
-        }
-        #[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
-        {
-            unsafe {
-                mimalloc::MiMalloc.dealloc(ptr, layout);
-            }
-            return;
-        }
-        unsafe { std::alloc::System.dealloc(ptr, layout) }
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(|counters| {
+            counters.realloc_count.increment();
+            // the caller guarantees that `new_size` rounded up to the alignment does not overflow
+            let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+            // bytes by which the allocation grew or shrank
+            let delta = get_aligned_size(new_layout).abs_diff(get_aligned_size(layout));
+            counters.realloc_bytes.increment_by(delta as u64);
+            add_live_bytes(get_aligned_size(new_layout) as isize - get_aligned_size(layout) as isize);
+        });
+
+        unsafe { self.inner.realloc(ptr, layout, new_size) }
     }
 }
 
-impl CountingAllocator {
-    /// Default counters to be used with the `CountingAllocator`.
+/// Records into the counters of the current thread, provided that instrumentation has been enabled for
+/// it and is not disabled globally. Allocations made while recording, e.g. by a metrics backend initializing lazily, are delegated
+/// straight to the inner allocator rather than recursing into the counters, and are not counted.
+#[inline]
+fn record(f: impl FnOnce(&Counters)) {
+    if INSTRUMENTATION_ENABLED.get()
+        && GLOBAL_INSTRUMENTATION_ENABLED.load(Ordering::Relaxed)
+        && !RECORDING.replace(true)
+    {
+        f(current_counters());
+        RECORDING.set(false);
+    }
+}
+
+impl<A> CountingAllocator<A> {
+    /// Counting allocator delegating to `inner`.
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+impl DefaultCountingAllocator {
+    /// Counting allocator delegating to the allocator selected by the features, see [DefaultAllocator].
+    pub const DEFAULT: Self = Self::new(DEFAULT_ALLOCATOR);
+
+    /// Default counters to be used with the `CountingAllocator`, which are the same regardless of the
+    /// inner allocator, and the gauges of [live_bytes] and [peak_bytes] (set by [update_live_bytes_gauges]),
+    /// along with the histogram of allocation sizes if the `size-histogram` feature is enabled.
     pub fn metrics() -> Vec<PreAllocatedMetric> {
-        vec![
+        #[allow(unused_mut)]
+        let mut metrics = vec![
             PreAllocatedMetric::counter("global_allocator", ALLOC_COUNTER_ID, &[("fn_name", "alloc")]),
-            PreAllocatedMetric::counter("global_allocator", ALLOC_BYTES_COUNTER_ID, &[("fn_name", "alloc_bytes")]),
+            bytes_counter(ALLOC_BYTES_COUNTER_ID, "alloc_bytes"),
             PreAllocatedMetric::counter("global_allocator", DEALLOC_COUNTER_ID, &[("fn_name", "dealloc")]),
-            PreAllocatedMetric::counter("global_allocator", DEALLOC_BYTES_COUNTER_ID, &[("fn_name", "dealloc_bytes")]),
-        ]
+            bytes_counter(DEALLOC_BYTES_COUNTER_ID, "dealloc_bytes"),
+            PreAllocatedMetric::counter("global_allocator", REALLOC_COUNTER_ID, &[("fn_name", "realloc")]),
+            bytes_counter(REALLOC_BYTES_COUNTER_ID, "realloc_bytes"),
+            PreAllocatedMetric::gauge_with_unit(
+                "global_allocator",
+                LIVE_BYTES_GAUGE_ID,
+                &[("fn_name", "live_bytes")],
+                "bytes",
+            ),
+            PreAllocatedMetric::gauge_with_unit(
+                "global_allocator",
+                PEAK_BYTES_GAUGE_ID,
+                &[("fn_name", "peak_bytes")],
+                "bytes",
+            ),
+        ];
+        #[cfg(feature = "size-histogram")]
+        metrics.push(PreAllocatedMetric::histogram_with_unit(
+            "global_allocator",
+            ALLOC_SIZE_HISTOGRAM_ID,
+            &[("fn_name", "alloc_size")],
+            "bytes",
+        ));
+        metrics
     }
 }
 
+fn bytes_counter(id: Id, fn_name: &str) -> PreAllocatedMetric {
+    PreAllocatedMetric::counter_with_unit("global_allocator", id, &[("fn_name", fn_name)], "bytes")
+}
+
+/// Bytes currently allocated by instrumented threads, i.e. the bytes allocated minus the bytes
+/// de-allocated by them, which goes negative if they free more memory than they allocated.
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+/// Highest value of [LIVE_BYTES] so far.
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+static LIVE_BYTES_GAUGE: LazyLock<Gauge> = LazyLock::new(|| Gauge::new_with_id(LIVE_BYTES_GAUGE_ID));
+static PEAK_BYTES_GAUGE: LazyLock<Gauge> = LazyLock::new(|| Gauge::new_with_id(PEAK_BYTES_GAUGE_ID));
+
+/// Bytes currently allocated by the threads that have enabled instrumentation, i.e. the bytes they have
+/// allocated minus the bytes they have de-allocated (with reallocations accounted for the size by which
+/// the allocation grew or shrank). The gauge declared by [DefaultCountingAllocator::metrics] is only set
+/// when [update_live_bytes_gauges] is called.
+///
+/// Only instrumented allocations are tracked, so memory allocated by other threads, or before
+/// instrumentation was enabled, is not included. If instrumented threads free more of such memory than
+/// they allocate, the value is reported as zero. The value is updated with relaxed atomics and may lag
+/// behind allocations made concurrently on other threads.
+///
+/// ## Examples
+///
+/// ```no_run
+/// use metricus_allocator::{enable_allocator_instrumentation, live_bytes, peak_bytes};
+///
+/// enable_allocator_instrumentation();
+/// let levels = vec![0u64; 1024];
+/// println!("live: {} bytes, peak: {} bytes", live_bytes(), peak_bytes());
+/// ```
+pub fn live_bytes() -> usize {
+    non_negative(LIVE_BYTES.load(Ordering::Relaxed))
+}
+
+/// Highest value of [live_bytes] since the process started, i.e. its high-water mark. The gauge
+/// declared by [DefaultCountingAllocator::metrics] is only set when [update_live_bytes_gauges] is called.
+pub fn peak_bytes() -> usize {
+    PEAK_BYTES.load(Ordering::Relaxed)
+}
+
+/// Sets the gauges declared by [DefaultCountingAllocator::metrics] to the current [live_bytes] and
+/// [peak_bytes]. The gauges are not set on allocation, to keep the cost of each allocation down, so the
+/// application calls this periodically instead, typically from the loop that also publishes the metrics.
+/// The exported values are then as of the last call. Backends that do not export gauges, such as the
+/// `metricus_agent` aggregator, produce no output for them.
+///
+/// ## Examples
+///
+/// ```no_run
+/// use metricus_allocator::{enable_allocator_instrumentation, update_live_bytes_gauges};
+///
+/// enable_allocator_instrumentation();
+/// let levels = vec![0u64; 1024];
+/// // e.g. before every publish
+/// update_live_bytes_gauges();
+/// ```
+pub fn update_live_bytes_gauges() {
+    LIVE_BYTES_GAUGE.set(live_bytes() as i64);
+    PEAK_BYTES_GAUGE.set(peak_bytes() as i64);
+}
+
+/// Adds `delta` (which may be negative) to the live bytes and raises the peak if needed.
+#[inline]
+fn add_live_bytes(delta: isize) {
+    let live = non_negative(
+        LIVE_BYTES
+            .fetch_add(delta as usize, Ordering::Relaxed)
+            .wrapping_add(delta as usize),
+    );
+    // only contend on the peak when it is exceeded
+    if live > PEAK_BYTES.load(Ordering::Relaxed) {
+        PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+    }
+}
+
+/// Bytes as tracked with wrapping arithmetic, with negative values clamped to zero.
+#[inline]
+const fn non_negative(bytes: usize) -> usize {
+    if (bytes as isize) < 0 { 0 } else { bytes }
+}
+
 thread_local! {
     static INSTRUMENTATION_ENABLED: Cell<bool> = const { Cell::new(false) };
+    /// Set while the thread records into its counters, see [record].
+    static RECORDING: Cell<bool> = const { Cell::new(false) };
+    /// Slot of the current allocation zone plus one, zero when no zone is set.
+    static CURRENT_ZONE: Cell<usize> = const { Cell::new(0) };
+    /// Counters of the thread, if it has opted in to its own counters.
+    static THREAD_COUNTERS: Cell<Option<&'static Counters>> = const { Cell::new(None) };
+    /// Removes the thread from the instrumented threads once it exits.
+    static REGISTRATION: RefCell<Option<Registration>> = const { RefCell::new(None) };
 }
 
 /// This should be called by a thread that wants to opt in to send allocation and de-allocation
@@ -104,9 +305,10 @@ thread_local! {
 ///
 /// use metricus_allocator::enable_allocator_instrumentation;
 /// use metricus_allocator::CountingAllocator;
+/// use std::alloc::System;
 ///
 /// #[global_allocator]
-/// static GLOBAL: CountingAllocator = CountingAllocator;
+/// static GLOBAL: CountingAllocator = CountingAllocator::new(System);
 ///
 /// fn main() {
 ///     enable_allocator_instrumentation();
@@ -118,9 +320,10 @@ thread_local! {
 ///
 /// use metricus_allocator::enable_allocator_instrumentation;
 /// use metricus_allocator::CountingAllocator;
+/// use std::alloc::System;
 ///
 /// #[global_allocator]
-/// static GLOBAL: CountingAllocator = CountingAllocator;
+/// static GLOBAL: CountingAllocator = CountingAllocator::new(System);
 ///
 /// fn main() {
 ///     let _ = std::thread::spawn(|| {
@@ -129,18 +332,252 @@ thread_local! {
 /// }
 /// ```
 pub fn enable_allocator_instrumentation() {
+    if INSTRUMENTATION_ENABLED.get() {
+        return;
+    }
+    // register before enabling so that the registration's own allocations are not counted
+    let thread = std::thread::current();
+    let registered = {
+        let mut threads = INSTRUMENTED_THREADS.lock().unwrap_or_else(|err| err.into_inner());
+        if threads.len() < MAX_INSTRUMENTED_THREADS {
+            threads.push(InstrumentedThread {
+                name: thread.name().map(str::to_owned),
+                id: thread.id(),
+            });
+            true
+        } else {
+            false
+        }
+    };
+    if registered {
+        let _ = REGISTRATION.try_with(|registration| *registration.borrow_mut() = Some(Registration(thread.id())));
+    }
     INSTRUMENTATION_ENABLED.set(true);
 }
 
+/// Stops the current thread from sending allocation and de-allocation metrics, after it has opted in
+/// with [enable_allocator_instrumentation], and removes it from the [instrumented_threads]. The thread
+/// can opt in again later.
+///
+/// ## Examples
+///
+/// ```no_run
+/// use metricus_allocator::{disable_allocator_instrumentation, enable_allocator_instrumentation};
+///
+/// enable_allocator_instrumentation();
+/// let orders = vec![0u64; 1024]; // counted
+/// disable_allocator_instrumentation();
+/// let scratch = vec![0u64; 1024]; // not counted
+/// ```
+pub fn disable_allocator_instrumentation() {
+    if !INSTRUMENTATION_ENABLED.replace(false) {
+        return;
+    }
+    let _ = REGISTRATION.try_with(|registration| registration.borrow_mut().take());
+}
+
+/// Enables or disables allocator instrumentation for all threads at once, e.g. to leave out the
+/// allocations made while a benchmark warms up. Instrumentation is enabled globally by default.
+///
+/// The global switch applies on top of the per thread one: allocations are only counted on threads
+/// that have called [enable_allocator_instrumentation] (or [enable_per_thread_allocator_instrumentation])
+/// and only while instrumentation is enabled globally. Disabling it globally does not change the per
+/// thread state, so the threads that had opted in resume counting once it is enabled again, and threads
+/// can still opt in or out in the meantime. Checking the global switch costs a single relaxed atomic load
+/// per allocation on instrumented threads, and a change may take a moment to become visible to other
+/// threads.
+///
+/// ## Examples
+///
+/// ```no_run
+/// use metricus_allocator::{enable_allocator_instrumentation, set_allocator_instrumentation_enabled};
+///
+/// enable_allocator_instrumentation();
+/// set_allocator_instrumentation_enabled(false);
+/// // warm up
+/// set_allocator_instrumentation_enabled(true);
+/// // measure
+/// ```
+pub fn set_allocator_instrumentation_enabled(enabled: bool) {
+    GLOBAL_INSTRUMENTATION_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+static GLOBAL_INSTRUMENTATION_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Enables allocator instrumentation for the current thread like [enable_allocator_instrumentation], but
+/// records its allocations and de-allocations into counters of its own, additionally tagged with `thread`
+/// set to the thread name (or its id if it is unnamed), so that it is possible to tell which thread
+/// allocates the most. Threads that only call [enable_allocator_instrumentation] keep recording into the
+/// shared counters. While an allocation zone is set (see [set_allocation_zone]), allocations are recorded
+/// into the counters of the zone rather than those of the thread.
+///
+/// The counters of a thread are registered with the metrics backend when this is called, so the backend
+/// must be set before, and are kept for the lifetime of the process. Threads with the same name share
+/// their counters, so that e.g. a restarted worker thread continues where its predecessor left off. At
+/// most [MAX_INSTRUMENTED_THREADS] distinct threads get their own counters, any further threads record
+/// into the shared counters.
+///
+/// ## Examples
+///
+/// ```no_run
+/// use metricus_allocator::enable_per_thread_allocator_instrumentation;
+///
+/// let worker = std::thread::Builder::new()
+///     .name("worker".to_owned())
+///     .spawn(|| {
+///         enable_per_thread_allocator_instrumentation();
+///         let orders = vec![0u64; 1024]; // accounted to `thread = "worker"`
+///     })
+///     .unwrap();
+/// worker.join().unwrap();
+/// ```
+pub fn enable_per_thread_allocator_instrumentation() {
+    if THREAD_COUNTERS.get().is_none() {
+        // create the counters with instrumentation disabled so that their own allocations are not counted
+        let enabled = INSTRUMENTATION_ENABLED.replace(false);
+        let thread = std::thread::current();
+        let name = match thread.name() {
+            Some(name) => name.to_owned(),
+            None => format!("{:?}", thread.id()),
+        };
+        let counters = {
+            let mut threads = THREAD_COUNTERS_BY_NAME.lock().unwrap_or_else(|err| err.into_inner());
+            match threads.iter().find(|(thread, _)| *thread == name) {
+                Some((_, counters)) => Some(*counters),
+                None if threads.len() < MAX_INSTRUMENTED_THREADS => {
+                    let counters: &'static Counters = Box::leak(Box::new(Counters::tagged("thread", &name)));
+                    threads.push((name, counters));
+                    Some(counters)
+                }
+                None => None,
+            }
+        };
+        THREAD_COUNTERS.set(counters);
+        INSTRUMENTATION_ENABLED.set(enabled);
+    }
+    enable_allocator_instrumentation();
+}
+
+/// Counters of the threads using their own counters, by thread name.
+static THREAD_COUNTERS_BY_NAME: Mutex<Vec<(String, &'static Counters)>> = Mutex::new(Vec::new());
+
+/// A thread that has enabled allocator instrumentation, see [instrumented_threads].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstrumentedThread {
+    pub name: Option<String>,
+    pub id: ThreadId,
+}
+
+/// Lists the live threads that have called [enable_allocator_instrumentation], e.g. to find out which
+/// worker thread forgot to enable it when allocation numbers are lower than expected. Threads are removed
+/// from the list when they exit.
+///
+/// The list holds at most [MAX_INSTRUMENTED_THREADS] threads, threads enabling instrumentation while it
+/// is full are instrumented but not listed. The list is guarded by a lock which is only taken when
+/// instrumentation is enabled, when an instrumented thread exits and by this function, so it does not
+/// affect the cost of allocations. The returned list is a copy that may be outdated as soon as it is
+/// returned if other threads are starting or exiting concurrently.
+///
+/// ## Examples
+///
+/// ```no_run
+/// use metricus_allocator::{enable_allocator_instrumentation, instrumented_threads};
+///
+/// enable_allocator_instrumentation();
+/// for thread in instrumented_threads() {
+///     println!("{:?} {}", thread.id, thread.name.as_deref().unwrap_or("<unnamed>"));
+/// }
+/// ```
+pub fn instrumented_threads() -> Vec<InstrumentedThread> {
+    INSTRUMENTED_THREADS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+}
+
+static INSTRUMENTED_THREADS: Mutex<Vec<InstrumentedThread>> = Mutex::new(Vec::new());
+
+struct Registration(ThreadId);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut threads = INSTRUMENTED_THREADS.lock().unwrap_or_else(|err| err.into_inner());
+        threads.retain(|thread| thread.id != self.0);
+    }
+}
+
+/// Attributes allocations and de-allocations made by the current thread to `zone` (e.g. the name of
+/// a subsystem) until another zone is set or the zone is cleared with [clear_allocation_zone]. The
+/// counters are then additionally tagged with `zone` so that it is possible to tell which subsystem
+/// allocates the most. This only has an effect on threads that have enabled instrumentation.
+///
+/// At most [MAX_ALLOCATION_ZONES] distinct zones are supported, allocations in any further zones are
+/// reported without a zone tag. The counters of a zone are registered with the metrics backend the
+/// first time it is set, so the backend must be set before. Setting a zone takes a lock and should be
+/// done at subsystem boundaries rather than on a hot path, while the cost on each allocation is a single
+/// additional thread-local read.
+///
+/// ## Examples
+///
+/// ```no_run
+/// use metricus_allocator::{clear_allocation_zone, enable_allocator_instrumentation, set_allocation_zone};
+///
+/// enable_allocator_instrumentation();
+/// set_allocation_zone("order_book");
+/// let levels = vec![0u64; 1024]; // accounted to the `order_book` zone
+/// clear_allocation_zone();
+/// ```
+pub fn set_allocation_zone(zone: &'static str) {
+    let slot = {
+        let mut zones = ZONES.lock().unwrap_or_else(|err| err.into_inner());
+        match zones.iter().position(|name| *name == zone) {
+            Some(slot) => Some(slot),
+            None if zones.len() < MAX_ALLOCATION_ZONES => {
+                zones.push(zone);
+                Some(zones.len() - 1)
+            }
+            None => None,
+        }
+    };
+    match slot {
+        Some(slot) => {
+            ZONE_COUNTERS[slot].get_or_init(|| Counters::tagged("zone", zone));
+            CURRENT_ZONE.set(slot + 1);
+        }
+        None => CURRENT_ZONE.set(0),
+    }
+}
+
+/// Stops attributing allocations made by the current thread to a zone, see [set_allocation_zone].
+pub fn clear_allocation_zone() {
+    CURRENT_ZONE.set(0);
+}
+
+#[inline]
+fn current_counters() -> &'static Counters {
+    match CURRENT_ZONE.get() {
+        0 => THREAD_COUNTERS.get().unwrap_or(&COUNTERS),
+        slot => ZONE_COUNTERS[slot - 1].get().unwrap_or(&COUNTERS),
+    }
+}
+
+/// Zone names indexed by slot.
+static ZONES: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+static ZONE_COUNTERS: [OnceLock<Counters>; MAX_ALLOCATION_ZONES] = [const { OnceLock::new() }; MAX_ALLOCATION_ZONES];
+
 static COUNTERS: LazyLock<Counters> = LazyLock::new(|| Counters {
     // `counter_with_id` creates a counter object without registering it.
     // These allocation counters are created lazily on first use and cache the active metrics handle.
-    // If they are initialized before `set_metrics`, they will remain bound to the no-op backend.
-    // Ensure the backend is set before enabling allocator instrumentation if you want these to emit.
+    // If they are initialized before `set_metrics`, they move over to the new backend on their next use,
+    // which only emits if that backend has pre-allocated the ids (see `DefaultCountingAllocator::metrics`).
     alloc_count: Counter::new_with_id(ALLOC_COUNTER_ID),
     alloc_bytes: Counter::new_with_id(ALLOC_BYTES_COUNTER_ID),
     dealloc_count: Counter::new_with_id(DEALLOC_COUNTER_ID),
     dealloc_bytes: Counter::new_with_id(DEALLOC_BYTES_COUNTER_ID),
+    realloc_count: Counter::new_with_id(REALLOC_COUNTER_ID),
+    realloc_bytes: Counter::new_with_id(REALLOC_BYTES_COUNTER_ID),
+    #[cfg(feature = "size-histogram")]
+    alloc_size: Histogram::new_with_id(ALLOC_SIZE_HISTOGRAM_ID),
 });
 
 struct Counters {
@@ -148,4 +585,26 @@ struct Counters {
     alloc_bytes: Counter,
     dealloc_count: Counter,
     dealloc_bytes: Counter,
+    realloc_count: Counter,
+    realloc_bytes: Counter,
+    /// Aligned size of each allocation.
+    #[cfg(feature = "size-histogram")]
+    alloc_size: Histogram,
+}
+
+impl Counters {
+    /// Counters additionally tagged with `key = value`, e.g. the allocation zone.
+    fn tagged(key: &str, value: &str) -> Self {
+        let counter = |fn_name| Counter::new("global_allocator", &[("fn_name", fn_name), (key, value)]);
+        Self {
+            alloc_count: counter("alloc"),
+            alloc_bytes: counter("alloc_bytes"),
+            dealloc_count: counter("dealloc"),
+            dealloc_bytes: counter("dealloc_bytes"),
+            realloc_count: counter("realloc"),
+            realloc_bytes: counter("realloc_bytes"),
+            #[cfg(feature = "size-histogram")]
+            alloc_size: Histogram::new("global_allocator", &[("fn_name", "alloc_size"), (key, value)]),
+        }
+    }
 }
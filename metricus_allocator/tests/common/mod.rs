@@ -0,0 +1,145 @@
+use metricus::{Id, Metrics, PreAllocatedMetric, Tags, set_metrics};
+use metricus_allocator::DefaultCountingAllocator;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+type OwnedTags = Vec<(String, String)>;
+
+/// Backend keeping the value of every counter incremented and every gauge set by id, as the shared
+/// allocator metrics are created with pre-allocated ids rather than registered. The counters of zones and
+/// threads are registered, and are kept by their tags. Every increment allocates, as a backend that
+/// allocates while the allocator records must not make the allocator recurse or count its own allocations.
+#[derive(Clone, Default)]
+pub struct IdMetrics {
+    counters: Arc<Mutex<HashMap<Id, u64>>>,
+    gauges: Arc<Mutex<HashMap<Id, i64>>>,
+    histograms: Arc<Mutex<HashMap<Id, Vec<u64>>>>,
+    registered: Arc<Mutex<HashMap<OwnedTags, Id>>>,
+}
+
+impl IdMetrics {
+    /// Installs a backend the first time this is called, and returns the installed backend on every call.
+    pub fn install() -> IdMetrics {
+        static INSTALLED: OnceLock<IdMetrics> = OnceLock::new();
+        INSTALLED
+            .get_or_init(|| {
+                let metrics = IdMetrics::default();
+                set_metrics(metrics.clone());
+                metrics
+            })
+            .clone()
+    }
+
+    /// Value of the allocator counter tagged with `fn_name`, zero if it has not been incremented.
+    #[allow(dead_code)] // not used by every test binary
+    pub fn allocator_counter(&self, fn_name: &str) -> u64 {
+        let id = DefaultCountingAllocator::metrics()
+            .into_iter()
+            .find_map(|metric| match metric {
+                PreAllocatedMetric::Counter { id, tags, .. } if tags.iter().any(|(_, value)| value == fn_name) => {
+                    Some(id)
+                }
+                _ => None,
+            })
+            .unwrap();
+        self.counters().get(&id).copied().unwrap_or_default()
+    }
+
+    /// Value of the registered allocator counter with the given tags, `None` if it has not been registered.
+    #[allow(dead_code)] // not used by every test binary
+    pub fn registered_counter(&self, tags: Tags) -> Option<u64> {
+        let id = *self.registered().get(&owned(tags))?;
+        Some(self.counters().get(&id).copied().unwrap_or_default())
+    }
+
+    /// Values recorded into the allocator histogram tagged with `fn_name`.
+    #[allow(dead_code)] // not used by every test binary
+    pub fn allocator_histogram(&self, fn_name: &str) -> Vec<u64> {
+        let id = DefaultCountingAllocator::metrics()
+            .into_iter()
+            .find_map(|metric| match metric {
+                PreAllocatedMetric::Histogram { id, tags, .. } if tags.iter().any(|(_, value)| value == fn_name) => {
+                    Some(id)
+                }
+                _ => None,
+            })
+            .unwrap();
+        self.histograms().get(&id).cloned().unwrap_or_default()
+    }
+
+    /// Value of the allocator gauge tagged with `fn_name`, `None` if it has not been set.
+    #[allow(dead_code)] // not used by every test binary
+    pub fn allocator_gauge(&self, fn_name: &str) -> Option<i64> {
+        let id = DefaultCountingAllocator::metrics()
+            .into_iter()
+            .find_map(|metric| match metric {
+                PreAllocatedMetric::Gauge { id, tags, .. } if tags.iter().any(|(_, value)| value == fn_name) => {
+                    Some(id)
+                }
+                _ => None,
+            })
+            .unwrap();
+        self.gauges().get(&id).copied()
+    }
+
+    fn counters(&self) -> MutexGuard<'_, HashMap<Id, u64>> {
+        self.counters.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn gauges(&self) -> MutexGuard<'_, HashMap<Id, i64>> {
+        self.gauges.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn histograms(&self) -> MutexGuard<'_, HashMap<Id, Vec<u64>>> {
+        self.histograms.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn registered(&self) -> MutexGuard<'_, HashMap<OwnedTags, Id>> {
+        self.registered.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Id of the metric with the given tags, registering it on first use. Ids are handed out from zero,
+    /// well below the pre-allocated ids of the allocator.
+    fn register(&mut self, tags: Tags) -> Id {
+        let mut registered = self.registered();
+        let next = registered.len() as Id;
+        *registered.entry(owned(tags)).or_insert(next)
+    }
+}
+
+fn owned(tags: Tags) -> OwnedTags {
+    tags.iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+impl Metrics for IdMetrics {
+    fn name(&self) -> &'static str {
+        "id"
+    }
+
+    fn new_counter(&mut self, _name: &str, tags: Tags) -> Id {
+        self.register(tags)
+    }
+
+    fn delete_counter(&mut self, _id: Id) {}
+
+    fn increment_counter_by(&mut self, id: Id, delta: u64) {
+        let delta = std::hint::black_box(Box::new(delta));
+        *self.counters().entry(id).or_default() += *delta;
+    }
+
+    fn set_gauge(&mut self, id: Id, value: i64) {
+        self.gauges().insert(id, value);
+    }
+
+    fn new_histogram(&mut self, _name: &str, tags: Tags) -> Id {
+        self.register(tags)
+    }
+
+    fn delete_histogram(&mut self, _id: Id) {}
+
+    fn record(&mut self, id: Id, value: u64) {
+        self.histograms().entry(id).or_default().push(value);
+    }
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use common::IdMetrics;
+use metricus_allocator::{
+    CountingAllocator, disable_allocator_instrumentation, enable_allocator_instrumentation,
+    set_allocator_instrumentation_enabled,
+};
+use std::alloc::System;
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator::new(System);
+
+#[test]
+fn disabling_instrumentation_globally_pauses_instrumented_threads() {
+    let metrics = IdMetrics::install();
+    let bytes = || metrics.allocator_counter("alloc_bytes");
+    let allocate = |size| drop(std::hint::black_box(Vec::<u8>::with_capacity(size)));
+
+    let before = bytes();
+    enable_allocator_instrumentation();
+    set_allocator_instrumentation_enabled(false);
+    allocate(4096);
+    std::thread::spawn(move || {
+        enable_allocator_instrumentation();
+        allocate(2048);
+    })
+    .join()
+    .unwrap();
+    assert_eq!(before, bytes());
+
+    // threads that opted in resume counting without opting in again
+    set_allocator_instrumentation_enabled(true);
+    allocate(1024);
+    disable_allocator_instrumentation();
+    assert_eq!(before + 1024, bytes());
+}
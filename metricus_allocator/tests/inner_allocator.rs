@@ -0,0 +1,43 @@
+mod common;
+
+use common::IdMetrics;
+use metricus_allocator::{CountingAllocator, disable_allocator_instrumentation, enable_allocator_instrumentation};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Bytes the inner allocator was asked to allocate.
+static DELEGATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Delegates to the system allocator, keeping the number of bytes it was asked to allocate.
+struct Tracking;
+
+unsafe impl GlobalAlloc for Tracking {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        DELEGATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator<Tracking> = CountingAllocator::new(Tracking);
+
+#[test]
+fn allocations_are_counted_and_delegated_to_the_inner_allocator() {
+    let metrics = IdMetrics::install();
+    let counted = metrics.allocator_counter("alloc_bytes");
+    let delegated = DELEGATED_BYTES.load(Ordering::Relaxed);
+
+    enable_allocator_instrumentation();
+    let buffer = std::hint::black_box(Vec::<u8>::with_capacity(4096));
+    disable_allocator_instrumentation();
+    let delegated = DELEGATED_BYTES.load(Ordering::Relaxed) - delegated;
+    drop(buffer);
+
+    // the inner allocator also serves the allocations of the backend, which are not counted
+    assert!(delegated >= 4096, "{delegated} bytes delegated");
+    assert_eq!(counted + 4096, metrics.allocator_counter("alloc_bytes"));
+}
@@ -0,0 +1,48 @@
+use metricus_allocator::{
+    CountingAllocator, disable_allocator_instrumentation, enable_allocator_instrumentation, instrumented_threads,
+};
+use std::alloc::System;
+use std::sync::mpsc;
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator::new(System);
+
+fn names() -> Vec<Option<String>> {
+    let mut names: Vec<_> = instrumented_threads().into_iter().map(|thread| thread.name).collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn threads_are_listed_while_instrumented() {
+    let (tx_ready, rx_ready) = mpsc::channel();
+    let (tx_exit, rx_exit) = mpsc::channel::<()>();
+    let worker = std::thread::Builder::new()
+        .name("worker".to_owned())
+        .spawn(move || {
+            enable_allocator_instrumentation();
+            tx_ready.send(std::thread::current().id()).unwrap();
+            let _ = rx_exit.recv();
+        })
+        .unwrap();
+    let worker_id = rx_ready.recv().unwrap();
+    let unnamed = std::thread::spawn(|| {
+        enable_allocator_instrumentation();
+        let listed = instrumented_threads();
+        disable_allocator_instrumentation();
+        (listed, std::thread::current().id())
+    });
+    let (listed, unnamed_id) = unnamed.join().unwrap();
+    assert!(
+        listed
+            .iter()
+            .any(|thread| thread.id == unnamed_id && thread.name.is_none())
+    );
+    assert!(listed.iter().any(|thread| thread.id == worker_id));
+    // disabling instrumentation removes the thread, as does exiting
+    assert_eq!(vec![Some("worker".to_owned())], names());
+
+    drop(tx_exit);
+    worker.join().unwrap();
+    assert!(names().is_empty());
+}
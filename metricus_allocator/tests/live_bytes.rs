@@ -0,0 +1,38 @@
+mod common;
+
+use common::IdMetrics;
+use metricus_allocator::{
+    CountingAllocator, disable_allocator_instrumentation, enable_allocator_instrumentation, live_bytes, peak_bytes,
+    update_live_bytes_gauges,
+};
+use std::alloc::System;
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator::new(System);
+
+#[test]
+fn live_bytes_gauges_are_only_set_when_updated() {
+    let metrics = IdMetrics::install();
+    let counters = || ["alloc_bytes", "dealloc_bytes"].map(|fn_name| metrics.allocator_counter(fn_name));
+    let before = live_bytes();
+    let counted = counters();
+
+    enable_allocator_instrumentation();
+    let buffer = std::hint::black_box(Vec::<u8>::with_capacity(4096));
+    let allocated = live_bytes();
+    drop(buffer);
+    disable_allocator_instrumentation();
+
+    // the bytes allocated less the bytes de-allocated
+    assert_eq!(before + 4096, allocated);
+    assert_eq!(before, live_bytes());
+    assert_eq!([counted[0] + 4096, counted[1] + 4096], counters());
+    assert!(peak_bytes() >= allocated);
+    // allocating does not touch the gauges
+    assert_eq!(None, metrics.allocator_gauge("live_bytes"));
+    assert_eq!(None, metrics.allocator_gauge("peak_bytes"));
+
+    update_live_bytes_gauges();
+    assert_eq!(Some(live_bytes() as i64), metrics.allocator_gauge("live_bytes"));
+    assert_eq!(Some(peak_bytes() as i64), metrics.allocator_gauge("peak_bytes"));
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use common::IdMetrics;
+use metricus_allocator::{
+    CountingAllocator, disable_allocator_instrumentation, enable_per_thread_allocator_instrumentation,
+};
+use std::alloc::System;
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator::new(System);
+
+#[test]
+fn named_threads_are_counted_separately() {
+    let metrics = IdMetrics::install();
+    let thread_bytes = |thread| metrics.registered_counter(&[("fn_name", "alloc_bytes"), ("thread", thread)]);
+    let shared = metrics.allocator_counter("alloc_bytes");
+
+    let spawn = |name: &str, size: usize| {
+        std::thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || {
+                enable_per_thread_allocator_instrumentation();
+                drop(std::hint::black_box(Vec::<u8>::with_capacity(size)));
+                disable_allocator_instrumentation();
+            })
+            .unwrap()
+    };
+    let workers = [spawn("matcher", 4096), spawn("gateway", 1024)];
+    workers.into_iter().for_each(|worker| worker.join().unwrap());
+    assert_eq!(Some(4096), thread_bytes("matcher"));
+    assert_eq!(Some(1024), thread_bytes("gateway"));
+    assert_eq!(shared, metrics.allocator_counter("alloc_bytes"));
+
+    // a thread with the same name continues where its predecessor left off
+    spawn("matcher", 64).join().unwrap();
+    assert_eq!(Some(4160), thread_bytes("matcher"));
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use common::IdMetrics;
+use metricus_allocator::{CountingAllocator, disable_allocator_instrumentation, enable_allocator_instrumentation};
+use std::alloc::System;
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator::new(System);
+
+#[test]
+fn reallocating_a_vec_moves_the_realloc_counters() {
+    let metrics = IdMetrics::install();
+    let mut values: Vec<u64> = Vec::with_capacity(4);
+    values.push(1);
+    let counters = |fn_name| metrics.allocator_counter(fn_name);
+    let before = ["alloc", "alloc_bytes", "realloc", "realloc_bytes"].map(counters);
+
+    enable_allocator_instrumentation();
+    // grows the allocation from 32 to 512 bytes
+    values.reserve_exact(63);
+    disable_allocator_instrumentation();
+
+    let after = ["alloc", "alloc_bytes", "realloc", "realloc_bytes"].map(counters);
+    assert_eq!(64, values.capacity());
+    // counted as a reallocation rather than as an allocation followed by a de-allocation
+    assert_eq!(before[0], after[0]);
+    assert_eq!(before[1], after[1]);
+    assert_eq!(before[2] + 1, after[2]);
+    assert_eq!(before[3] + 480, after[3]);
+}
@@ -0,0 +1,27 @@
+mod common;
+
+use common::IdMetrics;
+use metricus_allocator::{CountingAllocator, disable_allocator_instrumentation, enable_allocator_instrumentation};
+use std::alloc::System;
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator::new(System);
+
+const COUNTERS: [&str; 4] = ["alloc", "alloc_bytes", "dealloc", "dealloc_bytes"];
+
+#[test]
+fn allocations_of_the_backend_are_not_counted() {
+    let metrics = IdMetrics::install();
+    let counters = |fn_name| metrics.allocator_counter(fn_name);
+    let before = COUNTERS.map(counters);
+
+    enable_allocator_instrumentation();
+    // recording the allocation and the de-allocation increments two counters each, and every increment
+    // allocates and frees a box in the backend
+    let buffer = std::hint::black_box(Vec::<u8>::with_capacity(64));
+    drop(buffer);
+    disable_allocator_instrumentation();
+
+    let after = COUNTERS.map(counters);
+    assert_eq!([before[0] + 1, before[1] + 64, before[2] + 1, before[3] + 64], after);
+}
@@ -0,0 +1,24 @@
+mod common;
+
+use common::IdMetrics;
+use metricus_allocator::{CountingAllocator, disable_allocator_instrumentation, enable_allocator_instrumentation};
+use std::alloc::{Layout, System};
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator::new(System);
+
+#[test]
+fn aligned_allocation_sizes_are_recorded() {
+    let metrics = IdMetrics::install();
+    let before = metrics.allocator_histogram("alloc_size").len();
+
+    enable_allocator_instrumentation();
+    drop(std::hint::black_box(Vec::<u8>::with_capacity(4096)));
+    drop(std::hint::black_box(Box::new([0u8; 5])));
+    // rounded up to the alignment
+    let layout = Layout::from_size_align(5, 8).unwrap();
+    unsafe { std::alloc::dealloc(std::alloc::alloc(layout), layout) };
+    disable_allocator_instrumentation();
+
+    assert_eq!(&[4096, 5, 8], &metrics.allocator_histogram("alloc_size")[before..]);
+}
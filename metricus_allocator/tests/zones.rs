@@ -0,0 +1,48 @@
+mod common;
+
+use common::IdMetrics;
+use metricus_allocator::{
+    CountingAllocator, MAX_ALLOCATION_ZONES, clear_allocation_zone, disable_allocator_instrumentation,
+    enable_allocator_instrumentation, set_allocation_zone,
+};
+use std::alloc::System;
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator::new(System);
+
+#[test]
+fn allocations_are_counted_per_zone_until_the_zones_run_out() {
+    let metrics = IdMetrics::install();
+    let zone_bytes = |zone| metrics.registered_counter(&[("fn_name", "alloc_bytes"), ("zone", zone)]);
+    let shared_bytes = || metrics.allocator_counter("alloc_bytes");
+
+    enable_allocator_instrumentation();
+    set_allocation_zone("order_book");
+    drop(std::hint::black_box(Vec::<u8>::with_capacity(4096)));
+    clear_allocation_zone();
+    let shared = shared_bytes();
+    drop(std::hint::black_box(Vec::<u8>::with_capacity(1024)));
+    disable_allocator_instrumentation();
+    assert_eq!(Some(4096), zone_bytes("order_book"));
+    assert_eq!(shared + 1024, shared_bytes());
+
+    // the zone counters are registered when the zone is first set, and setting it again reuses them
+    for zone in 1..MAX_ALLOCATION_ZONES {
+        let zone: &'static str = format!("zone_{zone}").leak();
+        set_allocation_zone(zone);
+        assert_eq!(Some(0), zone_bytes(zone));
+    }
+    enable_allocator_instrumentation();
+    set_allocation_zone("order_book");
+    drop(std::hint::black_box(Vec::<u8>::with_capacity(64)));
+
+    // past the limit, allocations are counted without a zone
+    set_allocation_zone("risk");
+    let shared = shared_bytes();
+    drop(std::hint::black_box(Vec::<u8>::with_capacity(512)));
+    clear_allocation_zone();
+    disable_allocator_instrumentation();
+    assert_eq!(Some(4160), zone_bytes("order_book"));
+    assert_eq!(None, zone_bytes("risk"));
+    assert_eq!(shared + 512, shared_bytes());
+}
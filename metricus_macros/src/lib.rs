@@ -3,15 +3,21 @@
 use proc_macro::TokenStream;
 use std::collections::HashSet;
 
-use proc_macro2::{Ident, Span};
+use proc_macro2::{Delimiter, Group, Ident, Span, TokenTree};
 
 use quote::quote;
-use syn::{AttributeArgs, ItemFn, Lit, Meta, MetaList, MetaNameValue, NestedMeta, parse_macro_input};
+use syn::{
+    Attribute, AttributeArgs, Data, DeriveInput, Expr, FnArg, ImplItem, Item, ItemFn, ItemImpl, ItemMod, Lit, LitStr,
+    Meta, MetaList, MetaNameValue, NestedMeta, Pat, ReturnType, Token, parse::Parser, parse_macro_input,
+    punctuated::Punctuated,
+};
 
 /// The `counter` attribute macro instruments a function with a metrics counter,
 /// allowing you to measure how many times a function is called. It requires to specify
 /// `measurement` name under which the count will be recorded. It also accepts optional `tags`
 /// represented as comma-separated list of key-value tuples such as `tags(key1 = "value1", key2 = "value2")`.
+/// Tag values can also be integer or boolean literals, e.g. `tags(shard = 3, critical = true)`, which are
+/// tagged with their string representation (integers in decimal, without suffix).
 /// The function name (`fn_name`) is automatically added as a tag, so there is no need to include it manually.
 /// All keys must be unique.
 ///
@@ -42,8 +48,108 @@ use syn::{AttributeArgs, ItemFn, Lit, Meta, MetaList, MetaNameValue, NestedMeta,
 /// ```
 /// Here, each call to `my_function_without_tags` increments a counter with the measurement name
 /// "counters". Only the function name is tagged automatically, since no additional tags were provided.
+///
+/// The measurement can also be given as a path to a `const &str`, e.g. to share measurement names
+/// across many instrumented functions.
+///
+/// ```ignore
+/// use metricus_macros::counter;
+///
+/// mod names {
+///     pub const REQUESTS: &str = "requests";
+/// }
+///
+/// #[counter(measurement = names::REQUESTS)]
+/// fn handle_request() {
+///     // function body
+/// }
+/// ```
+///
+/// Split the counter by the value of one or more function parameters with `tag_arg`. Each named
+/// parameter becomes a tag whose value is the parameter's `Display` representation at call time,
+/// and a separate counter is registered for every distinct combination of values. Only use this for
+/// low-cardinality parameters (e.g. a small enum), since every new value creates a new series that
+/// is never released. Each call also pays for a shared lock and for formatting the values to compare
+/// them with the known combinations, which does not allocate once a combination has been seen.
+///
+/// ```ignore
+/// use metricus_macros::counter;
+///
+/// #[counter(measurement = "orders", tag_arg(side))]
+/// fn submit_order(side: Side, quantity: u64) {
+///     // function body
+/// }
+/// ```
+///
+/// Increment the counter by a value computed on each call rather than by one with `by`, which takes a
+/// `u64` expression that can refer to the function parameters, e.g. the size of a processed batch. The
+/// expression is evaluated when the function is entered, before the function body runs.
+///
+/// ```ignore
+/// use metricus_macros::counter;
+///
+/// #[counter(measurement = "processed", by = "items.len() as u64")]
+/// fn process_batch(items: &[Order]) {
+///     // function body
+/// }
+/// ```
+///
+/// Record a one-time event, such as the first request served, with `once`. Only the first call
+/// increments the counter, all later calls (from any thread) leave it untouched. The flag is kept in
+/// a static generated for the annotated function, so it applies per instrumented function rather than
+/// per process-wide measurement. `once` cannot be combined with `tag_arg`.
+///
+/// ```ignore
+/// use metricus_macros::counter;
+///
+/// #[counter(measurement = "milestones", tags(event = "first_request"), once)]
+/// fn serve_request() {
+///     // function body
+/// }
+/// ```
+///
+/// Rename the key of the function name tag with `fn_name_tag`, e.g. to follow an existing tagging
+/// convention, or leave the tag out with `fn_name_tag = false` (or `no_fn_name`). The key must not
+/// be used by any of the other tags.
+///
+/// ```ignore
+/// use metricus_macros::counter;
+///
+/// #[counter(measurement = "counters", fn_name_tag = "function")]
+/// fn tagged_with_function() {
+///     // function body
+/// }
+///
+/// #[counter(measurement = "counters", no_fn_name)]
+/// fn not_tagged() {
+///     // function body
+/// }
+/// ```
+///
+/// Instrument function with a counter only when a given cargo feature is enabled. The `cfg` argument
+/// accepts either a feature name or a full `cfg(...)` predicate. When the predicate is inactive only the
+/// instrumentation is compiled out and the function expands to its bare body.
+///
+/// ```ignore
+/// use metricus_macros::counter;
+///
+/// #[counter(measurement = "counters", cfg = "metrics")]
+/// fn my_function() {
+///     // function body
+/// }
+///
+/// #[counter(measurement = "counters", cfg(all(feature = "metrics", not(test))))]
+/// fn my_other_function() {
+///     // function body
+/// }
+/// ```
 #[proc_macro_attribute]
 pub fn counter(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let (attr, measurement_path) = match extract_measurement_path(attr.into()) {
+        Ok(extracted) => extracted,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
+    let attr = TokenStream::from(attr);
     let args = parse_macro_input!(attr as AttributeArgs);
     let input_fn = parse_macro_input!(item as ItemFn);
     let fn_name = &input_fn.sig.ident;
@@ -51,14 +157,17 @@ pub fn counter(attr: TokenStream, item: TokenStream) -> TokenStream {
     // initialize variables to hold parsed values
     let mut measurement = None;
     let mut tags = Vec::new();
+    let mut cfg = None;
+    let mut tag_args = Vec::new();
+    let mut once = None;
+    let mut by = None;
 
-    // auto include method name
-    let method_name = fn_name.to_string();
-    tags.push(("fn_name".to_string(), method_name));
-
-    // keys must be unique
-    let keys: HashSet<String> = tags.iter().map(|(k, _)| k).cloned().collect();
-    assert_eq!(keys.len(), tags.len(), "must include unique tag keys");
+    // auto include method name, unless disabled
+    match fn_name_tag(&args) {
+        Ok(Some(key)) => tags.push((key, fn_name.to_string())),
+        Ok(None) => {}
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    }
 
     // Parse attributes for measurement and tags
     for arg in args {
@@ -70,17 +179,64 @@ pub fn counter(attr: TokenStream, item: TokenStream) -> TokenStream {
             })) if path.is_ident("measurement") => {
                 measurement = Some(value.value());
             }
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                ref path,
+                lit: Lit::Str(ref value),
+                ..
+            })) if path.is_ident("by") => match value.parse::<Expr>() {
+                Ok(expr) => by = Some(expr),
+                Err(err) => {
+                    return TokenStream::from(
+                        syn::Error::new_spanned(value, format!("Expected an expression for by: {err}"))
+                            .to_compile_error(),
+                    );
+                }
+            },
+            NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("once") => {
+                once = Some(path.clone());
+            }
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                ref path,
+                lit: Lit::Str(ref value),
+                ..
+            })) if path.is_ident("cfg") => {
+                let feature = value.value();
+                cfg = Some(quote! { #[cfg(feature = #feature)] });
+            }
+            NestedMeta::Meta(Meta::List(MetaList {
+                ref path, ref nested, ..
+            })) if path.is_ident("cfg") => {
+                cfg = Some(quote! { #[cfg(#nested)] });
+            }
+            NestedMeta::Meta(Meta::List(MetaList {
+                ref path, ref nested, ..
+            })) if path.is_ident("tag_arg") => {
+                for meta in nested {
+                    match meta {
+                        NestedMeta::Meta(Meta::Path(path)) if path.get_ident().is_some() => {
+                            let arg = path.get_ident().unwrap();
+                            if let Err(err) = check_fn_arg(&input_fn, arg) {
+                                return TokenStream::from(err.to_compile_error());
+                            }
+                            tag_args.push(arg.clone());
+                        }
+                        _ => {
+                            return TokenStream::from(
+                                syn::Error::new_spanned(meta, "Expected a function parameter name for tag_arg")
+                                    .to_compile_error(),
+                            );
+                        }
+                    }
+                }
+            }
             NestedMeta::Meta(Meta::List(MetaList {
                 ref path, ref nested, ..
             })) if path.is_ident("tags") => {
                 for meta in nested {
-                    if let NestedMeta::Meta(Meta::NameValue(MetaNameValue {
-                        path,
-                        lit: Lit::Str(value),
-                        ..
-                    })) = meta
-                    {
-                        tags.push((path.get_ident().unwrap().to_string(), value.value()));
+                    if let NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit, .. })) = meta {
+                        if let Err(err) = push_tag(&mut tags, path, lit) {
+                            return TokenStream::from(err.to_compile_error());
+                        }
                     } else {
                         return TokenStream::from(
                             syn::Error::new_spanned(meta, "Expected a name-value pair for tags").to_compile_error(),
@@ -92,26 +248,16 @@ pub fn counter(attr: TokenStream, item: TokenStream) -> TokenStream {
         }
     }
 
-    // Ensure consistent ordering of tags
-    tags.sort_unstable_by(|(k1, _), (k2, _)| k1.cmp(k2));
-
-    let tags: Vec<(&str, &str)> = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
-    let tags = tags.into_iter().map(|(k, v)| {
-        // Directly quote each tuple
-        quote! { (#k, #v) }
-    });
-
-    // Ensure measurement field is provided
-    let measurement = match measurement {
-        Some(measurement) => measurement,
-        None => {
-            return TokenStream::from(
-                syn::Error::new_spanned(&input_fn, "Missing required 'measurement' field").to_compile_error(),
-            );
-        }
+    let tags = match quote_tags(tags, &tag_args) {
+        Ok(tags) => tags,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
     };
 
-    let measurement = measurement.as_str();
+    // Ensure measurement field is provided, either as a literal or as a path to a constant
+    let measurement = match measurement_tokens(&input_fn, measurement.as_deref(), measurement_path.as_ref()) {
+        Ok(measurement) => measurement,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
 
     // Reconstruct the original function and inject the counter
 
@@ -125,13 +271,70 @@ pub fn counter(attr: TokenStream, item: TokenStream) -> TokenStream {
     let fn_where_clause = &input_fn.sig.generics.where_clause;
     let attrs = &input_fn.attrs;
 
+    if let (Some(once), false) = (&once, tag_args.is_empty()) {
+        return TokenStream::from(
+            syn::Error::new_spanned(once, "'once' cannot be combined with 'tag_arg'").to_compile_error(),
+        );
+    }
+
+    // the delta is evaluated ahead of the instrumentation so that it is not part of an unsafe block
+    let delta = by.as_ref().map(|by| {
+        quote! {
+            #cfg
+            let __delta: u64 = #by;
+        }
+    });
+    let increment = |counter: proc_macro2::TokenStream| match by {
+        Some(_) => quote! { metricus::CounterOps::increment_by(#counter, __delta) },
+        None => quote! { metricus::CounterOps::increment(#counter) },
+    };
+    let increment_static = increment(quote! { &COUNTER });
+    let increment_dynamic = increment(quote! { __counter });
+
+    let instrumentation = if once.is_some() {
+        // the cheap load keeps the flag's cache line shared once it has been set
+        quote! {
+            #cfg
+            static FIRED: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+            #cfg
+            static mut COUNTER: core::cell::LazyCell<metricus::Counter> = core::cell::LazyCell::new(|| metricus::Counter::new(#measurement, &[ #(#tags),* ]));
+            #cfg
+            if !FIRED.load(core::sync::atomic::Ordering::Relaxed) && !FIRED.swap(true, core::sync::atomic::Ordering::Relaxed) {
+                #[allow(static_mut_refs)]
+                unsafe { #increment_static; }
+            }
+        }
+    } else if tag_args.is_empty() {
+        quote! {
+            #cfg
+            static mut COUNTER: core::cell::LazyCell<metricus::Counter> = core::cell::LazyCell::new(|| metricus::Counter::new(#measurement, &[ #(#tags),* ]));
+            #cfg
+            #[allow(static_mut_refs)]
+            unsafe { #increment_static; }
+        }
+    } else {
+        // one counter per distinct combination of argument values, created on first use
+        let tag_values_len = proc_macro2::Literal::usize_unsuffixed(tag_args.len());
+        quote! {
+            #cfg
+            static COUNTERS: metricus::TaggedMetrics<metricus::Counter, #tag_values_len> = metricus::TaggedMetrics::new();
+            #cfg
+            {
+                let __counter = COUNTERS.get_or_create([ #( &#tag_args as &dyn core::fmt::Display ),* ], |__tag_values| {
+                    metricus::Counter::new(#measurement, &[ #(#tags),* ])
+                });
+                #increment_dynamic;
+            }
+        }
+    };
+
     let generated = quote! {
         #(#attrs)*
         #fn_vis #fn_async #fn_unsafe fn #fn_name #fn_generics (#fn_args) #fn_output #fn_where_clause {
 
-            static mut COUNTER: core::cell::LazyCell<metricus::Counter> = core::cell::LazyCell::new(|| metricus::Counter::new(#measurement, &[ #(#tags),* ]));
-            #[allow(static_mut_refs)]
-            unsafe { metricus::CounterOps::increment(&COUNTER); }
+            #delta
+
+            #instrumentation
 
             #( #fn_body )*
         }
@@ -144,8 +347,9 @@ pub fn counter(attr: TokenStream, item: TokenStream) -> TokenStream {
 /// supplied id. This can be useful to provide instrumentation for memory allocators where we need to 'defer' metric
 /// registration until the backend has been registered.
 ///
-/// This macro accepts either `u64` value that represents counter `id` or the name of a const function that returns the id
-/// of the counter to be created.
+/// This macro accepts either `u64` value that represents counter `id`, the name of a const function that returns the id
+/// of the counter to be created, or any other expression evaluating to the id, such as an offset from a shared base id
+/// or an associated const. The expression is evaluated when the function is first called.
 ///
 /// ## Examples
 ///
@@ -174,51 +378,33 @@ pub fn counter(attr: TokenStream, item: TokenStream) -> TokenStream {
 ///     // function body
 /// }
 /// ```
+///
+/// Using an expression as id.
+///
+/// ```ignore
+/// use metricus_macros::counter_with_id;
+///
+/// const BASE_ID: CounterId = 100;
+///
+/// #[counter_with_id(id = BASE_ID + 3)]
+/// fn my_function() {
+///     // function body
+/// }
+/// ```
 #[proc_macro_attribute]
 pub fn counter_with_id(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let args = parse_macro_input!(attr as AttributeArgs);
     let input_fn = parse_macro_input!(item as ItemFn);
     let fn_name = &input_fn.sig.ident;
 
-    // Initialize variables to hold parsed values
-    let mut counter_id_fn = None;
-    let mut counter_id_value = None;
-
-    // Parse attributes for measurement and tags
-    for arg in args {
-        match arg {
-            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
-                ref path,
-                lit: Lit::Str(ref value),
-                ..
-            })) if path.is_ident("id") => {
-                counter_id_fn = Some(value.value());
-            }
-            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
-                ref path,
-                lit: Lit::Int(value),
-                ..
-            })) if path.is_ident("id") => {
-                counter_id_value = Some(value);
-            }
-            _ => {}
+    // Parse attributes for the id
+    let counter_id = match parse_id(attr.into()) {
+        Ok(Some(counter_id)) => counter_id,
+        Ok(None) => {
+            return TokenStream::from(
+                syn::Error::new_spanned(&input_fn, "Missing required 'id' field").to_compile_error(),
+            );
         }
-    }
-
-    // Ensure counter_id field is provided
-    let counter_id = match counter_id_value {
-        Some(id_int) => quote! { #id_int },
-        None => match counter_id_fn {
-            Some(f) => {
-                let getter_fn = Ident::new(f.as_str(), Span::call_site());
-                quote! { #getter_fn() }
-            }
-            None => {
-                return TokenStream::from(
-                    syn::Error::new_spanned(&input_fn, "Missing required 'id' field").to_compile_error(),
-                );
-            }
-        },
+        Err(err) => return TokenStream::from(err.to_compile_error()),
     };
 
     let fn_body = &input_fn.block.stmts;
@@ -246,113 +432,48 @@ pub fn counter_with_id(attr: TokenStream, item: TokenStream) -> TokenStream {
     generated.into()
 }
 
-/// The `span` attribute macro instruments a function with a metrics span that will be recorded
-/// using a histogram, allowing you to measure how long a given function took to execute
-/// in nanoseconds. It requires to specify `measurement` name under which the count will be recorded.
-/// It also accepts optional `tags` represented as comma-separated list of key-value tuples such as
-/// `tags(key1 = "value1", key2 = "value2")`. The function name (`fn_name`) is automatically added
-/// as a tag, so there is no need to include it manually. All keys must be unique.
+/// The `histogram_with_id` attribute macro instruments a function with a span recorded into a histogram
+/// with a user supplied id, the same way as [macro@span] measures the duration of a function. Like
+/// [macro@counter_with_id], this is meant for code running before the backend has been registered (such
+/// as subsystems initialized early), where the histogram is created by the backend beforehand and the
+/// registration is 'deferred'.
 ///
-/// ## Examples
+/// The id is given the same way as for [macro@counter_with_id], either as a `u64` value, the name of a
+/// const function that returns the id, or any other expression evaluating to the id.
 ///
-/// Instrument function with a span with tags.
+/// ## Examples
 ///
 /// ```ignore
-/// use metrics_macros::span;
-///
-/// #[span(measurement = "latencies", tags(key1 = "value1", key2 = "value2"))]
-/// fn my_function_with_tags() {
-///     // function body
-/// }
-/// ```
-///
-/// Instrument function with a span without tags.
+/// use metricus_macros::histogram_with_id;
 ///
-/// ```ignore
-/// use metrics_macros::span;
+/// const BASE_ID: HistogramId = 200;
 ///
-/// #[span(measurement = "latencies")]
-/// fn my_function_without_tags() {
+/// #[histogram_with_id(id = BASE_ID + 1)]
+/// fn my_function() {
 ///     // function body
 /// }
 /// ```
 #[proc_macro_attribute]
-pub fn span(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let args = parse_macro_input!(attr as AttributeArgs);
+pub fn histogram_with_id(attr: TokenStream, item: TokenStream) -> TokenStream {
     let input_fn = parse_macro_input!(item as ItemFn);
     let fn_name = &input_fn.sig.ident;
 
-    // Initialize variables to hold parsed values
-    let mut measurement = None;
-    let mut tags = Vec::new();
-
-    // auto include method name
-    let method_name = fn_name.to_string();
-    tags.push(("fn_name".to_string(), method_name));
-
-    // keys must be unique
-    let keys: HashSet<String> = tags.iter().map(|(k, _)| k).cloned().collect();
-    assert_eq!(keys.len(), tags.len(), "must include unique tag keys");
-
-    // Parse attributes for measurement and tags
-    for arg in args {
-        match arg {
-            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
-                ref path,
-                lit: Lit::Str(ref value),
-                ..
-            })) if path.is_ident("measurement") => {
-                measurement = Some(value.value());
-            }
-            NestedMeta::Meta(Meta::List(MetaList {
-                ref path, ref nested, ..
-            })) if path.is_ident("tags") => {
-                for meta in nested {
-                    if let NestedMeta::Meta(Meta::NameValue(MetaNameValue {
-                        path,
-                        lit: Lit::Str(value),
-                        ..
-                    })) = meta
-                    {
-                        tags.push((path.get_ident().unwrap().to_string(), value.value()));
-                    } else {
-                        return TokenStream::from(
-                            syn::Error::new_spanned(meta, "Expected a name-value pair for tags").to_compile_error(),
-                        );
-                    }
-                }
-            }
-            _ => {}
-        }
-    }
-
-    // Ensure consistent ordering of tags
-    tags.sort_unstable_by(|(k1, _), (k2, _)| k1.cmp(k2));
-
-    let tags: Vec<(&str, &str)> = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
-    let tags = tags.into_iter().map(|(k, v)| {
-        // Directly quote each tuple
-        quote! { (#k, #v) }
-    });
-
-    // Ensure measurement field is provided
-    let measurement = match measurement {
-        Some(measurement) => measurement,
-        None => {
+    // Parse attributes for the id
+    let histogram_id = match parse_id(attr.into()) {
+        Ok(Some(histogram_id)) => histogram_id,
+        Ok(None) => {
             return TokenStream::from(
-                syn::Error::new_spanned(&input_fn, "Missing required 'measurement' field").to_compile_error(),
+                syn::Error::new_spanned(&input_fn, "Missing required 'id' field").to_compile_error(),
             );
         }
+        Err(err) => return TokenStream::from(err.to_compile_error()),
     };
 
-    let measurement = measurement.as_str();
-
-    // Reconstruct the original function and inject the histogram span
     let fn_body = &input_fn.block.stmts;
     let fn_vis = &input_fn.vis;
     let fn_unsafe = &input_fn.sig.unsafety;
-    let fn_args = &input_fn.sig.inputs;
     let fn_async = &input_fn.sig.asyncness;
+    let fn_args = &input_fn.sig.inputs;
     let fn_output = &input_fn.sig.output;
     let fn_generics = &input_fn.sig.generics;
     let fn_where_clause = &input_fn.sig.generics.where_clause;
@@ -362,7 +483,7 @@ pub fn span(attr: TokenStream, item: TokenStream) -> TokenStream {
         #(#attrs)*
         #fn_vis #fn_async #fn_unsafe fn #fn_name #fn_generics (#fn_args) #fn_output #fn_where_clause {
 
-            static mut HISTOGRAM: core::cell::LazyCell<metricus::Histogram> = core::cell::LazyCell::new(|| metricus::Histogram::new(#measurement, &[ #(#tags),* ]));
+            static mut HISTOGRAM: core::cell::LazyCell<metricus::Histogram> = core::cell::LazyCell::new(|| metricus::Histogram::new_with_id(#histogram_id));
             #[allow(static_mut_refs)]
             let _span = unsafe { metricus::HistogramOps::span(&HISTOGRAM) };
 
@@ -372,3 +493,1176 @@ pub fn span(attr: TokenStream, item: TokenStream) -> TokenStream {
 
     generated.into()
 }
+
+/// The `span` attribute macro instruments a function with a metrics span that will be recorded
+/// using a histogram, allowing you to measure how long a given function took to execute
+/// in nanoseconds. It requires to specify `measurement` name under which the count will be recorded.
+/// It also accepts optional `tags` represented as comma-separated list of key-value tuples such as
+/// `tags(key1 = "value1", key2 = "value2")`, where values can also be integer or boolean literals as
+/// for `counter`. The function name (`fn_name`) is automatically added as a tag, so there is no need to
+/// include it manually. All keys must be unique. As for `counter`, the key of the function name tag can
+/// be changed with `fn_name_tag = "key"` and the tag left out with `fn_name_tag = false` or `no_fn_name`.
+///
+/// ## Examples
+///
+/// Instrument function with a span with tags.
+///
+/// ```ignore
+/// use metrics_macros::span;
+///
+/// #[span(measurement = "latencies", tags(key1 = "value1", key2 = "value2"))]
+/// fn my_function_with_tags() {
+///     // function body
+/// }
+/// ```
+///
+/// Instrument function with a span without tags.
+///
+/// ```ignore
+/// use metrics_macros::span;
+///
+/// #[span(measurement = "latencies")]
+/// fn my_function_without_tags() {
+///     // function body
+/// }
+/// ```
+///
+/// Use a path to a `const &str` to share the measurement name, as with [macro@counter].
+///
+/// ```ignore
+/// use metrics_macros::span;
+///
+/// const LATENCIES: &str = "latencies";
+///
+/// #[span(measurement = LATENCIES)]
+/// fn my_function_with_const_measurement() {
+///     // function body
+/// }
+/// ```
+///
+/// Split the span by the value of one or more function parameters with `tag_arg`. Each named parameter
+/// becomes a tag whose value is the parameter's `Display` representation at call time, and a separate
+/// histogram is registered for every distinct combination of values. Only use this for low-cardinality
+/// parameters (e.g. a small enum), since every new value creates a new series that is never released.
+/// As for [macro@counter], the lookup of the histogram takes a shared lock on every call.
+///
+/// ```ignore
+/// use metrics_macros::span;
+///
+/// #[span(measurement = "latencies", tag_arg(side))]
+/// fn submit_order(side: Side, quantity: u64) {
+///     // function body
+/// }
+/// ```
+///
+/// Instrument async function with a span that records both the wall-clock duration and the time
+/// actually spent polling the function's future (i.e. excluding the time it was suspended). The
+/// active time is recorded into a second histogram named after the measurement with an `_active`
+/// suffix, so each call pays for one extra histogram record and two clock reads per poll.
+///
+/// ```ignore
+/// use metrics_macros::span;
+///
+/// #[span(measurement = "latencies", dual_time)]
+/// async fn my_async_function() {
+///     // function body
+/// }
+/// ```
+///
+/// Record the duration only for calls that return `Ok` or only for calls that return `Err` with
+/// `record_on = "ok"` or `record_on = "err"`, e.g. to isolate slow failures. Defaults to `"always"`.
+/// As with [macro@count_errors], the function body is moved into a closure (or an `async` block) so
+/// that early returns and the `?` operator are accounted for, therefore the return type must be spelled
+/// out as a concrete `Result` type (or an alias of it). The clock is read for every call, the duration
+/// is just not recorded for the other calls. Cannot be combined with `dual_time`.
+///
+/// ```ignore
+/// use metrics_macros::span;
+///
+/// #[span(measurement = "latencies", record_on = "err")]
+/// fn submit_order(price: u64) -> Result<(), OrderError> {
+///     // function body
+/// }
+/// ```
+///
+/// Record the duration only for calls that panic with `record_on = "panic"`, so that the number of
+/// samples is the number of calls that unwound (see `metricus::Span::on_panic`). The function may
+/// return any type in this case. Cannot be combined with `dual_time` or multiple measurements.
+///
+/// ```ignore
+/// use metrics_macros::span;
+///
+/// #[span(measurement = "panicked_latencies", record_on = "panic")]
+/// fn recover(state: &mut State) {
+///     // function body
+/// }
+/// ```
+///
+/// Record the same duration into several histograms, e.g. per operation and across all operations,
+/// by passing a list of measurements. All histograms get the same tags. The clock is read only twice,
+/// as for a single measurement, but each additional measurement costs one more histogram record when
+/// the span ends (see `metricus::Histogram::span_all`). Cannot be combined with `tag_arg` or `dual_time`.
+///
+/// ```ignore
+/// use metrics_macros::span;
+///
+/// #[span(measurement = ["op_latency", "all_latency"], tags(op = "insert"))]
+/// fn insert() {
+///     // function body
+/// }
+/// ```
+///
+/// Time only one in every `sample` calls of a very hot function, e.g. `sample = 100`, to reduce the
+/// amount of recorded data and the cost of reading the clock. Each call increments a counter in a static
+/// generated for the annotated function, and only the calls for which it is a multiple of `sample` are
+/// timed, the other calls skip the span entirely. The counter uses relaxed ordering, so under
+/// concurrency the sampling is approximate rather than exactly every n-th call. The number of samples
+/// has to be scaled by `sample` to estimate the number of calls. Cannot be combined with `dual_time`.
+///
+/// ```ignore
+/// use metrics_macros::span;
+///
+/// #[span(measurement = "latencies", sample = 100)]
+/// fn on_market_data(update: &Update) {
+///     // function body
+/// }
+/// ```
+///
+/// Instrument function with a span only when a given cargo feature is enabled. The `cfg` argument
+/// accepts either a feature name or a full `cfg(...)` predicate. When the predicate is inactive the
+/// function is left untouched, so timing cost can be kept out of builds that do not need it.
+///
+/// ```ignore
+/// use metrics_macros::span;
+///
+/// #[span(measurement = "latencies", cfg = "spans")]
+/// fn my_function() {
+///     // function body
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn span(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let (attr, extra_measurements) = match extract_measurement_list(attr.into()) {
+        Ok(extracted) => extracted,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
+    let (attr, measurement_path) = match extract_measurement_path(attr) {
+        Ok(extracted) => extracted,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
+    let attr = TokenStream::from(attr);
+    let args = parse_macro_input!(attr as AttributeArgs);
+    let input_fn = parse_macro_input!(item as ItemFn);
+    let fn_name = &input_fn.sig.ident;
+
+    // Initialize variables to hold parsed values
+    let mut measurement = None;
+    let mut tags = Vec::new();
+    let mut cfg = None;
+    let mut tag_args = Vec::new();
+    let mut dual_time = false;
+    // name of the `Result` method telling when the span is cancelled rather than recorded
+    let mut record_on = None;
+    let mut record_on_panic = false;
+    let mut sample = None;
+
+    // auto include method name, unless disabled
+    match fn_name_tag(&args) {
+        Ok(Some(key)) => tags.push((key, fn_name.to_string())),
+        Ok(None) => {}
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    }
+
+    // Parse attributes for measurement and tags
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                ref path,
+                lit: Lit::Str(ref value),
+                ..
+            })) if path.is_ident("measurement") => {
+                measurement = Some(value.value());
+            }
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                ref path,
+                lit: Lit::Str(ref value),
+                ..
+            })) if path.is_ident("cfg") => {
+                let feature = value.value();
+                cfg = Some(quote! { #[cfg(feature = #feature)] });
+            }
+            NestedMeta::Meta(Meta::List(MetaList {
+                ref path, ref nested, ..
+            })) if path.is_ident("cfg") => {
+                cfg = Some(quote! { #[cfg(#nested)] });
+            }
+            NestedMeta::Meta(Meta::List(MetaList {
+                ref path, ref nested, ..
+            })) if path.is_ident("tag_arg") => {
+                for meta in nested {
+                    match meta {
+                        NestedMeta::Meta(Meta::Path(path)) if path.get_ident().is_some() => {
+                            let arg = path.get_ident().unwrap();
+                            if let Err(err) = check_fn_arg(&input_fn, arg) {
+                                return TokenStream::from(err.to_compile_error());
+                            }
+                            tag_args.push(arg.clone());
+                        }
+                        _ => {
+                            return TokenStream::from(
+                                syn::Error::new_spanned(meta, "Expected a function parameter name for tag_arg")
+                                    .to_compile_error(),
+                            );
+                        }
+                    }
+                }
+            }
+            NestedMeta::Meta(Meta::List(MetaList {
+                ref path, ref nested, ..
+            })) if path.is_ident("tags") => {
+                for meta in nested {
+                    if let NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit, .. })) = meta {
+                        if let Err(err) = push_tag(&mut tags, path, lit) {
+                            return TokenStream::from(err.to_compile_error());
+                        }
+                    } else {
+                        return TokenStream::from(
+                            syn::Error::new_spanned(meta, "Expected a name-value pair for tags").to_compile_error(),
+                        );
+                    }
+                }
+            }
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                ref path,
+                lit: Lit::Str(ref value),
+                ..
+            })) if path.is_ident("record_on") => match value.value().as_str() {
+                "always" => record_on = None,
+                "ok" => record_on = Some(quote! { is_err }),
+                "err" => record_on = Some(quote! { is_ok }),
+                "panic" => record_on_panic = true,
+                _ => {
+                    return TokenStream::from(
+                        syn::Error::new_spanned(
+                            value,
+                            "Expected one of \"ok\", \"err\", \"panic\" or \"always\" for record_on",
+                        )
+                        .to_compile_error(),
+                    );
+                }
+            },
+            NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("dual_time") => {
+                dual_time = true;
+            }
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                ref path,
+                lit: Lit::Int(ref value),
+                ..
+            })) if path.is_ident("sample") => match value.base10_parse::<u64>() {
+                Ok(n) if n > 0 => sample = Some(proc_macro2::Literal::u64_unsuffixed(n)),
+                _ => {
+                    return TokenStream::from(
+                        syn::Error::new_spanned(value, "Expected a positive integer for sample").to_compile_error(),
+                    );
+                }
+            },
+            _ => {}
+        }
+    }
+
+    if dual_time && sample.is_some() {
+        return TokenStream::from(
+            syn::Error::new_spanned(&input_fn.sig, "'dual_time' cannot be combined with 'sample'").to_compile_error(),
+        );
+    }
+
+    if dual_time && input_fn.sig.asyncness.is_none() {
+        return TokenStream::from(
+            syn::Error::new_spanned(&input_fn.sig, "'dual_time' can only be used with async functions")
+                .to_compile_error(),
+        );
+    }
+
+    if dual_time && !tag_args.is_empty() {
+        return TokenStream::from(
+            syn::Error::new_spanned(&input_fn.sig, "'dual_time' cannot be combined with 'tag_arg'").to_compile_error(),
+        );
+    }
+
+    if (record_on.is_some() || record_on_panic) && dual_time {
+        return TokenStream::from(
+            syn::Error::new_spanned(&input_fn.sig, "'dual_time' cannot be combined with 'record_on'")
+                .to_compile_error(),
+        );
+    }
+
+    if !extra_measurements.is_empty() && record_on_panic {
+        return TokenStream::from(
+            syn::Error::new_spanned(
+                &extra_measurements[0],
+                "multiple measurements cannot be combined with 'record_on = \"panic\"'",
+            )
+            .to_compile_error(),
+        );
+    }
+
+    if !extra_measurements.is_empty() && (dual_time || !tag_args.is_empty()) {
+        return TokenStream::from(
+            syn::Error::new_spanned(
+                &extra_measurements[0],
+                "multiple measurements cannot be combined with 'dual_time' or 'tag_arg'",
+            )
+            .to_compile_error(),
+        );
+    }
+
+    let tags = match quote_tags(tags, &tag_args) {
+        Ok(tags) => tags,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
+
+    // Ensure measurement field is provided, either as a literal or as a path to a constant
+    let measurement_name = measurement.as_deref();
+    let measurement = match measurement_tokens(&input_fn, measurement_name, measurement_path.as_ref()) {
+        Ok(measurement) => measurement,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
+
+    // Reconstruct the original function and inject the histogram span
+    let fn_body = &input_fn.block.stmts;
+    let fn_vis = &input_fn.vis;
+    let fn_unsafe = &input_fn.sig.unsafety;
+    let fn_args = &input_fn.sig.inputs;
+    let fn_async = &input_fn.sig.asyncness;
+    let fn_output = &input_fn.sig.output;
+    let fn_generics = &input_fn.sig.generics;
+    let fn_where_clause = &input_fn.sig.generics.where_clause;
+    let attrs = &input_fn.attrs;
+
+    // For `dual_time` the body is moved into an inner future whose active (poll) time is recorded
+    // into a second histogram, next to the wall-clock time recorded by the span.
+    let fn_body = if dual_time {
+        let active_measurement = match measurement_name {
+            Some(measurement) => {
+                let active_measurement = format!("{measurement}_active");
+                quote! { #active_measurement }
+            }
+            None => quote! { &format!("{}_active", #measurement) },
+        };
+        let return_type = match fn_output {
+            ReturnType::Default => quote! { () },
+            ReturnType::Type(_, ty) => quote! { #ty },
+        };
+        quote! {
+            #cfg
+            static mut ACTIVE_HISTOGRAM: core::cell::LazyCell<metricus::Histogram> = core::cell::LazyCell::new(|| metricus::Histogram::new(#active_measurement, &[ #(#tags),* ]));
+            let __future = async move { #( #fn_body )* };
+            #cfg
+            #[allow(static_mut_refs)]
+            let __future = unsafe { metricus::HistogramOps::poll_timed(&ACTIVE_HISTOGRAM, __future) };
+            // the binding pins down the output type of the inner future, e.g. for the `?` operator
+            let __output: #return_type = __future.await;
+            __output
+        }
+    } else {
+        quote! { #( #fn_body )* }
+    };
+
+    // statics holding the histograms, and the (unsafe) expression starting the span
+    let (statics, start_span) = if !extra_measurements.is_empty() {
+        // one histogram per measurement, all recording the duration measured by a single span
+        let histograms: Vec<_> = (0..=extra_measurements.len())
+            .map(|index| Ident::new(&format!("HISTOGRAM_{index}"), Span::call_site()))
+            .collect();
+        let measurements = std::iter::once(measurement.clone())
+            .chain(extra_measurements.iter().map(|measurement| quote! { #measurement }));
+        let statics = histograms.iter().zip(measurements).map(|(histogram, measurement)| {
+            quote! {
+                #cfg
+                static mut #histogram: core::cell::LazyCell<metricus::Histogram> = core::cell::LazyCell::new(|| metricus::Histogram::new(#measurement, &[ #(#tags),* ]));
+            }
+        });
+        (
+            quote! { #(#statics)* },
+            quote! { metricus::Histogram::span_all([ #( core::cell::LazyCell::force(&#histograms) ),* ]) },
+        )
+    } else if tag_args.is_empty() {
+        (
+            quote! {
+                #cfg
+                static mut HISTOGRAM: core::cell::LazyCell<metricus::Histogram> = core::cell::LazyCell::new(|| metricus::Histogram::new(#measurement, &[ #(#tags),* ]));
+            },
+            quote! { metricus::HistogramOps::span(&HISTOGRAM) },
+        )
+    } else {
+        // one histogram per distinct combination of argument values, created on first use
+        let tag_values_len = proc_macro2::Literal::usize_unsuffixed(tag_args.len());
+        (
+            quote! {
+                #cfg
+                static HISTOGRAMS: metricus::TaggedMetrics<metricus::Histogram, #tag_values_len> = metricus::TaggedMetrics::new();
+            },
+            quote! {{
+                let __histogram = HISTOGRAMS.get_or_create([ #( &#tag_args as &dyn core::fmt::Display ),* ], |__tag_values| {
+                    metricus::Histogram::new(#measurement, &[ #(#tags),* ])
+                });
+                metricus::HistogramOps::span(__histogram)
+            }},
+        )
+    };
+
+    // with `sample` the span is an `Option`, only started for every n-th call
+    let instrumentation = match &sample {
+        None => quote! {
+            #statics
+            #cfg
+            #[allow(static_mut_refs, unused_unsafe)]
+            let _span = unsafe { #start_span };
+        },
+        Some(sample) => quote! {
+            #statics
+            #cfg
+            static SAMPLE_COUNT: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+            #cfg
+            #[allow(static_mut_refs, unused_unsafe)]
+            let _span = (SAMPLE_COUNT.fetch_add(1, core::sync::atomic::Ordering::Relaxed) % #sample == 0)
+                .then(|| unsafe { #start_span });
+        },
+    };
+
+    // the span is only recorded if it is dropped while unwinding
+    let instrumentation = match (record_on_panic, &sample) {
+        (false, _) => instrumentation,
+        (true, None) => quote! {
+            #instrumentation
+            #cfg
+            let _span = _span.on_panic();
+        },
+        (true, Some(_)) => quote! {
+            #instrumentation
+            #cfg
+            let _span = _span.map(|__span| __span.on_panic());
+        },
+    };
+    let cancel_span = match sample {
+        None => quote! { _span.cancel(); },
+        Some(_) => quote! {
+            if let Some(__span) = _span {
+                __span.cancel();
+            }
+        },
+    };
+
+    // With `record_on` the body runs on its own so that every return path yields the result, and the
+    // span is cancelled for the results that should not be recorded.
+    let fn_body = match record_on {
+        None => fn_body,
+        Some(cancel_if) => {
+            let return_type = match fn_output {
+                ReturnType::Type(_, ty) => ty,
+                ReturnType::Default => {
+                    return TokenStream::from(
+                        syn::Error::new_spanned(&input_fn.sig, "'record_on' requires a function returning a Result")
+                            .to_compile_error(),
+                    );
+                }
+            };
+            let result = if fn_async.is_some() {
+                quote! {
+                    let __result: #return_type = async move { #fn_body }.await;
+                }
+            } else {
+                quote! {
+                    #[allow(clippy::redundant_closure_call)]
+                    let __result: #return_type = (move || -> #return_type { #fn_body })();
+                }
+            };
+            quote! {
+                #result
+                #cfg
+                if __result.#cancel_if() {
+                    #cancel_span
+                }
+                __result
+            }
+        }
+    };
+
+    let generated = quote! {
+        #(#attrs)*
+        #fn_vis #fn_async #fn_unsafe fn #fn_name #fn_generics (#fn_args) #fn_output #fn_where_clause {
+
+            #instrumentation
+
+            #fn_body
+        }
+    };
+
+    generated.into()
+}
+
+/// The `instrument_all` attribute macro instruments every method of an `impl` block with a span, as if
+/// each of them had been annotated with [macro@span] with the same arguments. Each method is tagged with
+/// its own name (`fn_name`), so the methods share the measurement but are recorded as separate series.
+/// `async` methods, generic methods and methods of generic types are supported, as with [macro@span].
+///
+/// Methods marked with `#[skip_instrument]` are left untouched, as are methods that already have a
+/// `span` attribute of their own, e.g. to use other arguments.
+///
+/// ## Examples
+///
+/// ```ignore
+/// use metricus_macros::instrument_all;
+///
+/// struct OrderService;
+///
+/// #[instrument_all(measurement = "order_service", tags(service = "orders"))]
+/// impl OrderService {
+///     // recorded with `fn_name = "submit"`
+///     pub fn submit(&self, price: u64) {}
+///
+///     // recorded with `fn_name = "cancel"`
+///     pub async fn cancel(&mut self, id: u64) {}
+///
+///     #[skip_instrument]
+///     fn validate(&self, price: u64) -> bool {
+///         price > 0
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn instrument_all(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let attr = proc_macro2::TokenStream::from(attr);
+    let mut input_impl = parse_macro_input!(item as ItemImpl);
+
+    // the arguments are passed on to `span` as they are, but a missing measurement is reported once
+    let has_measurement = attr
+        .clone()
+        .into_iter()
+        .any(|token| matches!(token, TokenTree::Ident(ident) if ident == "measurement"));
+    if !has_measurement {
+        return TokenStream::from(
+            syn::Error::new_spanned(&input_impl.self_ty, "Missing required 'measurement' field").to_compile_error(),
+        );
+    }
+
+    for impl_item in &mut input_impl.items {
+        let ImplItem::Method(method) = impl_item else {
+            continue;
+        };
+        let skip = method.attrs.iter().any(|attr| attr.path.is_ident("skip_instrument"));
+        method.attrs.retain(|attr| !attr.path.is_ident("skip_instrument"));
+        let has_span = method
+            .attrs
+            .iter()
+            .any(|attr| attr.path.segments.last().is_some_and(|segment| segment.ident == "span"));
+        if !skip && !has_span {
+            method
+                .attrs
+                .push(syn::parse_quote! { #[::metricus_macros::span(#attr)] });
+        }
+    }
+
+    quote! { #input_impl }.into()
+}
+
+/// The `count_errors` attribute macro instruments a function returning a `Result` with a counter
+/// of the calls that return `Err`. It requires to specify `measurement` name under which the count
+/// will be recorded and accepts optional `tags` and `cfg` arguments, the same as [macro@counter].
+/// The function name (`fn_name`) is automatically added as a tag, its key can be changed or the tag
+/// left out with `fn_name_tag` and `no_fn_name` as for [macro@counter].
+///
+/// The function body is moved into a closure (or an `async` block for async functions) so that
+/// early returns and the `?` operator are accounted for, therefore the return type must be spelled
+/// out as a concrete `Result` type (or an alias of it).
+///
+/// ## Examples
+///
+/// ```ignore
+/// use metricus_macros::count_errors;
+///
+/// #[count_errors(measurement = "errors", tags(component = "parser"))]
+/// fn parse(input: &str) -> Result<u64, std::num::ParseIntError> {
+///     Ok(input.parse()?)
+/// }
+/// ```
+///
+/// Split the counter by error kind with `by_variant`. The increment is then tagged with `variant`
+/// set to the name of the error's enum variant, so there is at most one series per variant. The
+/// error type must implement `metricus::VariantName`, which can be derived with [macro@VariantName].
+///
+/// ```ignore
+/// use metricus_macros::{count_errors, VariantName};
+///
+/// #[derive(Debug, VariantName)]
+/// enum OrderError {
+///     InvalidPrice,
+///     InsufficientFunds { missing: u64 },
+///     Rejected(String),
+/// }
+///
+/// #[count_errors(measurement = "order_errors", by_variant)]
+/// fn submit_order(price: u64) -> Result<(), OrderError> {
+///     if price == 0 {
+///         return Err(OrderError::InvalidPrice);
+///     }
+///     Ok(())
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn count_errors(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as AttributeArgs);
+    let input_fn = parse_macro_input!(item as ItemFn);
+    let fn_name = &input_fn.sig.ident;
+
+    // initialize variables to hold parsed values
+    let mut measurement = None;
+    let mut tags = Vec::new();
+    let mut cfg = None;
+    let mut by_variant = false;
+
+    // auto include method name, unless disabled
+    match fn_name_tag(&args) {
+        Ok(Some(key)) => tags.push((key, fn_name.to_string())),
+        Ok(None) => {}
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    }
+
+    // Parse attributes for measurement and tags
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                ref path,
+                lit: Lit::Str(ref value),
+                ..
+            })) if path.is_ident("measurement") => {
+                measurement = Some(value.value());
+            }
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                ref path,
+                lit: Lit::Str(ref value),
+                ..
+            })) if path.is_ident("cfg") => {
+                let feature = value.value();
+                cfg = Some(quote! { #[cfg(feature = #feature)] });
+            }
+            NestedMeta::Meta(Meta::List(MetaList {
+                ref path, ref nested, ..
+            })) if path.is_ident("cfg") => {
+                cfg = Some(quote! { #[cfg(#nested)] });
+            }
+            NestedMeta::Meta(Meta::List(MetaList {
+                ref path, ref nested, ..
+            })) if path.is_ident("tags") => {
+                for meta in nested {
+                    if let NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit, .. })) = meta {
+                        if let Err(err) = push_tag(&mut tags, path, lit) {
+                            return TokenStream::from(err.to_compile_error());
+                        }
+                    } else {
+                        return TokenStream::from(
+                            syn::Error::new_spanned(meta, "Expected a name-value pair for tags").to_compile_error(),
+                        );
+                    }
+                }
+            }
+            NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("by_variant") => {
+                by_variant = true;
+            }
+            _ => {}
+        }
+    }
+
+    // the variant is passed on like an argument tag, with its value taken from the error
+    let variant_tag = by_variant.then(|| Ident::new("variant", Span::call_site()));
+    let tags = match quote_tags(tags, variant_tag.as_slice()) {
+        Ok(tags) => tags,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
+
+    // Ensure measurement field is provided
+    let measurement = match measurement {
+        Some(measurement) => measurement,
+        None => {
+            return TokenStream::from(
+                syn::Error::new_spanned(&input_fn, "Missing required 'measurement' field").to_compile_error(),
+            );
+        }
+    };
+
+    let measurement = measurement.as_str();
+
+    let fn_body = &input_fn.block.stmts;
+    let fn_vis = &input_fn.vis;
+    let fn_unsafe = &input_fn.sig.unsafety;
+    let fn_async = &input_fn.sig.asyncness;
+    let fn_args = &input_fn.sig.inputs;
+    let fn_output = &input_fn.sig.output;
+    let fn_generics = &input_fn.sig.generics;
+    let fn_where_clause = &input_fn.sig.generics.where_clause;
+    let attrs = &input_fn.attrs;
+
+    let return_type = match fn_output {
+        ReturnType::Type(_, ty) => ty,
+        ReturnType::Default => {
+            return TokenStream::from(
+                syn::Error::new_spanned(&input_fn.sig, "'count_errors' requires a function returning a Result")
+                    .to_compile_error(),
+            );
+        }
+    };
+
+    // run the body on its own so that every return path yields the result to inspect
+    let result = if fn_async.is_some() {
+        quote! {
+            let __result: #return_type = async move { #( #fn_body )* }.await;
+        }
+    } else {
+        quote! {
+            #[allow(clippy::redundant_closure_call)]
+            let __result: #return_type = (move || -> #return_type { #( #fn_body )* })();
+        }
+    };
+
+    let instrumentation = if by_variant {
+        quote! {
+            #cfg
+            static COUNTERS: metricus::TaggedMetrics<metricus::Counter, 1> = metricus::TaggedMetrics::new();
+            #cfg
+            if let Err(__err) = &__result {
+                let __variant = metricus::VariantName::variant_name(__err);
+                let __counter = COUNTERS.get_or_create([ &__variant as &dyn core::fmt::Display ], |__tag_values| {
+                    metricus::Counter::new(#measurement, &[ #(#tags),* ])
+                });
+                metricus::CounterOps::increment(__counter);
+            }
+        }
+    } else {
+        quote! {
+            #cfg
+            static mut COUNTER: core::cell::LazyCell<metricus::Counter> = core::cell::LazyCell::new(|| metricus::Counter::new(#measurement, &[ #(#tags),* ]));
+            #cfg
+            if __result.is_err() {
+                #[allow(static_mut_refs)]
+                unsafe { metricus::CounterOps::increment(&COUNTER); }
+            }
+        }
+    };
+
+    let generated = quote! {
+        #(#attrs)*
+        #fn_vis #fn_async #fn_unsafe fn #fn_name #fn_generics (#fn_args) #fn_output #fn_where_clause {
+
+            #result
+
+            #instrumentation
+
+            __result
+        }
+    };
+
+    generated.into()
+}
+
+/// Derives `metricus::VariantName` for an enum, returning the name of the variant of a value. This is
+/// used by `#[count_errors(by_variant)]` to tag error counters with the error kind.
+///
+/// ## Examples
+///
+/// ```ignore
+/// use metricus::VariantName;
+///
+/// #[derive(metricus_macros::VariantName)]
+/// enum OrderError {
+///     InvalidPrice,
+///     Rejected(String),
+/// }
+///
+/// assert_eq!("Rejected", OrderError::Rejected("closed".to_string()).variant_name());
+/// ```
+#[proc_macro_derive(VariantName)]
+pub fn derive_variant_name(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    let name = &input.ident;
+    let Data::Enum(data) = &input.data else {
+        return TokenStream::from(
+            syn::Error::new_spanned(&input, "'VariantName' can only be derived for enums").to_compile_error(),
+        );
+    };
+    let arms = data.variants.iter().map(|variant| {
+        let ident = &variant.ident;
+        let variant_name = ident.to_string();
+        quote! { Self::#ident { .. } => #variant_name }
+    });
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let generated = quote! {
+        impl #impl_generics metricus::VariantName for #name #ty_generics #where_clause {
+            fn variant_name(&self) -> &'static str {
+                match self {
+                    #( #arms, )*
+                }
+            }
+        }
+    };
+
+    generated.into()
+}
+
+/// The `module_tags` attribute macro adds common tags to every metric declared with [macro@counter],
+/// [macro@span] or [macro@count_errors] within the annotated module, including functions in nested
+/// modules and `impl` blocks. Tag values can be string, integer or boolean literals, as for function
+/// tags. Tags given on a function take precedence over module tags with the same key. Rust does not (yet) allow attribute macros as inner attributes (`#![...]`), so the attribute
+/// has to be put on the module declaration instead, and only works with inline modules (`mod name { ... }`)
+/// as attributes on out-of-line modules are unstable too.
+///
+/// The instrumentation attributes are recognized by name (`counter`, `span`, `count_errors`,
+/// `instrument_all`, optionally path-qualified), so they must not be renamed on import within the module.
+///
+/// ## Examples
+///
+/// ```ignore
+/// use metricus_macros::{counter, module_tags, span};
+///
+/// #[module_tags(subsystem = "matching")]
+/// mod matching {
+///     use super::*;
+///
+///     // tagged with `subsystem = "matching"` and `fn_name = "match_order"`
+///     #[counter(measurement = "orders")]
+///     pub fn match_order() {}
+///
+///     // the function level tag wins, so this is tagged with `subsystem = "auction"`
+///     #[span(measurement = "latencies", tags(subsystem = "auction"))]
+///     pub fn run_auction() {}
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn module_tags(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as AttributeArgs);
+    let mut input_mod = parse_macro_input!(item as ItemMod);
+
+    let mut tags = Vec::new();
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue { ref path, ref lit, .. }))
+                if path.get_ident().is_some() =>
+            {
+                if let Err(err) = tag_value(lit) {
+                    return TokenStream::from(err.to_compile_error());
+                }
+                tags.push((path.get_ident().unwrap().clone(), lit.clone()));
+            }
+            _ => {
+                return TokenStream::from(
+                    syn::Error::new_spanned(arg, "Expected a name-value pair for module tags").to_compile_error(),
+                );
+            }
+        }
+    }
+
+    let Some((_, items)) = &mut input_mod.content else {
+        return TokenStream::from(
+            syn::Error::new_spanned(&input_mod, "'module_tags' can only be used on inline modules").to_compile_error(),
+        );
+    };
+    add_module_tags(items, &tags);
+
+    quote! { #input_mod }.into()
+}
+
+/// Adds the module tags to the instrumentation attributes of all functions within the items.
+fn add_module_tags(items: &mut [Item], tags: &[(Ident, Lit)]) {
+    for item in items {
+        match item {
+            Item::Fn(item_fn) => item_fn.attrs.iter_mut().for_each(|attr| add_attr_tags(attr, tags)),
+            Item::Impl(item_impl) => {
+                item_impl.attrs.iter_mut().for_each(|attr| add_attr_tags(attr, tags));
+                for impl_item in &mut item_impl.items {
+                    if let ImplItem::Method(method) = impl_item {
+                        method.attrs.iter_mut().for_each(|attr| add_attr_tags(attr, tags));
+                    }
+                }
+            }
+            Item::Mod(ItemMod {
+                content: Some((_, items)),
+                ..
+            }) => add_module_tags(items, tags),
+            _ => {}
+        }
+    }
+}
+
+/// Merges the tags into the `tags(...)` argument of an instrumentation attribute, keeping the keys that
+/// are already present. The other arguments are left untouched.
+fn add_attr_tags(attr: &mut Attribute, tags: &[(Ident, Lit)]) {
+    let is_instrumentation = attr.path.segments.last().is_some_and(|segment| {
+        ["counter", "span", "count_errors", "instrument_all"].contains(&segment.ident.to_string().as_str())
+    });
+    if !is_instrumentation {
+        return;
+    }
+    let args = match attr.tokens.clone().into_iter().next() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => group.stream(),
+        _ => return,
+    };
+
+    let mut merged = Vec::new();
+    let mut found = false;
+    let mut tokens = args.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match (&token, tokens.peek()) {
+            (TokenTree::Ident(ident), Some(TokenTree::Group(group)))
+                if ident == "tags" && group.delimiter() == Delimiter::Parenthesis =>
+            {
+                let existing = tag_keys(&group.stream());
+                let missing = tags.iter().filter(|(key, _)| !existing.contains(&key.to_string()));
+                let inner = group.stream();
+                let separator = needs_separator(&inner).then(|| quote! { , });
+                let missing = missing.map(|(key, value)| quote! { #key = #value });
+                let mut group = Group::new(Delimiter::Parenthesis, quote! { #inner #separator #( #missing ),* });
+                group.set_span(token.span());
+                tokens.next();
+                merged.push(token);
+                merged.push(TokenTree::Group(group));
+                found = true;
+            }
+            _ => merged.push(token),
+        }
+    }
+    let merged: proc_macro2::TokenStream = merged.into_iter().collect();
+    let extra = (!found).then(|| {
+        let separator = needs_separator(&merged).then(|| quote! { , });
+        let tags = tags.iter().map(|(key, value)| quote! { #key = #value });
+        quote! { #separator tags( #( #tags ),* ) }
+    });
+    attr.tokens = quote! { ( #merged #extra ) };
+}
+
+/// Keys of the `key = value` pairs of a `tags(...)` argument. Values are skipped, so that e.g. the `true`
+/// of `enabled = true` is not taken for a key.
+fn tag_keys(tags: &proc_macro2::TokenStream) -> HashSet<String> {
+    let mut keys = HashSet::new();
+    let mut at_key = true;
+    let mut tokens = tags.clone().into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Punct(punct) if punct.as_char() == ',' => at_key = true,
+            TokenTree::Ident(key) if at_key => {
+                if matches!(tokens.peek(), Some(TokenTree::Punct(punct)) if punct.as_char() == '=') {
+                    keys.insert(key.to_string());
+                }
+                at_key = false;
+            }
+            _ => at_key = false,
+        }
+    }
+    keys
+}
+
+/// String representation of a tag value, which can be a string, integer or boolean literal.
+fn tag_value(lit: &Lit) -> syn::Result<String> {
+    match lit {
+        Lit::Str(value) => Ok(value.value()),
+        Lit::Int(value) => Ok(value.base10_digits().to_owned()),
+        Lit::Bool(value) => Ok(value.value.to_string()),
+        _ => Err(syn::Error::new_spanned(lit, "Expected a string, integer or boolean literal as tag value")),
+    }
+}
+
+/// Adds a tag given as `key = value`, keys must be unique (including the key of the function name tag).
+fn push_tag(tags: &mut Vec<(String, String)>, path: &syn::Path, lit: &Lit) -> syn::Result<()> {
+    let Some(key) = path.get_ident().map(Ident::to_string) else {
+        return Err(syn::Error::new_spanned(path, "Expected an identifier as tag key"));
+    };
+    if tags.iter().any(|(k, _)| *k == key) {
+        return Err(syn::Error::new_spanned(path, format!("duplicate tag key `{key}`")));
+    }
+    tags.push((key, tag_value(lit)?));
+    Ok(())
+}
+
+/// Key of the tag with the function name, set by the `fn_name_tag` or `no_fn_name` arguments.
+fn fn_name_tag(args: &[NestedMeta]) -> syn::Result<Option<String>> {
+    let mut key = Some("fn_name".to_string());
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit, .. })) if path.is_ident("fn_name_tag") => {
+                key = fn_name_tag_key(lit)?;
+            }
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("no_fn_name") => key = None,
+            _ => {}
+        }
+    }
+    Ok(key)
+}
+
+/// Key of the tag with the function name given by `fn_name_tag`, which is either a string or `false` to
+/// leave out the tag (`true` keeps the default `fn_name`).
+fn fn_name_tag_key(lit: &Lit) -> syn::Result<Option<String>> {
+    match lit {
+        Lit::Str(value) if !value.value().is_empty() => Ok(Some(value.value())),
+        Lit::Bool(value) => Ok(value.value.then(|| "fn_name".to_string())),
+        _ => Err(syn::Error::new_spanned(lit, "Expected a non-empty tag key or a boolean for fn_name_tag")),
+    }
+}
+
+/// Whether a comma has to be inserted before appending to a comma-separated list.
+fn needs_separator(list: &proc_macro2::TokenStream) -> bool {
+    match list.clone().into_iter().last() {
+        Some(TokenTree::Punct(punct)) => punct.as_char() != ',',
+        Some(_) => true,
+        None => false,
+    }
+}
+
+/// Replaces `measurement = ["a", "b", ...]` in the attribute arguments with `measurement = "a"` so that
+/// the arguments can be parsed as usual, and returns the remaining measurements.
+fn extract_measurement_list(attr: proc_macro2::TokenStream) -> syn::Result<(proc_macro2::TokenStream, Vec<LitStr>)> {
+    let mut tokens: Vec<TokenTree> = attr.into_iter().collect();
+    let position = tokens.windows(3).position(|window| match window {
+        [
+            TokenTree::Ident(ident),
+            TokenTree::Punct(punct),
+            TokenTree::Group(group),
+        ] => ident == "measurement" && punct.as_char() == '=' && group.delimiter() == Delimiter::Bracket,
+        _ => false,
+    });
+    let Some(position) = position else {
+        return Ok((tokens.into_iter().collect(), Vec::new()));
+    };
+    let TokenTree::Group(group) = &tokens[position + 2] else {
+        unreachable!()
+    };
+    let parser = Punctuated::<LitStr, Token![,]>::parse_terminated;
+    let mut measurements = parser.parse2(group.stream())?.into_iter();
+    let Some(first) = measurements.next() else {
+        return Err(syn::Error::new_spanned(group.stream(), "Expected at least one measurement"));
+    };
+    tokens[position + 2] = TokenTree::Literal(proc_macro2::Literal::string(&first.value()));
+    Ok((tokens.into_iter().collect(), measurements.collect()))
+}
+
+/// Takes `measurement = PATH` out of the attribute arguments, as syn only parses literal values, and
+/// returns the remaining arguments along with the path, which names a `const &str` (or `static`).
+fn extract_measurement_path(
+    attr: proc_macro2::TokenStream,
+) -> syn::Result<(proc_macro2::TokenStream, Option<syn::Path>)> {
+    let mut tokens: Vec<TokenTree> = attr.into_iter().collect();
+    let position = tokens.windows(3).position(|window| match window {
+        [TokenTree::Ident(ident), TokenTree::Punct(punct), value] => {
+            ident == "measurement"
+                && punct.as_char() == '='
+                && match value {
+                    TokenTree::Ident(_) => true,
+                    TokenTree::Punct(punct) => punct.as_char() == ':',
+                    _ => false,
+                }
+        }
+        _ => false,
+    });
+    let Some(position) = position else {
+        return Ok((tokens.into_iter().collect(), None));
+    };
+    let end = tokens[position + 2..]
+        .iter()
+        .position(|token| matches!(token, TokenTree::Punct(punct) if punct.as_char() == ','))
+        .map_or(tokens.len(), |offset| position + 2 + offset);
+    let value: proc_macro2::TokenStream = tokens[position + 2..end].iter().cloned().collect();
+    let path = syn::parse2::<syn::Path>(value.clone()).map_err(|_| {
+        syn::Error::new_spanned(value, "Expected a string literal or a path to a `const &str` for measurement")
+    })?;
+    // drop the argument along with its trailing comma
+    tokens.drain(position..(end + 1).min(tokens.len()));
+    Ok((tokens.into_iter().collect(), Some(path)))
+}
+
+/// Measurement given either as a string literal or as a path to a constant.
+fn measurement_tokens(
+    input_fn: &ItemFn,
+    measurement: Option<&str>,
+    path: Option<&syn::Path>,
+) -> syn::Result<proc_macro2::TokenStream> {
+    match (measurement, path) {
+        (Some(measurement), None) => Ok(quote! { #measurement }),
+        (None, Some(path)) => Ok(quote! { #path }),
+        (Some(_), Some(path)) => Err(syn::Error::new_spanned(path, "'measurement' is specified more than once")),
+        (None, None) => Err(syn::Error::new_spanned(input_fn, "Missing required 'measurement' field")),
+    }
+}
+
+/// Expression evaluating to the id given by `id = ...`, where a string literal names a const function
+/// returning the id and anything else is taken as the id itself.
+fn parse_id(attr: proc_macro2::TokenStream) -> syn::Result<Option<proc_macro2::TokenStream>> {
+    let parser = Punctuated::<Expr, Token![,]>::parse_terminated;
+    let mut id = None;
+    for arg in parser.parse2(attr)? {
+        match arg {
+            Expr::Assign(assign) if matches!(assign.left.as_ref(), Expr::Path(left) if left.path.is_ident("id")) => {
+                id = Some(match *assign.right {
+                    Expr::Lit(syn::ExprLit {
+                        lit: Lit::Str(value), ..
+                    }) => {
+                        let getter_fn = value.parse::<syn::Path>()?;
+                        quote! { #getter_fn() }
+                    }
+                    value => quote! { #value },
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(id)
+}
+
+/// Checks that `arg` names one of the function parameters.
+fn check_fn_arg(input_fn: &ItemFn, arg: &Ident) -> syn::Result<()> {
+    let found = input_fn.sig.inputs.iter().any(|input| match input {
+        FnArg::Typed(pat_type) => matches!(pat_type.pat.as_ref(), Pat::Ident(pat) if pat.ident == *arg),
+        FnArg::Receiver(_) => false,
+    });
+    if found {
+        Ok(())
+    } else {
+        Err(syn::Error::new_spanned(arg, format!("function has no parameter named `{arg}`")))
+    }
+}
+
+/// Quotes the static tags together with the tags derived from function arguments, ordered by key.
+/// The values of argument tags refer to the `__tag_values` array of string slices passed to the closure
+/// creating the metric, see `metricus::TaggedMetrics`.
+fn quote_tags(tags: Vec<(String, String)>, tag_args: &[Ident]) -> syn::Result<Vec<proc_macro2::TokenStream>> {
+    let mut quoted: Vec<(String, proc_macro2::TokenStream)> = tags
+        .into_iter()
+        .map(|(k, v)| {
+            // Directly quote each tuple
+            let tag = quote! { (#k, #v) };
+            (k, tag)
+        })
+        .collect();
+    for (index, arg) in tag_args.iter().enumerate() {
+        let key = arg.to_string();
+        if quoted.iter().any(|(k, _)| *k == key) {
+            return Err(syn::Error::new_spanned(arg, format!("duplicate tag key `{key}`")));
+        }
+        let index = proc_macro2::Literal::usize_unsuffixed(index);
+        let tag = quote! { (#key, &*__tag_values[#index]) };
+        quoted.push((key, tag));
+    }
+
+    // Ensure consistent ordering of tags
+    quoted.sort_unstable_by(|(k1, _), (k2, _)| k1.cmp(k2));
+    Ok(quoted.into_iter().map(|(_, tag)| tag).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_keys_skip_values() {
+        let keys = tag_keys(&quote! { enabled = true, venue = "xlon", primary = false });
+        let expected: HashSet<String> = ["enabled", "venue", "primary"].map(String::from).into();
+        assert_eq!(expected, keys);
+    }
+}
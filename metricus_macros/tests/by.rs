@@ -0,0 +1,30 @@
+use metricus::TestMetrics;
+use metricus_macros::counter;
+
+#[counter(measurement = "by_processed", by = "items.len() as u64")]
+fn process_batch(items: &mut Vec<u32>) {
+    // the delta is computed before the body runs
+    items.clear();
+}
+
+#[counter(
+    measurement = "by_bytes",
+    tags(stage = "parse"),
+    by = "header + payload.len() as u64"
+)]
+fn parse_message(header: u64, payload: &[u8]) {
+    let _ = (header, payload);
+}
+
+#[test]
+fn counters_are_incremented_by_the_computed_delta() {
+    let metrics = TestMetrics::install();
+    process_batch(&mut vec![1, 2, 3]);
+    process_batch(&mut Vec::new());
+    process_batch(&mut vec![4; 10]);
+    assert_eq!(Some(13), metrics.counter_value("by_processed", &[("fn_name", "process_batch")]));
+
+    parse_message(16, b"order");
+    let tags = [("fn_name", "parse_message"), ("stage", "parse")];
+    assert_eq!(Some(21), metrics.counter_value("by_bytes", &tags));
+}
@@ -0,0 +1,60 @@
+use metricus::TestMetrics;
+use metricus_macros::{counter, span};
+
+#[counter(measurement = "cfg_counter_active", cfg(test))]
+fn counted_when_active() -> u64 {
+    1
+}
+
+#[counter(measurement = "cfg_counter_inactive", cfg(not(test)))]
+fn counted_when_inactive() -> u64 {
+    2
+}
+
+#[span(measurement = "cfg_span_active", cfg(test))]
+fn timed_when_active() -> u64 {
+    3
+}
+
+#[span(measurement = "cfg_span_inactive", cfg(not(test)))]
+fn timed_when_inactive() -> u64 {
+    4
+}
+
+#[test]
+fn counter_is_compiled_in_when_cfg_is_active() {
+    let metrics = TestMetrics::install();
+    assert_eq!(1, counted_when_active());
+    assert_eq!(1, counted_when_active());
+    assert_eq!(Some(2), metrics.counter_value("cfg_counter_active", &[("fn_name", "counted_when_active")]));
+}
+
+#[test]
+fn counter_is_compiled_out_when_cfg_is_inactive() {
+    let metrics = TestMetrics::install();
+    assert_eq!(2, counted_when_inactive());
+    assert_eq!(None, metrics.counter_value("cfg_counter_inactive", &[("fn_name", "counted_when_inactive")]));
+}
+
+#[test]
+fn span_is_compiled_in_when_cfg_is_active() {
+    let metrics = TestMetrics::install();
+    assert_eq!(3, timed_when_active());
+    assert_eq!(
+        1,
+        metrics
+            .recorded_values("cfg_span_active", &[("fn_name", "timed_when_active")])
+            .len()
+    );
+}
+
+#[test]
+fn span_is_compiled_out_when_cfg_is_inactive() {
+    let metrics = TestMetrics::install();
+    assert_eq!(4, timed_when_inactive());
+    assert!(
+        metrics
+            .recorded_values("cfg_span_inactive", &[("fn_name", "timed_when_inactive")])
+            .is_empty()
+    );
+}
@@ -0,0 +1,71 @@
+use metricus::{Id, Metrics, Tags, set_metrics};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+/// Backend keeping counter values and recorded values by id, as the metrics of the `*_with_id` macros
+/// are created with the ids given to the macro rather than registered.
+#[derive(Clone, Default)]
+pub struct IdMetrics {
+    counters: Arc<Mutex<HashMap<Id, u64>>>,
+    histograms: Arc<Mutex<HashMap<Id, Vec<u64>>>>,
+}
+
+impl IdMetrics {
+    /// Installs a backend the first time this is called, and returns the installed backend on every call.
+    pub fn install() -> IdMetrics {
+        static INSTALLED: OnceLock<IdMetrics> = OnceLock::new();
+        INSTALLED
+            .get_or_init(|| {
+                let metrics = IdMetrics::default();
+                set_metrics(metrics.clone());
+                metrics
+            })
+            .clone()
+    }
+
+    /// Value of the counter, zero if it has not been incremented.
+    #[allow(dead_code)] // not used by every test binary
+    pub fn counter(&self, id: Id) -> u64 {
+        self.counters().get(&id).copied().unwrap_or_default()
+    }
+
+    /// Values recorded into the histogram.
+    #[allow(dead_code)] // not used by every test binary
+    pub fn histogram(&self, id: Id) -> Vec<u64> {
+        self.histograms().get(&id).cloned().unwrap_or_default()
+    }
+
+    fn counters(&self) -> MutexGuard<'_, HashMap<Id, u64>> {
+        self.counters.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn histograms(&self) -> MutexGuard<'_, HashMap<Id, Vec<u64>>> {
+        self.histograms.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Metrics for IdMetrics {
+    fn name(&self) -> &'static str {
+        "id"
+    }
+
+    fn new_counter(&mut self, _name: &str, _tags: Tags) -> Id {
+        Id::MAX
+    }
+
+    fn delete_counter(&mut self, _id: Id) {}
+
+    fn increment_counter_by(&mut self, id: Id, delta: u64) {
+        *self.counters().entry(id).or_default() += delta;
+    }
+
+    fn new_histogram(&mut self, _name: &str, _tags: Tags) -> Id {
+        Id::MAX
+    }
+
+    fn delete_histogram(&mut self, _id: Id) {}
+
+    fn record(&mut self, id: Id, value: u64) {
+        self.histograms().entry(id).or_default().push(value);
+    }
+}
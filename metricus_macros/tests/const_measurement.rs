@@ -0,0 +1,42 @@
+use metricus::TestMetrics;
+use metricus_macros::{counter, span};
+
+mod names {
+    pub const REQUESTS: &str = "const_measurement_requests";
+    pub const LATENCY: &str = "const_measurement_latency";
+}
+
+const ERRORS: &str = "const_measurement_errors";
+
+#[counter(measurement = names::REQUESTS, tags(route = "orders"))]
+fn get_orders() {}
+
+#[counter(measurement = names::REQUESTS, tags(route = "fills"))]
+fn get_fills() {}
+
+#[counter(measurement = ERRORS)]
+fn reject() {}
+
+#[span(measurement = names::LATENCY)]
+fn get_book() {}
+
+#[test]
+fn measurement_names_can_be_consts() {
+    let metrics = TestMetrics::install();
+    get_orders();
+    get_orders();
+    get_fills();
+    reject();
+    get_book();
+
+    let requests = |fn_name, route| metrics.counter_value(names::REQUESTS, &[("fn_name", fn_name), ("route", route)]);
+    assert_eq!(Some(2), requests("get_orders", "orders"));
+    assert_eq!(Some(1), requests("get_fills", "fills"));
+    assert_eq!(Some(1), metrics.counter_value(ERRORS, &[("fn_name", "reject")]));
+    assert_eq!(
+        1,
+        metrics
+            .recorded_values(names::LATENCY, &[("fn_name", "get_book")])
+            .len()
+    );
+}
@@ -0,0 +1,53 @@
+use metricus::{TestMetrics, VariantName};
+use metricus_macros::{VariantName, count_errors};
+use std::thread;
+
+// the fields are only there to cover every kind of variant
+#[allow(dead_code)]
+#[derive(Debug, VariantName)]
+enum OrderError {
+    InvalidPrice,
+    InsufficientFunds { missing: u64 },
+    Rejected(String),
+}
+
+#[count_errors(measurement = "count_errors_orders", by_variant)]
+fn submit_order(price: u64) -> Result<u64, OrderError> {
+    match price {
+        0 => Err(OrderError::InvalidPrice),
+        1 => Err(OrderError::InsufficientFunds { missing: 10 }),
+        2 => Err(OrderError::Rejected("closed".to_string())),
+        _ => Ok(price),
+    }
+}
+
+const THREADS: u64 = 8;
+const CALLS: u64 = 1_000;
+
+#[test]
+fn derives_the_name_of_unit_tuple_and_struct_variants() {
+    assert_eq!("InvalidPrice", OrderError::InvalidPrice.variant_name());
+    assert_eq!("InsufficientFunds", OrderError::InsufficientFunds { missing: 1 }.variant_name());
+    assert_eq!("Rejected", OrderError::Rejected("closed".to_string()).variant_name());
+}
+
+#[test]
+fn errors_are_counted_by_variant_across_threads() {
+    let metrics = TestMetrics::install();
+    thread::scope(|scope| {
+        for _ in 0..THREADS {
+            scope.spawn(|| {
+                for call in 0..CALLS {
+                    let _ = submit_order(call % 4);
+                }
+            });
+        }
+    });
+    let count =
+        |variant| metrics.counter_value("count_errors_orders", &[("fn_name", "submit_order"), ("variant", variant)]);
+    let expected = Some(THREADS * CALLS / 4);
+    assert_eq!(expected, count("InvalidPrice"));
+    assert_eq!(expected, count("InsufficientFunds"));
+    assert_eq!(expected, count("Rejected"));
+    assert_eq!(None, count("Ok"));
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::IdMetrics;
+use metricus::Id;
+use metricus_macros::counter_with_id;
+
+const BASE_ID: Id = 100;
+
+struct Ids;
+
+impl Ids {
+    const CANCELS: Id = BASE_ID + 10;
+}
+
+const fn amends_id() -> Id {
+    BASE_ID * 2
+}
+
+#[counter_with_id(id = 7)]
+fn submit_order() {}
+
+#[counter_with_id(id = "amends_id")]
+fn amend_order() {}
+
+#[counter_with_id(id = BASE_ID + 3)]
+fn match_order() {}
+
+#[counter_with_id(id = Ids::CANCELS)]
+fn cancel_order() {}
+
+#[test]
+fn counters_are_created_with_the_given_ids() {
+    let metrics = IdMetrics::install();
+    submit_order();
+    amend_order();
+    amend_order();
+    match_order();
+    cancel_order();
+    cancel_order();
+    cancel_order();
+
+    assert_eq!(1, metrics.counter(7));
+    assert_eq!(2, metrics.counter(200));
+    assert_eq!(1, metrics.counter(103));
+    assert_eq!(3, metrics.counter(110));
+}
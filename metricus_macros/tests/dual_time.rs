@@ -0,0 +1,44 @@
+use metricus::TestMetrics;
+use metricus_macros::span;
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+/// Polls the future to completion on the current thread, the futures under test never wait.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+#[span(measurement = "dual_time_parse", dual_time)]
+async fn parse(input: &str) -> Result<u64, std::num::ParseIntError> {
+    let value: u64 = input.parse()?;
+    Ok(value * 2)
+}
+
+#[span(measurement = "dual_time_unit", dual_time)]
+async fn unit() {}
+
+#[test]
+fn records_wall_clock_and_active_time() {
+    let metrics = TestMetrics::install();
+    assert_eq!(Ok(42), block_on(parse("21")));
+    assert!(block_on(parse("not a number")).is_err());
+    let tags = [("fn_name", "parse")];
+    assert_eq!(2, metrics.recorded_values("dual_time_parse", &tags).len());
+    assert_eq!(2, metrics.recorded_values("dual_time_parse_active", &tags).len());
+}
+
+#[test]
+fn supports_functions_without_return_type() {
+    let metrics = TestMetrics::install();
+    block_on(unit());
+    let tags = [("fn_name", "unit")];
+    assert_eq!(1, metrics.recorded_values("dual_time_unit", &tags).len());
+    assert_eq!(1, metrics.recorded_values("dual_time_unit_active", &tags).len());
+}
@@ -0,0 +1,36 @@
+use metricus::TestMetrics;
+use metricus_macros::{counter, span};
+
+#[counter(measurement = "fn_name_tag_orders", fn_name_tag = "function", tags(venue = "xlon"))]
+fn submit_order() {}
+
+#[counter(measurement = "fn_name_tag_orders", fn_name_tag = false, tags(venue = "xnas"))]
+fn cancel_order() {}
+
+#[counter(measurement = "fn_name_tag_orders", fn_name_tag = true, tags(venue = "xetr"))]
+fn amend_order() {}
+
+#[span(measurement = "fn_name_tag_latency", no_fn_name)]
+fn match_order() {}
+
+#[span(measurement = "fn_name_tag_latency", fn_name_tag = "function")]
+fn route_order() {}
+
+#[test]
+fn function_name_tag_can_be_renamed_or_left_out() {
+    let metrics = TestMetrics::install();
+    submit_order();
+    cancel_order();
+    amend_order();
+    match_order();
+    route_order();
+
+    let orders = |tags: &[(&str, &str)]| metrics.counter_value("fn_name_tag_orders", tags);
+    assert_eq!(Some(1), orders(&[("function", "submit_order"), ("venue", "xlon")]));
+    assert_eq!(Some(1), orders(&[("venue", "xnas")]));
+    assert_eq!(Some(1), orders(&[("fn_name", "amend_order"), ("venue", "xetr")]));
+
+    assert_eq!(1, metrics.recorded_values("fn_name_tag_latency", &[]).len());
+    let tags = [("function", "route_order")];
+    assert_eq!(1, metrics.recorded_values("fn_name_tag_latency", &tags).len());
+}
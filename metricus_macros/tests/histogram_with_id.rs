@@ -0,0 +1,40 @@
+mod common;
+
+use common::IdMetrics;
+use metricus::Id;
+use metricus_macros::histogram_with_id;
+use std::time::Duration;
+
+const BASE_ID: Id = 300;
+
+const fn load_id() -> Id {
+    BASE_ID + 2
+}
+
+#[histogram_with_id(id = 9)]
+fn warm_up() {
+    std::thread::sleep(Duration::from_millis(5));
+}
+
+#[histogram_with_id(id = "load_id")]
+fn load_config() {}
+
+#[histogram_with_id(id = BASE_ID + 1)]
+fn open_journal() -> u32 {
+    42
+}
+
+#[test]
+fn function_durations_are_recorded_with_the_given_ids() {
+    let metrics = IdMetrics::install();
+    warm_up();
+    load_config();
+    load_config();
+    assert_eq!(42, open_journal());
+
+    let recorded = metrics.histogram(9);
+    assert_eq!(1, recorded.len());
+    assert!(recorded[0] >= 5_000_000, "{}ns is less than the time slept", recorded[0]);
+    assert_eq!(2, metrics.histogram(302).len());
+    assert_eq!(1, metrics.histogram(301).len());
+}
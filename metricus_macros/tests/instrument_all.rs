@@ -0,0 +1,56 @@
+use metricus::TestMetrics;
+use metricus_macros::{instrument_all, span};
+
+struct OrderService {
+    orders: u64,
+}
+
+#[instrument_all(measurement = "instrument_all_orders", tags(service = "orders"))]
+impl OrderService {
+    fn submit(&mut self, price: u64) -> bool {
+        self.orders += 1;
+        self.validate(price)
+    }
+
+    fn cancel(&mut self) {
+        self.orders -= 1;
+    }
+
+    #[skip_instrument]
+    fn validate(&self, price: u64) -> bool {
+        price > 0
+    }
+
+    #[span(measurement = "instrument_all_audit")]
+    fn audit(&self) -> u64 {
+        self.orders
+    }
+}
+
+#[test]
+fn records_each_method_as_its_own_series() {
+    let metrics = TestMetrics::install();
+    let mut service = OrderService { orders: 0 };
+    assert!(service.submit(10));
+    assert!(!service.submit(0));
+    service.cancel();
+    assert_eq!(1, service.audit());
+
+    let samples = |measurement, fn_name| {
+        metrics
+            .recorded_values(measurement, &[("fn_name", fn_name), ("service", "orders")])
+            .len()
+    };
+    assert_eq!(2, samples("instrument_all_orders", "submit"));
+    assert_eq!(1, samples("instrument_all_orders", "cancel"));
+    // skipped methods are not instrumented at all
+    assert_eq!(0, samples("instrument_all_orders", "validate"));
+    // methods with a span of their own keep it, with its arguments only
+    assert_eq!(0, samples("instrument_all_orders", "audit"));
+    assert_eq!(
+        1,
+        metrics
+            .recorded_values("instrument_all_audit", &[("fn_name", "audit")])
+            .len()
+    );
+}
@@ -0,0 +1,94 @@
+use metricus::TestMetrics;
+use metricus_macros::module_tags;
+
+#[module_tags(subsystem = "matching", shard = 3, primary = true)]
+mod matching {
+    use metricus_macros::{count_errors, counter, span};
+
+    #[counter(measurement = "module_tags_orders")]
+    pub fn match_order() {}
+
+    // function tags win over module tags with the same key, including boolean values
+    #[counter(measurement = "module_tags_overrides", tags(subsystem = "auction", primary = false))]
+    pub fn run_auction() {}
+
+    // a boolean value on the function is not mistaken for a key
+    #[span(measurement = "module_tags_latency", tags(cached = true))]
+    pub fn lookup() {}
+
+    pub struct Book;
+
+    impl Book {
+        #[count_errors(measurement = "module_tags_errors", no_fn_name)]
+        pub fn insert(&self) -> Result<(), ()> {
+            Err(())
+        }
+    }
+
+    pub mod nested {
+        use metricus_macros::counter;
+
+        #[counter(measurement = "module_tags_nested", no_fn_name)]
+        pub fn cancel_order() {}
+    }
+}
+
+#[test]
+fn module_tags_are_merged_into_function_tags() {
+    let metrics = TestMetrics::install();
+    matching::match_order();
+    assert_eq!(
+        Some(1),
+        metrics.counter_value(
+            "module_tags_orders",
+            &[
+                ("fn_name", "match_order"),
+                ("primary", "true"),
+                ("shard", "3"),
+                ("subsystem", "matching")
+            ]
+        )
+    );
+}
+
+#[test]
+fn function_tags_take_precedence() {
+    let metrics = TestMetrics::install();
+    matching::run_auction();
+    assert_eq!(
+        Some(1),
+        metrics.counter_value(
+            "module_tags_overrides",
+            &[
+                ("fn_name", "run_auction"),
+                ("primary", "false"),
+                ("shard", "3"),
+                ("subsystem", "auction")
+            ]
+        )
+    );
+}
+
+#[test]
+fn module_tags_are_added_next_to_boolean_function_tags() {
+    let metrics = TestMetrics::install();
+    matching::lookup();
+    let tags = [
+        ("cached", "true"),
+        ("fn_name", "lookup"),
+        ("primary", "true"),
+        ("shard", "3"),
+        ("subsystem", "matching"),
+    ];
+    assert_eq!(1, metrics.recorded_values("module_tags_latency", &tags).len());
+}
+
+#[test]
+fn module_tags_apply_to_methods_and_nested_modules() {
+    let metrics = TestMetrics::install();
+    let _ = matching::Book.insert();
+    matching::nested::cancel_order();
+    let tags = [("primary", "true"), ("shard", "3"), ("subsystem", "matching")];
+    assert_eq!(Some(1), metrics.counter_value("module_tags_errors", &tags));
+    assert_eq!(Some(1), metrics.counter_value("module_tags_nested", &tags));
+}
@@ -0,0 +1,35 @@
+use metricus::TestMetrics;
+use metricus_macros::span;
+use std::time::Duration;
+
+#[span(measurement = ["multi_op_latency", "multi_all_latency"], tags(op = "insert"))]
+fn insert() {
+    std::thread::sleep(Duration::from_millis(1));
+}
+
+#[span(measurement = ["multi_op_latency", "multi_all_latency"], tags(op = "delete"))]
+fn delete() -> u32 {
+    7
+}
+
+#[test]
+fn records_the_same_duration_into_every_measurement() {
+    let metrics = TestMetrics::install();
+    insert();
+    insert();
+    let tags = [("fn_name", "insert"), ("op", "insert")];
+    let op = metrics.recorded_values("multi_op_latency", &tags);
+    let all = metrics.recorded_values("multi_all_latency", &tags);
+    assert_eq!(2, op.len());
+    assert_eq!(op, all);
+    assert!(op.iter().all(|duration| *duration >= 1_000_000));
+}
+
+#[test]
+fn keeps_the_return_value_and_tags_of_each_function() {
+    let metrics = TestMetrics::install();
+    assert_eq!(7, delete());
+    let tags = [("fn_name", "delete"), ("op", "delete")];
+    assert_eq!(1, metrics.recorded_values("multi_op_latency", &tags).len());
+    assert_eq!(1, metrics.recorded_values("multi_all_latency", &tags).len());
+}
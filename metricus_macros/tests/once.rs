@@ -0,0 +1,25 @@
+use metricus::TestMetrics;
+use metricus_macros::counter;
+use std::sync::Barrier;
+use std::thread;
+
+#[counter(measurement = "once_milestones", tags(event = "first_request"), once)]
+fn serve_request() {}
+
+#[test]
+fn increments_exactly_once_across_threads() {
+    let metrics = TestMetrics::install();
+    let barrier = Barrier::new(8);
+    thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                barrier.wait();
+                for _ in 0..1_000 {
+                    serve_request();
+                }
+            });
+        }
+    });
+    let tags = [("event", "first_request"), ("fn_name", "serve_request")];
+    assert_eq!(Some(1), metrics.counter_value("once_milestones", &tags));
+}
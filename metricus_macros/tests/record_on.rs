@@ -0,0 +1,95 @@
+use metricus::TestMetrics;
+use metricus_macros::span;
+use std::future::Future;
+use std::num::ParseIntError;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+/// Polls the future to completion on the current thread, the futures under test never wait.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+#[span(measurement = "record_on_ok", record_on = "ok")]
+fn parse_ok(input: &str) -> Result<u64, ParseIntError> {
+    if input.is_empty() {
+        return Ok(0);
+    }
+    let value: u64 = input.parse()?;
+    Ok(value)
+}
+
+#[span(measurement = "record_on_err", record_on = "err")]
+fn parse_err(input: &str) -> Result<u64, ParseIntError> {
+    if input.is_empty() {
+        return Ok(0);
+    }
+    let value: u64 = input.parse()?;
+    Ok(value)
+}
+
+#[span(measurement = "record_on_async", record_on = "err")]
+async fn parse_async(input: &str) -> Result<u64, ParseIntError> {
+    let value: u64 = input.parse()?;
+    Ok(value)
+}
+
+struct Parser {
+    radix: u32,
+}
+
+impl Parser {
+    #[span(measurement = "record_on_method", record_on = "ok")]
+    fn parse(&self, input: &str) -> Result<u64, ParseIntError> {
+        u64::from_str_radix(input, self.radix)
+    }
+}
+
+fn samples(measurement: &str, fn_name: &str) -> usize {
+    TestMetrics::install()
+        .recorded_values(measurement, &[("fn_name", fn_name)])
+        .len()
+}
+
+#[test]
+fn records_only_ok_results() {
+    let _ = TestMetrics::install();
+    assert_eq!(Ok(42), parse_ok("42"));
+    // an early return and an error propagated with `?`
+    assert_eq!(Ok(0), parse_ok(""));
+    assert!(parse_ok("x").is_err());
+    assert_eq!(2, samples("record_on_ok", "parse_ok"));
+}
+
+#[test]
+fn records_only_err_results() {
+    let _ = TestMetrics::install();
+    assert_eq!(Ok(42), parse_err("42"));
+    assert_eq!(Ok(0), parse_err(""));
+    assert!(parse_err("x").is_err());
+    assert!(parse_err("-1").is_err());
+    assert_eq!(2, samples("record_on_err", "parse_err"));
+}
+
+#[test]
+fn supports_async_functions() {
+    let _ = TestMetrics::install();
+    assert_eq!(Ok(42), block_on(parse_async("42")));
+    assert!(block_on(parse_async("x")).is_err());
+    assert_eq!(1, samples("record_on_async", "parse_async"));
+}
+
+#[test]
+fn supports_methods_taking_self() {
+    let _ = TestMetrics::install();
+    let parser = Parser { radix: 16 };
+    assert_eq!(Ok(255), parser.parse("ff"));
+    assert!(parser.parse("zz").is_err());
+    assert_eq!(1, samples("record_on_method", "parse"));
+}
@@ -0,0 +1,28 @@
+use metricus::TestMetrics;
+use metricus_macros::span;
+use std::panic::catch_unwind;
+
+#[span(measurement = "record_on_panic_latencies", record_on = "panic")]
+fn recover(fail: bool) -> u64 {
+    if fail {
+        panic!("order book corrupted");
+    }
+    42
+}
+
+#[test]
+fn records_only_calls_that_panic() {
+    let metrics = TestMetrics::install();
+    let samples = || {
+        metrics
+            .recorded_values("record_on_panic_latencies", &[("fn_name", "recover")])
+            .len()
+    };
+    assert_eq!(42, recover(false));
+    assert_eq!(0, samples());
+
+    assert!(catch_unwind(|| recover(true)).is_err());
+    assert_eq!(1, samples());
+    assert_eq!(42, recover(false));
+    assert_eq!(1, samples());
+}
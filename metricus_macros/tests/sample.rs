@@ -0,0 +1,17 @@
+use metricus::TestMetrics;
+use metricus_macros::span;
+
+#[span(measurement = "sample_latencies", sample = 10)]
+fn on_market_data(price: u64) -> u64 {
+    price * 2
+}
+
+#[test]
+fn times_one_in_every_n_calls() {
+    let metrics = TestMetrics::install();
+    for price in 0..50 {
+        assert_eq!(price * 2, on_market_data(price));
+    }
+    let samples = metrics.recorded_values("sample_latencies", &[("fn_name", "on_market_data")]);
+    assert_eq!(5, samples.len());
+}
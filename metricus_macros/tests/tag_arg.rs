@@ -0,0 +1,80 @@
+use metricus::TestMetrics;
+use metricus_macros::{counter, span};
+use std::fmt::{Display, Formatter};
+use std::thread;
+
+#[derive(Clone, Copy)]
+enum Side {
+    Buy,
+    Sell,
+}
+
+impl Display for Side {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Side::Buy => f.write_str("buy"),
+            Side::Sell => f.write_str("sell"),
+        }
+    }
+}
+
+#[counter(measurement = "tag_arg_orders", tag_arg(side, venue))]
+fn submit_order(side: Side, venue: &str) {
+    let _ = (side, venue);
+}
+
+#[span(measurement = "tag_arg_latency", tag_arg(side))]
+fn cancel_order(side: Side) {
+    let _ = side;
+}
+
+const THREADS: usize = 8;
+const CALLS: usize = 1_000;
+
+#[test]
+fn counters_are_split_by_argument_values_across_threads() {
+    let metrics = TestMetrics::install();
+    thread::scope(|scope| {
+        for thread in 0..THREADS {
+            scope.spawn(move || {
+                for call in 0..CALLS {
+                    let side = if (thread + call) % 2 == 0 {
+                        Side::Buy
+                    } else {
+                        Side::Sell
+                    };
+                    submit_order(side, if thread % 2 == 0 { "xnas" } else { "xlon" });
+                }
+            });
+        }
+    });
+    let count = |side, venue| {
+        metrics.counter_value("tag_arg_orders", &[("fn_name", "submit_order"), ("side", side), ("venue", venue)])
+    };
+    let expected = Some((THREADS * CALLS / 4) as u64);
+    assert_eq!(expected, count("buy", "xnas"));
+    assert_eq!(expected, count("sell", "xnas"));
+    assert_eq!(expected, count("buy", "xlon"));
+    assert_eq!(expected, count("sell", "xlon"));
+}
+
+#[test]
+fn spans_are_split_by_argument_values_across_threads() {
+    let metrics = TestMetrics::install();
+    thread::scope(|scope| {
+        for thread in 0..THREADS {
+            scope.spawn(move || {
+                for _ in 0..CALLS {
+                    cancel_order(if thread % 2 == 0 { Side::Buy } else { Side::Sell });
+                }
+            });
+        }
+    });
+    let samples = |side| {
+        metrics
+            .recorded_values("tag_arg_latency", &[("fn_name", "cancel_order"), ("side", side)])
+            .len()
+    };
+    assert_eq!(THREADS * CALLS / 2, samples("buy"));
+    assert_eq!(THREADS * CALLS / 2, samples("sell"));
+}
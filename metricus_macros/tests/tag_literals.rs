@@ -0,0 +1,29 @@
+use metricus::TestMetrics;
+use metricus_macros::{counter, span};
+
+#[counter(
+    measurement = "tag_literals_orders",
+    tags(shard = 3, critical = true, venue = "xlon")
+)]
+fn route_order() {}
+
+#[span(measurement = "tag_literals_latency", tags(shard = 12, cached = false))]
+fn lookup_order() {}
+
+#[test]
+fn integer_and_boolean_tags_are_exported_as_strings() {
+    let metrics = TestMetrics::install();
+    route_order();
+    route_order();
+    lookup_order();
+
+    let tags = [
+        ("critical", "true"),
+        ("fn_name", "route_order"),
+        ("shard", "3"),
+        ("venue", "xlon"),
+    ];
+    assert_eq!(Some(2), metrics.counter_value("tag_literals_orders", &tags));
+    let tags = [("cached", "false"), ("fn_name", "lookup_order"), ("shard", "12")];
+    assert_eq!(1, metrics.recorded_values("tag_literals_latency", &tags).len());
+}
@@ -0,0 +1,8 @@
+use metricus_macros::counter;
+
+#[counter(measurement = "processed", by = "items.len() as")]
+fn process_batch(items: &[u32]) {
+    let _ = items;
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use metricus_macros::counter;
+
+#[counter(measurement = "orders", tags(venue = "lse", venue = "xnas"))]
+fn submit_order() {}
+
+#[counter(measurement = "orders", tags(side = "buy"), tag_arg(side))]
+fn cancel_order(side: &str) {
+    let _ = side;
+}
+
+fn main() {}
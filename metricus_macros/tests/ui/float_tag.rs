@@ -0,0 +1,6 @@
+use metricus_macros::counter;
+
+#[counter(measurement = "orders", tags(ratio = 0.5))]
+fn submit_order() {}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use metricus_macros::{counter, span};
+
+#[counter(measurement = "orders", fn_name_tag = "function", tags(function = "submit"))]
+fn submit_order() {}
+
+#[span(measurement = "latency", fn_name_tag = "")]
+fn match_order() {}
+
+fn main() {}
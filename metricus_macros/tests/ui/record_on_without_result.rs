@@ -0,0 +1,8 @@
+use metricus_macros::span;
+
+#[span(measurement = "latencies", record_on = "ok")]
+fn submit_order(price: u64) {
+    let _ = price;
+}
+
+fn main() {}
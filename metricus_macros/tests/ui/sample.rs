@@ -0,0 +1,9 @@
+use metricus_macros::span;
+
+#[span(measurement = "latencies", sample = 0)]
+fn never_sampled() {}
+
+#[span(measurement = "latencies", sample = 10, dual_time)]
+async fn dual_time_sampled() {}
+
+fn main() {}